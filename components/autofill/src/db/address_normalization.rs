@@ -0,0 +1,318 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+* License, v. 2.0. If a copy of the MPL was not distributed with this
+* file, You can obtain one at http://mozilla.org/MPL/2.0/.
+*/
+
+//! Normalizing addresses, so that the same address saved with slightly
+//! different formatting - "St." vs "Street", a phone number with or without a
+//! country code, or a postal code with or without spaces - can be found and
+//! merged instead of being saved twice.
+//!
+//! This is modelled on the merge logic in desktop's form autofill. Addresses
+//! are only compared in their normalized form; we always store the values we
+//! were given.
+
+use crate::db::models::address::{InternalAddress, UpdatableAddressFields};
+
+/// Abbreviations that are expanded in street addresses.
+const STREET_ABBREVIATIONS: &[(&str, &str)] = &[
+    ("apt", "apartment"),
+    ("ave", "avenue"),
+    ("blvd", "boulevard"),
+    ("cir", "circle"),
+    ("ct", "court"),
+    ("dr", "drive"),
+    ("e", "east"),
+    ("fl", "floor"),
+    ("hwy", "highway"),
+    ("ln", "lane"),
+    ("n", "north"),
+    ("ne", "northeast"),
+    ("nw", "northwest"),
+    ("pkwy", "parkway"),
+    ("pl", "place"),
+    ("rd", "road"),
+    ("s", "south"),
+    ("se", "southeast"),
+    ("sq", "square"),
+    ("st", "street"),
+    ("ste", "suite"),
+    ("sw", "southwest"),
+    ("ter", "terrace"),
+    ("w", "west"),
+];
+
+/// Phone numbers shorter than this aren't compared by suffix, because they're
+/// too short to tell apart.
+const MIN_TEL_SUFFIX_DIGITS: usize = 7;
+
+/// The most digits a country code can have.
+const MAX_COUNTRY_CODE_DIGITS: usize = 3;
+
+/// Lowercases the text, drops periods and apostrophes, and turns any other
+/// punctuation into spaces, so that "O'Brien, Jr." becomes "obrien jr".
+fn normalize_text(s: &str) -> String {
+    s.to_lowercase()
+        .chars()
+        .filter(|c| *c != '.' && *c != '\'')
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn normalize_street_address(s: &str) -> String {
+    normalize_text(s)
+        .split(' ')
+        .map(|word| {
+            STREET_ABBREVIATIONS
+                .iter()
+                .find(|(abbreviation, _)| *abbreviation == word)
+                .map_or(word, |&(_, expanded)| expanded)
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Drops spaces and dashes, so that "SW1A 1AA" and "sw1a1aa" are the same.
+fn normalize_postal_code(s: &str) -> String {
+    s.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_uppercase)
+        .collect()
+}
+
+/// Keeps just the digits, without leading zeros, which are either an
+/// international call prefix or a national trunk prefix.
+fn normalize_tel(s: &str) -> String {
+    let digits = s.chars().filter(char::is_ascii_digit).collect::<String>();
+    digits.trim_start_matches('0').to_string()
+}
+
+/// Returns true if two normalized phone numbers are the same, or if they're
+/// the same except that one of them has a country code.
+fn tel_matches(a: &str, b: &str) -> bool {
+    let (longer, shorter) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    longer == shorter
+        || (shorter.len() >= MIN_TEL_SUFFIX_DIGITS
+            && longer.len() - shorter.len() <= MAX_COUNTRY_CODE_DIGITS
+            && longer.ends_with(shorter))
+}
+
+/// The normalized form of an address, which is only used for comparing.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct NormalizedAddress {
+    given_name: String,
+    additional_name: String,
+    family_name: String,
+    organization: String,
+    street_address: String,
+    address_level3: String,
+    address_level2: String,
+    address_level1: String,
+    postal_code: String,
+    country: String,
+    tel: String,
+    email: String,
+}
+
+impl NormalizedAddress {
+    pub(crate) fn new(fields: &UpdatableAddressFields) -> Self {
+        Self {
+            given_name: normalize_text(&fields.given_name),
+            additional_name: normalize_text(&fields.additional_name),
+            family_name: normalize_text(&fields.family_name),
+            organization: normalize_text(&fields.organization),
+            street_address: normalize_street_address(&fields.street_address),
+            address_level3: normalize_text(&fields.address_level3),
+            address_level2: normalize_text(&fields.address_level2),
+            address_level1: normalize_text(&fields.address_level1),
+            postal_code: normalize_postal_code(&fields.postal_code),
+            country: normalize_text(&fields.country),
+            tel: normalize_tel(&fields.tel),
+            email: fields.email.trim().to_lowercase(),
+        }
+    }
+
+    /// Returns true if the two addresses are the same after normalizing.
+    ///
+    /// If `strict` is false, a field that's empty in one of the addresses
+    /// doesn't count as a difference, so an address can be merged into one
+    /// that has more fields filled in. In that case, at least one of the
+    /// name, organization, street address, phone number or email must be the
+    /// same, so that addresses which only share, say, a country aren't merged.
+    pub(crate) fn matches(&self, other: &Self, strict: bool) -> bool {
+        fn same(a: &str, b: &str) -> bool {
+            a == b
+        }
+        // Each field, whether it identifies the address, and how to compare
+        // the values.
+        let fields: [(&str, &str, bool, fn(&str, &str) -> bool); 12] = [
+            (&self.given_name, &other.given_name, true, same),
+            (&self.additional_name, &other.additional_name, false, same),
+            (&self.family_name, &other.family_name, true, same),
+            (&self.organization, &other.organization, true, same),
+            (&self.street_address, &other.street_address, true, same),
+            (&self.address_level3, &other.address_level3, false, same),
+            (&self.address_level2, &other.address_level2, false, same),
+            (&self.address_level1, &other.address_level1, false, same),
+            (&self.postal_code, &other.postal_code, false, same),
+            (&self.country, &other.country, false, same),
+            (&self.tel, &other.tel, true, tel_matches),
+            (&self.email, &other.email, true, same),
+        ];
+        let mut has_identifying_match = false;
+        for (a, b, identifying, matches) in fields {
+            if a.is_empty() || b.is_empty() {
+                if strict && a != b {
+                    return false;
+                }
+                continue;
+            }
+            if !matches(a, b) {
+                return false;
+            }
+            has_identifying_match |= identifying;
+        }
+        strict || has_identifying_match
+    }
+}
+
+impl From<&InternalAddress> for NormalizedAddress {
+    fn from(address: &InternalAddress) -> Self {
+        Self::new(&UpdatableAddressFields::from(address))
+    }
+}
+
+/// Fills in the fields of `existing` that are empty with the fields from
+/// `new`, which must be a non-strict match. The phone number is replaced if
+/// the new one has a country code and the existing one doesn't. Returns true
+/// if anything changed.
+pub(crate) fn merge_address_fields(
+    existing: &mut InternalAddress,
+    new: UpdatableAddressFields,
+) -> bool {
+    fn fill(existing: &mut String, new: String) -> bool {
+        if existing.is_empty() && !new.is_empty() {
+            *existing = new;
+            true
+        } else {
+            false
+        }
+    }
+    let mut changed = false;
+    changed |= fill(&mut existing.given_name, new.given_name);
+    changed |= fill(&mut existing.additional_name, new.additional_name);
+    changed |= fill(&mut existing.family_name, new.family_name);
+    changed |= fill(&mut existing.organization, new.organization);
+    changed |= fill(&mut existing.street_address, new.street_address);
+    changed |= fill(&mut existing.address_level3, new.address_level3);
+    changed |= fill(&mut existing.address_level2, new.address_level2);
+    changed |= fill(&mut existing.address_level1, new.address_level1);
+    changed |= fill(&mut existing.postal_code, new.postal_code);
+    changed |= fill(&mut existing.country, new.country);
+    changed |= fill(&mut existing.email, new.email);
+    if normalize_tel(&new.tel).len() > normalize_tel(&existing.tel).len() {
+        existing.tel = new.tel;
+        changed = true;
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(street_address: &str, postal_code: &str, tel: &str) -> NormalizedAddress {
+        NormalizedAddress::new(&UpdatableAddressFields {
+            given_name: "Jane".to_string(),
+            family_name: "Doe".to_string(),
+            street_address: street_address.to_string(),
+            address_level2: "Seattle".to_string(),
+            address_level1: "WA".to_string(),
+            postal_code: postal_code.to_string(),
+            country: "US".to_string(),
+            tel: tel.to_string(),
+            ..UpdatableAddressFields::default()
+        })
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize_text("  O'Brien,  Jr. "), "obrien jr");
+        assert_eq!(
+            normalize_street_address("123 N. Main St., Apt #4"),
+            "123 north main street apartment 4"
+        );
+        assert_eq!(normalize_postal_code("sw1a 1aa"), "SW1A1AA");
+        assert_eq!(normalize_postal_code("98101-1234"), "981011234");
+        assert_eq!(normalize_tel("+1 (206) 555-1234"), "12065551234");
+        assert_eq!(normalize_tel("020 7946 0958"), "2079460958");
+    }
+
+    #[test]
+    fn test_tel_matches() {
+        assert!(tel_matches("12065551234", "2065551234"));
+        assert!(tel_matches("442079460958", "2079460958"));
+        assert!(!tel_matches("12065551234", "2065559999"));
+        assert!(!tel_matches("12065551234", "1234"));
+    }
+
+    #[test]
+    fn test_matches() {
+        let a = address("123 Main Street", "98101", "+1 206-555-1234");
+        assert!(a.matches(&address("123 main st.", "98 101", "(206) 555 1234"), true));
+        assert!(!a.matches(&address("124 Main Street", "98101", ""), false));
+
+        // Empty fields only match in non-strict mode.
+        let b = address("123 Main Street", "", "");
+        assert!(a.matches(&b, false));
+        assert!(b.matches(&a, false));
+        assert!(!a.matches(&b, true));
+
+        // Addresses that only share their location aren't duplicates.
+        let c = NormalizedAddress::new(&UpdatableAddressFields {
+            address_level2: "Seattle".to_string(),
+            country: "US".to_string(),
+            email: "jane@example.com".to_string(),
+            ..UpdatableAddressFields::default()
+        });
+        assert!(!c.matches(&address("", "", ""), false));
+    }
+
+    #[test]
+    fn test_merge_address_fields() {
+        let mut existing = InternalAddress {
+            given_name: "Jane".to_string(),
+            street_address: "123 Main Street".to_string(),
+            tel: "206-555-1234".to_string(),
+            ..InternalAddress::default()
+        };
+        let changed = merge_address_fields(
+            &mut existing,
+            UpdatableAddressFields {
+                given_name: "jane".to_string(),
+                family_name: "Doe".to_string(),
+                street_address: "123 Main St".to_string(),
+                tel: "+1 206 555 1234".to_string(),
+                ..UpdatableAddressFields::default()
+            },
+        );
+        assert!(changed);
+        assert_eq!(existing.given_name, "Jane");
+        assert_eq!(existing.family_name, "Doe");
+        assert_eq!(existing.street_address, "123 Main Street");
+        assert_eq!(existing.tel, "+1 206 555 1234");
+
+        let changed = merge_address_fields(
+            &mut existing,
+            UpdatableAddressFields {
+                given_name: "Jane".to_string(),
+                tel: "206 555 1234".to_string(),
+                ..UpdatableAddressFields::default()
+            },
+        );
+        assert!(!changed);
+    }
+}
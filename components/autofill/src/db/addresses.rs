@@ -4,6 +4,7 @@
 */
 
 use crate::db::{
+    address_normalization::{merge_address_fields, NormalizedAddress},
     models::{
         address::{InternalAddress, UpdatableAddressFields},
         Metadata,
@@ -16,16 +17,8 @@ use rusqlite::{Connection, Transaction};
 use sync_guid::Guid;
 use types::Timestamp;
 
-pub(crate) fn add_address(
-    conn: &Connection,
-    new: UpdatableAddressFields,
-) -> Result<InternalAddress> {
-    let tx = conn.unchecked_transaction()?;
-    let now = Timestamp::now();
-
-    // We return an InternalAddress, so set it up first, including the missing
-    // fields, before we insert it.
-    let address = InternalAddress {
+fn new_internal_address(new: UpdatableAddressFields, now: Timestamp) -> InternalAddress {
+    InternalAddress {
         guid: Guid::random(),
         given_name: new.given_name,
         additional_name: new.additional_name,
@@ -44,12 +37,70 @@ pub(crate) fn add_address(
             time_last_modified: now,
             ..Default::default()
         },
-    };
+    }
+}
+
+pub(crate) fn add_address(
+    conn: &Connection,
+    new: UpdatableAddressFields,
+) -> Result<InternalAddress> {
+    let tx = conn.unchecked_transaction()?;
+
+    // We return an InternalAddress, so set it up first, including the missing
+    // fields, before we insert it.
+    let address = new_internal_address(new, Timestamp::now());
     add_internal_address(&tx, &address)?;
     tx.commit()?;
     Ok(address)
 }
 
+/// Adds an address, or, if there's already an address that's the same after
+/// normalizing (see [`find_duplicate_address`]), fills in the fields that
+/// address is missing instead, and returns it.
+pub(crate) fn add_or_merge_address(
+    conn: &Connection,
+    new: UpdatableAddressFields,
+) -> Result<InternalAddress> {
+    let tx = conn.unchecked_transaction()?;
+    let now = Timestamp::now();
+    let address = match find_duplicate_address(&tx, &new)? {
+        Some(mut existing) => {
+            if merge_address_fields(&mut existing, new) {
+                existing.metadata.time_last_modified = now;
+                update_internal_address(&tx, &existing, true)?;
+                existing.metadata.sync_change_counter += 1;
+            }
+            existing
+        }
+        None => {
+            let address = new_internal_address(new, now);
+            add_internal_address(&tx, &address)?;
+            address
+        }
+    };
+    tx.commit()?;
+    Ok(address)
+}
+
+/// Finds an existing address that's the same as `fields` after normalizing,
+/// ignoring fields that are empty in either address. If there are several,
+/// the most recently used one is returned.
+pub(crate) fn find_duplicate_address(
+    conn: &Connection,
+    fields: &UpdatableAddressFields,
+) -> Result<Option<InternalAddress>> {
+    let normalized = NormalizedAddress::new(fields);
+    Ok(get_all_addresses(conn)?
+        .into_iter()
+        .filter(|address| NormalizedAddress::from(address).matches(&normalized, false))
+        .max_by_key(|address| {
+            (
+                address.metadata.time_last_used,
+                address.metadata.time_last_modified,
+            )
+        }))
+}
+
 pub(crate) fn add_internal_address(tx: &Transaction<'_>, address: &InternalAddress) -> Result<()> {
     tx.execute(
         &format!(
@@ -687,4 +738,106 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_address_find_duplicate() -> Result<()> {
+        let db = new_mem_db();
+        let saved_address = add_address(
+            &db,
+            UpdatableAddressFields {
+                given_name: "jane".to_string(),
+                family_name: "doe".to_string(),
+                street_address: "123 Second Avenue".to_string(),
+                address_level2: "Chicago, IL".to_string(),
+                postal_code: "60601".to_string(),
+                country: "United States".to_string(),
+                tel: "(312) 555-0100".to_string(),
+
+                ..UpdatableAddressFields::default()
+            },
+        )?;
+
+        let duplicate = find_duplicate_address(
+            &db,
+            &UpdatableAddressFields {
+                given_name: "Jane".to_string(),
+                family_name: "Doe".to_string(),
+                street_address: "123 Second Ave.".to_string(),
+                postal_code: "60 601".to_string(),
+                tel: "+1 312 555 0100".to_string(),
+
+                ..UpdatableAddressFields::default()
+            },
+        )?
+        .expect("should find duplicate");
+        assert_eq!(duplicate.guid, saved_address.guid);
+
+        let not_duplicate = find_duplicate_address(
+            &db,
+            &UpdatableAddressFields {
+                given_name: "jane".to_string(),
+                family_name: "doe".to_string(),
+                street_address: "125 Second Avenue".to_string(),
+
+                ..UpdatableAddressFields::default()
+            },
+        )?;
+        assert!(not_duplicate.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_address_add_or_merge() -> Result<()> {
+        let db = new_mem_db();
+        let saved_address = add_or_merge_address(
+            &db,
+            UpdatableAddressFields {
+                given_name: "jane".to_string(),
+                family_name: "doe".to_string(),
+                street_address: "123 Second Avenue".to_string(),
+                address_level2: "Chicago, IL".to_string(),
+                country: "United States".to_string(),
+
+                ..UpdatableAddressFields::default()
+            },
+        )?;
+
+        // Saving the same address with different formatting and an email
+        // fills in the email.
+        let merged_address = add_or_merge_address(
+            &db,
+            UpdatableAddressFields {
+                given_name: "Jane".to_string(),
+                family_name: "Doe".to_string(),
+                street_address: "123 Second Ave".to_string(),
+                email: "jane@example.com".to_string(),
+
+                ..UpdatableAddressFields::default()
+            },
+        )?;
+        assert_eq!(merged_address.guid, saved_address.guid);
+        assert_eq!(merged_address.street_address, "123 Second Avenue");
+        assert_eq!(merged_address.email, "jane@example.com");
+
+        let stored_address = get_address(&db, &saved_address.guid)?;
+        assert_eq!(stored_address.email, "jane@example.com");
+        assert_eq!(stored_address.metadata.sync_change_counter, 1);
+
+        // A different address is added.
+        let other_address = add_or_merge_address(
+            &db,
+            UpdatableAddressFields {
+                given_name: "jane".to_string(),
+                family_name: "doe".to_string(),
+                street_address: "1300 Broadway".to_string(),
+
+                ..UpdatableAddressFields::default()
+            },
+        )?;
+        assert_ne!(other_address.guid, saved_address.guid);
+        assert_eq!(get_all_addresses(&db)?.len(), 2);
+
+        Ok(())
+    }
 }
@@ -29,6 +29,25 @@ pub struct UpdatableAddressFields {
     pub email: String,
 }
 
+impl From<&InternalAddress> for UpdatableAddressFields {
+    fn from(ia: &InternalAddress) -> Self {
+        UpdatableAddressFields {
+            given_name: ia.given_name.clone(),
+            additional_name: ia.additional_name.clone(),
+            family_name: ia.family_name.clone(),
+            organization: ia.organization.clone(),
+            street_address: ia.street_address.clone(),
+            address_level3: ia.address_level3.clone(),
+            address_level2: ia.address_level2.clone(),
+            address_level1: ia.address_level1.clone(),
+            postal_code: ia.postal_code.clone(),
+            country: ia.country.clone(),
+            tel: ia.tel.clone(),
+            email: ia.email.clone(),
+        }
+    }
+}
+
 // "Address" is what we return to consumers and has most of the metadata.
 #[derive(Debug, Clone, Hash, PartialEq, Eq, Default)]
 pub struct Address {
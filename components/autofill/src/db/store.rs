@@ -118,8 +118,27 @@ impl Store {
     }
 
     #[handle_error(Error)]
-    pub fn add_address(&self, new_address: UpdatableAddressFields) -> ApiResult<Address> {
-        Ok(addresses::add_address(&self.db.lock().unwrap().writer, new_address)?.into())
+    pub fn add_address(
+        &self,
+        new_address: UpdatableAddressFields,
+        merge_duplicates: bool,
+    ) -> ApiResult<Address> {
+        let db = self.db.lock().unwrap();
+        let address = if merge_duplicates {
+            addresses::add_or_merge_address(&db.writer, new_address)?
+        } else {
+            addresses::add_address(&db.writer, new_address)?
+        };
+        Ok(address.into())
+    }
+
+    #[handle_error(Error)]
+    pub fn find_duplicate_address(
+        &self,
+        fields: UpdatableAddressFields,
+    ) -> ApiResult<Option<Address>> {
+        let address = addresses::find_duplicate_address(&self.db.lock().unwrap().writer, &fields)?;
+        Ok(address.map(Into::into))
     }
 
     #[handle_error(Error)]
@@ -4,6 +4,7 @@
 */
 
 use super::AddressPayload;
+use crate::db::address_normalization::NormalizedAddress;
 use crate::db::addresses::{add_internal_address, update_internal_address};
 use crate::db::models::address::InternalAddress;
 use crate::db::schema::ADDRESS_COMMON_COLS;
@@ -155,13 +156,17 @@ impl ProcessIncomingRecordImpl for IncomingAddressesImpl {
 
     /// Returns a local record that has the same values as the given incoming record (with the exception
     /// of the `guid` values which should differ) that will be used as a local duplicate record for
-    /// syncing.
+    /// syncing. Values are compared after normalizing, using the same comparator as
+    /// `find_duplicate_address()`, but strictly, so a field that's empty in only one of the records
+    /// is a difference - the local record is replaced by the incoming one, so it mustn't have any
+    /// data the incoming record doesn't.
     fn get_local_dupe(
         &self,
         tx: &Transaction<'_>,
         incoming: &Self::Record,
     ) -> Result<Option<Self::Record>> {
-        let sql = format!("
+        let sql = format!(
+            "
             SELECT
                 {common_cols},
                 sync_change_counter
@@ -173,48 +178,19 @@ impl ProcessIncomingRecordImpl for IncomingAddressesImpl {
                 AND guid NOT IN (
                     SELECT guid
                     FROM addresses_mirror
-                )
-                -- and sql can check the field values.
-                AND given_name == :given_name
-                AND additional_name == :additional_name
-                AND family_name == :family_name
-                AND organization == :organization
-                AND street_address == :street_address
-                AND address_level3 == :address_level3
-                AND address_level2 == :address_level2
-                AND address_level1 == :address_level1
-                AND postal_code == :postal_code
-                AND country == :country
-                AND tel == :tel
-                AND email == :email", common_cols = ADDRESS_COMMON_COLS);
-
-        let params = named_params! {
-            ":guid": incoming.guid,
-            ":given_name": incoming.given_name,
-            ":additional_name": incoming.additional_name,
-            ":family_name": incoming.family_name,
-            ":organization": incoming.organization,
-            ":street_address": incoming.street_address,
-            ":address_level3": incoming.address_level3,
-            ":address_level2": incoming.address_level2,
-            ":address_level1": incoming.address_level1,
-            ":postal_code": incoming.postal_code,
-            ":country": incoming.country,
-            ":tel": incoming.tel,
-            ":email": incoming.email,
-        };
-
-        let result = tx.query_row(&sql, params, |row| {
-            Ok(Self::Record::from_row(row).expect("wtf? '?' doesn't work :("))
-        });
+                )",
+            common_cols = ADDRESS_COMMON_COLS
+        );
 
-        match result {
-            Ok(r) => Ok(Some(r)),
-            Err(e) => match e {
-                rusqlite::Error::QueryReturnedNoRows => Ok(None),
-                _ => Err(Error::SqlError(e)),
-            },
-        }
+        let normalized = NormalizedAddress::from(incoming);
+        let candidates = tx.query_rows_and_then(
+            &sql,
+            named_params! { ":guid": incoming.guid },
+            |row| -> Result<Self::Record> { Ok(Self::Record::from_row(row)?) },
+        )?;
+        Ok(candidates
+            .into_iter()
+            .find(|candidate| NormalizedAddress::from(candidate).matches(&normalized, true)))
     }
 
     fn update_local_record(
@@ -447,6 +423,37 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_get_local_dupe() -> Result<()> {
+        let mut db = new_syncable_mem_db();
+        let tx = db.transaction()?;
+        let ri = IncomingAddressesImpl {};
+        let incoming = test_record('A');
+
+        // The same address, formatted differently.
+        let mut local = incoming.clone();
+        local.guid = SyncGuid::new(&expand_test_guid('B'));
+        local.given_name = "John".to_string();
+        local.street_address = "1300 Broadway.".to_string();
+        local.address_level2 = "new york ny".to_string();
+        ri.insert_local_record(&tx, local)?;
+
+        let dupe = ri
+            .get_local_dupe(&tx, &incoming)?
+            .expect("should find dupe");
+        assert_eq!(dupe.guid, expand_test_guid('B'));
+
+        // A local record with a field the incoming record doesn't have isn't
+        // a dupe, because it would lose that field.
+        let mut local = incoming.clone();
+        local.guid = SyncGuid::new(&expand_test_guid('C'));
+        local.email = "john@example.com".to_string();
+        ri.remove_record(&tx, &SyncGuid::new(&expand_test_guid('B')))?;
+        ri.insert_local_record(&tx, local)?;
+        assert!(ri.get_local_dupe(&tx, &incoming)?.is_none());
+        Ok(())
+    }
+
     #[test]
     fn test_get_incoming() {
         let mut db = new_syncable_mem_db();
@@ -106,6 +106,176 @@ class DatabaseLoginsStorage(dbPath: String) : AutoCloseable {
         }
     }
 
+    @Throws(LoginsApiException::class)
+    fun findMatchingLogins(
+        origin: String,
+        formActionOrigin: String?,
+        httpRealm: String?,
+        options: LoginMatchOptions = LoginMatchOptions(),
+    ): List<LoginMatch> {
+        return readQueryCounters.measure {
+            store.findMatchingLogins(origin, formActionOrigin, httpRealm, options)
+        }
+    }
+
+    @Throws(LoginsApiException::class)
+    fun importCsv(csvData: String, encryptionKey: String): List<CsvImportRow> {
+        return writeQueryCounters.measure {
+            store.importCsv(csvData, encryptionKey)
+        }
+    }
+
+    @Throws(LoginsApiException::class)
+    fun exportCsv(encryptionKey: String): String {
+        return readQueryCounters.measure {
+            store.exportCsv(encryptionKey)
+        }
+    }
+
+    @Throws(LoginsApiException::class)
+    fun generateTotp(id: String, encryptionKey: String, now: Long): String {
+        return readQueryCounters.measure {
+            store.generateTotp(id, encryptionKey, now)
+        }
+    }
+
+    @Throws(LoginsApiException::class)
+    fun getPasswordHistory(id: String): List<PasswordHistoryEntry> {
+        return readQueryCounters.measure {
+            store.getPasswordHistory(id)
+        }
+    }
+
+    @Throws(LoginsApiException::class)
+    fun restorePassword(id: String, historyId: Long, encryptionKey: String): EncryptedLogin {
+        return writeQueryCounters.measure {
+            store.restorePassword(id, historyId, encryptionKey)
+        }
+    }
+
+    @Throws(LoginsApiException::class)
+    fun rotateKey(
+        oldKey: String,
+        newKey: String,
+        canary: String,
+        canaryText: String,
+        quarantine: Boolean = false,
+    ): KeyRotationResult {
+        return writeQueryCounters.measure {
+            store.rotateKey(oldKey, newKey, canary, canaryText, quarantine)
+        }
+    }
+
+    @Throws(LoginsApiException::class)
+    fun scanForUndecryptable(encryptionKey: String): List<String> {
+        return readQueryCounters.measure {
+            store.scanForUndecryptable(encryptionKey)
+        }
+    }
+
+    @Throws(LoginsApiException::class)
+    fun deleteUndecryptableAndResync(encryptionKey: String): List<String> {
+        return writeQueryCounters.measure {
+            store.deleteUndecryptableAndResync(encryptionKey)
+        }
+    }
+
+    @Throws(LoginsApiException::class)
+    fun addPasskey(entry: PasskeyEntry, userVerified: Boolean, encryptionKey: String): PasskeyRegistration {
+        return writeQueryCounters.measure {
+            store.addPasskey(entry, userVerified, encryptionKey)
+        }
+    }
+
+    @Throws(LoginsApiException::class)
+    fun importPasskey(passkey: PasskeyImport, encryptionKey: String): Passkey {
+        return writeQueryCounters.measure {
+            store.importPasskey(passkey, encryptionKey)
+        }
+    }
+
+    @Throws(LoginsApiException::class)
+    fun getPasskey(id: String): Passkey? {
+        return readQueryCounters.measure {
+            store.getPasskey(id)
+        }
+    }
+
+    @Throws(LoginsApiException::class)
+    fun listPasskeys(): List<Passkey> {
+        return readQueryCounters.measure {
+            store.listPasskeys()
+        }
+    }
+
+    @Throws(LoginsApiException::class)
+    fun getPasskeysForRp(rpId: String): List<Passkey> {
+        return readQueryCounters.measure {
+            store.getPasskeysForRp(rpId)
+        }
+    }
+
+    @Throws(LoginsApiException::class)
+    fun updatePasskey(id: String, userName: String, userDisplayName: String): Passkey {
+        return writeQueryCounters.measure {
+            store.updatePasskey(id, userName, userDisplayName)
+        }
+    }
+
+    @Throws(LoginsApiException::class)
+    fun deletePasskey(id: String): Boolean {
+        return writeQueryCounters.measure {
+            store.deletePasskey(id)
+        }
+    }
+
+    @Throws(LoginsApiException::class)
+    fun signPasskeyAssertion(
+        id: String,
+        clientDataHash: String,
+        userVerified: Boolean,
+        encryptionKey: String,
+    ): PasskeyAssertion {
+        return writeQueryCounters.measure {
+            store.signPasskeyAssertion(id, clientDataHash, userVerified, encryptionKey)
+        }
+    }
+
+    @Throws(LoginsApiException::class)
+    fun audit(encryptionKey: String): LoginsAuditReport {
+        return readQueryCounters.measure {
+            store.audit(encryptionKey)
+        }
+    }
+
+    @Throws(LoginsApiException::class)
+    fun getBreachAlerts(breaches: List<Breach>): List<BreachAlert> {
+        return readQueryCounters.measure {
+            store.getBreachAlerts(breaches)
+        }
+    }
+
+    @Throws(LoginsApiException::class)
+    fun recordBreachAlertDismissal(id: String) {
+        writeQueryCounters.measure {
+            store.recordBreachAlertDismissal(id)
+        }
+    }
+
+    @Throws(LoginsApiException::class)
+    fun recordVulnerablePasswords(ids: List<String>, encryptionKey: String) {
+        writeQueryCounters.measure {
+            store.recordVulnerablePasswords(ids, encryptionKey)
+        }
+    }
+
+    @Throws(LoginsApiException::class)
+    fun isVulnerablePassword(secFields: SecureLoginFields, encryptionKey: String): Boolean {
+        return readQueryCounters.measure {
+            store.isVulnerablePassword(secFields, encryptionKey)
+        }
+    }
+
     fun registerWithSyncManager() {
         return store.registerWithSyncManager()
     }
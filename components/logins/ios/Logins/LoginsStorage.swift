@@ -97,6 +97,208 @@ open class LoginsStorage {
         }
     }
 
+    /// Find the logins to offer for a page, best match first.
+    open func findMatchingLogins(
+        origin: String,
+        formActionOrigin: String?,
+        httpRealm: String?,
+        options: LoginMatchOptions = LoginMatchOptions()
+    ) throws -> [LoginMatch] {
+        return try queue.sync {
+            try self.store.findMatchingLogins(
+                origin: origin,
+                formActionOrigin: formActionOrigin,
+                httpRealm: httpRealm,
+                options: options
+            )
+        }
+    }
+
+    /// Import logins from CSV text exported by another password manager.
+    ///
+    /// Returns what happened to each row of the file.
+    open func importCsv(csvData: String, encryptionKey: String) throws -> [CsvImportRow] {
+        return try queue.sync {
+            try self.store.importCsv(csvData: csvData, encryptionKey: encryptionKey)
+        }
+    }
+
+    /// Export all logins as cleartext CSV, in the same format as desktop.
+    open func exportCsv(encryptionKey: String) throws -> String {
+        return try queue.sync {
+            try self.store.exportCsv(encryptionKey: encryptionKey)
+        }
+    }
+
+    /// Generate the current one-time password for the login with the given id. `now` is in
+    /// milliseconds since the unix epoch.
+    open func generateTotp(id: String, encryptionKey: String, now: Int64) throws -> String {
+        return try queue.sync {
+            try self.store.generateTotp(id: id, encryptionKey: encryptionKey, now: now)
+        }
+    }
+
+    /// Get the previous passwords of the login with the given id, most recently replaced first.
+    open func getPasswordHistory(id: String) throws -> [PasswordHistoryEntry] {
+        return try queue.sync {
+            try self.store.getPasswordHistory(id: id)
+        }
+    }
+
+    /// Make a previous password the current password of its login.
+    ///
+    /// Throws `LoginStoreError.NoSuchRecord` if there was no such login or history entry.
+    open func restorePassword(id: String, historyId: Int64, encryptionKey: String) throws -> EncryptedLogin {
+        return try queue.sync {
+            try self.store.restorePassword(id: id, historyId: historyId, encryptionKey: encryptionKey)
+        }
+    }
+
+    /// Re-encrypt everything in the database with `newKey` instead of `oldKey`.
+    ///
+    /// `canary` is the output of `createCanary(text: canaryText, encryptionKey: oldKey)`. Data
+    /// which can't be decrypted with `oldKey` is reported in the result, and removed from the
+    /// logins if `quarantine` is true. Throws `LoginStoreError.IncorrectKey` if `canary` wasn't
+    /// created with `oldKey`. The result has a canary for `newKey`, to store along with it.
+    open func rotateKey(
+        oldKey: String,
+        newKey: String,
+        canary: String,
+        canaryText: String,
+        quarantine: Bool = false
+    ) throws -> KeyRotationResult {
+        return try queue.sync {
+            try self.store.rotateKey(
+                oldKey: oldKey,
+                newKey: newKey,
+                canary: canary,
+                canaryText: canaryText,
+                quarantine: quarantine
+            )
+        }
+    }
+
+    /// Get the ids of logins, then passkeys, which can't be decrypted with `encryptionKey`.
+    open func scanForUndecryptable(encryptionKey: String) throws -> [String] {
+        return try queue.sync {
+            try self.store.scanForUndecryptable(encryptionKey: encryptionKey)
+        }
+    }
+
+    /// Delete the local and mirror records which can't be decrypted with `encryptionKey`, and
+    /// reset sync so the next sync downloads them again. Undecryptable passkeys, which aren't
+    /// synced, are deleted for good. Returns the ids of the affected logins and passkeys.
+    open func deleteUndecryptableAndResync(encryptionKey: String) throws -> [String] {
+        return try queue.sync {
+            try self.store.deleteUndecryptableAndResync(encryptionKey: encryptionKey)
+        }
+    }
+
+    /// Create a passkey, with a new key pair and credential id.
+    open func addPasskey(
+        entry: PasskeyEntry,
+        userVerified: Bool,
+        encryptionKey: String
+    ) throws -> PasskeyRegistration {
+        return try queue.sync {
+            try self.store.addPasskey(entry: entry, userVerified: userVerified, encryptionKey: encryptionKey)
+        }
+    }
+
+    /// Import a passkey created somewhere else, keeping its credential id and key.
+    open func importPasskey(passkey: PasskeyImport, encryptionKey: String) throws -> Passkey {
+        return try queue.sync {
+            try self.store.importPasskey(passkey: passkey, encryptionKey: encryptionKey)
+        }
+    }
+
+    open func getPasskey(id: String) throws -> Passkey? {
+        return try queue.sync {
+            try self.store.getPasskey(id: id)
+        }
+    }
+
+    open func listPasskeys() throws -> [Passkey] {
+        return try queue.sync {
+            try self.store.listPasskeys()
+        }
+    }
+
+    /// Get the passkeys for a relying party, most recently used first.
+    open func getPasskeysForRp(rpId: String) throws -> [Passkey] {
+        return try queue.sync {
+            try self.store.getPasskeysForRp(rpId: rpId)
+        }
+    }
+
+    /// Update the user name and display name of a passkey.
+    ///
+    /// Throws `LoginStoreError.NoSuchRecord` if there was no such passkey.
+    open func updatePasskey(id: String, userName: String, userDisplayName: String) throws -> Passkey {
+        return try queue.sync {
+            try self.store.updatePasskey(id: id, userName: userName, userDisplayName: userDisplayName)
+        }
+    }
+
+    /// Delete the passkey with the given id. Returns true if it existed.
+    open func deletePasskey(id: String) throws -> Bool {
+        return try queue.sync {
+            try self.store.deletePasskey(id: id)
+        }
+    }
+
+    /// Sign an assertion with a passkey, for the base64url encoded SHA-256 hash of the client data.
+    open func signPasskeyAssertion(
+        id: String,
+        clientDataHash: String,
+        userVerified: Bool,
+        encryptionKey: String
+    ) throws -> PasskeyAssertion {
+        return try queue.sync {
+            try self.store.signPasskeyAssertion(
+                id: id,
+                clientDataHash: clientDataHash,
+                userVerified: userVerified,
+                encryptionKey: encryptionKey
+            )
+        }
+    }
+
+    /// Report reused passwords, weak passwords and missing usernames across all logins.
+    open func audit(encryptionKey: String) throws -> LoginsAuditReport {
+        return try queue.sync {
+            try self.store.audit(encryptionKey: encryptionKey)
+        }
+    }
+
+    /// Get the logins whose password was last changed before a breach of their domain.
+    open func getBreachAlerts(breaches: [Breach]) throws -> [BreachAlert] {
+        return try queue.sync {
+            try self.store.getBreachAlerts(breaches: breaches)
+        }
+    }
+
+    /// Remember that the user dismissed the breach alert for the login with the given id.
+    open func recordBreachAlertDismissal(id: String) throws {
+        try queue.sync {
+            try self.store.recordBreachAlertDismissal(id: id)
+        }
+    }
+
+    /// Add the current passwords of the given logins to the set of vulnerable passwords.
+    open func recordVulnerablePasswords(ids: [String], encryptionKey: String) throws {
+        try queue.sync {
+            try self.store.recordVulnerablePasswords(ids: ids, encryptionKey: encryptionKey)
+        }
+    }
+
+    /// Check a decrypted password against the set of vulnerable passwords.
+    open func isVulnerablePassword(secFields: SecureLoginFields, encryptionKey: String) throws -> Bool {
+        return try queue.sync {
+            try self.store.isVulnerablePassword(secFields: secFields, encryptionKey: encryptionKey)
+        }
+    }
+
     /// Register with the sync manager
     open func registerWithSyncManager() {
         return queue.sync {
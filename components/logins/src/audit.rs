@@ -0,0 +1,392 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A "password health" audit of all logins.
+//!
+//! Every login is decrypted exactly once, and the report only refers to logins by their id, so
+//! no cleartext crosses the FFI. The checks are deliberately simple so that every app reports
+//! the same results:
+//!
+//! - Passwords used by logins on different sites are reported as reused. The "site" is the
+//!   registrable domain, according to the public suffix list.
+//! - Passwords which are short, very common, trivially guessable or identical to the username
+//!   are reported as weak.
+//! - Logins without a username are listed, since they can't be autofilled properly.
+//!
+//! Logins which can't be decrypted are skipped and counted, rather than failing the whole audit.
+
+use crate::db::LoginDb;
+use crate::encryption::EncryptorDecryptor;
+use crate::error::*;
+use std::collections::{BTreeSet, HashMap};
+use url::{Host, Url};
+
+/// Passwords shorter than this (in characters) are considered weak.
+const MIN_PASSWORD_LENGTH: usize = 8;
+
+// Some of the most common passwords from public breach corpora. Compared case-insensitively.
+const COMMON_PASSWORDS: &[&str] = &[
+    "000000",
+    "111111",
+    "123123",
+    "123321",
+    "1234",
+    "12345",
+    "123456",
+    "1234567",
+    "12345678",
+    "123456789",
+    "1234567890",
+    "1q2w3e4r",
+    "1qaz2wsx",
+    "654321",
+    "abc123",
+    "admin",
+    "asdfghjkl",
+    "baseball",
+    "dragon",
+    "football",
+    "iloveyou",
+    "letmein",
+    "master",
+    "monkey",
+    "passw0rd",
+    "password",
+    "password1",
+    "princess",
+    "qwerty",
+    "qwerty123",
+    "qwertyuiop",
+    "sunshine",
+    "superman",
+    "trustno1",
+    "welcome",
+    "zaq12wsx",
+];
+
+/// Why a password was considered weak.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WeakPasswordReason {
+    /// Shorter than 8 characters.
+    TooShort,
+    /// One of the most commonly used passwords.
+    Common,
+    /// A single repeated character, or a run of consecutive characters like "abcdef".
+    Pattern,
+    /// The same as the login's username.
+    MatchesUsername,
+}
+
+/// Logins on different sites which all use the same password.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReusedPasswordGroup {
+    pub ids: Vec<String>,
+    /// The sites the logins are for, as guessed from their origins.
+    pub sites: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WeakPassword {
+    pub id: String,
+    pub reasons: Vec<WeakPasswordReason>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct LoginsAuditReport {
+    pub reused_passwords: Vec<ReusedPasswordGroup>,
+    pub weak_passwords: Vec<WeakPassword>,
+    /// The ids of logins with an empty username.
+    pub missing_usernames: Vec<String>,
+    /// How many logins were skipped because they couldn't be decrypted.
+    pub undecryptable_count: u32,
+}
+
+/// The site of an origin: the registrable domain of its host, or the host itself if that's a
+/// public suffix or an IP address. Origins without a host are returned unchanged.
+pub(crate) fn site_for_origin(origin: &str) -> String {
+    let url = match Url::parse(origin) {
+        Ok(url) => url,
+        Err(_) => return origin.to_owned(),
+    };
+    match url.host() {
+        Some(Host::Domain(domain)) => {
+            psl_support::registrable_domain(domain).unwrap_or_else(|| domain.to_owned())
+        }
+        Some(host) => host.to_string(),
+        None => origin.to_owned(),
+    }
+}
+
+fn is_pattern(password: &str) -> bool {
+    let chars = password.chars().map(u32::from).collect::<Vec<_>>();
+    if chars.len() < 3 {
+        return false;
+    }
+    let steps = chars
+        .windows(2)
+        .map(|w| i64::from(w[1]) - i64::from(w[0]))
+        .collect::<Vec<_>>();
+    let step = steps[0];
+    (-1..=1).contains(&step) && steps.iter().all(|s| *s == step)
+}
+
+fn weak_password_reasons(username: &str, password: &str) -> Vec<WeakPasswordReason> {
+    let mut reasons = Vec::new();
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        reasons.push(WeakPasswordReason::TooShort);
+    }
+    let lower = password.to_lowercase();
+    if COMMON_PASSWORDS.contains(&lower.as_str()) {
+        reasons.push(WeakPasswordReason::Common);
+    }
+    if is_pattern(&lower) {
+        reasons.push(WeakPasswordReason::Pattern);
+    }
+    if !username.is_empty() && username.to_lowercase() == lower {
+        reasons.push(WeakPasswordReason::MatchesUsername);
+    }
+    reasons
+}
+
+impl LoginDb {
+    pub fn audit(&self, encdec: &EncryptorDecryptor) -> Result<LoginsAuditReport> {
+        let mut report = LoginsAuditReport::default();
+        // password -> [(id, site)]
+        let mut by_password: HashMap<String, Vec<(String, String)>> = HashMap::new();
+        for login in self.get_all()? {
+            let login = match login.decrypt(encdec) {
+                Ok(login) => login,
+                Err(e) => {
+                    log::warn!("Skipping login which can't be decrypted: {}", e);
+                    report.undecryptable_count += 1;
+                    continue;
+                }
+            };
+            let id = login.record.id;
+            if login.sec_fields.username.trim().is_empty() {
+                report.missing_usernames.push(id.clone());
+            }
+            let reasons =
+                weak_password_reasons(&login.sec_fields.username, &login.sec_fields.password);
+            if !reasons.is_empty() {
+                report.weak_passwords.push(WeakPassword {
+                    id: id.clone(),
+                    reasons,
+                });
+            }
+            by_password
+                .entry(login.sec_fields.password)
+                .or_default()
+                .push((id, site_for_origin(&login.fields.origin)));
+        }
+        report.reused_passwords = by_password
+            .into_values()
+            .filter_map(|logins| {
+                let sites = logins
+                    .iter()
+                    .map(|(_, site)| site.clone())
+                    .collect::<BTreeSet<_>>();
+                if sites.len() < 2 {
+                    return None;
+                }
+                let mut ids = logins.into_iter().map(|(id, _)| id).collect::<Vec<_>>();
+                ids.sort();
+                Some(ReusedPasswordGroup {
+                    ids,
+                    sites: sites.into_iter().collect(),
+                })
+            })
+            .collect();
+        // HashMap iteration order is random, so make the report stable.
+        report.reused_passwords.sort_by(|a, b| a.ids.cmp(&b.ids));
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::test_utils::TEST_ENCRYPTOR;
+    use crate::login::{LoginEntry, LoginFields, SecureLoginFields};
+
+    fn add_login(db: &LoginDb, origin: &str, username: &str, password: &str) -> String {
+        db.add(
+            LoginEntry {
+                fields: LoginFields {
+                    origin: origin.into(),
+                    http_realm: Some("realm".into()),
+                    ..Default::default()
+                },
+                sec_fields: SecureLoginFields {
+                    username: username.into(),
+                    password: password.into(),
+                    otp_secret: None,
+                },
+            },
+            &TEST_ENCRYPTOR,
+        )
+        .unwrap()
+        .record
+        .id
+    }
+
+    #[test]
+    fn test_site_for_origin() {
+        for (origin, site) in &[
+            ("https://example.com", "example.com"),
+            ("https://www.example.com", "example.com"),
+            ("https://a.b.example.com:8080", "example.com"),
+            ("https://www.example.co.uk", "example.co.uk"),
+            ("https://example.co.uk", "example.co.uk"),
+            ("https://t.co", "t.co"),
+            ("https://app.example.io", "example.io"),
+            ("https://www.ibm.de", "ibm.de"),
+            ("https://login.ibm.de", "ibm.de"),
+            ("https://a.github.io", "a.github.io"),
+            ("https://b.github.io", "b.github.io"),
+            ("https://github.io", "github.io"),
+            ("http://localhost:8080", "localhost"),
+            ("http://127.0.0.1:8000", "127.0.0.1"),
+            ("https://[::1]", "[::1]"),
+            ("file://", "file://"),
+        ] {
+            assert_eq!(site_for_origin(origin), *site, "{}", origin);
+        }
+    }
+
+    #[test]
+    fn test_weak_password_reasons() {
+        use WeakPasswordReason::*;
+        assert_eq!(weak_password_reasons("user", "correct horse"), vec![]);
+        assert_eq!(weak_password_reasons("user", "hunter2"), vec![TooShort]);
+        assert_eq!(weak_password_reasons("user", "Password"), vec![Common]);
+        assert_eq!(weak_password_reasons("user", "abcdefghij"), vec![Pattern]);
+        assert_eq!(weak_password_reasons("user", "9876543210"), vec![Pattern]);
+        assert_eq!(weak_password_reasons("user", "zzzzzzzzzz"), vec![Pattern]);
+        assert_eq!(
+            weak_password_reasons("someone@example.com", "SOMEONE@example.com"),
+            vec![MatchesUsername]
+        );
+        assert_eq!(
+            weak_password_reasons("1234", "1234"),
+            vec![TooShort, Common, Pattern, MatchesUsername]
+        );
+    }
+
+    #[test]
+    fn test_audit() {
+        let db = LoginDb::open_in_memory().unwrap();
+        let a = add_login(
+            &db,
+            "https://www.example.com",
+            "alice",
+            "correct horse battery",
+        );
+        let b = add_login(
+            &db,
+            "https://login.example.com",
+            "alice2",
+            "correct horse battery",
+        );
+        let c = add_login(&db, "https://example.org", "", "correct horse battery");
+        let d = add_login(&db, "https://example.net", "dave", "qwerty");
+        let e = add_login(&db, "https://example.co.uk", "erin", "qwerty");
+        add_login(&db, "https://example.com.au", "frank", "staple paperclip");
+        // Different sites under a shared suffix.
+        let g = add_login(&db, "https://a.github.io", "grace", "battery staple");
+        let h = add_login(&db, "https://b.github.io", "heidi", "battery staple");
+
+        let report = db.audit(&TEST_ENCRYPTOR).unwrap();
+
+        let mut group1 = vec![a, b, c.clone()];
+        group1.sort();
+        let mut group2 = vec![d.clone(), e.clone()];
+        group2.sort();
+        let mut group3 = vec![g, h];
+        group3.sort();
+        let mut expected = vec![
+            ReusedPasswordGroup {
+                ids: group1,
+                sites: vec!["example.com".into(), "example.org".into()],
+            },
+            ReusedPasswordGroup {
+                ids: group2,
+                sites: vec!["example.co.uk".into(), "example.net".into()],
+            },
+            ReusedPasswordGroup {
+                ids: group3,
+                sites: vec!["a.github.io".into(), "b.github.io".into()],
+            },
+        ];
+        expected.sort_by(|a, b| a.ids.cmp(&b.ids));
+        assert_eq!(report.reused_passwords, expected);
+
+        let mut weak = report
+            .weak_passwords
+            .iter()
+            .map(|w| w.id.clone())
+            .collect::<Vec<_>>();
+        weak.sort();
+        let mut expected_weak = vec![d, e];
+        expected_weak.sort();
+        assert_eq!(weak, expected_weak);
+
+        assert_eq!(report.missing_usernames, vec![c]);
+        assert_eq!(report.undecryptable_count, 0);
+    }
+
+    #[test]
+    fn test_audit_undecryptable() {
+        let db = LoginDb::open_in_memory().unwrap();
+        let a = add_login(&db, "https://example.com", "", "correct horse battery");
+        let other = EncryptorDecryptor::new_with_random_key().unwrap();
+        db.add(
+            LoginEntry {
+                fields: LoginFields {
+                    origin: "https://example.org".into(),
+                    http_realm: Some("realm".into()),
+                    ..Default::default()
+                },
+                sec_fields: SecureLoginFields {
+                    username: "".into(),
+                    password: "correct horse battery".into(),
+                    otp_secret: None,
+                },
+            },
+            &other,
+        )
+        .unwrap();
+
+        let report = db.audit(&TEST_ENCRYPTOR).unwrap();
+        assert_eq!(
+            report,
+            LoginsAuditReport {
+                missing_usernames: vec![a],
+                undecryptable_count: 1,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_audit_same_site_not_reused() {
+        let db = LoginDb::open_in_memory().unwrap();
+        add_login(
+            &db,
+            "https://www.example.com",
+            "alice",
+            "correct horse battery",
+        );
+        add_login(
+            &db,
+            "https://accounts.example.com",
+            "bob",
+            "correct horse battery",
+        );
+        assert_eq!(
+            db.audit(&TEST_ENCRYPTOR).unwrap(),
+            LoginsAuditReport::default()
+        );
+    }
+}
@@ -0,0 +1,357 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Breach alerts and vulnerable passwords.
+//!
+//! This mirrors desktop's `LoginBreaches.jsm`. The consuming app supplies a list of known
+//! breaches (typically from the "fxmonitor-breaches" remote-settings collection) and we
+//! report which logins were probably exposed by one of them - that is, logins for the breached
+//! domain whose password was last changed before the breach happened.
+//!
+//! The user can dismiss an alert for a login, which we remember until a new breach for that
+//! domain is added to the dataset.
+//!
+//! Separately, we keep a set of hashes of passwords which are known to have been exposed, so
+//! that we can warn about other logins which reuse them.
+
+use crate::db::LoginDb;
+use crate::encryption::EncryptorDecryptor;
+use crate::error::*;
+use crate::login::SecureLoginFields;
+use crate::util;
+use rusqlite::named_params;
+use sql_support::ConnExt;
+use std::collections::HashMap;
+use std::time::SystemTime;
+use url::Host;
+
+// Breaches which didn't expose passwords don't generate alerts.
+const PASSWORDS_DATA_CLASS: &str = "Passwords";
+
+/// A breach, as described by a dataset such as the "fxmonitor-breaches" remote-settings
+/// collection.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Breach {
+    /// The name of the breach, eg "LinkedIn".
+    pub name: String,
+    /// The breached domain. Logins for this domain and its subdomains are affected.
+    pub domain: String,
+    /// When the breach happened, in milliseconds since the unix epoch.
+    pub breach_date: i64,
+    /// When the breach was added to the dataset, in milliseconds since the unix epoch.
+    pub added_date: i64,
+    /// The kinds of data exposed, eg "Passwords" or "Email addresses".
+    pub data_classes: Vec<String>,
+}
+
+/// A login which was probably exposed by a breach.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BreachAlert {
+    pub id: String,
+    pub breach_name: String,
+}
+
+// Separates the key used for password digests from any other keys derived from the encryption
+// key.
+const PASSWORD_DIGEST_INFO: &str = "logins vulnerable password digest";
+
+/// A digest of a password, keyed with the encryption key so that the passwords can't be
+/// recovered from the database by a dictionary attack.
+fn password_digest(password: &str, encdec: &EncryptorDecryptor) -> Result<String> {
+    let digest = encdec.keyed_digest(password.as_bytes(), PASSWORD_DIGEST_INFO)?;
+    Ok(digest.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+impl LoginDb {
+    /// Find logins whose password was last changed before a breach of their domain. There's at
+    /// most one alert per login, and logins whose alert was dismissed after the breach was added
+    /// to the dataset are skipped.
+    pub fn get_breach_alerts(&self, breaches: &[Breach]) -> Result<Vec<BreachAlert>> {
+        let breaches = breaches
+            .iter()
+            .filter(|b| b.data_classes.iter().any(|c| c == PASSWORDS_DATA_CLASS))
+            .filter_map(|b| Host::parse(&b.domain).ok().map(|host| (b, host)))
+            .collect::<Vec<_>>();
+        let dismissals = self.get_breach_alert_dismissals()?;
+        let mut alerts = Vec::new();
+        for login in self.get_all()? {
+            let dismissed = dismissals.get(&login.record.id).copied();
+            let breach = breaches.iter().find(|(breach, host)| {
+                login.record.time_password_changed < breach.breach_date
+                    && dismissed.map_or(true, |dismissed| dismissed < breach.added_date)
+                    && util::origin_matches_base_host(&login.fields.origin, host)
+            });
+            if let Some((breach, _)) = breach {
+                alerts.push(BreachAlert {
+                    id: login.record.id,
+                    breach_name: breach.name.clone(),
+                });
+            }
+        }
+        Ok(alerts)
+    }
+
+    fn get_breach_alert_dismissals(&self) -> Result<HashMap<String, i64>> {
+        let mut stmt = self
+            .db
+            .prepare_cached("SELECT guid, timeDismissed FROM loginsBreachAlertDismissals")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Remember that the user dismissed the breach alert for a login.
+    pub fn record_breach_alert_dismissal(&self, id: &str) -> Result<()> {
+        if !self.exists(id)? {
+            return Err(Error::NoSuchRecord(id.to_owned()));
+        }
+        self.execute_cached(
+            "REPLACE INTO loginsBreachAlertDismissals (guid, timeDismissed)
+             VALUES (:guid, :now_ms)",
+            named_params! {
+                ":guid": id,
+                ":now_ms": util::system_time_ms_i64(SystemTime::now()),
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Add the current passwords of the given logins to the set of vulnerable passwords. This
+    /// should be called for logins with a breach alert before their password is changed.
+    pub fn record_vulnerable_passwords(
+        &self,
+        ids: &[String],
+        encdec: &EncryptorDecryptor,
+    ) -> Result<()> {
+        let tx = self.unchecked_transaction()?;
+        for id in ids {
+            let login = self
+                .get_by_id(id)?
+                .ok_or_else(|| Error::NoSuchRecord(id.to_owned()))?;
+            let password = login.decrypt_fields(encdec)?.password;
+            self.execute_cached(
+                "INSERT OR IGNORE INTO loginsVulnerablePasswords (hash, encPassword)
+                 VALUES (:hash, :enc_password)",
+                named_params! {
+                    ":hash": password_digest(&password, encdec)?,
+                    ":enc_password": encdec.encrypt(&password, "encrypt vulnerable password")?,
+                },
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Check if the password in some decrypted fields is in the set of vulnerable passwords.
+    pub fn is_vulnerable_password(
+        &self,
+        sec_fields: &SecureLoginFields,
+        encdec: &EncryptorDecryptor,
+    ) -> Result<bool> {
+        Ok(self.query_row(
+            "SELECT EXISTS(SELECT 1 FROM loginsVulnerablePasswords WHERE hash = :hash)",
+            named_params! { ":hash": password_digest(&sec_fields.password, encdec)? },
+            |row| row.get(0),
+        )?)
+    }
+
+    /// Re-key the set of vulnerable passwords with a new encryption key. This must be called in
+    /// the key rotation transaction. Passwords which can't be decrypted with `old` can't be
+    /// matched any more, so they're removed. Returns how many passwords were re-keyed.
+    pub(crate) fn rotate_vulnerable_passwords(
+        &self,
+        old: &EncryptorDecryptor,
+        new: &EncryptorDecryptor,
+    ) -> Result<u32> {
+        let enc_passwords: Vec<String> = self.query_rows_and_then(
+            "SELECT encPassword FROM loginsVulnerablePasswords",
+            [],
+            |row| row.get(0),
+        )?;
+        self.execute("DELETE FROM loginsVulnerablePasswords", [])?;
+        let mut rotated_count = 0;
+        for enc_password in enc_passwords {
+            match old.decrypt(&enc_password, "decrypt vulnerable password") {
+                Ok(password) => {
+                    self.execute_cached(
+                        "INSERT OR IGNORE INTO loginsVulnerablePasswords (hash, encPassword)
+                         VALUES (:hash, :enc_password)",
+                        named_params! {
+                            ":hash": password_digest(&password, new)?,
+                            ":enc_password": new.encrypt(&password, "encrypt vulnerable password")?,
+                        },
+                    )?;
+                    rotated_count += 1;
+                }
+                Err(e) => {
+                    log::warn!("Dropping a vulnerable password we can't decrypt: {}", e);
+                }
+            }
+        }
+        Ok(rotated_count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::test_utils::TEST_ENCRYPTOR;
+    use crate::login::{EncryptedLogin, LoginEntry, LoginFields};
+
+    fn add_login(db: &LoginDb, origin: &str, password: &str) -> EncryptedLogin {
+        db.add(
+            LoginEntry {
+                fields: LoginFields {
+                    origin: origin.into(),
+                    form_action_origin: Some(origin.into()),
+                    ..Default::default()
+                },
+                sec_fields: SecureLoginFields {
+                    username: "user".into(),
+                    password: password.into(),
+                    otp_secret: None,
+                },
+            },
+            &TEST_ENCRYPTOR,
+        )
+        .unwrap()
+    }
+
+    fn breach(domain: &str, breach_date: i64, added_date: i64) -> Breach {
+        Breach {
+            name: format!("{} breach", domain),
+            domain: domain.into(),
+            breach_date,
+            added_date,
+            data_classes: vec!["Email addresses".into(), "Passwords".into()],
+        }
+    }
+
+    fn alert_ids(db: &LoginDb, breaches: &[Breach]) -> Vec<String> {
+        db.get_breach_alerts(breaches)
+            .unwrap()
+            .into_iter()
+            .map(|alert| alert.id)
+            .collect()
+    }
+
+    #[test]
+    fn test_breach_alerts() {
+        let db = LoginDb::open_in_memory().unwrap();
+        let login = add_login(&db, "https://www.example.com", "password");
+        let changed = login.record.time_password_changed;
+        add_login(&db, "https://example.org", "password");
+
+        // Breach happened after the password was last changed.
+        let alerts = db
+            .get_breach_alerts(&[breach("example.com", changed + 1, changed + 2)])
+            .unwrap();
+        assert_eq!(
+            alerts,
+            vec![BreachAlert {
+                id: login.record.id.clone(),
+                breach_name: "example.com breach".into(),
+            }]
+        );
+
+        // Breach happened before the password was changed.
+        assert!(alert_ids(&db, &[breach("example.com", changed - 1, changed + 2)]).is_empty());
+
+        // Breach of some other domain.
+        assert!(alert_ids(&db, &[breach("www.example.com.au", changed + 1, 0)]).is_empty());
+
+        // Breach which didn't expose passwords.
+        let mut no_passwords = breach("example.com", changed + 1, changed + 2);
+        no_passwords.data_classes = vec!["Email addresses".into()];
+        assert!(alert_ids(&db, &[no_passwords]).is_empty());
+    }
+
+    #[test]
+    fn test_breach_alert_dismissal() {
+        let db = LoginDb::open_in_memory().unwrap();
+        let login = add_login(&db, "https://example.com", "password");
+        let changed = login.record.time_password_changed;
+        db.record_breach_alert_dismissal(&login.record.id).unwrap();
+
+        // The breach was added before the dismissal, so it shouldn't alert.
+        assert!(alert_ids(&db, &[breach("example.com", changed + 1, changed - 1)]).is_empty());
+        // But a breach added later should.
+        let later = util::system_time_ms_i64(SystemTime::now()) + 1000;
+        assert_eq!(
+            alert_ids(&db, &[breach("example.com", changed + 1, later)]),
+            vec![login.record.id.clone()]
+        );
+
+        assert!(matches!(
+            db.record_breach_alert_dismissal("not-a-guid"),
+            Err(Error::NoSuchRecord(_))
+        ));
+
+        // Deleting the login forgets the dismissal.
+        db.delete(&login.record.id).unwrap();
+        assert_eq!(
+            db.query_one::<i64>("SELECT COUNT(*) FROM loginsBreachAlertDismissals")
+                .unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_vulnerable_passwords() {
+        let db = LoginDb::open_in_memory().unwrap();
+        let breached = add_login(&db, "https://example.com", "hunter2");
+        let other = add_login(&db, "https://example.org", "hunter2");
+        let other_fields = other.decrypt_fields(&TEST_ENCRYPTOR).unwrap();
+        assert!(!db
+            .is_vulnerable_password(&other_fields, &TEST_ENCRYPTOR)
+            .unwrap());
+
+        db.record_vulnerable_passwords(&[breached.record.id.clone()], &TEST_ENCRYPTOR)
+            .unwrap();
+        assert!(db
+            .is_vulnerable_password(&other_fields, &TEST_ENCRYPTOR)
+            .unwrap());
+        assert!(!db
+            .is_vulnerable_password(
+                &SecureLoginFields {
+                    username: "user".into(),
+                    password: "something else".into(),
+                    otp_secret: None,
+                },
+                &TEST_ENCRYPTOR,
+            )
+            .unwrap());
+
+        // The stored digest is keyed, so it isn't a plain hash of the password, and doesn't
+        // match with another key.
+        let hash: String = db
+            .query_one("SELECT hash FROM loginsVulnerablePasswords")
+            .unwrap();
+        let plain_hash: String =
+            rc_crypto::digest::digest(&rc_crypto::digest::SHA256, "hunter2".as_bytes())
+                .unwrap()
+                .as_ref()
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect();
+        assert_eq!(hash.len(), 64);
+        assert_ne!(hash, plain_hash);
+        let other_key = EncryptorDecryptor::new_with_random_key().unwrap();
+        assert!(!db
+            .is_vulnerable_password(&other_fields, &other_key)
+            .unwrap());
+
+        // Re-keying the set makes it match with the new key instead.
+        assert_eq!(
+            db.rotate_vulnerable_passwords(&TEST_ENCRYPTOR, &other_key)
+                .unwrap(),
+            1
+        );
+        assert!(db
+            .is_vulnerable_password(&other_fields, &other_key)
+            .unwrap());
+        assert!(!db
+            .is_vulnerable_password(&other_fields, &TEST_ENCRYPTOR)
+            .unwrap());
+    }
+}
@@ -0,0 +1,453 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! CSV import and export of logins.
+//!
+//! Users switching from other password managers typically bring a CSV export with them. There's
+//! no real standard for these files, but in practice they all have a header row naming the
+//! columns, and the column names used by the common password managers are well known. We follow
+//! desktop's `LoginCSVImport.jsm` and look for a set of aliases for each field we care about,
+//! ignoring any columns we don't understand.
+//!
+//! Each row is then run through the same fixup and dupe-checking logic used by `add_or_update()`,
+//! and the consumer gets a report of what happened to every row.
+//!
+//! Exporting writes the same columns as desktop's "Export Logins" feature, which means an export
+//! from here can be imported into desktop (and vice-versa).
+
+use crate::db::LoginDb;
+use crate::encryption::EncryptorDecryptor;
+use crate::error::*;
+use crate::login::{LoginEntry, LoginFields, SecureLoginFields, ValidateAndFixup};
+
+/// What happened to a single row of a CSV import.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CsvImportStatus {
+    /// A new login was added.
+    Added,
+    /// An existing login was updated with the password (or username) from the row.
+    Updated,
+    /// An identical login already exists, so nothing was done.
+    Skipped,
+    /// The row couldn't be turned into a valid login.
+    Invalid,
+}
+
+/// The result of importing a single row of a CSV file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsvImportRow {
+    /// The 1-based index of the record in the file, not counting the header.
+    pub row: u32,
+    /// The id of the login which was added, updated or matched. None for invalid rows.
+    pub id: Option<String>,
+    pub status: CsvImportStatus,
+    /// Why the row was invalid. Never contains the username or password.
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Column {
+    Origin,
+    Username,
+    Password,
+    HttpRealm,
+    FormActionOrigin,
+}
+
+// Lower-case column names used by Firefox, Chrome, 1Password, Bitwarden and friends.
+const COLUMN_ALIASES: &[(Column, &[&str])] = &[
+    (
+        Column::Origin,
+        &[
+            "url",
+            "origin",
+            "hostname",
+            "login_uri",
+            "login url",
+            "website",
+            "web site",
+        ],
+    ),
+    (
+        Column::Username,
+        &["username", "login_username", "login name", "login", "user"],
+    ),
+    (Column::Password, &["password", "login_password"]),
+    (Column::HttpRealm, &["httprealm"]),
+    (Column::FormActionOrigin, &["formactionorigin"]),
+];
+
+// The columns we write, which are the same as desktop's export.
+const EXPORT_HEADER: &[&str] = &[
+    "url",
+    "username",
+    "password",
+    "httpRealm",
+    "formActionOrigin",
+    "guid",
+    "timeCreated",
+    "timeLastUsed",
+    "timePasswordChanged",
+];
+
+/// Maps the columns we understand to their index in each row.
+#[derive(Debug)]
+struct CsvColumns {
+    origin: usize,
+    password: usize,
+    username: Option<usize>,
+    http_realm: Option<usize>,
+    form_action_origin: Option<usize>,
+}
+
+impl CsvColumns {
+    fn from_header(header: &[String]) -> Result<Self> {
+        let find = |column: Column| {
+            let aliases = COLUMN_ALIASES
+                .iter()
+                .find(|(c, _)| *c == column)
+                .map(|(_, aliases)| *aliases)
+                .unwrap_or_default();
+            header
+                .iter()
+                .position(|name| aliases.contains(&name.trim().to_lowercase().as_str()))
+        };
+        Ok(Self {
+            origin: find(Column::Origin)
+                .ok_or_else(|| Error::InvalidCsv("no url column".into()))?,
+            password: find(Column::Password)
+                .ok_or_else(|| Error::InvalidCsv("no password column".into()))?,
+            username: find(Column::Username),
+            http_realm: find(Column::HttpRealm),
+            form_action_origin: find(Column::FormActionOrigin),
+        })
+    }
+
+    fn entry_from_row(&self, row: &[String]) -> LoginEntry {
+        let get = |index: Option<usize>| {
+            index
+                .and_then(|i| row.get(i))
+                .filter(|value| !value.is_empty())
+                .cloned()
+        };
+        let http_realm = get(self.http_realm);
+        // Like desktop, rows without a realm are assumed to be form logins which can be used with
+        // any form on the site.
+        let form_action_origin = match get(self.form_action_origin) {
+            Some(form_action_origin) => Some(form_action_origin),
+            None if http_realm.is_none() => Some(String::new()),
+            None => None,
+        };
+        LoginEntry {
+            fields: LoginFields {
+                origin: get(Some(self.origin)).unwrap_or_default(),
+                form_action_origin,
+                http_realm,
+                ..Default::default()
+            },
+            sec_fields: SecureLoginFields {
+                username: get(self.username).unwrap_or_default(),
+                password: get(Some(self.password)).unwrap_or_default(),
+                otp_secret: None,
+            },
+        }
+    }
+}
+
+/// Split CSV text into rows of fields, following RFC 4180. Blank lines are ignored.
+fn parse_rows(input: &str) -> Result<Vec<Vec<String>>> {
+    fn finish_row(rows: &mut Vec<Vec<String>>, row: Vec<String>) {
+        if row.iter().any(|field| !field.is_empty()) {
+            rows.push(row);
+        }
+    }
+
+    let input = input.strip_prefix('\u{feff}').unwrap_or(input);
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => in_quotes = false,
+                _ => field.push(c),
+            }
+            continue;
+        }
+        match c {
+            '"' => in_quotes = true,
+            ',' => row.push(std::mem::take(&mut field)),
+            // The '\n' which follows will end the row.
+            '\r' if chars.peek() == Some(&'\n') => (),
+            '\r' | '\n' => {
+                row.push(std::mem::take(&mut field));
+                finish_row(&mut rows, std::mem::take(&mut row));
+            }
+            _ => field.push(c),
+        }
+    }
+    if in_quotes {
+        return Err(Error::InvalidCsv("unterminated quoted field".into()));
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        finish_row(&mut rows, row);
+    }
+    Ok(rows)
+}
+
+fn write_row<S: AsRef<str>>(out: &mut String, fields: &[S]) {
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        out.push('"');
+        out.push_str(&field.as_ref().replace('"', "\"\""));
+        out.push('"');
+    }
+    out.push_str("\r\n");
+}
+
+impl LoginDb {
+    /// Import logins from CSV text, returning what happened to each row.
+    ///
+    /// Invalid rows don't stop the import, but other errors (eg, SQL errors) do. Rows already
+    /// imported at that point remain in the database.
+    pub fn import_csv(&self, data: &str, encdec: &EncryptorDecryptor) -> Result<Vec<CsvImportRow>> {
+        let mut rows = parse_rows(data)?.into_iter();
+        let header = rows
+            .next()
+            .ok_or_else(|| Error::InvalidCsv("no header row".into()))?;
+        let columns = CsvColumns::from_header(&header)?;
+        let mut report = Vec::new();
+        for (i, row) in rows.enumerate() {
+            let row_num = i as u32 + 1;
+            let entry = columns.entry_from_row(&row);
+            report.push(match self.import_csv_entry(entry, encdec) {
+                Ok((status, id)) => CsvImportRow {
+                    row: row_num,
+                    id: Some(id),
+                    status,
+                    reason: None,
+                },
+                Err(Error::InvalidLogin(why)) => CsvImportRow {
+                    row: row_num,
+                    id: None,
+                    status: CsvImportStatus::Invalid,
+                    reason: Some(why.to_string()),
+                },
+                Err(e) => return Err(e),
+            });
+        }
+        Ok(report)
+    }
+
+    fn import_csv_entry(
+        &self,
+        entry: LoginEntry,
+        encdec: &EncryptorDecryptor,
+    ) -> Result<(CsvImportStatus, String)> {
+        let entry = entry.fixup()?;
+        Ok(match self.find_login_to_update(entry.clone(), encdec)? {
+            Some(existing)
+                if existing.sec_fields.username == entry.sec_fields.username
+                    && existing.sec_fields.password == entry.sec_fields.password =>
+            {
+                (CsvImportStatus::Skipped, existing.record.id)
+            }
+            Some(existing) => {
+                // CSV files don't carry the form field names or OTP secrets, so keep any we
+                // already know.
+                let entry = LoginEntry {
+                    fields: LoginFields {
+                        username_field: existing.fields.username_field,
+                        password_field: existing.fields.password_field,
+                        ..entry.fields
+                    },
+                    sec_fields: SecureLoginFields {
+                        otp_secret: existing.sec_fields.otp_secret,
+                        ..entry.sec_fields
+                    },
+                };
+                let updated = self.update(&existing.record.id, entry, encdec)?;
+                (CsvImportStatus::Updated, updated.record.id)
+            }
+            None => (CsvImportStatus::Added, self.add(entry, encdec)?.record.id),
+        })
+    }
+
+    /// Export all logins as cleartext CSV, in the same format as desktop.
+    pub fn export_csv(&self, encdec: &EncryptorDecryptor) -> Result<String> {
+        let mut out = String::new();
+        write_row(&mut out, EXPORT_HEADER);
+        for login in self.get_all()? {
+            let login = login.decrypt(encdec)?;
+            write_row(
+                &mut out,
+                &[
+                    login.fields.origin,
+                    login.sec_fields.username,
+                    login.sec_fields.password,
+                    login.fields.http_realm.unwrap_or_default(),
+                    login.fields.form_action_origin.unwrap_or_default(),
+                    login.record.id,
+                    login.record.time_created.to_string(),
+                    login.record.time_last_used.to_string(),
+                    login.record.time_password_changed.to_string(),
+                ],
+            );
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::test_utils::TEST_ENCRYPTOR;
+
+    fn statuses(report: &[CsvImportRow]) -> Vec<CsvImportStatus> {
+        report.iter().map(|r| r.status).collect()
+    }
+
+    #[test]
+    fn test_parse_rows() {
+        assert_eq!(
+            parse_rows("\u{feff}a,b,c\r\n\"x,1\",\"say \"\"hi\"\"\",\"multi\nline\"\n\n1,,3")
+                .unwrap(),
+            vec![
+                vec!["a", "b", "c"],
+                vec!["x,1", "say \"hi\"", "multi\nline"],
+                vec!["1", "", "3"],
+            ]
+        );
+        assert!(matches!(
+            parse_rows("a,\"b").unwrap_err(),
+            Error::InvalidCsv(_)
+        ));
+    }
+
+    #[test]
+    fn test_column_aliases() {
+        let header = |s: &str| parse_rows(s).unwrap().remove(0);
+        // Chrome
+        let columns = CsvColumns::from_header(&header("name,url,username,password,note")).unwrap();
+        assert_eq!((columns.origin, columns.password), (1, 3));
+        assert_eq!(columns.username, Some(2));
+        // Bitwarden
+        let columns = CsvColumns::from_header(&header(
+            "folder,favorite,type,name,notes,fields,reprompt,login_uri,login_username,login_password,login_totp",
+        ))
+        .unwrap();
+        assert_eq!((columns.origin, columns.password), (7, 9));
+        assert_eq!(columns.username, Some(8));
+        // 1Password
+        let columns =
+            CsvColumns::from_header(&header("Title,Url,Username,Password,OTPAuth,Notes")).unwrap();
+        assert_eq!((columns.origin, columns.password), (1, 3));
+        assert_eq!(columns.http_realm, None);
+        // Missing a password column.
+        assert!(CsvColumns::from_header(&header("url,username")).is_err());
+    }
+
+    #[test]
+    fn test_import() {
+        let db = LoginDb::open_in_memory().unwrap();
+        let report = db
+            .import_csv(
+                "url,username,password,httpRealm\n\
+                 https://example.com/login,alice,pass1,\n\
+                 https://example.com,alice,pass1,\n\
+                 https://example.com,alice,pass2,\n\
+                 https://example.com,bob,pass3,\n\
+                 https://example.com,bob,pass4,the realm\n\
+                 https://example.com,carol,,\n\
+                 not a url,dave,pass5,\n",
+                &TEST_ENCRYPTOR,
+            )
+            .unwrap();
+        assert_eq!(
+            statuses(&report),
+            vec![
+                CsvImportStatus::Added,
+                CsvImportStatus::Skipped,
+                CsvImportStatus::Updated,
+                CsvImportStatus::Added,
+                CsvImportStatus::Added,
+                CsvImportStatus::Invalid,
+                CsvImportStatus::Invalid,
+            ]
+        );
+        assert_eq!(report[0].id, report[1].id);
+        assert_eq!(report[0].id, report[2].id);
+        assert_eq!(report[5].row, 6);
+        assert_eq!(report[5].reason.as_deref(), Some("Password is empty"));
+        assert_eq!(db.get_all().unwrap().len(), 3);
+
+        let alice = db
+            .get_by_id(report[0].id.as_ref().unwrap())
+            .unwrap()
+            .unwrap()
+            .decrypt(&TEST_ENCRYPTOR)
+            .unwrap();
+        assert_eq!(alice.fields.origin, "https://example.com");
+        assert_eq!(alice.fields.form_action_origin, Some("".into()));
+        assert_eq!(alice.sec_fields.password, "pass2");
+
+        let bob_realm = db
+            .get_by_id(report[4].id.as_ref().unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(bob_realm.fields.http_realm, Some("the realm".into()));
+        assert_eq!(bob_realm.fields.form_action_origin, None);
+    }
+
+    #[test]
+    fn test_import_fills_blank_username() {
+        let db = LoginDb::open_in_memory().unwrap();
+        let report = db
+            .import_csv(
+                "url,username,password\nhttps://example.com,,pass\nhttps://example.com,alice,pass\n",
+                &TEST_ENCRYPTOR,
+            )
+            .unwrap();
+        assert_eq!(
+            statuses(&report),
+            vec![CsvImportStatus::Added, CsvImportStatus::Updated]
+        );
+        assert_eq!(db.get_all().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_export_roundtrip() {
+        let db = LoginDb::open_in_memory().unwrap();
+        db.import_csv(
+            "url,username,password\nhttps://example.com,\"al,ice\",\"p\"\"ss\"\n",
+            &TEST_ENCRYPTOR,
+        )
+        .unwrap();
+        let exported = db.export_csv(&TEST_ENCRYPTOR).unwrap();
+        let rows = parse_rows(&exported).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0], EXPORT_HEADER);
+        assert_eq!(
+            rows[1][..5],
+            ["https://example.com", "al,ice", "p\"ss", "", ""]
+        );
+
+        // Importing our own export into a new database adds the same login.
+        let db2 = LoginDb::open_in_memory().unwrap();
+        let report = db2.import_csv(&exported, &TEST_ENCRYPTOR).unwrap();
+        assert_eq!(statuses(&report), vec![CsvImportStatus::Added]);
+        // And importing it again into the original database is a no-op.
+        let report = db.import_csv(&exported, &TEST_ENCRYPTOR).unwrap();
+        assert_eq!(statuses(&report), vec![CsvImportStatus::Skipped]);
+    }
+}
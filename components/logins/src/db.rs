@@ -25,6 +25,7 @@
 use crate::encryption::EncryptorDecryptor;
 use crate::error::*;
 use crate::login::*;
+use crate::password_history;
 use crate::schema;
 use crate::sync::SyncStatus;
 use crate::util;
@@ -41,7 +42,7 @@ use std::path::Path;
 use std::sync::Arc;
 use std::time::SystemTime;
 use sync_guid::Guid;
-use url::{Host, Url};
+use url::Host;
 
 pub struct LoginDb {
     pub db: Connection,
@@ -158,33 +159,9 @@ impl LoginDb {
         let rows = stmt
             .query_and_then([], EncryptedLogin::from_row)?
             .filter(|r| {
-                let login = r
-                    .as_ref()
-                    .ok()
-                    .and_then(|login| Url::parse(&login.fields.origin).ok());
-                let this_host = login.as_ref().and_then(|url| url.host());
-                match (&base_host, this_host) {
-                    (Host::Domain(base), Some(Host::Domain(look))) => {
-                        // a fairly long-winded way of saying
-                        // `login.fields.origin == base_domain ||
-                        //  login.fields.origin.ends_with('.' + base_domain);`
-                        let mut rev_input = base.chars().rev();
-                        let mut rev_host = look.chars().rev();
-                        loop {
-                            match (rev_input.next(), rev_host.next()) {
-                                (Some(ref a), Some(ref b)) if a == b => continue,
-                                (None, None) => return true, // exactly equal
-                                (None, Some(ref h)) => return *h == '.',
-                                _ => return false,
-                            }
-                        }
-                    }
-                    // ip addresses must match exactly.
-                    (Host::Ipv4(base), Some(Host::Ipv4(look))) => *base == look,
-                    (Host::Ipv6(base), Some(Host::Ipv6(look))) => *base == look,
-                    // all "mismatches" in domain types are false.
-                    _ => false,
-                }
+                r.as_ref().map_or(false, |login| {
+                    util::origin_matches_base_host(&login.fields.origin, &base_host)
+                })
             });
         rows.collect::<Result<_>>()
     }
@@ -382,10 +359,22 @@ impl LoginDb {
         sguid: &str,
         entry: LoginEntry,
         encdec: &EncryptorDecryptor,
+    ) -> Result<EncryptedLogin> {
+        let tx = self.unchecked_transaction()?;
+        let result = self.update_in_transaction(sguid, entry, encdec)?;
+        tx.commit()?;
+        Ok(result)
+    }
+
+    // The guts of `update()`, for callers which need to do more in the same transaction.
+    pub(crate) fn update_in_transaction(
+        &self,
+        sguid: &str,
+        entry: LoginEntry,
+        encdec: &EncryptorDecryptor,
     ) -> Result<EncryptedLogin> {
         let guid = Guid::new(sguid);
         let now_ms = util::system_time_ms_i64(SystemTime::now());
-        let tx = self.unchecked_transaction()?;
 
         let entry = entry.fixup()?;
 
@@ -418,6 +407,12 @@ impl LoginDb {
             if existing.decrypt_fields(encdec)?.password == entry.sec_fields.password {
                 existing.record.time_password_changed
             } else {
+                password_history::record_password_history(
+                    self,
+                    &existing.record.id,
+                    &existing.sec_fields,
+                    now_ms,
+                )?;
                 now_ms
             };
 
@@ -435,7 +430,6 @@ impl LoginDb {
         };
 
         self.update_existing_login(&result)?;
-        tx.commit()?;
         Ok(result)
     }
 
@@ -615,6 +609,16 @@ impl LoginDb {
             named_params! { ":guid": id },
         )?;
 
+        // Forget any local-only state for the login.
+        self.execute(
+            "DELETE FROM loginsBreachAlertDismissals WHERE guid = :guid",
+            named_params! { ":guid": id },
+        )?;
+        self.execute(
+            "DELETE FROM loginsPasswordHistory WHERE guid = :guid",
+            named_params! { ":guid": id },
+        )?;
+
         // If we don't have a local record for this ID, but do have it in the mirror
         // insert a tombstone.
         self.execute(&format!("
@@ -690,6 +694,15 @@ impl LoginDb {
         self.execute("UPDATE loginsM SET is_overridden = 1", [])?;
         scope.err_if_interrupted()?;
 
+        self.execute("DELETE FROM loginsBreachAlertDismissals", [])?;
+        scope.err_if_interrupted()?;
+
+        self.execute("DELETE FROM loginsPasswordHistory", [])?;
+        scope.err_if_interrupted()?;
+
+        self.execute("DELETE FROM loginsQuarantine", [])?;
+        scope.err_if_interrupted()?;
+
         self.execute(
             &format!("
                 INSERT OR IGNORE INTO loginsL
@@ -710,6 +723,11 @@ impl LoginDb {
             "DELETE FROM loginsL",
             "DELETE FROM loginsM",
             "DELETE FROM loginsSyncMeta",
+            "DELETE FROM loginsBreachAlertDismissals",
+            "DELETE FROM loginsVulnerablePasswords",
+            "DELETE FROM loginsPasswordHistory",
+            "DELETE FROM loginsQuarantine",
+            "DELETE FROM loginsPasskeys",
         ])?;
         tx.commit()?;
         Ok(())
@@ -923,6 +941,7 @@ mod tests {
             sec_fields: SecureLoginFields {
                 username: "test".into(),
                 password: "sekret".into(),
+                otp_secret: None,
             },
         };
 
@@ -969,6 +988,7 @@ mod tests {
                     sec_fields: SecureLoginFields {
                         username: "😍".into(),
                         password: "😍".into(),
+                        otp_secret: None,
                     },
                 },
                 &TEST_ENCRYPTOR,
@@ -1006,6 +1026,7 @@ mod tests {
                     sec_fields: SecureLoginFields {
                         username: "😍".into(),
                         password: "😍".into(),
+                        otp_secret: None,
                     },
                 },
                 &TEST_ENCRYPTOR,
@@ -1140,6 +1161,7 @@ mod tests {
             sec_fields: SecureLoginFields {
                 username: "test_user".into(),
                 password: "test_password".into(),
+                otp_secret: None,
             },
         };
         let login = db.add(to_add, &TEST_ENCRYPTOR).unwrap();
@@ -1164,6 +1186,7 @@ mod tests {
                     sec_fields: SecureLoginFields {
                         username: "user1".into(),
                         password: "password1".into(),
+                        otp_secret: None,
                     },
                 },
                 &TEST_ENCRYPTOR,
@@ -1180,6 +1203,7 @@ mod tests {
                 sec_fields: SecureLoginFields {
                     username: "user2".into(),
                     password: "password2".into(),
+                    otp_secret: None,
                 },
             },
             &TEST_ENCRYPTOR,
@@ -1212,6 +1236,7 @@ mod tests {
                     sec_fields: SecureLoginFields {
                         username: "user1".into(),
                         password: "password1".into(),
+                        otp_secret: None,
                     },
                 },
                 &TEST_ENCRYPTOR,
@@ -1237,6 +1262,7 @@ mod tests {
                     sec_fields: SecureLoginFields {
                         username: "test_user".into(),
                         password: "test_password".into(),
+                        otp_secret: None,
                     },
                 },
                 &TEST_ENCRYPTOR,
@@ -1273,6 +1299,7 @@ mod tests {
                     sec_fields: SecureLoginFields {
                         username: "test_user_1".into(),
                         password: "test_password_1".into(),
+                        otp_secret: None,
                     },
                 },
                 &TEST_ENCRYPTOR,
@@ -1290,6 +1317,7 @@ mod tests {
                     sec_fields: SecureLoginFields {
                         username: "test_user_1".into(),
                         password: "test_password_2".into(),
+                        otp_secret: None,
                     },
                 },
                 &TEST_ENCRYPTOR,
@@ -1332,6 +1360,7 @@ mod tests {
                 sec_fields: SecureLoginFields {
                     username: username.into(),
                     password: password.into(),
+                    otp_secret: None,
                 },
             }
         }
@@ -1370,6 +1399,7 @@ mod tests {
                     sec_fields: SecureLoginFields {
                         username: "user".into(),
                         password: "pass".into(),
+                        otp_secret: None,
                     },
                 },
                 &TEST_ENCRYPTOR,
@@ -1386,6 +1416,7 @@ mod tests {
                     sec_fields: SecureLoginFields {
                         username: "user".into(),
                         password: "pass".into(),
+                        otp_secret: None,
                     },
                 },
                 &TEST_ENCRYPTOR,
@@ -79,6 +79,9 @@ pub enum Error {
     #[error("CryptoError({0})")]
     CryptoError(#[from] EncryptorDecryptorError),
 
+    #[error("Crypto primitive error: {0}")]
+    RcCryptoError(#[from] rc_crypto::Error),
+
     #[error("{0}")]
     Interrupted(#[from] interrupt_support::Interrupted),
 
@@ -87,6 +90,21 @@ pub enum Error {
 
     #[error("Migration Error: {0}")]
     MigrationError(String),
+
+    #[error("Invalid CSV data: {0}")]
+    InvalidCsv(String),
+
+    #[error("Can't generate a password: {0}")]
+    PasswordGenerationFailed(String),
+
+    #[error("Invalid OTP secret: {0}")]
+    InvalidOtpSecret(String),
+
+    #[error("Invalid passkey: {0}")]
+    InvalidPasskey(String),
+
+    #[error("The canary wasn't created with this key")]
+    IncorrectCanary,
 }
 
 /// Error::InvalidLogin subtypes
@@ -121,6 +139,11 @@ impl GetErrorHandling for Error {
             Self::InvalidLogin(why) => ErrorHandling::convert(LoginsApiError::InvalidRecord {
                 reason: why.to_string(),
             }),
+            Self::InvalidCsv(why) | Self::InvalidOtpSecret(why) | Self::InvalidPasskey(why) => {
+                ErrorHandling::convert(LoginsApiError::InvalidRecord {
+                    reason: why.to_string(),
+                })
+            }
             Self::MalformedIncomingRecord => {
                 ErrorHandling::convert(LoginsApiError::InvalidRecord {
                     reason: "invalid incoming record".to_string(),
@@ -141,6 +164,9 @@ impl GetErrorHandling for Error {
             }
             Self::CryptoError { .. } => ErrorHandling::convert(LoginsApiError::IncorrectKey)
                 .report_error("logins-crypto-error"),
+            Self::IncorrectCanary => {
+                ErrorHandling::convert(LoginsApiError::IncorrectKey).log_warning()
+            }
             Self::Interrupted(_) => ErrorHandling::convert(LoginsApiError::Interrupted {
                 reason: self.to_string(),
             }),
@@ -167,7 +193,7 @@ impl GetErrorHandling for Error {
             //
             // For now, just log a warning.  Eventually, it would be nice to count these with
             // telemetry.
-            Self::InvalidDatabaseFile(_) => {
+            Self::InvalidDatabaseFile(_) | Self::PasswordGenerationFailed(_) => {
                 ErrorHandling::convert(LoginsApiError::UnexpectedLoginsApiError {
                     reason: self.to_string(),
                 })
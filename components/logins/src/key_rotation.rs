@@ -0,0 +1,523 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Re-encrypting the database with a new key.
+//!
+//! The app proves that the old key is the one its data was encrypted with by passing a canary it
+//! made with `create_canary()`, which we check before changing anything. Once everything is
+//! re-encrypted, we return a canary for the new key made from the same text, so the app can store
+//! it together with the new key.
+//!
+//! Every encrypted value we store - the `secFields` of both `loginsL` and `loginsM`, the mirror's
+//! `enc_unknown_fields`, and the `secFields` of `loginsPasswordHistory` and `loginsPasskeys` - is
+//! decrypted with the old key and encrypted again with the new one, all in a single transaction.
+//! The set of vulnerable passwords, whose digests are keyed with the encryption key, is re-keyed
+//! in the same transaction.
+//!
+//! Values which can't be decrypted with the old key don't stop the rotation. They're reported,
+//! and can optionally be moved to the `loginsQuarantine` table. Quarantining a login row removes
+//! it locally without writing a tombstone, so the server copy is left alone. When a mirror record
+//! is quarantined, the next sync downloads everything again, which restores the server's copy;
+//! until then, a login which only had a mirror record is missing locally.
+
+use crate::db::LoginDb;
+use crate::encryption::EncryptorDecryptor;
+use crate::error::*;
+use crate::schema;
+use crate::sync::SyncStatus;
+use crate::util;
+use rusqlite::named_params;
+use sql_support::ConnExt;
+use std::collections::{BTreeSet, HashSet};
+use std::time::SystemTime;
+
+/// The result of re-encrypting the database with a new key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyRotationResult {
+    /// How many encrypted values were re-encrypted with the new key.
+    pub rotated_count: u32,
+    /// The ids of logins and passkeys with data which couldn't be decrypted with the old key.
+    /// Unless it was quarantined, that data is still encrypted with the old key.
+    pub failed_ids: Vec<String>,
+    /// A canary for the new key, made from the same text as the old key's canary.
+    pub new_canary: String,
+}
+
+/// A column holding encrypted data.
+struct EncryptedColumn {
+    /// The name we record in `loginsQuarantine.source`.
+    source: &'static str,
+    table: &'static str,
+    column: &'static str,
+}
+
+const ENCRYPTED_COLUMNS: [EncryptedColumn; 5] = [
+    EncryptedColumn {
+        source: "loginsL",
+        table: "loginsL",
+        column: "secFields",
+    },
+    EncryptedColumn {
+        source: "loginsM",
+        table: "loginsM",
+        column: "secFields",
+    },
+    EncryptedColumn {
+        source: "loginsM.enc_unknown_fields",
+        table: "loginsM",
+        column: "enc_unknown_fields",
+    },
+    EncryptedColumn {
+        source: "loginsPasswordHistory",
+        table: "loginsPasswordHistory",
+        column: "secFields",
+    },
+    EncryptedColumn {
+        source: "loginsPasskeys",
+        table: "loginsPasskeys",
+        column: "secFields",
+    },
+];
+
+struct EncryptedValue {
+    id: i64,
+    guid: String,
+    ciphertext: String,
+}
+
+impl LoginDb {
+    fn get_encrypted_values(&self, column: &EncryptedColumn) -> Result<Vec<EncryptedValue>> {
+        // Tombstones have an empty `secFields`.
+        self.query_rows_and_then(
+            &format!(
+                "SELECT id, guid, {column} FROM {table}
+                 WHERE {column} IS NOT NULL AND {column} != ''
+                 ORDER BY id",
+                column = column.column,
+                table = column.table,
+            ),
+            [],
+            |row| -> Result<EncryptedValue> {
+                Ok(EncryptedValue {
+                    id: row.get(0)?,
+                    guid: row.get(1)?,
+                    ciphertext: row.get(2)?,
+                })
+            },
+        )
+    }
+
+    fn quarantine(
+        &self,
+        column: &EncryptedColumn,
+        value: &EncryptedValue,
+        now_ms: i64,
+    ) -> Result<()> {
+        self.execute_cached(
+            "INSERT INTO loginsQuarantine (guid, source, ciphertext, timeQuarantined)
+             VALUES (:guid, :source, :ciphertext, :now_ms)",
+            named_params! {
+                ":guid": value.guid,
+                ":source": column.source,
+                ":ciphertext": value.ciphertext,
+                ":now_ms": now_ms,
+            },
+        )?;
+        match column.source {
+            "loginsL" => {
+                self.execute_cached(
+                    "DELETE FROM loginsL WHERE id = :id",
+                    named_params! { ":id": value.id },
+                )?;
+                // Fall back to the mirror, if there is one.
+                self.execute_cached(
+                    "UPDATE loginsM SET is_overridden = 0 WHERE guid = :guid",
+                    named_params! { ":guid": value.guid },
+                )?;
+            }
+            "loginsM" => {
+                self.execute_cached(
+                    "DELETE FROM loginsM WHERE id = :id",
+                    named_params! { ":id": value.id },
+                )?;
+                // The local record, if there is one, is now the only copy we have, so upload it.
+                self.execute_cached(
+                    &format!(
+                        "UPDATE loginsL SET sync_status = {changed}
+                         WHERE guid = :guid AND sync_status = {synced}",
+                        changed = SyncStatus::Changed as u8,
+                        synced = SyncStatus::Synced as u8,
+                    ),
+                    named_params! { ":guid": value.guid },
+                )?;
+                // Otherwise, download the server's copy again on the next sync.
+                self.put_meta(schema::LAST_SYNC_META_KEY, &0i64)?;
+            }
+            "loginsM.enc_unknown_fields" => {
+                self.execute_cached(
+                    "UPDATE loginsM SET enc_unknown_fields = NULL WHERE id = :id",
+                    named_params! { ":id": value.id },
+                )?;
+            }
+            "loginsPasswordHistory" => {
+                self.execute_cached(
+                    "DELETE FROM loginsPasswordHistory WHERE id = :id",
+                    named_params! { ":id": value.id },
+                )?;
+            }
+            _ => {
+                self.execute_cached(
+                    "DELETE FROM loginsPasskeys WHERE id = :id",
+                    named_params! { ":id": value.id },
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Re-encrypt everything in the database with `new` instead of `old`.
+    ///
+    /// `canary` must have been created with `old` from `canary_text`, otherwise we fail without
+    /// changing anything. Values which can't be decrypted with `old` are reported in the result
+    /// rather than failing the rotation, and moved to `loginsQuarantine` if `quarantine` is true.
+    pub fn rotate_key(
+        &self,
+        old: &EncryptorDecryptor,
+        new: &EncryptorDecryptor,
+        canary: &str,
+        canary_text: &str,
+        quarantine: bool,
+    ) -> Result<KeyRotationResult> {
+        if !old.check_canary(canary, canary_text)? {
+            return Err(Error::IncorrectCanary);
+        }
+        let tx = self.unchecked_transaction()?;
+        let now_ms = util::system_time_ms_i64(SystemTime::now());
+        let mut rotated_count = 0;
+        let mut failed = HashSet::new();
+        let mut failed_ids = BTreeSet::new();
+        for column in &ENCRYPTED_COLUMNS {
+            for value in self.get_encrypted_values(column)? {
+                match old.decrypt(&value.ciphertext, "decrypt for key rotation") {
+                    Ok(cleartext) => {
+                        let ciphertext = new.encrypt(&cleartext, "encrypt for key rotation")?;
+                        self.execute(
+                            &format!(
+                                "UPDATE {table} SET {column} = :ciphertext WHERE id = :id",
+                                table = column.table,
+                                column = column.column,
+                            ),
+                            named_params! { ":ciphertext": ciphertext, ":id": value.id },
+                        )?;
+                        rotated_count += 1;
+                    }
+                    Err(_) => {
+                        if quarantine {
+                            self.quarantine(column, &value, now_ms)?;
+                        }
+                        failed.insert((column.source, value.id));
+                        failed_ids.insert(value.guid);
+                    }
+                }
+            }
+        }
+        rotated_count += self.rotate_vulnerable_passwords(old, new)?;
+
+        // Make sure everything we rotated can be read back from the database with the new key
+        // before we commit to it.
+        for column in &ENCRYPTED_COLUMNS {
+            for value in self.get_encrypted_values(column)? {
+                if !failed.contains(&(column.source, value.id)) {
+                    new.decrypt(&value.ciphertext, "verify key rotation")?;
+                }
+            }
+        }
+        let new_canary = new.create_canary(canary_text)?;
+        if !new.check_canary(&new_canary, canary_text)? {
+            return Err(Error::IncorrectCanary);
+        }
+        tx.commit()?;
+        Ok(KeyRotationResult {
+            rotated_count,
+            failed_ids: failed_ids.into_iter().collect(),
+            new_canary,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_utils::insert_login;
+    use crate::encryption::test_utils::TEST_ENCRYPTOR;
+    use crate::login::{LoginEntry, LoginFields, SecureLoginFields};
+    use crate::passkeys::PasskeyEntry;
+
+    fn entry(username: &str, password: &str) -> LoginEntry {
+        LoginEntry {
+            fields: LoginFields {
+                origin: "https://www.example.com".into(),
+                http_realm: Some("realm".into()),
+                ..Default::default()
+            },
+            sec_fields: SecureLoginFields {
+                username: username.into(),
+                password: password.into(),
+                otp_secret: None,
+            },
+        }
+    }
+
+    const CANARY_TEXT: &str = "canary";
+
+    fn canary(encdec: &EncryptorDecryptor) -> String {
+        encdec.create_canary(CANARY_TEXT).unwrap()
+    }
+
+    fn new_encryptor() -> EncryptorDecryptor {
+        EncryptorDecryptor::new(&crate::encryption::create_key().unwrap()).unwrap()
+    }
+
+    fn quarantine_sources(db: &LoginDb) -> Vec<(String, String)> {
+        db.query_rows_and_then(
+            "SELECT guid, source FROM loginsQuarantine ORDER BY id",
+            [],
+            |row| -> Result<(String, String)> { Ok((row.get(0)?, row.get(1)?)) },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_rotate_key() {
+        let db = LoginDb::open_in_memory().unwrap();
+        let id = db
+            .add(entry("user", "first"), &TEST_ENCRYPTOR)
+            .unwrap()
+            .record
+            .id;
+        db.update(&id, entry("user", "second"), &TEST_ENCRYPTOR)
+            .unwrap();
+        // A synced login, with a local change.
+        insert_login(&db, "synced", Some("local"), Some("mirror"));
+        let passkey = db
+            .add_passkey(
+                PasskeyEntry {
+                    rp_id: "example.com".into(),
+                    user_handle: "dXNlcg".into(),
+                    ..Default::default()
+                },
+                false,
+                &TEST_ENCRYPTOR,
+            )
+            .unwrap()
+            .passkey;
+
+        let new = new_encryptor();
+        let result = db
+            .rotate_key(
+                &TEST_ENCRYPTOR,
+                &new,
+                &canary(&TEST_ENCRYPTOR),
+                CANARY_TEXT,
+                false,
+            )
+            .unwrap();
+        // 2 local rows, 1 mirror row, 1 history entry and 1 passkey.
+        assert_eq!(result.rotated_count, 5);
+        assert!(result.failed_ids.is_empty());
+        assert!(new.check_canary(&result.new_canary, CANARY_TEXT).unwrap());
+
+        let login = db.get_by_id(&id).unwrap().unwrap();
+        assert_eq!(login.decrypt_fields(&new).unwrap().password, "second");
+        assert!(login.decrypt_fields(&TEST_ENCRYPTOR).is_err());
+        let history = db.get_password_history(&id).unwrap();
+        assert_eq!(
+            SecureLoginFields::decrypt(&history[0].sec_fields, &new)
+                .unwrap()
+                .password,
+            "first"
+        );
+        let client_data_hash = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA";
+        assert!(db
+            .sign_passkey_assertion(&passkey.id, client_data_hash, false, &new)
+            .is_ok());
+        assert!(db
+            .sign_passkey_assertion(&passkey.id, client_data_hash, false, &TEST_ENCRYPTOR)
+            .is_err());
+        let mirror: String = db
+            .query_row(
+                "SELECT secFields FROM loginsM WHERE guid = 'synced'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(
+            SecureLoginFields::decrypt(&mirror, &new).unwrap().password,
+            "mirror"
+        );
+
+        // A canary which wasn't made with the old key, or from different text, fails the rotation
+        // without changing anything.
+        assert!(matches!(
+            db.rotate_key(
+                &TEST_ENCRYPTOR,
+                &new_encryptor(),
+                &result.new_canary,
+                CANARY_TEXT,
+                true
+            ),
+            Err(Error::CryptoError(_))
+        ));
+        assert!(matches!(
+            db.rotate_key(
+                &new,
+                &new_encryptor(),
+                &result.new_canary,
+                "other text",
+                true
+            ),
+            Err(Error::IncorrectCanary)
+        ));
+        assert_eq!(
+            db.get_by_id(&id)
+                .unwrap()
+                .unwrap()
+                .decrypt_fields(&new)
+                .unwrap()
+                .password,
+            "second"
+        );
+        assert!(quarantine_sources(&db).is_empty());
+    }
+
+    #[test]
+    fn test_rotate_key_undecryptable() {
+        let db = LoginDb::open_in_memory().unwrap();
+        let good = db
+            .add(entry("good", "password"), &TEST_ENCRYPTOR)
+            .unwrap()
+            .record
+            .id;
+        // Add some logins with a different key.
+        let other = new_encryptor();
+        let mut bad_entry = entry("bad", "password");
+        // Adding checks for dupes by decrypting the logins for the same target.
+        bad_entry.fields.http_realm = Some("other realm".into());
+        let bad = db.add(bad_entry, &other).unwrap().record.id;
+        insert_login(&db, "synced", Some("local"), Some("mirror"));
+        db.execute(
+            "UPDATE loginsL SET secFields = :sec_fields WHERE guid = 'synced'",
+            named_params! {
+                ":sec_fields": SecureLoginFields {
+                    username: "user".into(),
+                    password: "local".into(),
+                    otp_secret: None,
+                }
+                .encrypt(&other)
+                .unwrap(),
+            },
+        )
+        .unwrap();
+
+        let mut failed_ids = vec![bad.clone(), "synced".to_string()];
+        failed_ids.sort();
+
+        // Without quarantine, the failures are just reported.
+        let new = new_encryptor();
+        let result = db
+            .rotate_key(
+                &TEST_ENCRYPTOR,
+                &new,
+                &canary(&TEST_ENCRYPTOR),
+                CANARY_TEXT,
+                false,
+            )
+            .unwrap();
+        assert_eq!(result.rotated_count, 2);
+        assert_eq!(result.failed_ids, failed_ids);
+        assert!(db.get_by_id(&bad).unwrap().unwrap().decrypt(&other).is_ok());
+        assert!(quarantine_sources(&db).is_empty());
+
+        // With quarantine, they're moved out of the way.
+        let newer = new_encryptor();
+        let result = db
+            .rotate_key(&new, &newer, &result.new_canary, CANARY_TEXT, true)
+            .unwrap();
+        assert_eq!(result.rotated_count, 2);
+        assert_eq!(result.failed_ids, failed_ids);
+        assert_eq!(
+            quarantine_sources(&db),
+            vec![
+                (bad.clone(), "loginsL".to_string()),
+                ("synced".to_string(), "loginsL".to_string()),
+            ]
+        );
+        assert!(db.get_by_id(&bad).unwrap().is_none());
+        // The undecryptable local change was dropped in favor of the mirror.
+        let synced = db.get_by_id("synced").unwrap().unwrap();
+        assert_eq!(synced.decrypt_fields(&newer).unwrap().password, "mirror");
+        assert!(db
+            .get_by_id(&good)
+            .unwrap()
+            .unwrap()
+            .decrypt(&newer)
+            .is_ok());
+        // And nothing is left to fail.
+        let result = db
+            .rotate_key(
+                &newer,
+                &new_encryptor(),
+                &result.new_canary,
+                CANARY_TEXT,
+                false,
+            )
+            .unwrap();
+        assert_eq!(result.rotated_count, 2);
+        assert!(result.failed_ids.is_empty());
+    }
+
+    #[test]
+    fn test_quarantine_mirror_only() {
+        let db = LoginDb::open_in_memory().unwrap();
+        insert_login(&db, "good", None, Some("mirror"));
+        insert_login(&db, "bad", None, Some("mirror"));
+        db.execute(
+            "UPDATE loginsM SET secFields = :sec_fields WHERE guid = 'bad'",
+            named_params! {
+                ":sec_fields": SecureLoginFields {
+                    username: "user".into(),
+                    password: "mirror".into(),
+                    otp_secret: None,
+                }
+                .encrypt(&new_encryptor())
+                .unwrap(),
+            },
+        )
+        .unwrap();
+        db.put_meta(schema::LAST_SYNC_META_KEY, &12345i64).unwrap();
+
+        let new = new_encryptor();
+        let result = db
+            .rotate_key(
+                &TEST_ENCRYPTOR,
+                &new,
+                &canary(&TEST_ENCRYPTOR),
+                CANARY_TEXT,
+                true,
+            )
+            .unwrap();
+        assert_eq!(result.failed_ids, vec!["bad".to_string()]);
+        assert_eq!(
+            quarantine_sources(&db),
+            vec![("bad".to_string(), "loginsM".to_string())]
+        );
+        // The login is gone locally, but the next sync downloads it again.
+        assert!(db.get_by_id("bad").unwrap().is_none());
+        assert_eq!(
+            db.get_meta::<i64>(schema::LAST_SYNC_META_KEY).unwrap(),
+            Some(0)
+        );
+        assert!(db.get_by_id("good").unwrap().unwrap().decrypt(&new).is_ok());
+    }
+}
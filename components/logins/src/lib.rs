@@ -9,21 +9,41 @@
 mod error;
 mod login;
 
+mod audit;
+mod breach;
+mod csv;
 mod db;
 pub mod encryption;
+mod key_rotation;
+mod matching;
 pub mod migrate_sqlcipher_db;
+mod passkeys;
+mod password_generator;
+mod password_history;
+mod recovery;
 mod schema;
 mod store;
 mod sync;
+mod totp;
 mod util;
 
 uniffi::include_scaffolding!("logins");
 
+pub use crate::audit::{LoginsAuditReport, ReusedPasswordGroup, WeakPassword, WeakPasswordReason};
+pub use crate::breach::{Breach, BreachAlert};
+pub use crate::csv::{CsvImportRow, CsvImportStatus};
 pub use crate::db::LoginDb;
 use crate::encryption::{check_canary, create_canary, create_key};
 pub use crate::error::*;
+pub use crate::key_rotation::KeyRotationResult;
 pub use crate::login::*;
+pub use crate::matching::{LoginMatch, LoginMatchOptions, LoginMatchReason};
 pub use crate::migrate_sqlcipher_db::migrate_logins;
+pub use crate::passkeys::{
+    Passkey, PasskeyAssertion, PasskeyEntry, PasskeyImport, PasskeyRegistration,
+};
+pub use crate::password_generator::generate_password;
+pub use crate::password_history::PasswordHistoryEntry;
 pub use crate::store::*;
 pub use crate::sync::LoginsSyncEngine;
 
@@ -297,7 +297,7 @@ pub struct LoginFields {
 impl LoginFields {
     /// Internal helper for validation and fixups of an "origin" stored as
     /// a string.
-    fn validate_and_fixup_origin(origin: &str) -> Result<Option<String>> {
+    pub(crate) fn validate_and_fixup_origin(origin: &str) -> Result<Option<String>> {
         // Check we can parse the origin, then use the normalized version of it.
         match Url::parse(origin) {
             Ok(mut u) => {
@@ -363,6 +363,10 @@ pub struct SecureLoginFields {
     pub username: String,
     #[serde(rename = "p")]
     pub password: String,
+    // An `otpauth://` URI holding a TOTP secret for the site, if the user saved one. Older
+    // versions don't know about this, so it's omitted when not set.
+    #[serde(rename = "o", default, skip_serializing_if = "Option::is_none")]
+    pub otp_secret: Option<String>,
 }
 
 impl SecureLoginFields {
@@ -686,6 +690,12 @@ impl ValidateAndFixup for SecureLoginFields {
             }
             .into());
         }
+        if matches!(&self.otp_secret, Some(secret) if secret.contains('\0')) {
+            return Err(InvalidLogin::IllegalFieldValue {
+                field_info: "`otp_secret` contains Nul".into(),
+            }
+            .into());
+        }
         Ok(None)
     }
 }
@@ -721,6 +731,7 @@ pub mod test_utils {
         let sec_fields = SecureLoginFields {
             username: "user".to_string(),
             password: password.to_string(),
+            otp_secret: None,
         };
         EncryptedLogin {
             record: RecordFields {
@@ -811,6 +822,7 @@ mod tests {
             sec_fields: SecureLoginFields {
                 username: "test".into(),
                 password: "test".into(),
+                otp_secret: None,
             },
         };
 
@@ -823,6 +835,7 @@ mod tests {
             sec_fields: SecureLoginFields {
                 username: "test".into(),
                 password: "test".into(),
+                otp_secret: None,
             },
         };
 
@@ -835,6 +848,7 @@ mod tests {
             sec_fields: SecureLoginFields {
                 username: "test".into(),
                 password: "".into(),
+                otp_secret: None,
             },
         };
 
@@ -848,6 +862,7 @@ mod tests {
             sec_fields: SecureLoginFields {
                 username: "".into(),
                 password: "test".into(),
+                otp_secret: None,
             },
         };
 
@@ -859,6 +874,7 @@ mod tests {
             sec_fields: SecureLoginFields {
                 username: "".into(),
                 password: "test".into(),
+                otp_secret: None,
             },
         };
 
@@ -871,6 +887,7 @@ mod tests {
             sec_fields: SecureLoginFields {
                 username: "".into(),
                 password: "test".into(),
+                otp_secret: None,
             },
         };
 
@@ -883,6 +900,7 @@ mod tests {
             sec_fields: SecureLoginFields {
                 username: "test".into(),
                 password: "test".into(),
+                otp_secret: None,
             },
         };
 
@@ -895,6 +913,7 @@ mod tests {
             sec_fields: SecureLoginFields {
                 username: "\0".into(),
                 password: "test".into(),
+                otp_secret: None,
             },
         };
 
@@ -907,6 +926,7 @@ mod tests {
             sec_fields: SecureLoginFields {
                 username: "username".into(),
                 password: "test\0".into(),
+                otp_secret: None,
             },
         };
 
@@ -919,6 +939,7 @@ mod tests {
             sec_fields: SecureLoginFields {
                 username: "test".into(),
                 password: "test".into(),
+                otp_secret: None,
             },
         };
 
@@ -932,6 +953,7 @@ mod tests {
             sec_fields: SecureLoginFields {
                 username: "test".into(),
                 password: "test".into(),
+                otp_secret: None,
             },
         };
 
@@ -944,6 +966,7 @@ mod tests {
             sec_fields: SecureLoginFields {
                 username: "test".into(),
                 password: "test".into(),
+                otp_secret: None,
             },
         };
 
@@ -956,6 +979,7 @@ mod tests {
             sec_fields: SecureLoginFields {
                 username: "test".into(),
                 password: "test\n".into(),
+                otp_secret: None,
             },
         };
 
@@ -969,6 +993,7 @@ mod tests {
             sec_fields: SecureLoginFields {
                 username: "test".into(),
                 password: "test".into(),
+                otp_secret: None,
             },
         };
 
@@ -981,6 +1006,7 @@ mod tests {
             sec_fields: SecureLoginFields {
                 username: "test".into(),
                 password: "test".into(),
+                otp_secret: None,
             },
         };
 
@@ -993,6 +1019,7 @@ mod tests {
             sec_fields: SecureLoginFields {
                 username: "test".into(),
                 password: "test".into(),
+                otp_secret: None,
             },
         };
 
@@ -1005,6 +1032,7 @@ mod tests {
             sec_fields: SecureLoginFields {
                 username: "test".into(),
                 password: "test".into(),
+                otp_secret: None,
             },
         };
 
@@ -1017,6 +1045,7 @@ mod tests {
             sec_fields: SecureLoginFields {
                 username: "test".into(),
                 password: "test".into(),
+                otp_secret: None,
             },
         };
 
@@ -1029,6 +1058,7 @@ mod tests {
             sec_fields: SecureLoginFields {
                 username: "test".into(),
                 password: "test".into(),
+                otp_secret: None,
             },
         };
 
@@ -1041,6 +1071,7 @@ mod tests {
             sec_fields: SecureLoginFields {
                 username: "test".into(),
                 password: "test".into(),
+                otp_secret: None,
             },
         };
 
@@ -1053,6 +1084,7 @@ mod tests {
             sec_fields: SecureLoginFields {
                 username: "test".into(),
                 password: "test".into(),
+                otp_secret: None,
             },
         };
 
@@ -1209,6 +1241,7 @@ mod tests {
             sec_fields: SecureLoginFields {
                 username: "test".into(),
                 password: "test".into(),
+                otp_secret: None,
             },
         };
 
@@ -1221,6 +1254,7 @@ mod tests {
             sec_fields: SecureLoginFields {
                 username: "test".into(),
                 password: "test".into(),
+                otp_secret: None,
             },
         };
 
@@ -1233,6 +1267,7 @@ mod tests {
             sec_fields: SecureLoginFields {
                 username: "test".into(),
                 password: "test".into(),
+                otp_secret: None,
             },
         };
         let login_with_empty_fsu = LoginEntry {
@@ -1244,6 +1279,7 @@ mod tests {
             sec_fields: SecureLoginFields {
                 username: "test".into(),
                 password: "test".into(),
+                otp_secret: None,
             },
         };
 
@@ -1261,6 +1297,7 @@ mod tests {
             sec_fields: SecureLoginFields {
                 username: "".into(),
                 password: "test".into(),
+                otp_secret: None,
             },
         };
 
@@ -1324,6 +1361,7 @@ mod tests {
         let sf = SecureLoginFields {
             username: "foo".into(),
             password: "pwd".into(),
+            otp_secret: None,
         };
         assert_eq!(
             serde_json::to_string(&sf).unwrap(),
@@ -1333,6 +1371,7 @@ mod tests {
         let expected = SecureLoginFields {
             username: "user".into(),
             password: "p".into(),
+            otp_secret: None,
         };
         assert_eq!(got, expected);
     }
@@ -0,0 +1,392 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Finding the logins to offer for a page, like desktop's `LoginHelper.searchLoginsWithObject`.
+//!
+//! Matches are ranked by how closely the login's origin matches the page: the exact origin
+//! first, then other hosts on the same site, then logins saved for `http://` which can be
+//! upgraded to `https://`. Like desktop, a "site" is a registrable domain according to the public
+//! suffix list, so that eg, logins for `victim.github.io` are never offered on
+//! `attacker.github.io`. Within each of those, logins whose form action origin (or HTTP realm)
+//! matches exactly come before those which match because it's empty or ignored, and then more
+//! recently used logins come first.
+
+use crate::db::LoginDb;
+use crate::error::*;
+use crate::login::{EncryptedLogin, LoginFields};
+use std::cmp::Reverse;
+use url::{Host, Url};
+
+/// Why a login matched, from best to worst.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LoginMatchReason {
+    /// The login is for exactly the requested origin.
+    ExactOrigin,
+    /// The login is for a different host on the same site, with the same port.
+    Subdomain,
+    /// The login is for the same host, but was saved for `http://` and `https://` is requested.
+    SchemeUpgrade,
+    /// Both `Subdomain` and `SchemeUpgrade`.
+    SubdomainSchemeUpgrade,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct LoginMatchOptions {
+    /// Also match logins for other hosts on the same site.
+    pub include_subdomains: bool,
+    /// Also match `http://` logins when looking for an `https://` origin.
+    pub include_scheme_upgrades: bool,
+    /// Match form logins whatever their form action origin.
+    pub ignore_form_action_origin: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoginMatch {
+    pub login: EncryptedLogin,
+    pub reason: LoginMatchReason,
+    /// Whether the form action origin or HTTP realm matched exactly, as opposed to being empty,
+    /// upgraded or ignored.
+    pub exact_target: bool,
+}
+
+fn normalize_origin(origin: &str) -> Result<String> {
+    Ok(LoginFields::validate_and_fixup_origin(origin)?.unwrap_or_else(|| origin.to_owned()))
+}
+
+fn same_host_and_port(a: &Url, b: &Url) -> bool {
+    a.host() == b.host() && a.port() == b.port()
+}
+
+/// The registrable domain of a URL's host, or None for IP addresses and hosts which are a public
+/// suffix, which never match other hosts.
+fn registrable_domain(url: &Url) -> Option<String> {
+    match url.host()? {
+        Host::Domain(domain) => psl_support::registrable_domain(domain),
+        _ => None,
+    }
+}
+
+fn is_scheme_upgrade(saved: &Url, requested: &Url) -> bool {
+    saved.scheme() == "http" && requested.scheme() == "https"
+}
+
+fn match_origin(
+    saved_origin: &str,
+    requested: &Url,
+    requested_site: Option<&str>,
+    options: &LoginMatchOptions,
+) -> Option<LoginMatchReason> {
+    let saved = Url::parse(saved_origin).ok()?;
+    let same_scheme = saved.scheme() == requested.scheme();
+    let upgrade = options.include_scheme_upgrades && is_scheme_upgrade(&saved, requested);
+    // A different port on the same host is a different origin, not a subdomain.
+    let subdomain = options.include_subdomains
+        && saved.host() != requested.host()
+        && saved.port() == requested.port()
+        && requested_site.is_some()
+        && registrable_domain(&saved).as_deref() == requested_site;
+    if !subdomain && !same_host_and_port(&saved, requested) {
+        return None;
+    }
+    match (subdomain, same_scheme, upgrade) {
+        (false, true, _) => Some(LoginMatchReason::ExactOrigin),
+        (true, true, _) => Some(LoginMatchReason::Subdomain),
+        (false, false, true) => Some(LoginMatchReason::SchemeUpgrade),
+        (true, false, true) => Some(LoginMatchReason::SubdomainSchemeUpgrade),
+        (_, false, false) => None,
+    }
+}
+
+/// Returns whether the form action origin matched exactly, or None if it didn't match at all.
+fn match_form_action_origin(
+    saved: &str,
+    requested: &str,
+    options: &LoginMatchOptions,
+) -> Option<bool> {
+    if saved == requested {
+        return Some(true);
+    }
+    // An empty form action origin matches any form, like on desktop.
+    if saved.is_empty() || requested.is_empty() || options.ignore_form_action_origin {
+        return Some(false);
+    }
+    let upgrade = match (Url::parse(saved), Url::parse(requested)) {
+        (Ok(saved), Ok(requested)) => {
+            same_host_and_port(&saved, &requested) && is_scheme_upgrade(&saved, &requested)
+        }
+        _ => false,
+    };
+    if options.include_scheme_upgrades && upgrade {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// Returns whether the target (form action origin or HTTP realm) of a login matched exactly,
+/// or None if it didn't match at all.
+fn match_target(
+    login: &LoginFields,
+    form_action_origin: Option<&str>,
+    http_realm: Option<&str>,
+    options: &LoginMatchOptions,
+) -> Option<bool> {
+    match (form_action_origin, http_realm) {
+        (Some(requested), _) => {
+            match_form_action_origin(login.form_action_origin.as_deref()?, requested, options)
+        }
+        (None, Some(requested)) if login.http_realm.as_deref() == Some(requested) => Some(true),
+        (None, Some(_)) => None,
+        (None, None) => Some(true),
+    }
+}
+
+impl LoginDb {
+    /// Find the logins to offer for a page, best match first. Pass a `form_action_origin` to
+    /// find form logins, an `http_realm` to find HTTP auth logins, or neither to find both.
+    pub fn find_matching_logins(
+        &self,
+        origin: &str,
+        form_action_origin: Option<&str>,
+        http_realm: Option<&str>,
+        options: &LoginMatchOptions,
+    ) -> Result<Vec<LoginMatch>> {
+        let origin = normalize_origin(origin)?;
+        let requested = Url::parse(&origin).map_err(|_| InvalidLogin::IllegalOrigin)?;
+        let requested_site = registrable_domain(&requested);
+        let form_action_origin = match form_action_origin {
+            Some(fao) if !fao.is_empty() => Some(normalize_origin(fao)?),
+            other => other.map(str::to_owned),
+        };
+        let mut matches = self
+            .get_all()?
+            .into_iter()
+            .filter_map(|login| {
+                let reason = match_origin(
+                    &login.fields.origin,
+                    &requested,
+                    requested_site.as_deref(),
+                    options,
+                )?;
+                let exact_target = match_target(
+                    &login.fields,
+                    form_action_origin.as_deref(),
+                    http_realm,
+                    options,
+                )?;
+                Some(LoginMatch {
+                    login,
+                    reason,
+                    exact_target,
+                })
+            })
+            .collect::<Vec<_>>();
+        matches.sort_by_key(|m| {
+            (
+                m.reason,
+                !m.exact_target,
+                Reverse(m.login.record.time_last_used),
+            )
+        });
+        Ok(matches)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::test_utils::TEST_ENCRYPTOR;
+    use crate::login::{LoginEntry, SecureLoginFields};
+
+    fn add_login(
+        db: &LoginDb,
+        origin: &str,
+        form_action_origin: Option<&str>,
+        http_realm: Option<&str>,
+    ) -> String {
+        db.add(
+            LoginEntry {
+                fields: LoginFields {
+                    origin: origin.into(),
+                    form_action_origin: form_action_origin.map(Into::into),
+                    http_realm: http_realm.map(Into::into),
+                    ..Default::default()
+                },
+                sec_fields: SecureLoginFields {
+                    username: "user".into(),
+                    password: "password".into(),
+                    otp_secret: None,
+                },
+            },
+            &TEST_ENCRYPTOR,
+        )
+        .unwrap()
+        .record
+        .id
+    }
+
+    fn find(
+        db: &LoginDb,
+        origin: &str,
+        form_action_origin: Option<&str>,
+        http_realm: Option<&str>,
+        options: &LoginMatchOptions,
+    ) -> Vec<(String, LoginMatchReason, bool)> {
+        db.find_matching_logins(origin, form_action_origin, http_realm, options)
+            .unwrap()
+            .into_iter()
+            .map(|m| (m.login.record.id, m.reason, m.exact_target))
+            .collect()
+    }
+
+    #[test]
+    fn test_origin_ranking() {
+        use LoginMatchReason::*;
+        let db = LoginDb::open_in_memory().unwrap();
+        let fao = Some("https://www.example.com");
+        let exact = add_login(&db, "https://www.example.com", fao, None);
+        let subdomain = add_login(&db, "https://accounts.example.com", fao, None);
+        let upgrade = add_login(&db, "http://www.example.com", fao, None);
+        let subdomain_upgrade = add_login(&db, "http://example.com", fao, None);
+        // A different origin on the same host never matches.
+        add_login(&db, "https://www.example.com:8443", fao, None);
+        add_login(&db, "https://www.example.org", fao, None);
+        add_login(&db, "https://notexample.com", fao, None);
+
+        let all = LoginMatchOptions {
+            include_subdomains: true,
+            include_scheme_upgrades: true,
+            ignore_form_action_origin: false,
+        };
+        // Some different ways of writing the same origin.
+        for origin in [
+            "https://www.example.com",
+            "https://www.example.com/login?next=/",
+            "https://WWW.example.com:443",
+        ] {
+            assert_eq!(
+                find(&db, origin, fao, None, &all),
+                vec![
+                    (exact.clone(), ExactOrigin, true),
+                    (subdomain.clone(), Subdomain, true),
+                    (upgrade.clone(), SchemeUpgrade, false),
+                    (subdomain_upgrade.clone(), SubdomainSchemeUpgrade, false),
+                ],
+                "{}",
+                origin
+            );
+        }
+
+        assert_eq!(
+            find(
+                &db,
+                "https://www.example.com",
+                fao,
+                None,
+                &LoginMatchOptions::default()
+            ),
+            vec![(exact, ExactOrigin, true)]
+        );
+        // We never downgrade.
+        assert_eq!(
+            find(&db, "http://www.example.com", fao, None, &all)
+                .into_iter()
+                .map(|(_, reason, _)| reason)
+                .collect::<Vec<_>>(),
+            vec![ExactOrigin, Subdomain]
+        );
+
+        assert!(matches!(
+            db.find_matching_logins("not an origin", None, None, &all),
+            Err(Error::InvalidLogin(InvalidLogin::IllegalOrigin))
+        ));
+    }
+
+    #[test]
+    fn test_public_suffixes() {
+        use LoginMatchReason::*;
+        let db = LoginDb::open_in_memory().unwrap();
+        let victim = add_login(&db, "https://victim.github.io", None, Some("realm"));
+        let www = add_login(&db, "https://www.victim.github.io", None, Some("realm"));
+        add_login(&db, "https://github.io", None, Some("realm"));
+        add_login(&db, "https://127.0.0.2", None, Some("realm"));
+        let all = LoginMatchOptions {
+            include_subdomains: true,
+            include_scheme_upgrades: true,
+            ignore_form_action_origin: false,
+        };
+
+        // Other sites under a shared suffix never match.
+        assert!(find(&db, "https://attacker.github.io", None, None, &all).is_empty());
+        assert_eq!(
+            find(&db, "https://victim.github.io", None, None, &all),
+            vec![(victim, ExactOrigin, true), (www, Subdomain, true)]
+        );
+        // Neither do hosts which are a public suffix, or IP addresses.
+        assert_eq!(find(&db, "https://github.io", None, None, &all).len(), 1);
+        assert!(find(&db, "https://127.0.0.1", None, None, &all).is_empty());
+    }
+
+    #[test]
+    fn test_targets() {
+        use LoginMatchReason::*;
+        let db = LoginDb::open_in_memory().unwrap();
+        let origin = "https://www.example.com";
+        let same_form = add_login(&db, origin, Some(origin), None);
+        let any_form = add_login(&db, origin, Some(""), None);
+        let other_form = add_login(&db, origin, Some("https://login.example.com"), None);
+        let http_form = add_login(&db, origin, Some("http://www.example.com"), None);
+        let realm = add_login(&db, origin, None, Some("realm"));
+        add_login(&db, origin, None, Some("other realm"));
+
+        let options = LoginMatchOptions::default();
+        assert_eq!(
+            find(
+                &db,
+                origin,
+                Some("https://www.example.com/submit"),
+                None,
+                &options
+            ),
+            vec![
+                (same_form.clone(), ExactOrigin, true),
+                (any_form.clone(), ExactOrigin, false),
+            ]
+        );
+        let found = find(
+            &db,
+            origin,
+            Some(origin),
+            None,
+            &LoginMatchOptions {
+                include_scheme_upgrades: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(found.len(), 3);
+        assert_eq!(found[0], (same_form.clone(), ExactOrigin, true));
+        assert!(found.contains(&(http_form.clone(), ExactOrigin, false)));
+
+        let found = find(
+            &db,
+            origin,
+            Some(origin),
+            None,
+            &LoginMatchOptions {
+                ignore_form_action_origin: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(found.len(), 4);
+        assert!(found.contains(&(other_form, ExactOrigin, false)));
+
+        assert_eq!(
+            find(&db, origin, None, Some("realm"), &options),
+            vec![(realm, ExactOrigin, true)]
+        );
+        // No target finds everything for the origin.
+        assert_eq!(find(&db, origin, None, None, &options).len(), 6);
+        assert!(find(&db, origin, None, Some("no such realm"), &options).is_empty());
+    }
+}
@@ -635,7 +635,11 @@ fn get_login_from_row(row: &Row<'_>) -> Result<Login> {
             username_field,
             password_field,
         },
-        sec_fields: SecureLoginFields { username, password },
+        sec_fields: SecureLoginFields {
+            username,
+            password,
+            otp_secret: None,
+        },
     };
     Ok(login)
 }
@@ -1128,6 +1132,7 @@ mod tests {
             sec_fields: SecureLoginFields {
                 username: "test".into(),
                 password: "test".into(),
+                otp_secret: None,
             },
         };
         let valid_login_guid2: Guid = Guid::random();
@@ -1145,6 +1150,7 @@ mod tests {
             sec_fields: SecureLoginFields {
                 username: "test2".into(),
                 password: "test2".into(),
+                otp_secret: None,
             },
         };
         let valid_login_guid3: Guid = Guid::random();
@@ -1162,6 +1168,7 @@ mod tests {
             sec_fields: SecureLoginFields {
                 username: "test3".into(),
                 password: "test3".into(),
+                otp_secret: None,
             },
         };
         // local login + mirror login with override
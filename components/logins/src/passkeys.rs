@@ -0,0 +1,962 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Passkeys (WebAuthn credentials).
+//!
+//! Passkeys live in the `loginsPasskeys` table, next to the logins. Like the `secFields` of a
+//! login, the private key is encrypted with the key the app gives us, and everything else is
+//! stored in the clear. Passkeys are local only for now.
+//!
+//! We only support ES256 (ECDSA with P-256 and SHA-256) credentials, which every relying party
+//! accepts. Binary values - credential ids, user handles, public keys, authenticator data and
+//! signatures - are base64url encoded without padding, as in the WebAuthn JSON serialization.
+//!
+//! New passkeys use "none" attestation, like other software authenticators, so registration
+//! returns an attestation object without a signature. Passkeys created elsewhere can be imported
+//! with their existing credential id and key.
+
+use crate::db::LoginDb;
+use crate::encryption::EncryptorDecryptor;
+use crate::error::*;
+use crate::util;
+use rc_crypto::agreement::{Curve, EcKey};
+use rc_crypto::digest;
+use rc_crypto::signature::{
+    EcdsaKeyPair, UnparsedPublicKey, ECDSA_P256_SHA256, ECDSA_P256_SHA256_FIXED_SIGNING,
+};
+use rusqlite::{named_params, Row};
+use serde_derive::{Deserialize, Serialize};
+use sql_support::ConnExt;
+use std::time::SystemTime;
+use sync_guid::Guid;
+
+/// How many random bytes are in the credential ids we create.
+const CREDENTIAL_ID_LEN: usize = 16;
+/// WebAuthn limits credential ids to 1023 bytes.
+const MAX_CREDENTIAL_ID_LEN: usize = 1023;
+/// WebAuthn limits user handles to 64 bytes.
+const MAX_USER_HANDLE_LEN: usize = 64;
+/// The client data hash is a SHA-256 hash.
+const CLIENT_DATA_HASH_LEN: usize = 32;
+
+/// The length of an uncompressed P-256 point: 0x04, then the x and y coordinates.
+const PUBLIC_KEY_LEN: usize = 65;
+/// Authenticators which use "none" attestation identify themselves with an all-zero AAGUID.
+const AAGUID: [u8; 16] = [0; 16];
+
+// Authenticator data flags.
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+// CBOR major types.
+const CBOR_NEGATIVE: u8 = 1;
+const CBOR_BYTES: u8 = 2;
+const CBOR_TEXT: u8 = 3;
+const CBOR_MAP: u8 = 5;
+
+/// The fields needed to create a passkey.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PasskeyEntry {
+    /// The relying party id, usually the domain of the site.
+    pub rp_id: String,
+    /// The relying party's id for the user.
+    pub user_handle: String,
+    pub user_name: String,
+    pub user_display_name: String,
+}
+
+/// A stored passkey. The private key never leaves the store.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Passkey {
+    pub id: String,
+    pub rp_id: String,
+    pub credential_id: String,
+    pub user_handle: String,
+    pub user_name: String,
+    pub user_display_name: String,
+    /// The public key, as an uncompressed P-256 point.
+    pub public_key: String,
+    pub sign_count: i64,
+    pub time_created: i64,
+    pub time_last_used: i64,
+}
+
+/// A passkey created somewhere else, to import with its existing credential id and key.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PasskeyImport {
+    pub entry: PasskeyEntry,
+    pub credential_id: String,
+    /// The private key's `d` value.
+    pub private_key: String,
+    /// The public key, as an uncompressed P-256 point.
+    pub public_key: String,
+    pub sign_count: i64,
+    /// When the passkey was created, in milliseconds since the unix epoch, or 0 for now.
+    pub time_created: i64,
+}
+
+/// A new passkey, and what an authenticator returns for `navigator.credentials.create()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PasskeyRegistration {
+    pub passkey: Passkey,
+    /// The public key as a COSE_Key, as it appears in the authenticator data.
+    pub cose_public_key: String,
+    /// The authenticator data, including the attested credential data.
+    pub authenticator_data: String,
+    /// A CBOR attestation object, with "none" attestation.
+    pub attestation_object: String,
+}
+
+/// What an authenticator returns for `navigator.credentials.get()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PasskeyAssertion {
+    pub credential_id: String,
+    pub user_handle: String,
+    pub authenticator_data: String,
+    /// A DER encoded ECDSA signature over the authenticator data and the client data hash.
+    pub signature: String,
+}
+
+/// The passkey fields which are stored encrypted.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct SecurePasskeyFields {
+    /// The private key's `d` value.
+    #[serde(rename = "d")]
+    private_key: String,
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::InvalidPasskey(reason.to_owned())
+}
+
+fn encode(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+fn decode(s: &str, what: &str) -> Result<Vec<u8>> {
+    base64::decode_config(s, base64::URL_SAFE_NO_PAD)
+        .map_err(|_| invalid(&format!("{} isn't base64url", what)))
+}
+
+/// The header of a CBOR data item. We never need lengths of 64K or more.
+fn cbor_header(major_type: u8, len: usize) -> Vec<u8> {
+    let major_type = major_type << 5;
+    match len {
+        0..=23 => vec![major_type | len as u8],
+        24..=0xff => vec![major_type | 24, len as u8],
+        _ => {
+            let mut out = vec![major_type | 25];
+            out.extend_from_slice(&(len as u16).to_be_bytes());
+            out
+        }
+    }
+}
+
+fn cbor_bytes(bytes: &[u8]) -> Vec<u8> {
+    let mut out = cbor_header(CBOR_BYTES, bytes.len());
+    out.extend_from_slice(bytes);
+    out
+}
+
+fn cbor_text(text: &str) -> Vec<u8> {
+    let mut out = cbor_header(CBOR_TEXT, text.len());
+    out.extend_from_slice(text.as_bytes());
+    out
+}
+
+/// Encode a small integer (between -24 and 23) as CBOR.
+fn cbor_int(n: i8) -> Vec<u8> {
+    if n >= 0 {
+        cbor_header(0, n as usize)
+    } else {
+        cbor_header(CBOR_NEGATIVE, (-1 - n) as usize)
+    }
+}
+
+/// Encode an uncompressed P-256 point as an ES256 COSE_Key. The keys are in the canonical order
+/// CTAP2 requires.
+fn cose_public_key(public_key: &[u8]) -> Result<Vec<u8>> {
+    if public_key.len() != PUBLIC_KEY_LEN || public_key[0] != 0x04 {
+        return Err(invalid("public_key must be an uncompressed P-256 point"));
+    }
+    let (x, y) = public_key[1..].split_at(32);
+    let mut out = cbor_header(CBOR_MAP, 5);
+    // kty: EC2
+    out.extend(cbor_int(1));
+    out.extend(cbor_int(2));
+    // alg: ES256
+    out.extend(cbor_int(3));
+    out.extend(cbor_int(-7));
+    // crv: P-256
+    out.extend(cbor_int(-1));
+    out.extend(cbor_int(1));
+    // x and y
+    out.extend(cbor_int(-2));
+    out.extend(cbor_bytes(x));
+    out.extend(cbor_int(-3));
+    out.extend(cbor_bytes(y));
+    Ok(out)
+}
+
+/// Build authenticator data, optionally with attested credential data.
+fn authenticator_data(
+    rp_id: &str,
+    flags: u8,
+    sign_count: u32,
+    attested_credential_data: Option<&[u8]>,
+) -> Result<Vec<u8>> {
+    let mut out = digest::digest(&digest::SHA256, rp_id.as_bytes())?
+        .as_ref()
+        .to_vec();
+    match attested_credential_data {
+        Some(data) => {
+            out.push(flags | FLAG_ATTESTED_CREDENTIAL_DATA);
+            out.extend_from_slice(&sign_count.to_be_bytes());
+            out.extend_from_slice(data);
+        }
+        None => {
+            out.push(flags);
+            out.extend_from_slice(&sign_count.to_be_bytes());
+        }
+    }
+    Ok(out)
+}
+
+fn user_flags(user_verified: bool) -> u8 {
+    if user_verified {
+        FLAG_USER_PRESENT | FLAG_USER_VERIFIED
+    } else {
+        FLAG_USER_PRESENT
+    }
+}
+
+fn validate_entry(entry: &PasskeyEntry) -> Result<()> {
+    if entry.rp_id.is_empty() {
+        return Err(invalid("rp_id is empty"));
+    }
+    let user_handle_len = decode(&entry.user_handle, "user_handle")?.len();
+    if user_handle_len == 0 || user_handle_len > MAX_USER_HANDLE_LEN {
+        return Err(invalid("user_handle must be between 1 and 64 bytes"));
+    }
+    Ok(())
+}
+
+/// Encode one half of a fixed-length ECDSA signature as an ASN.1 INTEGER.
+fn der_integer(bytes: &[u8]) -> Vec<u8> {
+    let first_non_zero = bytes
+        .iter()
+        .position(|b| *b != 0)
+        .unwrap_or(bytes.len() - 1);
+    let bytes = &bytes[first_non_zero..];
+    let mut out = vec![0x02];
+    // Integers are signed, so add a leading zero if the high bit is set.
+    if bytes[0] & 0x80 != 0 {
+        out.push(bytes.len() as u8 + 1);
+        out.push(0);
+    } else {
+        out.push(bytes.len() as u8);
+    }
+    out.extend_from_slice(bytes);
+    out
+}
+
+/// Convert a fixed-length `r || s` ECDSA signature to the DER encoding WebAuthn uses.
+fn der_signature(fixed: &[u8]) -> Vec<u8> {
+    let (r, s) = fixed.split_at(fixed.len() / 2);
+    let mut body = der_integer(r);
+    body.extend(der_integer(s));
+    // For P-256 the length always fits in a single byte.
+    let mut out = vec![0x30, body.len() as u8];
+    out.extend(body);
+    out
+}
+
+impl Passkey {
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get("guid")?,
+            rp_id: row.get("rpId")?,
+            credential_id: row.get("credentialId")?,
+            user_handle: row.get("userHandle")?,
+            user_name: row.get("userName")?,
+            user_display_name: row.get("userDisplayName")?,
+            public_key: row.get("publicKey")?,
+            sign_count: row.get("signCount")?,
+            time_created: row.get("timeCreated")?,
+            time_last_used: row.get("timeLastUsed")?,
+        })
+    }
+}
+
+const PASSKEY_COLS: &str = "guid, rpId, credentialId, userHandle, userName, userDisplayName,
+                            publicKey, signCount, timeCreated, timeLastUsed";
+
+impl LoginDb {
+    fn query_passkeys(
+        &self,
+        condition: &str,
+        params: &[(&str, &dyn rusqlite::ToSql)],
+    ) -> Result<Vec<Passkey>> {
+        self.query_rows_and_then_cached(
+            &format!(
+                "SELECT {cols} FROM loginsPasskeys {condition}
+                 ORDER BY timeLastUsed DESC, timeCreated DESC",
+                cols = PASSKEY_COLS,
+                condition = condition,
+            ),
+            params,
+            |row| Passkey::from_row(row).map_err(Error::from),
+        )
+    }
+
+    fn insert_passkey(
+        &self,
+        passkey: &Passkey,
+        sec_fields: &SecurePasskeyFields,
+        encdec: &EncryptorDecryptor,
+    ) -> Result<()> {
+        self.execute_cached(
+            "INSERT INTO loginsPasskeys (
+                guid, rpId, credentialId, userHandle, userName, userDisplayName, publicKey,
+                secFields, signCount, timeCreated, timeLastUsed
+             ) VALUES (
+                :guid, :rp_id, :credential_id, :user_handle, :user_name, :user_display_name,
+                :public_key, :sec_fields, :sign_count, :time_created, :time_last_used
+             )",
+            named_params! {
+                ":guid": passkey.id,
+                ":rp_id": passkey.rp_id,
+                ":credential_id": passkey.credential_id,
+                ":user_handle": passkey.user_handle,
+                ":user_name": passkey.user_name,
+                ":user_display_name": passkey.user_display_name,
+                ":public_key": passkey.public_key,
+                ":sec_fields": encdec.encrypt_struct(sec_fields, "encrypt passkey")?,
+                ":sign_count": passkey.sign_count,
+                ":time_created": passkey.time_created,
+                ":time_last_used": passkey.time_last_used,
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Create a passkey with a new key pair and credential id, and return what the authenticator
+    /// returns to the relying party.
+    pub fn add_passkey(
+        &self,
+        entry: PasskeyEntry,
+        user_verified: bool,
+        encdec: &EncryptorDecryptor,
+    ) -> Result<PasskeyRegistration> {
+        validate_entry(&entry)?;
+        let key_pair = EcdsaKeyPair::generate(&ECDSA_P256_SHA256_FIXED_SIGNING)?;
+        let sec_fields = SecurePasskeyFields {
+            private_key: encode(key_pair.export()?.private_key()),
+        };
+        let mut credential_id = [0u8; CREDENTIAL_ID_LEN];
+        rc_crypto::rand::fill(&mut credential_id)?;
+        let passkey = Passkey {
+            id: Guid::random().to_string(),
+            rp_id: entry.rp_id,
+            credential_id: encode(&credential_id),
+            user_handle: entry.user_handle,
+            user_name: entry.user_name,
+            user_display_name: entry.user_display_name,
+            public_key: encode(key_pair.public_key()),
+            sign_count: 0,
+            time_created: util::system_time_ms_i64(SystemTime::now()),
+            time_last_used: 0,
+        };
+        self.insert_passkey(&passkey, &sec_fields, encdec)?;
+
+        let cose_public_key = cose_public_key(key_pair.public_key())?;
+        let mut attested_credential_data = AAGUID.to_vec();
+        attested_credential_data.extend_from_slice(&(CREDENTIAL_ID_LEN as u16).to_be_bytes());
+        attested_credential_data.extend_from_slice(&credential_id);
+        attested_credential_data.extend_from_slice(&cose_public_key);
+        let authenticator_data = authenticator_data(
+            &passkey.rp_id,
+            user_flags(user_verified),
+            0,
+            Some(&attested_credential_data),
+        )?;
+        // The keys are in the canonical order CTAP2 requires.
+        let mut attestation_object = cbor_header(CBOR_MAP, 3);
+        attestation_object.extend(cbor_text("fmt"));
+        attestation_object.extend(cbor_text("none"));
+        attestation_object.extend(cbor_text("attStmt"));
+        attestation_object.extend(cbor_header(CBOR_MAP, 0));
+        attestation_object.extend(cbor_text("authData"));
+        attestation_object.extend(cbor_bytes(&authenticator_data));
+        Ok(PasskeyRegistration {
+            passkey,
+            cose_public_key: encode(&cose_public_key),
+            authenticator_data: encode(&authenticator_data),
+            attestation_object: encode(&attestation_object),
+        })
+    }
+
+    /// Import a passkey created somewhere else, keeping its credential id and key.
+    pub fn import_passkey(
+        &self,
+        import: PasskeyImport,
+        encdec: &EncryptorDecryptor,
+    ) -> Result<Passkey> {
+        validate_entry(&import.entry)?;
+        let credential_id_len = decode(&import.credential_id, "credential_id")?.len();
+        if credential_id_len == 0 || credential_id_len > MAX_CREDENTIAL_ID_LEN {
+            return Err(invalid("credential_id must be between 1 and 1023 bytes"));
+        }
+        if import.sign_count < 0 || import.sign_count > i64::from(u32::MAX) {
+            return Err(invalid("sign_count is out of range"));
+        }
+        let public_key = decode(&import.public_key, "public_key")?;
+        cose_public_key(&public_key)?;
+        let key_pair = EcdsaKeyPair::import(
+            &ECDSA_P256_SHA256_FIXED_SIGNING,
+            &EcKey::new(
+                Curve::P256,
+                &decode(&import.private_key, "private_key")?,
+                &public_key,
+            ),
+        )
+        .map_err(|_| invalid("private_key isn't a P-256 private key"))?;
+        // Make sure the two halves of the key pair belong together.
+        let message = b"passkey import";
+        let signature = key_pair.sign(message)?;
+        UnparsedPublicKey::new(&ECDSA_P256_SHA256, &public_key)
+            .verify(message, &signature)
+            .map_err(|_| invalid("private_key doesn't match public_key"))?;
+
+        let tx = self.unchecked_transaction()?;
+        let exists: bool = self.query_row(
+            "SELECT EXISTS(
+                 SELECT 1 FROM loginsPasskeys
+                 WHERE rpId = :rp_id AND credentialId = :credential_id
+             )",
+            named_params! {
+                ":rp_id": import.entry.rp_id,
+                ":credential_id": import.credential_id,
+            },
+            |row| row.get(0),
+        )?;
+        if exists {
+            return Err(invalid("a passkey with this credential_id already exists"));
+        }
+        let passkey = Passkey {
+            id: Guid::random().to_string(),
+            rp_id: import.entry.rp_id,
+            credential_id: import.credential_id,
+            user_handle: import.entry.user_handle,
+            user_name: import.entry.user_name,
+            user_display_name: import.entry.user_display_name,
+            public_key: import.public_key,
+            sign_count: import.sign_count,
+            time_created: if import.time_created > 0 {
+                import.time_created
+            } else {
+                util::system_time_ms_i64(SystemTime::now())
+            },
+            time_last_used: 0,
+        };
+        let sec_fields = SecurePasskeyFields {
+            private_key: import.private_key,
+        };
+        self.insert_passkey(&passkey, &sec_fields, encdec)?;
+        tx.commit()?;
+        Ok(passkey)
+    }
+
+    pub fn get_passkey(&self, id: &str) -> Result<Option<Passkey>> {
+        Ok(self
+            .query_passkeys("WHERE guid = :guid", named_params! { ":guid": id })?
+            .pop())
+    }
+
+    /// Get all passkeys, most recently used first.
+    pub fn list_passkeys(&self) -> Result<Vec<Passkey>> {
+        self.query_passkeys("", &[])
+    }
+
+    /// Get the passkeys for a relying party, most recently used first.
+    pub fn get_passkeys_for_rp(&self, rp_id: &str) -> Result<Vec<Passkey>> {
+        self.query_passkeys("WHERE rpId = :rp_id", named_params! { ":rp_id": rp_id })
+    }
+
+    /// Update the user name and display name of a passkey - eg, after the relying party signals
+    /// that they've changed. Nothing else about a passkey can be changed.
+    pub fn update_passkey(
+        &self,
+        id: &str,
+        user_name: &str,
+        user_display_name: &str,
+    ) -> Result<Passkey> {
+        self.execute_cached(
+            "UPDATE loginsPasskeys
+             SET userName = :user_name, userDisplayName = :user_display_name
+             WHERE guid = :guid",
+            named_params! {
+                ":guid": id,
+                ":user_name": user_name,
+                ":user_display_name": user_display_name,
+            },
+        )?;
+        self.get_passkey(id)?
+            .ok_or_else(|| Error::NoSuchRecord(id.to_owned()))
+    }
+
+    /// Delete a passkey. Returns true if it existed.
+    pub fn delete_passkey(&self, id: &str) -> Result<bool> {
+        Ok(self.execute_cached(
+            "DELETE FROM loginsPasskeys WHERE guid = :guid",
+            named_params! { ":guid": id },
+        )? > 0)
+    }
+
+    /// Sign an assertion with a passkey, for the `clientDataHash` (the SHA-256 hash of the client
+    /// data JSON) the browser passes to the authenticator. This increments the passkey's
+    /// signature counter and updates when it was last used.
+    pub fn sign_passkey_assertion(
+        &self,
+        id: &str,
+        client_data_hash: &str,
+        user_verified: bool,
+        encdec: &EncryptorDecryptor,
+    ) -> Result<PasskeyAssertion> {
+        let client_data_hash = decode(client_data_hash, "client_data_hash")?;
+        if client_data_hash.len() != CLIENT_DATA_HASH_LEN {
+            return Err(invalid("client_data_hash must be a SHA-256 hash"));
+        }
+        let tx = self.unchecked_transaction()?;
+        let (passkey, sec_fields) = self
+            .try_query_row(
+                &format!(
+                    "SELECT {cols}, secFields FROM loginsPasskeys WHERE guid = :guid",
+                    cols = PASSKEY_COLS
+                ),
+                named_params! { ":guid": id },
+                |row| -> Result<(Passkey, String)> {
+                    Ok((Passkey::from_row(row)?, row.get("secFields")?))
+                },
+                true,
+            )?
+            .ok_or_else(|| Error::NoSuchRecord(id.to_owned()))?;
+        let sec_fields: SecurePasskeyFields =
+            encdec.decrypt_struct(&sec_fields, "decrypt passkey")?;
+        let key_pair = EcdsaKeyPair::import(
+            &ECDSA_P256_SHA256_FIXED_SIGNING,
+            &EcKey::new(
+                Curve::P256,
+                &decode(&sec_fields.private_key, "private key")?,
+                &decode(&passkey.public_key, "public_key")?,
+            ),
+        )?;
+
+        // The counter is 32 bits in the authenticator data. Relying parties treat a counter that
+        // goes backwards as a cloned authenticator, so it sticks at the maximum instead of
+        // wrapping.
+        let sign_count = u32::try_from(passkey.sign_count)
+            .ok()
+            .and_then(|count| count.checked_add(1))
+            .unwrap_or(u32::MAX);
+        let authenticator_data =
+            authenticator_data(&passkey.rp_id, user_flags(user_verified), sign_count, None)?;
+        let mut signed_data = authenticator_data.clone();
+        signed_data.extend_from_slice(&client_data_hash);
+        let signature = der_signature(&key_pair.sign(&signed_data)?);
+
+        self.execute_cached(
+            "UPDATE loginsPasskeys
+             SET signCount = :sign_count, timeLastUsed = :now_ms
+             WHERE guid = :guid",
+            named_params! {
+                ":guid": id,
+                ":sign_count": sign_count,
+                ":now_ms": util::system_time_ms_i64(SystemTime::now()),
+            },
+        )?;
+        tx.commit()?;
+        Ok(PasskeyAssertion {
+            credential_id: passkey.credential_id,
+            user_handle: passkey.user_handle,
+            authenticator_data: encode(&authenticator_data),
+            signature: encode(&signature),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::test_utils::TEST_ENCRYPTOR;
+
+    fn entry(rp_id: &str) -> PasskeyEntry {
+        PasskeyEntry {
+            rp_id: rp_id.into(),
+            user_handle: encode(b"user-1234"),
+            user_name: "alice@example.com".into(),
+            user_display_name: "Alice".into(),
+        }
+    }
+
+    /// Convert a DER encoded signature back to `r || s`, for verifying.
+    fn fixed_signature(der: &[u8]) -> Vec<u8> {
+        assert_eq!(der[0], 0x30);
+        assert_eq!(der[1] as usize, der.len() - 2);
+        let mut out = Vec::new();
+        let mut rest = &der[2..];
+        while !rest.is_empty() {
+            assert_eq!(rest[0], 0x02);
+            let len = rest[1] as usize;
+            let int = &rest[2..2 + len];
+            let int = &int[int.len().saturating_sub(32)..];
+            out.extend(std::iter::repeat(0).take(32 - int.len()));
+            out.extend_from_slice(int);
+            rest = &rest[2 + len..];
+        }
+        out
+    }
+
+    #[test]
+    fn test_der_signature() {
+        let mut fixed = vec![0u8; 64];
+        fixed[0] = 0x80;
+        fixed[63] = 0x01;
+        assert_eq!(
+            der_signature(&fixed),
+            [
+                &[0x30, 0x26, 0x02, 0x21, 0x00][..],
+                &fixed[..32],
+                &[0x02, 0x01, 0x01]
+            ]
+            .concat()
+        );
+        assert_eq!(fixed_signature(&der_signature(&fixed)), fixed);
+    }
+
+    #[test]
+    fn test_passkey_crud() {
+        let db = LoginDb::open_in_memory().unwrap();
+        let passkey = db
+            .add_passkey(entry("example.com"), false, &TEST_ENCRYPTOR)
+            .unwrap()
+            .passkey;
+        assert_eq!(passkey.rp_id, "example.com");
+        assert_eq!(decode(&passkey.credential_id, "").unwrap().len(), 16);
+        assert_eq!(decode(&passkey.public_key, "").unwrap().len(), 65);
+        assert_eq!(passkey.sign_count, 0);
+        let other = db
+            .add_passkey(entry("example.org"), false, &TEST_ENCRYPTOR)
+            .unwrap()
+            .passkey;
+        assert_ne!(passkey.credential_id, other.credential_id);
+
+        assert_eq!(db.get_passkey(&passkey.id).unwrap(), Some(passkey.clone()));
+        assert_eq!(db.list_passkeys().unwrap().len(), 2);
+        assert_eq!(
+            db.get_passkeys_for_rp("example.com").unwrap(),
+            vec![passkey.clone()]
+        );
+
+        let updated = db
+            .update_passkey(&passkey.id, "alice@example.net", "Alice B")
+            .unwrap();
+        assert_eq!(updated.user_name, "alice@example.net");
+        assert_eq!(updated.user_display_name, "Alice B");
+        assert_eq!(updated.public_key, passkey.public_key);
+        assert!(matches!(
+            db.update_passkey("not-a-guid", "", ""),
+            Err(Error::NoSuchRecord(_))
+        ));
+
+        assert!(db.delete_passkey(&passkey.id).unwrap());
+        assert!(!db.delete_passkey(&passkey.id).unwrap());
+        assert_eq!(db.get_passkey(&passkey.id).unwrap(), None);
+        assert_eq!(db.list_passkeys().unwrap(), vec![other]);
+    }
+
+    #[test]
+    fn test_invalid_passkeys() {
+        let db = LoginDb::open_in_memory().unwrap();
+        for entry in [
+            entry(""),
+            PasskeyEntry {
+                user_handle: "not base64!".into(),
+                ..entry("example.com")
+            },
+            PasskeyEntry {
+                user_handle: "".into(),
+                ..entry("example.com")
+            },
+            PasskeyEntry {
+                user_handle: encode(&[0; 65]),
+                ..entry("example.com")
+            },
+        ] {
+            assert!(matches!(
+                db.add_passkey(entry, false, &TEST_ENCRYPTOR),
+                Err(Error::InvalidPasskey(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_sign_passkey_assertion() {
+        let db = LoginDb::open_in_memory().unwrap();
+        let passkey = db
+            .add_passkey(entry("example.com"), false, &TEST_ENCRYPTOR)
+            .unwrap()
+            .passkey;
+        let client_data_hash = digest::digest(&digest::SHA256, b"{\"type\":\"webauthn.get\"}")
+            .unwrap()
+            .as_ref()
+            .to_vec();
+        let public_key = decode(&passkey.public_key, "").unwrap();
+        let public_key = UnparsedPublicKey::new(&ECDSA_P256_SHA256, &public_key);
+
+        for (expected_count, user_verified) in [(1u32, false), (2, true)] {
+            let assertion = db
+                .sign_passkey_assertion(
+                    &passkey.id,
+                    &encode(&client_data_hash),
+                    user_verified,
+                    &TEST_ENCRYPTOR,
+                )
+                .unwrap();
+            assert_eq!(assertion.credential_id, passkey.credential_id);
+            assert_eq!(assertion.user_handle, passkey.user_handle);
+
+            let authenticator_data = decode(&assertion.authenticator_data, "").unwrap();
+            assert_eq!(authenticator_data.len(), 37);
+            assert_eq!(
+                &authenticator_data[..32],
+                digest::digest(&digest::SHA256, b"example.com")
+                    .unwrap()
+                    .as_ref()
+            );
+            assert_eq!(
+                authenticator_data[32] & FLAG_USER_PRESENT,
+                FLAG_USER_PRESENT
+            );
+            assert_eq!(
+                authenticator_data[32] & FLAG_USER_VERIFIED != 0,
+                user_verified
+            );
+            assert_eq!(authenticator_data[33..], expected_count.to_be_bytes());
+
+            let signature = decode(&assertion.signature, "").unwrap();
+            let signed_data = [&authenticator_data[..], &client_data_hash[..]].concat();
+            assert!(public_key
+                .verify(&signed_data, &fixed_signature(&signature))
+                .is_ok());
+        }
+        let passkey = db.get_passkey(&passkey.id).unwrap().unwrap();
+        assert_eq!(passkey.sign_count, 2);
+        assert!(passkey.time_last_used > 0);
+
+        assert!(matches!(
+            db.sign_passkey_assertion(&passkey.id, &encode(b"short"), false, &TEST_ENCRYPTOR),
+            Err(Error::InvalidPasskey(_))
+        ));
+        assert!(matches!(
+            db.sign_passkey_assertion(
+                "not-a-guid",
+                &encode(&client_data_hash),
+                false,
+                &TEST_ENCRYPTOR
+            ),
+            Err(Error::NoSuchRecord(_))
+        ));
+    }
+
+    #[test]
+    fn test_registration() {
+        let db = LoginDb::open_in_memory().unwrap();
+        let registration = db
+            .add_passkey(entry("example.com"), true, &TEST_ENCRYPTOR)
+            .unwrap();
+        let passkey = &registration.passkey;
+        let credential_id = decode(&passkey.credential_id, "").unwrap();
+        let public_key = decode(&passkey.public_key, "").unwrap();
+
+        let cose_public_key = decode(&registration.cose_public_key, "").unwrap();
+        assert_eq!(
+            cose_public_key,
+            [
+                &[0xa5, 0x01, 0x02, 0x03, 0x26, 0x20, 0x01, 0x21, 0x58, 0x20][..],
+                &public_key[1..33],
+                &[0x22, 0x58, 0x20],
+                &public_key[33..],
+            ]
+            .concat()
+        );
+
+        let authenticator_data = decode(&registration.authenticator_data, "").unwrap();
+        assert_eq!(
+            authenticator_data,
+            [
+                digest::digest(&digest::SHA256, b"example.com")
+                    .unwrap()
+                    .as_ref(),
+                &[FLAG_USER_PRESENT | FLAG_USER_VERIFIED | FLAG_ATTESTED_CREDENTIAL_DATA],
+                &[0, 0, 0, 0],
+                &AAGUID,
+                &[0, CREDENTIAL_ID_LEN as u8],
+                &credential_id,
+                &cose_public_key,
+            ]
+            .concat()
+        );
+
+        // {"fmt": "none", "attStmt": {}, "authData": <authenticator data>}
+        let attestation_object = decode(&registration.attestation_object, "").unwrap();
+        assert_eq!(
+            attestation_object,
+            [
+                &b"\xa3\x63fmt\x64none\x67attStmt\xa0\x68authData\x58"[..],
+                &[authenticator_data.len() as u8],
+                &authenticator_data,
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn test_import_passkey() {
+        let db = LoginDb::open_in_memory().unwrap();
+        let key_pair = EcdsaKeyPair::generate(&ECDSA_P256_SHA256_FIXED_SIGNING)
+            .unwrap()
+            .export()
+            .unwrap();
+        let import = PasskeyImport {
+            entry: entry("example.com"),
+            credential_id: encode(&[7; 32]),
+            private_key: encode(key_pair.private_key()),
+            public_key: encode(key_pair.public_key()),
+            sign_count: 41,
+            time_created: 1000,
+        };
+        let passkey = db.import_passkey(import.clone(), &TEST_ENCRYPTOR).unwrap();
+        assert_eq!(passkey.credential_id, import.credential_id);
+        assert_eq!(passkey.public_key, import.public_key);
+        assert_eq!(passkey.sign_count, 41);
+        assert_eq!(passkey.time_created, 1000);
+        assert_eq!(db.get_passkey(&passkey.id).unwrap(), Some(passkey.clone()));
+
+        // The imported key signs assertions, and the signature counter carries on.
+        let client_data_hash = [1; CLIENT_DATA_HASH_LEN];
+        let assertion = db
+            .sign_passkey_assertion(
+                &passkey.id,
+                &encode(&client_data_hash),
+                false,
+                &TEST_ENCRYPTOR,
+            )
+            .unwrap();
+        let authenticator_data = decode(&assertion.authenticator_data, "").unwrap();
+        assert_eq!(authenticator_data[33..], 42u32.to_be_bytes());
+        let signed_data = [&authenticator_data[..], &client_data_hash[..]].concat();
+        assert!(
+            UnparsedPublicKey::new(&ECDSA_P256_SHA256, key_pair.public_key())
+                .verify(
+                    &signed_data,
+                    &fixed_signature(&decode(&assertion.signature, "").unwrap())
+                )
+                .is_ok()
+        );
+
+        // The same credential can't be imported twice.
+        assert!(matches!(
+            db.import_passkey(import.clone(), &TEST_ENCRYPTOR),
+            Err(Error::InvalidPasskey(_))
+        ));
+
+        let other_key_pair = EcdsaKeyPair::generate(&ECDSA_P256_SHA256_FIXED_SIGNING)
+            .unwrap()
+            .export()
+            .unwrap();
+        for import in [
+            PasskeyImport {
+                entry: entry(""),
+                ..import.clone()
+            },
+            PasskeyImport {
+                credential_id: "".into(),
+                ..import.clone()
+            },
+            PasskeyImport {
+                credential_id: encode(&[0; MAX_CREDENTIAL_ID_LEN + 1]),
+                ..import.clone()
+            },
+            PasskeyImport {
+                sign_count: -1,
+                ..import.clone()
+            },
+            PasskeyImport {
+                public_key: encode(&key_pair.public_key()[1..]),
+                ..import.clone()
+            },
+            PasskeyImport {
+                private_key: "not base64!".into(),
+                ..import.clone()
+            },
+            // The halves of the key pair don't match.
+            PasskeyImport {
+                credential_id: encode(&[8; 32]),
+                public_key: encode(other_key_pair.public_key()),
+                ..import.clone()
+            },
+        ] {
+            assert!(matches!(
+                db.import_passkey(import, &TEST_ENCRYPTOR),
+                Err(Error::InvalidPasskey(_))
+            ));
+        }
+        assert_eq!(
+            db.list_passkeys()
+                .unwrap()
+                .into_iter()
+                .map(|passkey| passkey.id)
+                .collect::<Vec<_>>(),
+            vec![passkey.id]
+        );
+    }
+
+    #[test]
+    fn test_sign_count_saturates() {
+        let db = LoginDb::open_in_memory().unwrap();
+        let key_pair = EcdsaKeyPair::generate(&ECDSA_P256_SHA256_FIXED_SIGNING)
+            .unwrap()
+            .export()
+            .unwrap();
+        let passkey = db
+            .import_passkey(
+                PasskeyImport {
+                    entry: entry("example.com"),
+                    credential_id: encode(&[7; 32]),
+                    private_key: encode(key_pair.private_key()),
+                    public_key: encode(key_pair.public_key()),
+                    sign_count: i64::from(u32::MAX),
+                    time_created: 0,
+                },
+                &TEST_ENCRYPTOR,
+            )
+            .unwrap();
+        let assertion = db
+            .sign_passkey_assertion(
+                &passkey.id,
+                &encode(&[1; CLIENT_DATA_HASH_LEN]),
+                false,
+                &TEST_ENCRYPTOR,
+            )
+            .unwrap();
+        let authenticator_data = decode(&assertion.authenticator_data, "").unwrap();
+        assert_eq!(authenticator_data[33..], u32::MAX.to_be_bytes());
+        assert_eq!(
+            db.get_passkey(&passkey.id).unwrap().unwrap().sign_count,
+            i64::from(u32::MAX)
+        );
+    }
+}
@@ -0,0 +1,418 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Password generation, following desktop's `PasswordGenerator.jsm`.
+//!
+//! By default we generate 15 character passwords containing at least one lower-case letter,
+//! one upper-case letter and one digit, avoiding characters which are easily confused (eg, "l",
+//! "1" and "I").
+//!
+//! Sites with unusual requirements can be described using Apple's password rules syntax
+//! (<https://developer.apple.com/password-rules/>), which is what the "password-rules"
+//! remote-settings collection uses. For example:
+//!
+//! ```text
+//! minlength: 8; maxlength: 16; required: lower, upper; required: digit; required: [-_!];
+//! max-consecutive: 2;
+//! ```
+//!
+//! Malformed or unknown parts of a rule are ignored, like on desktop.
+
+use crate::error::*;
+use crate::util;
+use std::collections::HashMap;
+use url::Host;
+
+const DEFAULT_LENGTH: usize = 15;
+// The longest password we'll generate, however long a site's `minlength` is.
+const MAX_LENGTH: usize = 128;
+// How many times we try to satisfy `max-consecutive` before giving up.
+const MAX_ATTEMPTS: usize = 100;
+
+const LOWER: &str = "abcdefghijkmnpqrstuvwxyz"; // no l or o
+const UPPER: &str = "ABCDEFGHJKLMNPQRSTUVWXYZ"; // no I or O
+const DIGITS: &str = "23456789"; // no 0 or 1
+const SPECIAL: &str = "-~!@#$%^&*_+=)}:;\"'>,.?]";
+
+/// The rules for a site, parsed from the Apple password rules syntax.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct PasswordRules {
+    /// Each `required` property; the password must contain a character from each set.
+    required: Vec<Vec<char>>,
+    /// The union of all `allowed` properties.
+    allowed: Vec<char>,
+    max_consecutive: Option<usize>,
+    min_length: Option<usize>,
+    max_length: Option<usize>,
+}
+
+fn named_class(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "lower" => LOWER,
+        "upper" => UPPER,
+        "digit" => DIGITS,
+        "special" => SPECIAL,
+        // We never generate non-ascii characters or spaces.
+        "ascii-printable" | "unicode" => {
+            "abcdefghijkmnpqrstuvwxyzABCDEFGHJKLMNPQRSTUVWXYZ23456789-~!@#$%^&*_+=)}:;\"'>,.?]"
+        }
+        _ => return None,
+    })
+}
+
+/// Parse a comma separated list of character classes into the set of characters they allow.
+/// Custom classes are written like `[-_!]`; a `]` or `-` is literal if it's the first
+/// character in the brackets.
+fn parse_classes(value: &str) -> Vec<char> {
+    let mut chars = Vec::new();
+    let mut rest = value.trim();
+    while !rest.is_empty() {
+        if let Some(custom) = rest.strip_prefix('[') {
+            // Skip the first character when looking for the end so `[]...]` works.
+            let end = custom
+                .char_indices()
+                .skip(1)
+                .find(|(_, c)| *c == ']')
+                .map(|(i, _)| i);
+            let (class, remaining) = match end {
+                Some(end) => (&custom[..end], &custom[end + 1..]),
+                None => (custom, ""),
+            };
+            chars.extend(
+                class
+                    .replace("&quot;", "\"")
+                    .chars()
+                    .filter(|c| c.is_ascii_graphic()),
+            );
+            rest = remaining;
+        } else {
+            let (name, remaining) = rest.split_once(',').unwrap_or((rest, ""));
+            if let Some(class) = named_class(&name.trim().to_lowercase()) {
+                chars.extend(class.chars());
+            }
+            rest = remaining;
+        }
+        rest = rest.trim_start().trim_start_matches(',').trim_start();
+    }
+    chars.sort_unstable();
+    chars.dedup();
+    chars
+}
+
+/// Split a rule into its `name: value` properties. Semicolons inside a custom class don't end
+/// the property.
+fn split_properties(rules: &str) -> Vec<&str> {
+    let mut properties = Vec::new();
+    let mut start = 0;
+    let mut in_class = false;
+    for (i, c) in rules.char_indices() {
+        match c {
+            '[' if !in_class => in_class = true,
+            ']' if in_class && !rules[..i].ends_with('[') => in_class = false,
+            ';' if !in_class => {
+                properties.push(&rules[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    properties.push(&rules[start..]);
+    properties
+}
+
+impl PasswordRules {
+    fn parse(rules: &str) -> Self {
+        let mut parsed = Self::default();
+        for property in split_properties(rules) {
+            let (name, value) = match property.split_once(':') {
+                Some((name, value)) => (name.trim().to_lowercase(), value.trim()),
+                None => continue,
+            };
+            match name.as_str() {
+                "required" => {
+                    let class = parse_classes(value);
+                    if !class.is_empty() {
+                        parsed.required.push(class);
+                    }
+                }
+                "allowed" => parsed.allowed.extend(parse_classes(value)),
+                "max-consecutive" => parsed.max_consecutive = value.parse().ok(),
+                "minlength" => parsed.min_length = value.parse().ok(),
+                // A zero maxlength would leave no room for a password, so we ignore it like any
+                // other invalid value, and use the default length.
+                "maxlength" => parsed.max_length = value.parse().ok().filter(|max| *max > 0),
+                _ => log::warn!("Ignoring unknown password rule property"),
+            }
+        }
+        parsed
+    }
+
+    fn length(&self) -> usize {
+        let mut length = DEFAULT_LENGTH;
+        if let Some(min) = self.min_length {
+            length = length.max(min);
+        }
+        if let Some(max) = self.max_length {
+            length = length.min(max);
+        }
+        length.min(MAX_LENGTH)
+    }
+}
+
+/// A uniformly distributed random number less than `n`.
+fn random_index(n: usize) -> Result<usize> {
+    let n = match u32::try_from(n) {
+        Ok(n) if n > 0 => n,
+        _ => {
+            return Err(Error::PasswordGenerationFailed(format!(
+                "can't pick from {} characters",
+                n
+            )))
+        }
+    };
+    // Reject values which would make the result biased towards small numbers.
+    let limit = u32::MAX - u32::MAX % n;
+    loop {
+        let mut bytes = [0u8; 4];
+        rc_crypto::rand::fill(&mut bytes)?;
+        let value = u32::from_le_bytes(bytes);
+        if value < limit {
+            return Ok((value % n) as usize);
+        }
+    }
+}
+
+fn pick(chars: &[char]) -> Result<char> {
+    Ok(chars[random_index(chars.len())?])
+}
+
+fn too_many_consecutive(password: &[char], max_consecutive: usize) -> bool {
+    password
+        .windows(max_consecutive + 1)
+        .any(|w| w.iter().all(|c| *c == w[0]))
+}
+
+fn generate_with_rules(rules: &PasswordRules) -> Result<String> {
+    let required = if rules.required.is_empty() && rules.allowed.is_empty() {
+        vec![
+            LOWER.chars().collect(),
+            UPPER.chars().collect(),
+            DIGITS.chars().collect(),
+        ]
+    } else {
+        rules.required.clone()
+    };
+    let mut pool = required
+        .iter()
+        .flatten()
+        .chain(rules.allowed.iter())
+        .copied()
+        .collect::<Vec<_>>();
+    pool.sort_unstable();
+    pool.dedup();
+
+    let length = rules.length();
+    if length < required.len() {
+        return Err(Error::PasswordGenerationFailed(format!(
+            "{} required classes don't fit in {} characters",
+            required.len(),
+            length
+        )));
+    }
+    for _ in 0..MAX_ATTEMPTS {
+        let mut password = required
+            .iter()
+            .map(|class| pick(class))
+            .collect::<Result<Vec<_>>>()?;
+        while password.len() < length {
+            password.push(pick(&pool)?);
+        }
+        // Fisher-Yates, so the required characters aren't always at the start.
+        for i in (1..password.len()).rev() {
+            password.swap(i, random_index(i + 1)?);
+        }
+        match rules.max_consecutive {
+            Some(max) if max > 0 && too_many_consecutive(&password, max) => continue,
+            _ => return Ok(password.into_iter().collect()),
+        }
+    }
+    Err(Error::PasswordGenerationFailed(
+        "couldn't satisfy max-consecutive".into(),
+    ))
+}
+
+/// Find the rules for an origin in a map of domain to rules. As with `get_by_base_domain()`, an
+/// entry applies to its domain and all of its subdomains; the most specific entry wins.
+fn rules_for_origin<'a>(origin: &str, rules: &'a HashMap<String, String>) -> Option<&'a str> {
+    rules
+        .iter()
+        .filter(|(domain, _)| {
+            Host::parse(domain)
+                .map(|host| util::origin_matches_base_host(origin, &host))
+                .unwrap_or(false)
+        })
+        .max_by_key(|(domain, _)| domain.len())
+        .map(|(_, rules)| rules.as_str())
+}
+
+/// Generate a password for `origin`, using the matching entry from `rules` (a map of domain to
+/// Apple password rules) if there is one.
+#[handle_error(Error)]
+pub fn generate_password(origin: &str, rules: HashMap<String, String>) -> ApiResult<String> {
+    let rules = rules_for_origin(origin, &rules)
+        .map(PasswordRules::parse)
+        .unwrap_or_default();
+    generate_with_rules(&rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(s: &str) -> Vec<char> {
+        let mut chars = s.chars().collect::<Vec<_>>();
+        chars.sort_unstable();
+        chars.dedup();
+        chars
+    }
+
+    #[test]
+    fn test_parse() {
+        let rules = PasswordRules::parse(
+            "minlength: 8; maxlength: 16; required: lower, upper; required: digit; \
+             required: [-;!&quot;]; allowed: [ab]; max-consecutive: 2; nonsense; foo: bar",
+        );
+        let mut lower_upper = chars(LOWER);
+        lower_upper.extend(UPPER.chars());
+        lower_upper.sort_unstable();
+        assert_eq!(
+            rules,
+            PasswordRules {
+                required: vec![lower_upper, chars(DIGITS), chars("-;!\"")],
+                allowed: chars("ab"),
+                max_consecutive: Some(2),
+                min_length: Some(8),
+                max_length: Some(16),
+            }
+        );
+        assert_eq!(rules.length(), 15);
+        assert_eq!(PasswordRules::parse("minlength: 20").length(), 20);
+        assert_eq!(PasswordRules::parse("maxlength: 10").length(), 10);
+        assert_eq!(PasswordRules::parse("").length(), DEFAULT_LENGTH);
+        assert_eq!(
+            PasswordRules::parse("maxlength: 0").length(),
+            DEFAULT_LENGTH
+        );
+        assert_eq!(
+            PasswordRules::parse("minlength: 4000000000").length(),
+            MAX_LENGTH
+        );
+    }
+
+    #[test]
+    fn test_parse_custom_class_brackets() {
+        assert_eq!(parse_classes("[]-]"), chars("]-"));
+        assert_eq!(parse_classes("[abc], digit"), {
+            let mut c = chars("abc");
+            c.extend(DIGITS.chars());
+            c.sort_unstable();
+            c
+        });
+        // unterminated custom classes take the rest of the value.
+        assert_eq!(parse_classes("[xyz"), chars("xyz"));
+        // non-ascii characters are dropped.
+        assert_eq!(parse_classes("[é!]"), chars("!"));
+    }
+
+    #[test]
+    fn test_generate_default() {
+        for _ in 0..20 {
+            let password = generate_password("https://example.com", HashMap::new()).unwrap();
+            assert_eq!(password.chars().count(), DEFAULT_LENGTH);
+            assert!(password.chars().any(|c| LOWER.contains(c)));
+            assert!(password.chars().any(|c| UPPER.contains(c)));
+            assert!(password.chars().any(|c| DIGITS.contains(c)));
+            assert!(password.chars().all(|c| c.is_ascii_alphanumeric()));
+        }
+    }
+
+    #[test]
+    fn test_generate_with_rules() {
+        let rules = PasswordRules::parse(
+            "minlength: 20; maxlength: 20; required: digit; required: [!]; allowed: [ab]; \
+             max-consecutive: 1",
+        );
+        for _ in 0..20 {
+            let password = generate_with_rules(&rules).unwrap();
+            let password_chars = password.chars().collect::<Vec<_>>();
+            assert_eq!(password_chars.len(), 20);
+            assert!(password.contains('!'));
+            assert!(password.chars().any(|c| DIGITS.contains(c)));
+            assert!(password
+                .chars()
+                .all(|c| DIGITS.contains(c) || "!ab".contains(c)));
+            assert!(!too_many_consecutive(&password_chars, 1));
+        }
+    }
+
+    #[test]
+    fn test_generate_impossible() {
+        assert!(matches!(
+            generate_with_rules(&PasswordRules::parse(
+                "maxlength: 1; required: lower; required: upper"
+            )),
+            Err(Error::PasswordGenerationFailed(_))
+        ));
+        assert!(matches!(
+            generate_with_rules(&PasswordRules::parse("required: [a]; max-consecutive: 2")),
+            Err(Error::PasswordGenerationFailed(_))
+        ));
+        assert!(matches!(
+            random_index(0),
+            Err(Error::PasswordGenerationFailed(_))
+        ));
+    }
+
+    #[test]
+    fn test_generate_zero_maxlength() {
+        let password =
+            generate_with_rules(&PasswordRules::parse("maxlength: 0; allowed: [ab]")).unwrap();
+        assert_eq!(password.len(), DEFAULT_LENGTH);
+        assert!(password.chars().all(|c| "ab".contains(c)));
+    }
+
+    #[test]
+    fn test_generate_huge_minlength() {
+        let password = generate_with_rules(&PasswordRules::parse(
+            "minlength: 4000000000; allowed: [ab]",
+        ))
+        .unwrap();
+        assert_eq!(password.len(), MAX_LENGTH);
+    }
+
+    #[test]
+    fn test_rules_for_origin() {
+        let rules = [
+            ("example.com", "minlength: 20"),
+            ("accounts.example.com", "maxlength: 10"),
+            ("example.org", "required: digit"),
+        ]
+        .iter()
+        .map(|(d, r)| (d.to_string(), r.to_string()))
+        .collect::<HashMap<_, _>>();
+        assert_eq!(
+            rules_for_origin("https://www.example.com", &rules),
+            Some("minlength: 20")
+        );
+        assert_eq!(
+            rules_for_origin("https://login.accounts.example.com:8443", &rules),
+            Some("maxlength: 10")
+        );
+        assert_eq!(rules_for_origin("https://example.net", &rules), None);
+        assert_eq!(rules_for_origin("not a url", &rules), None);
+
+        let password = generate_password("https://accounts.example.com", rules).unwrap();
+        assert_eq!(password.len(), 10);
+    }
+}
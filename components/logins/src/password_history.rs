@@ -0,0 +1,239 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Previous passwords of each login.
+//!
+//! Whenever `update()` or an incoming sync changes the password of a login, the old secure fields
+//! are kept in the `loginsPasswordHistory` table, still encrypted. The history is local-only and
+//! bounded to the most recent [MAX_PASSWORD_HISTORY] entries for each login.
+//!
+//! Restoring an old password is just an `update()` of the login, so it's recorded in the
+//! history (and staged for sync) like any other password change.
+
+use crate::db::LoginDb;
+use crate::encryption::EncryptorDecryptor;
+use crate::error::*;
+use crate::login::{EncryptedLogin, LoginEntry, SecureLoginFields};
+use rusqlite::{named_params, Connection, Row};
+use sql_support::ConnExt;
+
+/// How many previous passwords we keep for each login.
+const MAX_PASSWORD_HISTORY: u32 = 10;
+
+/// A previous password of a login.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PasswordHistoryEntry {
+    pub id: i64,
+    /// The id of the login this was a password of.
+    pub login_id: String,
+    /// The login's encrypted `SecureLoginFields` before the password was changed.
+    pub sec_fields: String,
+    /// When the password was replaced, in milliseconds since the unix epoch.
+    pub time_replaced: i64,
+}
+
+impl PasswordHistoryEntry {
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get("id")?,
+            login_id: row.get("guid")?,
+            sec_fields: row.get("secFields")?,
+            time_replaced: row.get("timeReplaced")?,
+        })
+    }
+}
+
+/// Remember the encrypted secure fields a login had before its password was changed, and forget
+/// the oldest entries for it if there are too many. Must be called in a transaction.
+pub(crate) fn record_password_history(
+    conn: &Connection,
+    guid: &str,
+    sec_fields: &str,
+    now_ms: i64,
+) -> Result<()> {
+    conn.execute_cached(
+        "INSERT INTO loginsPasswordHistory (guid, secFields, timeReplaced)
+         VALUES (:guid, :sec_fields, :now_ms)",
+        named_params! {
+            ":guid": guid,
+            ":sec_fields": sec_fields,
+            ":now_ms": now_ms,
+        },
+    )?;
+    conn.execute_cached(
+        "DELETE FROM loginsPasswordHistory
+         WHERE guid = :guid AND id NOT IN (
+             SELECT id FROM loginsPasswordHistory
+             WHERE guid = :guid
+             ORDER BY id DESC
+             LIMIT :max
+         )",
+        named_params! {
+            ":guid": guid,
+            ":max": MAX_PASSWORD_HISTORY,
+        },
+    )?;
+    Ok(())
+}
+
+impl LoginDb {
+    /// Get the previous passwords of a login, most recently replaced first.
+    pub fn get_password_history(&self, id: &str) -> Result<Vec<PasswordHistoryEntry>> {
+        let mut stmt = self.db.prepare_cached(
+            "SELECT id, guid, secFields, timeReplaced FROM loginsPasswordHistory
+             WHERE guid = :guid
+             ORDER BY id DESC",
+        )?;
+        let rows = stmt.query_and_then(named_params! { ":guid": id }, |row| {
+            PasswordHistoryEntry::from_row(row).map_err(Error::from)
+        })?;
+        rows.collect()
+    }
+
+    /// Make a previous password the current password of its login. The username and other
+    /// fields of the login are left alone, and the current password is added to the history.
+    pub fn restore_password(
+        &self,
+        id: &str,
+        history_id: i64,
+        encdec: &EncryptorDecryptor,
+    ) -> Result<EncryptedLogin> {
+        let tx = self.unchecked_transaction()?;
+        let entry = self
+            .try_query_row(
+                "SELECT id, guid, secFields, timeReplaced FROM loginsPasswordHistory
+                 WHERE id = :id AND guid = :guid",
+                named_params! { ":id": history_id, ":guid": id },
+                PasswordHistoryEntry::from_row,
+                true,
+            )?
+            .ok_or_else(|| Error::NoSuchRecord(format!("{}/{}", id, history_id)))?;
+        let login = self
+            .get_by_id(id)?
+            .ok_or_else(|| Error::NoSuchRecord(id.to_owned()))?;
+        let current = login.decrypt_fields(encdec)?;
+        let previous = SecureLoginFields::decrypt(&entry.sec_fields, encdec)?;
+        let result = self.update_in_transaction(
+            id,
+            LoginEntry {
+                fields: login.fields,
+                sec_fields: SecureLoginFields {
+                    password: previous.password,
+                    ..current
+                },
+            },
+            encdec,
+        )?;
+        self.execute_cached(
+            "DELETE FROM loginsPasswordHistory WHERE id = :id",
+            named_params! { ":id": history_id },
+        )?;
+        tx.commit()?;
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::test_utils::TEST_ENCRYPTOR;
+    use crate::login::LoginFields;
+    use crate::sync::SyncStatus;
+
+    fn entry(password: &str) -> LoginEntry {
+        LoginEntry {
+            fields: LoginFields {
+                origin: "https://www.example.com".into(),
+                http_realm: Some("realm".into()),
+                ..Default::default()
+            },
+            sec_fields: SecureLoginFields {
+                username: "user".into(),
+                password: password.into(),
+                otp_secret: None,
+            },
+        }
+    }
+
+    fn history_passwords(db: &LoginDb, id: &str) -> Vec<String> {
+        db.get_password_history(id)
+            .unwrap()
+            .into_iter()
+            .map(|e| {
+                SecureLoginFields::decrypt(&e.sec_fields, &TEST_ENCRYPTOR)
+                    .unwrap()
+                    .password
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_password_history() {
+        let db = LoginDb::open_in_memory().unwrap();
+        let id = db.add(entry("first"), &TEST_ENCRYPTOR).unwrap().record.id;
+        assert!(db.get_password_history(&id).unwrap().is_empty());
+
+        db.update(&id, entry("second"), &TEST_ENCRYPTOR).unwrap();
+        // Changing something other than the password doesn't add to the history.
+        let mut new_field = entry("second");
+        new_field.fields.username_field = "user".into();
+        db.update(&id, new_field, &TEST_ENCRYPTOR).unwrap();
+        // `add_or_update()` goes through `update()` too.
+        db.add_or_update(entry("third"), &TEST_ENCRYPTOR).unwrap();
+        assert_eq!(history_passwords(&db, &id), vec!["second", "first"]);
+
+        // The history is bounded.
+        for i in 0..MAX_PASSWORD_HISTORY {
+            db.update(&id, entry(&format!("password {}", i)), &TEST_ENCRYPTOR)
+                .unwrap();
+        }
+        let history = history_passwords(&db, &id);
+        assert_eq!(history.len(), MAX_PASSWORD_HISTORY as usize);
+        assert_eq!(history[0], format!("password {}", MAX_PASSWORD_HISTORY - 2));
+        assert_eq!(history.last().unwrap(), "third");
+
+        db.delete(&id).unwrap();
+        assert!(db.get_password_history(&id).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_restore_password() {
+        let db = LoginDb::open_in_memory().unwrap();
+        let id = db.add(entry("first"), &TEST_ENCRYPTOR).unwrap().record.id;
+        let mut second = entry("second");
+        second.sec_fields.username = "someone".into();
+        db.update(&id, second, &TEST_ENCRYPTOR).unwrap();
+        let history = db.get_password_history(&id).unwrap();
+        assert_eq!(history.len(), 1);
+
+        let restored = db
+            .restore_password(&id, history[0].id, &TEST_ENCRYPTOR)
+            .unwrap();
+        let fields = restored.decrypt_fields(&TEST_ENCRYPTOR).unwrap();
+        assert_eq!(fields.password, "first");
+        // The current username is kept.
+        assert_eq!(fields.username, "someone");
+        // The restored entry is replaced by the password it replaced.
+        assert_eq!(history_passwords(&db, &id), vec!["second"]);
+
+        // The restore is a local change which will be synced.
+        let status: u8 = db
+            .query_row(
+                "SELECT sync_status FROM loginsL WHERE guid = :guid",
+                named_params! { ":guid": id },
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(status, SyncStatus::New as u8);
+
+        assert!(matches!(
+            db.restore_password(&id, history[0].id, &TEST_ENCRYPTOR),
+            Err(Error::NoSuchRecord(_))
+        ));
+        assert!(matches!(
+            db.restore_password("not-a-guid", history[0].id, &TEST_ENCRYPTOR),
+            Err(Error::NoSuchRecord(_))
+        ));
+    }
+}
@@ -0,0 +1,320 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Recovering from logins which can't be decrypted.
+//!
+//! If some logins were written with a different key (or are otherwise corrupt), every caller
+//! which decrypts them fails. Rather than wiping everything, we can find just those records,
+//! delete them locally and reset the logins engine, so the next sync downloads the server's copy
+//! of them again.
+//!
+//! Only the side of a login which can't be decrypted is deleted. If just the mirror is bad, the
+//! local record - which may have changes we haven't uploaded - is kept, and if just the local
+//! record is bad, we fall back to the mirror.
+//!
+//! Passkeys aren't synced, so there's nothing to restore an undecryptable passkey from. They're
+//! deleted too, since they can never be used again, and are gone for good.
+
+use crate::db::LoginDb;
+use crate::encryption::EncryptorDecryptor;
+use crate::error::*;
+use crate::login::SecureLoginFields;
+use crate::passkeys::SecurePasskeyFields;
+use rusqlite::named_params;
+use sql_support::ConnExt;
+use std::collections::BTreeSet;
+
+impl LoginDb {
+    /// Get the guid of every local or mirror record which can't be decrypted with `encdec`, and
+    /// whether it's the local record.
+    fn get_undecryptable_records(
+        &self,
+        encdec: &EncryptorDecryptor,
+    ) -> Result<Vec<(String, bool)>> {
+        let rows = self.query_rows_and_then(
+            "SELECT guid, secFields, 1 FROM loginsL WHERE is_deleted = 0
+             UNION ALL
+             SELECT guid, secFields, 0 FROM loginsM",
+            [],
+            |row| -> Result<(String, String, bool)> { Ok((row.get(0)?, row.get(1)?, row.get(2)?)) },
+        )?;
+        Ok(rows
+            .into_iter()
+            .filter(|(_, sec_fields, _)| SecureLoginFields::decrypt(sec_fields, encdec).is_err())
+            .map(|(guid, _, is_local)| (guid, is_local))
+            .collect())
+    }
+
+    /// Get the ids of passkeys whose private key can't be decrypted with `encdec`.
+    fn get_undecryptable_passkeys(&self, encdec: &EncryptorDecryptor) -> Result<Vec<String>> {
+        let rows = self.query_rows_and_then(
+            "SELECT guid, secFields FROM loginsPasskeys ORDER BY guid",
+            [],
+            |row| -> Result<(String, String)> { Ok((row.get(0)?, row.get(1)?)) },
+        )?;
+        Ok(rows
+            .into_iter()
+            .filter(|(_, sec_fields)| {
+                encdec
+                    .decrypt_struct::<SecurePasskeyFields>(sec_fields, "decrypt passkey")
+                    .is_err()
+            })
+            .map(|(guid, _)| guid)
+            .collect())
+    }
+
+    /// Get the ids of logins whose local or mirror record can't be decrypted with `encdec`,
+    /// followed by the ids of passkeys which can't be.
+    pub fn scan_for_undecryptable(&self, encdec: &EncryptorDecryptor) -> Result<Vec<String>> {
+        let mut ids = self
+            .get_undecryptable_records(encdec)?
+            .into_iter()
+            .map(|(guid, _)| guid)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        ids.extend(self.get_undecryptable_passkeys(encdec)?);
+        Ok(ids)
+    }
+
+    /// Delete the passkeys which can't be decrypted with `encdec`, and return their ids.
+    pub fn delete_undecryptable_passkeys(
+        &self,
+        encdec: &EncryptorDecryptor,
+    ) -> Result<Vec<String>> {
+        let ids = self.get_undecryptable_passkeys(encdec)?;
+        for id in &ids {
+            self.execute_cached(
+                "DELETE FROM loginsPasskeys WHERE guid = :guid",
+                named_params! { ":guid": id },
+            )?;
+        }
+        Ok(ids)
+    }
+
+    /// Delete the local and mirror records which can't be decrypted with `encdec`, without
+    /// writing tombstones. Returns the ids of the affected logins. Logins which are left with
+    /// neither record have the rest of their data deleted too.
+    ///
+    /// This must be called in a transaction. It doesn't reset sync, so on its own the server
+    /// copies won't be downloaded again - see `LoginStore::delete_undecryptable_and_resync()`,
+    /// which does both in the same transaction.
+    pub fn delete_undecryptable(&self, encdec: &EncryptorDecryptor) -> Result<Vec<String>> {
+        let records = self.get_undecryptable_records(encdec)?;
+        for (guid, is_local) in &records {
+            if *is_local {
+                self.execute_cached(
+                    "DELETE FROM loginsL WHERE guid = :guid",
+                    named_params! { ":guid": guid },
+                )?;
+                // Fall back to the mirror, if there is one.
+                self.execute_cached(
+                    "UPDATE loginsM SET is_overridden = 0 WHERE guid = :guid",
+                    named_params! { ":guid": guid },
+                )?;
+            } else {
+                self.execute_cached(
+                    "DELETE FROM loginsM WHERE guid = :guid",
+                    named_params! { ":guid": guid },
+                )?;
+            }
+        }
+        let ids = records
+            .into_iter()
+            .map(|(guid, _)| guid)
+            .collect::<BTreeSet<_>>();
+        for id in &ids {
+            if self.exists(id)? {
+                continue;
+            }
+            for sql in [
+                "DELETE FROM loginsBreachAlertDismissals WHERE guid = :guid",
+                "DELETE FROM loginsPasswordHistory WHERE guid = :guid",
+            ] {
+                self.execute_cached(sql, named_params! { ":guid": id })?;
+            }
+        }
+        Ok(ids.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_utils::{get_local_guids, get_mirror_guids, insert_login};
+    use crate::encryption::test_utils::{TEST_ENCRYPTION_KEY, TEST_ENCRYPTOR};
+    use crate::passkeys::PasskeyEntry;
+    use crate::schema;
+    use crate::store::LoginStore;
+    use std::sync::Arc;
+
+    // Insert a login with a mirror record, and a local record if `local` is Some, like
+    // `insert_login()`. Then make the local record undecryptable with the test key if `local` is
+    // Some(true), and the mirror record if `mirror` is true.
+    fn insert_undecryptable(db: &LoginDb, guid: &str, local: Option<bool>, mirror: bool) {
+        insert_login(db, guid, local.map(|_| "local"), Some("mirror"));
+        let other = EncryptorDecryptor::new_with_random_key().unwrap();
+        let sec_fields = SecureLoginFields {
+            username: "user".into(),
+            password: "password".into(),
+            otp_secret: None,
+        }
+        .encrypt(&other)
+        .unwrap();
+        if local == Some(true) {
+            db.execute(
+                "UPDATE loginsL SET secFields = :sec_fields WHERE guid = :guid",
+                named_params! { ":sec_fields": sec_fields, ":guid": guid },
+            )
+            .unwrap();
+        }
+        if mirror {
+            db.execute(
+                "UPDATE loginsM SET secFields = :sec_fields WHERE guid = :guid",
+                named_params! { ":sec_fields": sec_fields, ":guid": guid },
+            )
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_scan_and_delete() {
+        let db = LoginDb::open_in_memory().unwrap();
+        insert_login(&db, "good", Some("local"), Some("mirror"));
+        insert_login(&db, "local-only", Some("local"), None);
+        insert_undecryptable(&db, "bad-local", Some(true), false);
+        insert_undecryptable(&db, "bad-mirror", Some(false), true);
+        insert_undecryptable(&db, "bad-both", Some(true), true);
+        insert_undecryptable(&db, "bad-mirror-only", None, true);
+        for guid in ["bad-both", "bad-mirror"] {
+            db.execute(
+                "INSERT INTO loginsPasswordHistory (guid, secFields, timeReplaced)
+                 VALUES (:guid, '', 0)",
+                named_params! { ":guid": guid },
+            )
+            .unwrap();
+        }
+        let bad = vec!["bad-both", "bad-local", "bad-mirror", "bad-mirror-only"];
+
+        assert_eq!(db.scan_for_undecryptable(&TEST_ENCRYPTOR).unwrap(), bad);
+        let tx = db.unchecked_transaction().unwrap();
+        assert_eq!(db.delete_undecryptable(&TEST_ENCRYPTOR).unwrap(), bad);
+        tx.commit().unwrap();
+        assert!(db
+            .scan_for_undecryptable(&TEST_ENCRYPTOR)
+            .unwrap()
+            .is_empty());
+        // Only the undecryptable side of each login was deleted, and no tombstones were written.
+        assert_eq!(
+            get_local_guids(&db),
+            vec!["bad-mirror", "good", "local-only"]
+        );
+        assert_eq!(get_mirror_guids(&db), vec!["bad-local", "good"]);
+        // We fall back to the mirror when the local record is bad, and keep the local record
+        // when the mirror is bad.
+        assert!(db.get_by_id("bad-local").unwrap().is_some());
+        assert!(db.get_by_id("bad-mirror").unwrap().is_some());
+        // History is only deleted for logins which are gone completely.
+        let history: Vec<String> = db
+            .query_rows_and_then("SELECT guid FROM loginsPasswordHistory", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(history, vec!["bad-mirror"]);
+        assert!(db.delete_undecryptable(&TEST_ENCRYPTOR).unwrap().is_empty());
+    }
+
+    // Add a passkey whose private key can't be decrypted with the test key.
+    fn add_undecryptable_passkey(db: &LoginDb) -> String {
+        let other = EncryptorDecryptor::new_with_random_key().unwrap();
+        let entry = PasskeyEntry {
+            rp_id: "example.com".into(),
+            user_handle: "dXNlcg".into(),
+            ..Default::default()
+        };
+        db.add_passkey(entry, false, &other).unwrap().passkey.id
+    }
+
+    #[test]
+    fn test_scan_and_delete_passkeys() {
+        let db = LoginDb::open_in_memory().unwrap();
+        let entry = PasskeyEntry {
+            rp_id: "example.com".into(),
+            user_handle: "dXNlcg".into(),
+            ..Default::default()
+        };
+        let good = db
+            .add_passkey(entry, false, &TEST_ENCRYPTOR)
+            .unwrap()
+            .passkey
+            .id;
+        let bad = add_undecryptable_passkey(&db);
+        insert_undecryptable(&db, "bad-login", None, true);
+
+        assert_eq!(
+            db.scan_for_undecryptable(&TEST_ENCRYPTOR).unwrap(),
+            vec!["bad-login".to_string(), bad.clone()]
+        );
+        // Deleting undecryptable logins leaves passkeys alone.
+        assert_eq!(
+            db.delete_undecryptable(&TEST_ENCRYPTOR).unwrap(),
+            vec!["bad-login"]
+        );
+        assert_eq!(
+            db.delete_undecryptable_passkeys(&TEST_ENCRYPTOR).unwrap(),
+            vec![bad.clone()]
+        );
+        assert!(db
+            .scan_for_undecryptable(&TEST_ENCRYPTOR)
+            .unwrap()
+            .is_empty());
+        assert!(db.get_passkey(&bad).unwrap().is_none());
+        assert!(db.get_passkey(&good).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_delete_undecryptable_and_resync() {
+        let store = Arc::new(LoginStore::new_in_memory().unwrap());
+        let passkey;
+        {
+            let db = store.db.lock();
+            insert_login(&db, "good", None, Some("mirror"));
+            insert_undecryptable(&db, "bad", None, true);
+            insert_undecryptable(&db, "bad-mirror", Some(false), true);
+            passkey = add_undecryptable_passkey(&db);
+            db.put_meta(schema::LAST_SYNC_META_KEY, &12345i64).unwrap();
+            db.put_meta(schema::GLOBAL_SYNCID_META_KEY, &"global")
+                .unwrap();
+            db.put_meta(schema::COLLECTION_SYNCID_META_KEY, &"coll")
+                .unwrap();
+        }
+        let bad = vec!["bad".to_string(), "bad-mirror".to_string(), passkey.clone()];
+        assert_eq!(
+            store.scan_for_undecryptable(&TEST_ENCRYPTION_KEY).unwrap(),
+            bad
+        );
+        assert_eq!(
+            Arc::clone(&store)
+                .delete_undecryptable_and_resync(&TEST_ENCRYPTION_KEY)
+                .unwrap(),
+            bad
+        );
+        let db = store.db.lock();
+        assert!(db.get_passkey(&passkey).unwrap().is_none());
+        assert!(db.get_by_id("bad").unwrap().is_none());
+        assert!(db.get_by_id("good").unwrap().is_some());
+        // The local record had changes which weren't uploaded, so it's kept.
+        assert!(db.get_by_id("bad-mirror").unwrap().is_some());
+        // Everything will be downloaded again, but we're still using the same sync ids.
+        assert_eq!(
+            db.get_meta::<i64>(schema::LAST_SYNC_META_KEY).unwrap(),
+            Some(0)
+        );
+        assert_eq!(
+            db.get_meta::<String>(schema::COLLECTION_SYNCID_META_KEY)
+                .unwrap(),
+            Some("coll".to_string())
+        );
+    }
+}
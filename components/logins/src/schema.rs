@@ -2,7 +2,7 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-//! Logins Schema v4
+//! Logins Schema v6
 //! ================
 //!
 //! The schema we use is a evolution of the firefox-ios logins database format.
@@ -12,6 +12,14 @@
 //! - `loginsM`: The mirror table.
 //! - `loginsSyncMeta`: The table used to to store various sync metadata.
 //!
+//! There are also a couple of tables for local-only data which is never synced:
+//!
+//! - `loginsBreachAlertDismissals`: When the user last dismissed a breach alert for a login.
+//! - `loginsVulnerablePasswords`: Passwords known to have been exposed in a breach.
+//! - `loginsPasswordHistory`: Previous passwords of each login.
+//! - `loginsQuarantine`: Encrypted data which couldn't be decrypted when the key was rotated.
+//! - `loginsPasskeys`: Passkeys (WebAuthn credentials).
+//!
 //! ## `loginsL`
 //!
 //! This stores local login information, also known as the "overlay".
@@ -78,7 +86,7 @@
 //! This table was added (by this rust crate) in version 4, and so is not
 //! present in firefox-ios.
 //!
-//! Currently it is used to store two items:
+//! Currently it is used to store these items:
 //!
 //! 1. The last sync timestamp is stored under [LAST_SYNC_META_KEY], a
 //!    `sync15::ServerTimestamp` stored in integer milliseconds.
@@ -87,6 +95,47 @@
 //!    [GLOBAL_STATE_META_KEY]. This is a `sync15::GlobalState` stored as
 //!    JSON.
 //!
+//! 3. Whether `otpSecret`s in the mirror's unknown fields have been moved
+//!    into `secFields` is stored under [OTP_SECRETS_MIGRATED_META_KEY].
+//!
+//! ## `loginsBreachAlertDismissals`
+//!
+//! Added in version 3. Maps a login `guid` to `timeDismissed`, the millisecond timestamp at
+//! which the user last dismissed a breach alert for that login. Breaches added to the dataset
+//! after that time will alert again. Rows are removed when the login is deleted.
+//!
+//! ## `loginsVulnerablePasswords`
+//!
+//! Added in version 3. A set of passwords which were used by a login affected by a breach. Like
+//! desktop, this lets us warn about other logins which reuse one of those passwords, even after
+//! the breached login itself has been changed. `hash` is a hex-encoded HMAC-SHA256 of the
+//! password, keyed with a key derived from the encryption key, which is used to look passwords
+//! up. `encPassword` is the password encrypted like `secFields`, so that the set can be re-keyed
+//! when the encryption key is rotated.
+//!
+//! ## `loginsPasswordHistory`
+//!
+//! Added in version 4. Each row holds the `secFields` a login (identified by `guid`) had before
+//! its password was changed, locally or by an incoming sync, and `timeReplaced`, the millisecond
+//! timestamp of that change. `secFields` is encrypted in exactly the same way as in `loginsL`.
+//! We only keep the most recent few entries for each login, and rows are removed when the login
+//! is deleted.
+//!
+//! ## `loginsQuarantine`
+//!
+//! Added in version 5. When `rotate_key()` is asked to quarantine data it couldn't decrypt with
+//! the old key, each undecryptable value is moved here, so it isn't lost if the old key turns up
+//! again. `source` is the table (or column) it came from, `guid` is the login it belonged to,
+//! `ciphertext` is the value itself and `timeQuarantined` is a millisecond timestamp.
+//!
+//! ## `loginsPasskeys`
+//!
+//! Added in version 6. Each row is a passkey, identified by `guid`, for the relying party
+//! `rpId`. `credentialId`, `userHandle` and `publicKey` (an uncompressed P-256 point) are
+//! base64url encoded. `secFields` holds the private key, encrypted in the same way as the
+//! `secFields` of logins. `signCount` is the WebAuthn signature counter, and `timeCreated` and
+//! `timeLastUsed` are millisecond timestamps. Passkeys aren't synced yet.
+//!
 
 use crate::error::*;
 use lazy_static::lazy_static;
@@ -95,7 +144,11 @@ use sql_support::ConnExt;
 
 /// Version 1: SQLCipher -> plaintext migration.
 /// Version 2: addition of `loginsM.enc_unknown_fields`.
-pub(super) const VERSION: i64 = 2;
+/// Version 3: addition of `loginsBreachAlertDismissals` and `loginsVulnerablePasswords`.
+/// Version 4: addition of `loginsPasswordHistory`.
+/// Version 5: addition of `loginsQuarantine`.
+/// Version 6: addition of `loginsPasskeys`.
+pub(super) const VERSION: i64 = 6;
 
 /// Every column shared by both tables except for `id`
 ///
@@ -180,6 +233,70 @@ const CREATE_META_TABLE_SQL: &str = "
     )
 ";
 
+const CREATE_BREACH_ALERT_DISMISSALS_TABLE_SQL: &str = "
+    CREATE TABLE IF NOT EXISTS loginsBreachAlertDismissals (
+        guid          TEXT PRIMARY KEY,
+        -- Milliseconds
+        timeDismissed INTEGER NOT NULL
+    )
+";
+
+const CREATE_VULNERABLE_PASSWORDS_TABLE_SQL: &str = "
+    CREATE TABLE IF NOT EXISTS loginsVulnerablePasswords (
+        hash TEXT PRIMARY KEY,
+        encPassword TEXT NOT NULL
+    )
+";
+
+const CREATE_PASSWORD_HISTORY_TABLE_SQL: &str = "
+    CREATE TABLE IF NOT EXISTS loginsPasswordHistory (
+        id           INTEGER PRIMARY KEY AUTOINCREMENT,
+        guid         TEXT NOT NULL,
+        secFields    TEXT NOT NULL,
+        -- Milliseconds
+        timeReplaced INTEGER NOT NULL
+    )
+";
+
+const CREATE_PASSWORD_HISTORY_GUID_INDEX_SQL: &str = "
+    CREATE INDEX IF NOT EXISTS idx_loginsPasswordHistory_guid
+    ON loginsPasswordHistory (guid)
+";
+
+const CREATE_QUARANTINE_TABLE_SQL: &str = "
+    CREATE TABLE IF NOT EXISTS loginsQuarantine (
+        id              INTEGER PRIMARY KEY AUTOINCREMENT,
+        guid            TEXT NOT NULL,
+        source          TEXT NOT NULL,
+        ciphertext      TEXT NOT NULL,
+        -- Milliseconds
+        timeQuarantined INTEGER NOT NULL
+    )
+";
+
+const CREATE_PASSKEYS_TABLE_SQL: &str = "
+    CREATE TABLE IF NOT EXISTS loginsPasskeys (
+        id              INTEGER PRIMARY KEY AUTOINCREMENT,
+        guid            TEXT NOT NULL UNIQUE,
+        rpId            TEXT NOT NULL,
+        credentialId    TEXT NOT NULL,
+        userHandle      TEXT NOT NULL,
+        userName        TEXT NOT NULL DEFAULT '',
+        userDisplayName TEXT NOT NULL DEFAULT '',
+        publicKey       TEXT NOT NULL,
+        secFields       TEXT NOT NULL,
+        signCount       INTEGER NOT NULL DEFAULT 0,
+        -- Milliseconds
+        timeCreated     INTEGER NOT NULL,
+        timeLastUsed    INTEGER NOT NULL DEFAULT 0
+    )
+";
+
+const CREATE_PASSKEYS_RP_ID_INDEX_SQL: &str = "
+    CREATE INDEX IF NOT EXISTS idx_loginsPasskeys_rpId
+    ON loginsPasskeys (rpId)
+";
+
 const CREATE_OVERRIDE_ORIGIN_INDEX_SQL: &str = "
     CREATE INDEX IF NOT EXISTS idx_loginsM_is_overridden_origin
     ON loginsM (is_overridden, origin)
@@ -194,6 +311,7 @@ pub(crate) static LAST_SYNC_META_KEY: &str = "last_sync_time";
 pub(crate) static GLOBAL_STATE_META_KEY: &str = "global_state_v2";
 pub(crate) static GLOBAL_SYNCID_META_KEY: &str = "global_sync_id";
 pub(crate) static COLLECTION_SYNCID_META_KEY: &str = "passwords_sync_id";
+pub(crate) static OTP_SECRETS_MIGRATED_META_KEY: &str = "otp_secrets_migrated";
 
 pub(crate) fn init(db: &Connection) -> Result<()> {
     let user_version = db.query_one::<i64>("PRAGMA user_version")?;
@@ -218,7 +336,7 @@ pub(crate) fn init(db: &Connection) -> Result<()> {
 
 // Allow the redundant Ok() here.  It will make more sense once we have an actual upgrade function.
 #[allow(clippy::unnecessary_wraps)]
-fn upgrade(db: &Connection, from: i64) -> Result<()> {
+fn upgrade(db: &Connection, mut from: i64) -> Result<()> {
     log::debug!("Upgrading schema from {} to {}", from, VERSION);
     if from == VERSION {
         return Ok(());
@@ -232,10 +350,32 @@ fn upgrade(db: &Connection, from: i64) -> Result<()> {
     if from == 1 {
         // Just one new nullable column makes this fairly easy
         db.execute_batch("ALTER TABLE loginsM ADD enc_unknown_fields TEXT;")?;
+        from = 2;
+    }
+    if from == 2 {
+        db.execute_all(&[
+            CREATE_BREACH_ALERT_DISMISSALS_TABLE_SQL,
+            CREATE_VULNERABLE_PASSWORDS_TABLE_SQL,
+        ])?;
+        from = 3;
+    }
+    if from == 3 {
+        db.execute_all(&[
+            CREATE_PASSWORD_HISTORY_TABLE_SQL,
+            CREATE_PASSWORD_HISTORY_GUID_INDEX_SQL,
+        ])?;
+        from = 4;
+    }
+    if from == 4 {
+        db.execute_batch(CREATE_QUARANTINE_TABLE_SQL)?;
+        from = 5;
+    }
+    if from == 5 {
+        db.execute_all(&[CREATE_PASSKEYS_TABLE_SQL, CREATE_PASSKEYS_RP_ID_INDEX_SQL])?;
     }
     // XXX - next migration, be sure to:
-    // from = 2;
-    // if from == 2 ...
+    // from = 6;
+    // if from == 6 ...
     db.execute_batch(&SET_VERSION_SQL)?;
     Ok(())
 }
@@ -248,6 +388,13 @@ pub(crate) fn create(db: &Connection) -> Result<()> {
         CREATE_OVERRIDE_ORIGIN_INDEX_SQL,
         CREATE_DELETED_ORIGIN_INDEX_SQL,
         CREATE_META_TABLE_SQL,
+        CREATE_BREACH_ALERT_DISMISSALS_TABLE_SQL,
+        CREATE_VULNERABLE_PASSWORDS_TABLE_SQL,
+        CREATE_PASSWORD_HISTORY_TABLE_SQL,
+        CREATE_PASSWORD_HISTORY_GUID_INDEX_SQL,
+        CREATE_QUARANTINE_TABLE_SQL,
+        CREATE_PASSKEYS_TABLE_SQL,
+        CREATE_PASSKEYS_RP_ID_INDEX_SQL,
         &*SET_VERSION_SQL,
     ])?;
     Ok(())
@@ -313,4 +460,101 @@ mod tests {
         db.execute_batch("SELECT enc_unknown_fields FROM loginsM")
             .unwrap();
     }
+
+    #[test]
+    fn test_upgrade_v2() {
+        let connection = Connection::open_in_memory().unwrap();
+        create(&connection).unwrap();
+        // Drop the tables added since v2 and pretend to be v2.
+        connection
+            .execute_batch(
+                "DROP TABLE loginsBreachAlertDismissals;
+                 DROP TABLE loginsVulnerablePasswords;
+                 DROP TABLE loginsPasswordHistory;
+                 DROP TABLE loginsQuarantine;
+                 DROP TABLE loginsPasskeys;
+                 PRAGMA user_version = 2;",
+            )
+            .unwrap();
+
+        let db = LoginDb::with_connection(connection).unwrap();
+        let version = db.query_one::<i64>("PRAGMA user_version").unwrap();
+        assert_eq!(version, VERSION);
+
+        db.execute_batch(
+            "SELECT guid, timeDismissed FROM loginsBreachAlertDismissals;
+             SELECT hash, encPassword FROM loginsVulnerablePasswords;
+             SELECT guid, secFields, timeReplaced FROM loginsPasswordHistory;
+             SELECT guid, source, ciphertext, timeQuarantined FROM loginsQuarantine;
+             SELECT guid, rpId, secFields, signCount FROM loginsPasskeys;",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_upgrade_v3() {
+        let connection = Connection::open_in_memory().unwrap();
+        create(&connection).unwrap();
+        connection
+            .execute_batch(
+                "DROP TABLE loginsPasswordHistory;
+                 DROP TABLE loginsQuarantine;
+                 DROP TABLE loginsPasskeys;
+                 PRAGMA user_version = 3;",
+            )
+            .unwrap();
+
+        let db = LoginDb::with_connection(connection).unwrap();
+        let version = db.query_one::<i64>("PRAGMA user_version").unwrap();
+        assert_eq!(version, VERSION);
+
+        db.execute_batch(
+            "SELECT guid, secFields, timeReplaced FROM loginsPasswordHistory;
+             SELECT guid, source, ciphertext, timeQuarantined FROM loginsQuarantine;
+             SELECT guid, rpId, secFields, signCount FROM loginsPasskeys;",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_upgrade_v4() {
+        let connection = Connection::open_in_memory().unwrap();
+        create(&connection).unwrap();
+        connection
+            .execute_batch(
+                "DROP TABLE loginsQuarantine;
+                 DROP TABLE loginsPasskeys;
+                 PRAGMA user_version = 4;",
+            )
+            .unwrap();
+
+        let db = LoginDb::with_connection(connection).unwrap();
+        let version = db.query_one::<i64>("PRAGMA user_version").unwrap();
+        assert_eq!(version, VERSION);
+
+        db.execute_batch(
+            "SELECT guid, source, ciphertext, timeQuarantined FROM loginsQuarantine;
+             SELECT guid, rpId, secFields, signCount FROM loginsPasskeys;",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_upgrade_v5() {
+        let connection = Connection::open_in_memory().unwrap();
+        create(&connection).unwrap();
+        connection
+            .execute_batch(
+                "DROP TABLE loginsPasskeys;
+                 PRAGMA user_version = 5;",
+            )
+            .unwrap();
+
+        let db = LoginDb::with_connection(connection).unwrap();
+        let version = db.query_one::<i64>("PRAGMA user_version").unwrap();
+        assert_eq!(version, VERSION);
+
+        db.execute_batch("SELECT guid, rpId, secFields, signCount FROM loginsPasskeys")
+            .unwrap();
+    }
 }
@@ -1,10 +1,19 @@
 /* This Source Code Form is subject to the terms of the Mozilla Public
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use crate::audit::LoginsAuditReport;
+use crate::breach::{Breach, BreachAlert};
+use crate::csv::CsvImportRow;
 use crate::db::LoginDb;
 use crate::encryption::EncryptorDecryptor;
 use crate::error::*;
-use crate::login::{EncryptedLogin, Login, LoginEntry};
+use crate::key_rotation::KeyRotationResult;
+use crate::login::{EncryptedLogin, Login, LoginEntry, SecureLoginFields};
+use crate::matching::{LoginMatch, LoginMatchOptions};
+use crate::passkeys::{
+    Passkey, PasskeyAssertion, PasskeyEntry, PasskeyImport, PasskeyRegistration,
+};
+use crate::password_history::PasswordHistoryEntry;
 use crate::LoginsSyncEngine;
 use parking_lot::Mutex;
 use std::path::Path;
@@ -150,6 +159,197 @@ impl LoginStore {
         self.db.lock().add_or_update(entry, &encdec)
     }
 
+    #[handle_error(Error)]
+    pub fn find_matching_logins(
+        &self,
+        origin: &str,
+        form_action_origin: Option<String>,
+        http_realm: Option<String>,
+        options: LoginMatchOptions,
+    ) -> ApiResult<Vec<LoginMatch>> {
+        self.db.lock().find_matching_logins(
+            origin,
+            form_action_origin.as_deref(),
+            http_realm.as_deref(),
+            &options,
+        )
+    }
+
+    #[handle_error(Error)]
+    pub fn import_csv(&self, csv_data: &str, enc_key: &str) -> ApiResult<Vec<CsvImportRow>> {
+        let encdec = EncryptorDecryptor::new(enc_key)?;
+        self.db.lock().import_csv(csv_data, &encdec)
+    }
+
+    #[handle_error(Error)]
+    pub fn export_csv(&self, enc_key: &str) -> ApiResult<String> {
+        let encdec = EncryptorDecryptor::new(enc_key)?;
+        self.db.lock().export_csv(&encdec)
+    }
+
+    #[handle_error(Error)]
+    pub fn get_breach_alerts(&self, breaches: Vec<Breach>) -> ApiResult<Vec<BreachAlert>> {
+        self.db.lock().get_breach_alerts(&breaches)
+    }
+
+    #[handle_error(Error)]
+    pub fn record_breach_alert_dismissal(&self, id: &str) -> ApiResult<()> {
+        self.db.lock().record_breach_alert_dismissal(id)
+    }
+
+    #[handle_error(Error)]
+    pub fn record_vulnerable_passwords(&self, ids: Vec<String>, enc_key: &str) -> ApiResult<()> {
+        let encdec = EncryptorDecryptor::new(enc_key)?;
+        self.db.lock().record_vulnerable_passwords(&ids, &encdec)
+    }
+
+    #[handle_error(Error)]
+    pub fn is_vulnerable_password(
+        &self,
+        sec_fields: SecureLoginFields,
+        enc_key: &str,
+    ) -> ApiResult<bool> {
+        let encdec = EncryptorDecryptor::new(enc_key)?;
+        self.db.lock().is_vulnerable_password(&sec_fields, &encdec)
+    }
+
+    #[handle_error(Error)]
+    pub fn generate_totp(&self, id: &str, enc_key: &str, now: i64) -> ApiResult<String> {
+        let encdec = EncryptorDecryptor::new(enc_key)?;
+        self.db.lock().generate_totp(id, &encdec, now)
+    }
+
+    #[handle_error(Error)]
+    pub fn get_password_history(&self, id: &str) -> ApiResult<Vec<PasswordHistoryEntry>> {
+        self.db.lock().get_password_history(id)
+    }
+
+    #[handle_error(Error)]
+    pub fn restore_password(
+        &self,
+        id: &str,
+        history_id: i64,
+        enc_key: &str,
+    ) -> ApiResult<EncryptedLogin> {
+        let encdec = EncryptorDecryptor::new(enc_key)?;
+        self.db.lock().restore_password(id, history_id, &encdec)
+    }
+
+    #[handle_error(Error)]
+    pub fn rotate_key(
+        &self,
+        old_key: &str,
+        new_key: &str,
+        canary: &str,
+        canary_text: &str,
+        quarantine: bool,
+    ) -> ApiResult<KeyRotationResult> {
+        let old = EncryptorDecryptor::new(old_key)?;
+        let new = EncryptorDecryptor::new(new_key)?;
+        self.db
+            .lock()
+            .rotate_key(&old, &new, canary, canary_text, quarantine)
+    }
+
+    #[handle_error(Error)]
+    pub fn scan_for_undecryptable(&self, enc_key: &str) -> ApiResult<Vec<String>> {
+        let encdec = EncryptorDecryptor::new(enc_key)?;
+        self.db.lock().scan_for_undecryptable(&encdec)
+    }
+
+    #[handle_error(Error)]
+    pub fn delete_undecryptable_and_resync(
+        self: Arc<Self>,
+        enc_key: &str,
+    ) -> ApiResult<Vec<String>> {
+        let encdec = EncryptorDecryptor::new(enc_key)?;
+        let engine = LoginsSyncEngine::new(Arc::clone(&self))?;
+        let assoc = engine.do_get_sync_assoc()?;
+        let db = self.db.lock();
+        // Delete and reset in one transaction, so we can't end up with the logins deleted but
+        // the engine not reset.
+        let tx = db.unchecked_transaction()?;
+        let mut ids = db.delete_undecryptable(&encdec)?;
+        if !ids.is_empty() {
+            // Reset just this engine, keeping our sync ids, so the next sync downloads
+            // everything again - including the server's copy of the logins we just deleted.
+            engine.reset_in_tx(&db, &assoc)?;
+        }
+        // Passkeys aren't synced, so these can't be restored.
+        ids.extend(db.delete_undecryptable_passkeys(&encdec)?);
+        tx.commit()?;
+        Ok(ids)
+    }
+
+    #[handle_error(Error)]
+    pub fn add_passkey(
+        &self,
+        entry: PasskeyEntry,
+        user_verified: bool,
+        enc_key: &str,
+    ) -> ApiResult<PasskeyRegistration> {
+        let encdec = EncryptorDecryptor::new(enc_key)?;
+        self.db.lock().add_passkey(entry, user_verified, &encdec)
+    }
+
+    #[handle_error(Error)]
+    pub fn import_passkey(&self, import: PasskeyImport, enc_key: &str) -> ApiResult<Passkey> {
+        let encdec = EncryptorDecryptor::new(enc_key)?;
+        self.db.lock().import_passkey(import, &encdec)
+    }
+
+    #[handle_error(Error)]
+    pub fn get_passkey(&self, id: &str) -> ApiResult<Option<Passkey>> {
+        self.db.lock().get_passkey(id)
+    }
+
+    #[handle_error(Error)]
+    pub fn list_passkeys(&self) -> ApiResult<Vec<Passkey>> {
+        self.db.lock().list_passkeys()
+    }
+
+    #[handle_error(Error)]
+    pub fn get_passkeys_for_rp(&self, rp_id: &str) -> ApiResult<Vec<Passkey>> {
+        self.db.lock().get_passkeys_for_rp(rp_id)
+    }
+
+    #[handle_error(Error)]
+    pub fn update_passkey(
+        &self,
+        id: &str,
+        user_name: &str,
+        user_display_name: &str,
+    ) -> ApiResult<Passkey> {
+        self.db
+            .lock()
+            .update_passkey(id, user_name, user_display_name)
+    }
+
+    #[handle_error(Error)]
+    pub fn delete_passkey(&self, id: &str) -> ApiResult<bool> {
+        self.db.lock().delete_passkey(id)
+    }
+
+    #[handle_error(Error)]
+    pub fn sign_passkey_assertion(
+        &self,
+        id: &str,
+        client_data_hash: &str,
+        user_verified: bool,
+        enc_key: &str,
+    ) -> ApiResult<PasskeyAssertion> {
+        let encdec = EncryptorDecryptor::new(enc_key)?;
+        self.db
+            .lock()
+            .sign_passkey_assertion(id, client_data_hash, user_verified, &encdec)
+    }
+
+    #[handle_error(Error)]
+    pub fn audit(&self, enc_key: &str) -> ApiResult<LoginsAuditReport> {
+        let encdec = EncryptorDecryptor::new(enc_key)?;
+        self.db.lock().audit(&encdec)
+    }
+
     /// A convenience wrapper around sync_multiple.
     // Unfortunately, iOS still uses this until they use the sync manager
     // This can almost die later - consumers should never call it (they should
@@ -264,6 +464,7 @@ mod test {
             sec_fields: SecureLoginFields {
                 username: "coolperson21".into(),
                 password: "p4ssw0rd".into(),
+                otp_secret: None,
             },
         };
 
@@ -276,6 +477,7 @@ mod test {
             sec_fields: SecureLoginFields {
                 username: "asdf".into(),
                 password: "fdsa".into(),
+                otp_secret: None,
             },
         };
         let a_id = store
@@ -346,6 +548,7 @@ mod test {
             sec_fields: SecureLoginFields {
                 username: b.sec_fields.username.to_owned(),
                 password: "newpass".into(),
+                otp_secret: None,
             },
             ..b
         };
@@ -3,6 +3,7 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use super::merge::{LocalLogin, MirrorLogin, SyncLoginData};
+use super::payload::migrate_unknown_otp_secrets;
 use super::update_plan::UpdatePlan;
 use super::SyncStatus;
 use crate::db::CLONE_ENTIRE_MIRROR_SQL;
@@ -123,7 +124,7 @@ impl LoginsSyncEngine {
         // it manually.
         let db = self.store.db.lock();
         let tx = db.unchecked_transaction()?;
-        plan.execute(&tx, scope)?;
+        plan.execute(&tx, scope, self.encdec()?)?;
         tx.commit()?;
         Ok(())
     }
@@ -280,6 +281,7 @@ impl LoginsSyncEngine {
         telem: &mut telemetry::Engine,
         scope: &SqlInterruptScope,
     ) -> Result<OutgoingChangeset> {
+        migrate_unknown_otp_secrets(&self.store.db.lock(), self.encdec()?)?;
         let mut incoming_telemetry = telemetry::EngineIncoming::new();
         let data = self.fetch_login_data(inbound.changes, &mut incoming_telemetry, scope)?;
         let plan = {
@@ -369,15 +371,22 @@ impl LoginsSyncEngine {
     // the store would not do that :) Then it can go back into the sync trait
     // and return an anyhow::Result
     pub fn do_reset(&self, assoc: &EngineSyncAssociation) -> Result<()> {
-        log::info!("Executing reset on password engine!");
         let db = self.store.db.lock();
         let tx = db.unchecked_transaction()?;
+        self.reset_in_tx(&db, assoc)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Like `do_reset()`, but the caller must already hold the db lock and be in a transaction.
+    pub(crate) fn reset_in_tx(&self, db: &LoginDb, assoc: &EngineSyncAssociation) -> Result<()> {
+        log::info!("Executing reset on password engine!");
         db.execute_all(&[
             &CLONE_ENTIRE_MIRROR_SQL,
             "DELETE FROM loginsM",
             &format!("UPDATE loginsL SET sync_status = {}", SyncStatus::New as u8),
         ])?;
-        self.set_last_sync(&db, ServerTimestamp(0))?;
+        self.set_last_sync(db, ServerTimestamp(0))?;
         match assoc {
             EngineSyncAssociation::Disconnected => {
                 db.delete_meta(schema::GLOBAL_SYNCID_META_KEY)?;
@@ -389,10 +398,20 @@ impl LoginsSyncEngine {
             }
         };
         db.delete_meta(schema::GLOBAL_STATE_META_KEY)?;
-        tx.commit()?;
         Ok(())
     }
 
+    pub fn do_get_sync_assoc(&self) -> Result<EngineSyncAssociation> {
+        let db = self.store.db.lock();
+        let global = db.get_meta(schema::GLOBAL_SYNCID_META_KEY)?;
+        let coll = db.get_meta(schema::COLLECTION_SYNCID_META_KEY)?;
+        Ok(if let (Some(global), Some(coll)) = (global, coll) {
+            EngineSyncAssociation::Connected(CollSyncIds { global, coll })
+        } else {
+            EngineSyncAssociation::Disconnected
+        })
+    }
+
     // It would be nice if this were a batch-ish api (e.g. takes a slice of records and finds dupes
     // for each one if they exist)... I can't think of how to write that query, though.
     // This is subtly different from dupe handling by the main API and maybe
@@ -487,14 +506,7 @@ impl LegacySyncEngine for LoginsSyncEngine {
     }
 
     fn get_sync_assoc(&self) -> anyhow::Result<EngineSyncAssociation> {
-        let db = self.store.db.lock();
-        let global = db.get_meta(schema::GLOBAL_SYNCID_META_KEY)?;
-        let coll = db.get_meta(schema::COLLECTION_SYNCID_META_KEY)?;
-        Ok(if let (Some(global), Some(coll)) = (global, coll) {
-            EngineSyncAssociation::Connected(CollSyncIds { global, coll })
-        } else {
-            EngineSyncAssociation::Disconnected
-        })
+        Ok(self.do_get_sync_assoc()?)
     }
 
     fn reset(&self, assoc: &EngineSyncAssociation) -> anyhow::Result<()> {
@@ -737,6 +749,7 @@ mod tests {
             sec_fields: SecureLoginFields {
                 username: username.into(),
                 password: password.into(),
+                otp_secret: None,
             }
             .encrypt(&TEST_ENCRYPTOR)
             .unwrap(),
@@ -756,6 +769,7 @@ mod tests {
             sec_fields: SecureLoginFields {
                 username: "test".into(),
                 password: "test".into(),
+                otp_secret: None,
             },
         };
         let first_id = store
@@ -773,6 +787,7 @@ mod tests {
             sec_fields: SecureLoginFields {
                 username: "test1".into(),
                 password: "test1".into(),
+                otp_secret: None,
             },
         };
         let second_id = store
@@ -790,6 +805,7 @@ mod tests {
             sec_fields: SecureLoginFields {
                 username: "test1".into(),
                 password: "test1".into(),
+                otp_secret: None,
             },
         };
         let no_form_origin_id = store
@@ -894,6 +910,7 @@ mod tests {
                         sec_fields: SecureLoginFields {
                             username: "test".into(),
                             password: "test".into(),
+                            otp_secret: None,
                         },
                     },
                     &TEST_ENCRYPTION_KEY,
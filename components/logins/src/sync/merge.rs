@@ -188,6 +188,7 @@ pub(crate) struct LoginDelta {
     pub username: Option<String>,
     pub http_realm: Option<String>,
     pub form_action_origin: Option<String>,
+    pub otp_secret: Option<String>,
 
     pub time_created: Option<i64>,
     pub time_last_used: Option<i64>,
@@ -225,6 +226,7 @@ impl LoginDelta {
         merge_field!(merged, b, b_is_newer, username);
         merge_field!(merged, b, b_is_newer, http_realm);
         merge_field!(merged, b, b_is_newer, form_action_origin);
+        merge_field!(merged, b, b_is_newer, otp_secret);
 
         merge_field!(merged, b, b_is_newer, time_created);
         merge_field!(merged, b, b_is_newer, time_last_used);
@@ -278,6 +280,14 @@ impl EncryptedLogin {
         if let Some(username) = delta.username.take() {
             sec_fields.username = username;
         }
+        // Like `http_realm` below, Some("") means it was removed.
+        if let Some(secret) = delta.otp_secret.take() {
+            sec_fields.otp_secret = if secret.is_empty() {
+                None
+            } else {
+                Some(secret)
+            };
+        }
         self.sec_fields = sec_fields.encrypt(encdec)?;
 
         // Use Some("") to indicate that it should be changed to be None (hacky...)
@@ -317,6 +327,9 @@ impl EncryptedLogin {
         if self_sec_fields.username != older_sec_fields.username {
             delta.username = Some(self_sec_fields.username.clone());
         }
+        if self_sec_fields.otp_secret != older_sec_fields.otp_secret {
+            delta.otp_secret = Some(self_sec_fields.otp_secret.clone().unwrap_or_default());
+        }
         if self_sec_fields.password != older_sec_fields.password {
             delta.password = Some(self_sec_fields.password);
         }
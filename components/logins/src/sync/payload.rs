@@ -7,12 +7,16 @@
 // This struct is used for fetching/sending login records to the server.  There are a number
 // of differences between this and the top-level Login struct; some fields are renamed, some are
 // locally encrypted, etc.
+use crate::db::LoginDb;
 use crate::encryption::EncryptorDecryptor;
 use crate::error::*;
 use crate::login::ValidateAndFixup;
+use crate::schema;
 use crate::SecureLoginFields;
 use crate::{EncryptedLogin, LoginFields, RecordFields};
+use rusqlite::named_params;
 use serde_derive::*;
+use sql_support::ConnExt;
 use sync15::bso::OutgoingBso;
 use sync_guid::Guid;
 
@@ -63,6 +67,7 @@ impl IncomingLogin {
         let sec_fields = SecureLoginFields {
             username: p.username,
             password: p.password,
+            otp_secret: p.otp_secret,
         };
         // We handle NULL in the DB for migrated databases and it's wasteful
         // to encrypt the common case of an empty map, so...
@@ -139,6 +144,12 @@ pub struct LoginPayload {
     #[serde(default)]
     pub times_used: i64,
 
+    // An `otpauth://` URI. This was added after clients started round-tripping unknown fields,
+    // so older clients preserve it without knowing what it is.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub otp_secret: Option<String>,
+
     // Additional "unknown" round-tripped fields.
     #[serde(flatten)]
     unknown_fields: UnknownFields,
@@ -151,11 +162,14 @@ impl EncryptedLogin {
         encdec: &EncryptorDecryptor,
         enc_unknown_fields: Option<String>,
     ) -> Result<OutgoingBso> {
-        let unknown_fields = match enc_unknown_fields {
+        let mut unknown_fields: UnknownFields = match enc_unknown_fields {
             Some(s) => UnknownFields::decrypt(&s, encdec)?,
             None => Default::default(),
         };
         let sec_fields = SecureLoginFields::decrypt(&self.sec_fields, encdec)?;
+        // `migrate_unknown_otp_secrets()` has already moved any `otpSecret` from the unknown
+        // fields into `sec_fields`, so a value left here is stale.
+        unknown_fields.remove("otpSecret");
         Ok(OutgoingBso::from_content_with_id(
             crate::sync::LoginPayload {
                 guid: self.guid(),
@@ -170,12 +184,85 @@ impl EncryptedLogin {
                 time_password_changed: self.record.time_password_changed,
                 time_last_used: self.record.time_last_used,
                 times_used: self.record.times_used,
+                otp_secret: sec_fields.otp_secret,
                 unknown_fields,
             },
         )?)
     }
 }
 
+/// Records we stored before we knew about `otpSecret` have it in their unknown fields, and not in
+/// `secFields`. This moves it into the `secFields` of the mirror and of any local changes, once,
+/// so that from then on `secFields` is the only place we read it from.
+pub(super) fn migrate_unknown_otp_secrets(db: &LoginDb, encdec: &EncryptorDecryptor) -> Result<()> {
+    if db
+        .get_meta::<bool>(schema::OTP_SECRETS_MIGRATED_META_KEY)?
+        .unwrap_or_default()
+    {
+        return Ok(());
+    }
+    let tx = db.unchecked_transaction()?;
+    let rows = db.query_rows_and_then(
+        "SELECT guid, enc_unknown_fields FROM loginsM WHERE enc_unknown_fields IS NOT NULL",
+        [],
+        |row| -> Result<(String, String)> { Ok((row.get(0)?, row.get(1)?)) },
+    )?;
+    for (guid, enc_unknown_fields) in rows {
+        let mut unknown_fields = UnknownFields::decrypt(&enc_unknown_fields, encdec)?;
+        let otp_secret = match unknown_fields.remove("otpSecret") {
+            Some(serde_json::Value::String(secret)) => Some(secret),
+            Some(_) => None,
+            None => continue,
+        };
+        if let Some(otp_secret) = otp_secret {
+            for table in ["loginsM", "loginsL"] {
+                let sec_fields = db
+                    .try_query_row(
+                        &format!("SELECT secFields FROM {} WHERE guid = :guid", table),
+                        named_params! { ":guid": guid },
+                        |row| row.get::<_, Option<String>>(0),
+                        false,
+                    )?
+                    .flatten();
+                // Tombstones don't have any `secFields`.
+                let mut sec_fields = match sec_fields {
+                    Some(sec_fields) => SecureLoginFields::decrypt(&sec_fields, encdec)?,
+                    None => continue,
+                };
+                if sec_fields.otp_secret.is_some() {
+                    continue;
+                }
+                sec_fields.otp_secret = Some(otp_secret.clone());
+                db.execute(
+                    &format!(
+                        "UPDATE {} SET secFields = :sec_fields WHERE guid = :guid",
+                        table
+                    ),
+                    named_params! {
+                        ":guid": guid,
+                        ":sec_fields": sec_fields.encrypt(encdec)?,
+                    },
+                )?;
+            }
+        }
+        let enc_unknown_fields = if unknown_fields.is_empty() {
+            None
+        } else {
+            Some(unknown_fields.encrypt(encdec)?)
+        };
+        db.execute_cached(
+            "UPDATE loginsM SET enc_unknown_fields = :enc_unknown_fields WHERE guid = :guid",
+            named_params! {
+                ":guid": guid,
+                ":enc_unknown_fields": enc_unknown_fields,
+            },
+        )?;
+    }
+    db.put_meta(schema::OTP_SECRETS_MIGRATED_META_KEY, &true)?;
+    tx.commit()?;
+    Ok(())
+}
+
 // Quiet clippy, since this function is passed to deserialiaze_with...
 #[allow(clippy::unnecessary_wraps)]
 fn deserialize_timestamp<'de, D>(deserializer: D) -> std::result::Result<i64, D::Error>
@@ -193,6 +280,7 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::db::test_utils::insert_login;
     use crate::encryption::test_utils::{encrypt_struct, TEST_ENCRYPTOR};
     use crate::sync::merge::SyncLoginData;
     use crate::{EncryptedLogin, LoginFields, RecordFields, SecureLoginFields};
@@ -303,6 +391,134 @@ mod tests {
         assert_eq!(json.get("foo").unwrap().as_str().unwrap(), "bar");
     }
 
+    #[test]
+    fn test_payload_otp_secret() {
+        let secret = "otpauth://totp/alice?secret=GEZDGNBVGY3TQOJQ";
+        let bso = IncomingBso::from_test_content(serde_json::json!({
+            "id": "123412341234",
+            "httpRealm": "test",
+            "hostname": "https://www.example.com",
+            "username": "user",
+            "password": "password",
+            "otpSecret": secret,
+        }));
+        let payload = bso.into_content::<LoginPayload>().content().unwrap();
+        assert!(payload.unknown_fields.is_empty());
+        let login = IncomingLogin::from_incoming_payload(payload, &TEST_ENCRYPTOR)
+            .unwrap()
+            .login;
+        let sec_fields = login.decrypt_fields(&TEST_ENCRYPTOR).unwrap();
+        assert_eq!(sec_fields.otp_secret.as_deref(), Some(secret));
+        let outgoing = login.clone().into_bso(&TEST_ENCRYPTOR, None).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&outgoing.payload).unwrap();
+        assert_eq!(json["otpSecret"], secret);
+
+        // Once `migrate_unknown_otp_secrets()` has run, `secFields` is the only place we read the
+        // secret from, so a stale one in the unknown fields isn't brought back.
+        let mut unknown = UnknownFields::new();
+        unknown.insert("otpSecret".into(), secret.into());
+        let unknown = Some(unknown.encrypt(&TEST_ENCRYPTOR).unwrap());
+        let mut old_login = login;
+        old_login.sec_fields = SecureLoginFields {
+            username: "user".into(),
+            password: "password".into(),
+            otp_secret: None,
+        }
+        .encrypt(&TEST_ENCRYPTOR)
+        .unwrap();
+        let outgoing = old_login.into_bso(&TEST_ENCRYPTOR, unknown).unwrap();
+        assert!(!outgoing.payload.contains("otpSecret"));
+
+        // Logins without a secret don't send one.
+        let login = IncomingLogin::from_incoming_payload(
+            IncomingBso::from_test_content(serde_json::json!({
+                "id": "123412341234",
+                "httpRealm": "test",
+                "hostname": "https://www.example.com",
+                "username": "user",
+                "password": "password",
+            }))
+            .into_content::<LoginPayload>()
+            .content()
+            .unwrap(),
+            &TEST_ENCRYPTOR,
+        )
+        .unwrap()
+        .login;
+        let outgoing = login.into_bso(&TEST_ENCRYPTOR, None).unwrap();
+        assert!(!outgoing.payload.contains("otpSecret"));
+    }
+
+    #[test]
+    fn test_migrate_unknown_otp_secrets() {
+        let secret = "otpauth://totp/alice?secret=GEZDGNBVGY3TQOJQ";
+        let db = LoginDb::open_in_memory().unwrap();
+        insert_login(&db, "aaaaaaaaaaaa", Some("local"), Some("mirror"));
+        insert_login(&db, "bbbbbbbbbbbb", None, Some("mirror"));
+        let mut unknown = UnknownFields::new();
+        unknown.insert("otpSecret".into(), secret.into());
+        unknown.insert("foo".into(), "bar".into());
+        let set_unknown = |unknown: &UnknownFields| {
+            db.execute(
+                "UPDATE loginsM SET enc_unknown_fields = :enc_unknown_fields",
+                named_params! { ":enc_unknown_fields": unknown.encrypt(&TEST_ENCRYPTOR).unwrap() },
+            )
+            .unwrap();
+        };
+        let otp_secret = |table: &str, guid: &str| {
+            let sec_fields: String = db
+                .query_row(
+                    &format!("SELECT secFields FROM {} WHERE guid = :guid", table),
+                    named_params! { ":guid": guid },
+                    |row| row.get(0),
+                )
+                .unwrap();
+            SecureLoginFields::decrypt(&sec_fields, &TEST_ENCRYPTOR)
+                .unwrap()
+                .otp_secret
+        };
+        set_unknown(&unknown);
+
+        migrate_unknown_otp_secrets(&db, &TEST_ENCRYPTOR).unwrap();
+        for (table, guid) in [
+            ("loginsM", "aaaaaaaaaaaa"),
+            ("loginsL", "aaaaaaaaaaaa"),
+            ("loginsM", "bbbbbbbbbbbb"),
+        ] {
+            assert_eq!(otp_secret(table, guid).as_deref(), Some(secret));
+        }
+        let enc_unknown_fields: Vec<String> = db
+            .query_rows_and_then(
+                "SELECT enc_unknown_fields FROM loginsM",
+                [],
+                |row| -> Result<String> { Ok(row.get(0)?) },
+            )
+            .unwrap();
+        for enc_unknown_fields in enc_unknown_fields {
+            let remaining = UnknownFields::decrypt(&enc_unknown_fields, &TEST_ENCRYPTOR).unwrap();
+            assert!(remaining.get("otpSecret").is_none());
+            assert_eq!(remaining["foo"], "bar");
+        }
+
+        // The migration only runs once, so a secret that's removed locally stays removed.
+        set_unknown(&unknown);
+        db.execute(
+            "UPDATE loginsL SET secFields = :sec_fields",
+            named_params! {
+                ":sec_fields": SecureLoginFields {
+                    username: "user".into(),
+                    password: "local".into(),
+                    otp_secret: None,
+                }
+                .encrypt(&TEST_ENCRYPTOR)
+                .unwrap(),
+            },
+        )
+        .unwrap();
+        migrate_unknown_otp_secrets(&db, &TEST_ENCRYPTOR).unwrap();
+        assert_eq!(otp_secret("loginsL", "aaaaaaaaaaaa"), None);
+    }
+
     #[test]
     fn test_form_submit_payload_to_login() {
         let bso = IncomingBso::from_test_content(serde_json::json!({
@@ -347,6 +563,7 @@ mod tests {
             sec_fields: encrypt_struct(&SecureLoginFields {
                 username: "user".into(),
                 password: "password".into(),
+                otp_secret: None,
             }),
         };
         let bso = login.into_bso(&TEST_ENCRYPTOR, None).unwrap();
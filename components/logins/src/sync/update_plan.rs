@@ -6,10 +6,12 @@ use super::merge::{LocalLogin, MirrorLogin};
 use super::{IncomingLogin, SyncStatus};
 use crate::encryption::EncryptorDecryptor;
 use crate::error::*;
-use crate::login::EncryptedLogin;
+use crate::login::{EncryptedLogin, SecureLoginFields};
+use crate::password_history;
 use crate::util;
 use interrupt_support::SqlInterruptScope;
 use rusqlite::{named_params, Connection};
+use sql_support::ConnExt;
 use std::time::SystemTime;
 use sync15::ServerTimestamp;
 use sync_guid::Guid;
@@ -101,7 +103,7 @@ impl UpdatePlan {
             Ok(())
         })?;
 
-        sql_support::each_chunk(&self.delete_mirror, |chunk, _| {
+        sql_support::each_chunk(&self.delete_mirror, |chunk, _| -> Result<()> {
             conn.execute(
                 &format!(
                     "DELETE FROM loginsM WHERE guid IN ({vars})",
@@ -110,7 +112,99 @@ impl UpdatePlan {
                 rusqlite::params_from_iter(chunk),
             )?;
             Ok(())
-        })
+        })?;
+        Ok(())
+    }
+
+    /// Remember the current password of each login whose password the incoming changes replace,
+    /// like `LoginDb::update()` does for local changes. This must run before the changes are
+    /// applied.
+    fn record_password_history(
+        &self,
+        conn: &Connection,
+        scope: &SqlInterruptScope,
+        encdec: &EncryptorDecryptor,
+    ) -> Result<()> {
+        // The password we show is the local one if there is one, and the mirror's otherwise.
+        let local_sql = "SELECT secFields FROM loginsL WHERE guid = :guid AND is_deleted = 0";
+        let mirror_sql = "SELECT secFields FROM loginsM WHERE guid = :guid AND is_overridden = 0";
+        // Mirror inserts which aren't overridden replace a local record in a two-way merge.
+        let replacements = self
+            .mirror_inserts
+            .iter()
+            .filter(|(_, _, is_overridden)| !is_overridden)
+            .map(|(upstream, _, _)| (&upstream.login, local_sql))
+            .chain(
+                self.mirror_updates
+                    .iter()
+                    .map(|(upstream, _)| (&upstream.login, mirror_sql)),
+            )
+            .chain(self.local_updates.iter().map(|l| (&l.login, local_sql)));
+        let now_ms = util::system_time_ms_i64(SystemTime::now());
+        for (login, sql) in replacements {
+            let current = conn.try_query_row(
+                sql,
+                named_params! { ":guid": login.guid_str() },
+                |row| row.get::<_, String>(0),
+                true,
+            )?;
+            let current = match current {
+                Some(current) => current,
+                None => continue,
+            };
+            // If we can't decrypt either side, we can't tell whether the password changed.
+            let changed = match (
+                SecureLoginFields::decrypt(&current, encdec),
+                login.decrypt_fields(encdec),
+            ) {
+                (Ok(current), Ok(new)) => current.password != new.password,
+                _ => false,
+            };
+            if changed {
+                password_history::record_password_history(
+                    conn,
+                    login.guid_str(),
+                    &current,
+                    now_ms,
+                )?;
+            }
+            scope.err_if_interrupted()?;
+        }
+        Ok(())
+    }
+
+    /// Forget the password history of the logins we deleted which no longer exist at all. This
+    /// must run after the mirror inserts, since a two-way merge replaces a local record with a
+    /// mirror record.
+    fn perform_password_history_deletes(
+        &self,
+        conn: &Connection,
+        scope: &SqlInterruptScope,
+    ) -> Result<()> {
+        let deleted = self
+            .delete_local
+            .iter()
+            .chain(&self.delete_mirror)
+            .collect::<Vec<_>>();
+        sql_support::each_chunk(&deleted, |chunk, _| -> Result<()> {
+            conn.execute(
+                &format!(
+                    "DELETE FROM loginsPasswordHistory
+                     WHERE guid IN ({vars})
+                       AND NOT EXISTS (
+                           SELECT 1 FROM loginsL WHERE guid = loginsPasswordHistory.guid
+                       )
+                       AND NOT EXISTS (
+                           SELECT 1 FROM loginsM WHERE guid = loginsPasswordHistory.guid
+                       )",
+                    vars = sql_support::repeat_sql_vars(chunk.len())
+                ),
+                rusqlite::params_from_iter(chunk),
+            )?;
+            scope.err_if_interrupted()?;
+            Ok(())
+        })?;
+        Ok(())
     }
 
     // These aren't batched but probably should be.
@@ -261,7 +355,13 @@ impl UpdatePlan {
         Ok(())
     }
 
-    pub fn execute(&self, conn: &Connection, scope: &SqlInterruptScope) -> Result<()> {
+    pub fn execute(
+        &self,
+        conn: &Connection,
+        scope: &SqlInterruptScope,
+        encdec: &EncryptorDecryptor,
+    ) -> Result<()> {
+        self.record_password_history(conn, scope, encdec)?;
         log::debug!(
             "UpdatePlan: deleting {} records...",
             self.delete_local.len()
@@ -282,6 +382,7 @@ impl UpdatePlan {
             self.local_updates.len()
         );
         self.perform_local_updates(conn, scope)?;
+        self.perform_password_history_deletes(conn, scope)?;
         Ok(())
     }
 }
@@ -294,6 +395,7 @@ mod tests {
         get_server_modified, insert_login,
     };
     use crate::db::LoginDb;
+    use crate::encryption::test_utils::TEST_ENCRYPTOR;
     use crate::login::test_utils::enc_login;
 
     fn inc_login(id: &str, password: &str) -> crate::sync::IncomingLogin {
@@ -310,17 +412,78 @@ mod tests {
         insert_login(&db, "login2", Some("password"), Some("password"));
         insert_login(&db, "login3", Some("password"), Some("password"));
         insert_login(&db, "login4", Some("password"), Some("password"));
+        db.execute_batch(
+            "INSERT INTO loginsPasswordHistory (guid, secFields, timeReplaced)
+             VALUES ('login2', 'x', 0), ('login4', 'x', 0)",
+        )
+        .unwrap();
 
         UpdatePlan {
             delete_mirror: vec![Guid::new("login1"), Guid::new("login2")],
             delete_local: vec![Guid::new("login2"), Guid::new("login3")],
             ..UpdatePlan::default()
         }
-        .execute(&db, &db.begin_interrupt_scope().unwrap())
+        .execute(&db, &db.begin_interrupt_scope().unwrap(), &TEST_ENCRYPTOR)
         .unwrap();
 
         assert_eq!(get_local_guids(&db), vec!["login1", "login4"]);
         assert_eq!(get_mirror_guids(&db), vec!["login3", "login4"]);
+        // login2 is gone completely, so its password history is too.
+        let history_guids: Vec<String> = db
+            .query_rows_and_then("SELECT guid FROM loginsPasswordHistory", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(history_guids, vec!["login4"]);
+    }
+
+    #[test]
+    fn test_incoming_password_history() {
+        let db = LoginDb::open_in_memory().unwrap();
+        insert_login(&db, "mirror-only", None, Some("old-mirror"));
+        insert_login(&db, "overridden", Some("old-local"), Some("old-mirror"));
+        insert_login(&db, "merged", Some("old-local"), Some("old-mirror"));
+        insert_login(&db, "two-way", Some("old-local"), None);
+        insert_login(&db, "unchanged", None, Some("password"));
+
+        UpdatePlan {
+            mirror_updates: vec![
+                (inc_login("mirror-only", "new"), 20000),
+                (inc_login("overridden", "new"), 20000),
+                (inc_login("unchanged", "password"), 20000),
+            ],
+            mirror_inserts: vec![(inc_login("two-way", "new"), 20000, false)],
+            delete_local: vec![Guid::new("two-way")],
+            local_updates: vec![MirrorLogin {
+                login: enc_login("merged", "new"),
+                server_modified: ServerTimestamp(20000),
+                is_overridden: false,
+            }],
+            ..UpdatePlan::default()
+        }
+        .execute(&db, &db.begin_interrupt_scope().unwrap(), &TEST_ENCRYPTOR)
+        .unwrap();
+
+        // Only the passwords which were showing, and were replaced, are in the history.
+        for (guid, expected) in [
+            ("mirror-only", vec!["old-mirror"]),
+            ("overridden", vec![]),
+            ("merged", vec!["old-local"]),
+            ("two-way", vec!["old-local"]),
+            ("unchanged", vec![]),
+        ] {
+            let history = db
+                .get_password_history(guid)
+                .unwrap()
+                .into_iter()
+                .map(|e| {
+                    SecureLoginFields::decrypt(&e.sec_fields, &TEST_ENCRYPTOR)
+                        .unwrap()
+                        .password
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(history, expected, "{}", guid);
+        }
     }
 
     #[test]
@@ -343,7 +506,7 @@ mod tests {
             ],
             ..UpdatePlan::default()
         }
-        .execute(&db, &db.begin_interrupt_scope().unwrap())
+        .execute(&db, &db.begin_interrupt_scope().unwrap(), &TEST_ENCRYPTOR)
         .unwrap();
         check_mirror_login(&db, "unchanged", "password", initial_modified, false);
         check_mirror_login(&db, "changed", "new-password", 20000, false);
@@ -360,7 +523,7 @@ mod tests {
             ],
             ..UpdatePlan::default()
         }
-        .execute(&db, &db.begin_interrupt_scope().unwrap())
+        .execute(&db, &db.begin_interrupt_scope().unwrap(), &TEST_ENCRYPTOR)
         .unwrap();
         check_mirror_login(&db, "login1", "new-password", 20000, false);
         check_mirror_login(&db, "login2", "new-password2", 21000, true);
@@ -380,7 +543,7 @@ mod tests {
             }],
             ..UpdatePlan::default()
         }
-        .execute(&db, &db.begin_interrupt_scope().unwrap())
+        .execute(&db, &db.begin_interrupt_scope().unwrap(), &TEST_ENCRYPTOR)
         .unwrap();
         check_local_login(&db, "login", "new-password", before_update);
     }
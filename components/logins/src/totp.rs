@@ -0,0 +1,269 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Time-based one-time passwords (RFC 6238) for logins with an `otp_secret`.
+//!
+//! The secret is stored as a Key URI, as used in QR codes and understood by most authenticator
+//! apps - eg, `otpauth://totp/Example:alice?secret=JBSWY3DPEHPK3PXP&issuer=Example`. We
+//! support the `secret`, `algorithm`, `digits` and `period` parameters; everything else is
+//! ignored.
+
+use crate::db::LoginDb;
+use crate::encryption::EncryptorDecryptor;
+use crate::error::*;
+use rc_crypto::{digest, hmac};
+use url::Url;
+
+const DEFAULT_DIGITS: u32 = 6;
+const DEFAULT_PERIOD: i64 = 30;
+
+#[derive(Debug)]
+struct TotpParams {
+    key: Vec<u8>,
+    algorithm: &'static digest::Algorithm,
+    digits: u32,
+    period: i64,
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::InvalidOtpSecret(reason.to_owned())
+}
+
+/// Decode RFC 4648 base32, ignoring case, spaces and padding like most authenticator apps do.
+fn decode_base32(s: &str) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in s.chars().filter(|c| *c != ' ' && *c != '=') {
+        let value = match c.to_ascii_uppercase() {
+            c @ 'A'..='Z' => c as u32 - 'A' as u32,
+            c @ '2'..='7' => c as u32 - '2' as u32 + 26,
+            _ => return Err(invalid("secret isn't base32")),
+        };
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Ok(out)
+}
+
+impl TotpParams {
+    fn from_uri(uri: &str) -> Result<Self> {
+        let url = Url::parse(uri).map_err(|_| invalid("not a URI"))?;
+        if url.scheme() != "otpauth" || url.host_str() != Some("totp") {
+            return Err(invalid("not an otpauth://totp/ URI"));
+        }
+        let mut params = TotpParams {
+            key: Vec::new(),
+            algorithm: &digest::SHA1,
+            digits: DEFAULT_DIGITS,
+            period: DEFAULT_PERIOD,
+        };
+        for (name, value) in url.query_pairs() {
+            match name.as_ref() {
+                "secret" => params.key = decode_base32(&value)?,
+                "algorithm" => {
+                    params.algorithm = match value.to_ascii_uppercase().as_str() {
+                        "SHA1" => &digest::SHA1,
+                        "SHA256" => &digest::SHA256,
+                        "SHA512" => &digest::SHA512,
+                        _ => return Err(invalid("unsupported algorithm")),
+                    }
+                }
+                "digits" => {
+                    params.digits = value
+                        .parse()
+                        .ok()
+                        .filter(|d| (6..=8).contains(d))
+                        .ok_or_else(|| invalid("digits must be 6, 7 or 8"))?
+                }
+                "period" => {
+                    params.period = value
+                        .parse()
+                        .ok()
+                        .filter(|p| *p > 0)
+                        .ok_or_else(|| invalid("invalid period"))?
+                }
+                _ => (),
+            }
+        }
+        if params.key.is_empty() {
+            return Err(invalid("missing secret"));
+        }
+        Ok(params)
+    }
+
+    fn generate(&self, now_ms: i64) -> Result<String> {
+        if now_ms < 0 {
+            return Err(invalid("time is before the unix epoch"));
+        }
+        let counter = (now_ms / 1000 / self.period) as u64;
+        let key = hmac::SigningKey::new(self.algorithm, &self.key);
+        let hash = hmac::sign(&key, &counter.to_be_bytes())?;
+        let hash = hash.as_ref();
+        // "Dynamic truncation" from RFC 4226.
+        let offset = (hash[hash.len() - 1] & 0xf) as usize;
+        let mut code_bytes = [0u8; 4];
+        code_bytes.copy_from_slice(&hash[offset..offset + 4]);
+        let code = u32::from_be_bytes(code_bytes) & 0x7fff_ffff;
+        Ok(format!(
+            "{:0width$}",
+            code % 10u32.pow(self.digits),
+            width = self.digits as usize
+        ))
+    }
+}
+
+impl LoginDb {
+    /// Generate the current one-time password for a login, given the time in milliseconds since
+    /// the unix epoch.
+    pub fn generate_totp(
+        &self,
+        id: &str,
+        encdec: &EncryptorDecryptor,
+        now_ms: i64,
+    ) -> Result<String> {
+        let login = self
+            .get_by_id(id)?
+            .ok_or_else(|| Error::NoSuchRecord(id.to_owned()))?;
+        let uri = login
+            .decrypt_fields(encdec)?
+            .otp_secret
+            .ok_or_else(|| invalid("login has no OTP secret"))?;
+        TotpParams::from_uri(&uri)?.generate(now_ms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::test_utils::TEST_ENCRYPTOR;
+    use crate::login::{LoginEntry, LoginFields, SecureLoginFields};
+
+    const SHA1_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+    const SHA256_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZA====";
+    const SHA512_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNA=";
+
+    #[test]
+    fn test_decode_base32() {
+        assert_eq!(decode_base32(SHA1_SECRET).unwrap(), b"12345678901234567890");
+        assert_eq!(decode_base32("mzxw 6ytb oi==").unwrap(), b"foobar");
+        assert!(decode_base32("not base32!").is_err());
+    }
+
+    #[test]
+    fn test_rfc6238_vectors() {
+        // The test vectors from RFC 6238 appendix B.
+        for (secret, algorithm, vectors) in [
+            (
+                SHA1_SECRET,
+                "SHA1",
+                [
+                    (59, "94287082"),
+                    (1111111109, "07081804"),
+                    (20000000000, "65353130"),
+                ],
+            ),
+            (
+                SHA256_SECRET,
+                "SHA256",
+                [
+                    (59, "46119246"),
+                    (1111111109, "68084774"),
+                    (20000000000, "77737706"),
+                ],
+            ),
+            (
+                SHA512_SECRET,
+                "SHA512",
+                [
+                    (59, "90693936"),
+                    (1111111109, "25091201"),
+                    (20000000000, "47863826"),
+                ],
+            ),
+        ] {
+            let params = TotpParams::from_uri(&format!(
+                "otpauth://totp/Example:alice?secret={}&algorithm={}&digits=8&issuer=Example",
+                secret, algorithm
+            ))
+            .unwrap();
+            for (now_secs, code) in vectors {
+                assert_eq!(params.generate(now_secs * 1000).unwrap(), code);
+            }
+        }
+    }
+
+    #[test]
+    fn test_defaults() {
+        let params =
+            TotpParams::from_uri(&format!("otpauth://totp/alice?secret={}", SHA1_SECRET)).unwrap();
+        assert_eq!(params.digits, DEFAULT_DIGITS);
+        assert_eq!(params.period, DEFAULT_PERIOD);
+        // The last 6 digits of the 8 digit code.
+        assert_eq!(params.generate(59_000).unwrap(), "287082");
+        // The code is the same for the whole period.
+        assert_eq!(params.generate(31_000).unwrap(), "287082");
+        assert_ne!(params.generate(60_000).unwrap(), "287082");
+    }
+
+    #[test]
+    fn test_invalid_uris() {
+        for uri in [
+            "not a uri",
+            "https://totp/alice?secret=GEZDGNBV",
+            "otpauth://hotp/alice?secret=GEZDGNBV&counter=1",
+            "otpauth://totp/alice",
+            "otpauth://totp/alice?secret=GEZDGNBV&algorithm=MD5",
+            "otpauth://totp/alice?secret=GEZDGNBV&digits=4",
+            "otpauth://totp/alice?secret=GEZDGNBV&period=0",
+        ] {
+            assert!(
+                matches!(TotpParams::from_uri(uri), Err(Error::InvalidOtpSecret(_))),
+                "{}",
+                uri
+            );
+        }
+    }
+
+    #[test]
+    fn test_generate_totp() {
+        let db = LoginDb::open_in_memory().unwrap();
+        let mut entry = LoginEntry {
+            fields: LoginFields {
+                origin: "https://www.example.com".into(),
+                http_realm: Some("realm".into()),
+                ..Default::default()
+            },
+            sec_fields: SecureLoginFields {
+                username: "alice".into(),
+                password: "password".into(),
+                otp_secret: None,
+            },
+        };
+        let without_secret = db.add(entry.clone(), &TEST_ENCRYPTOR).unwrap();
+        assert!(matches!(
+            db.generate_totp(&without_secret.record.id, &TEST_ENCRYPTOR, 59_000),
+            Err(Error::InvalidOtpSecret(_))
+        ));
+
+        entry.fields.http_realm = Some("other realm".into());
+        entry.sec_fields.otp_secret = Some(format!("otpauth://totp/alice?secret={}", SHA1_SECRET));
+        let with_secret = db.add(entry, &TEST_ENCRYPTOR).unwrap();
+        assert_eq!(
+            db.generate_totp(&with_secret.record.id, &TEST_ENCRYPTOR, 59_000)
+                .unwrap(),
+            "287082"
+        );
+
+        assert!(matches!(
+            db.generate_totp("not-a-guid", &TEST_ENCRYPTOR, 59_000),
+            Err(Error::NoSuchRecord(_))
+        ));
+    }
+}
@@ -5,7 +5,7 @@
 use crate::error::*;
 use rusqlite::Row;
 use std::time;
-use url::Url;
+use url::{Host, Url};
 
 pub fn url_host_port(url_str: &str) -> Option<String> {
     let url = Url::parse(url_str).ok()?;
@@ -17,6 +17,36 @@ pub fn url_host_port(url_str: &str) -> Option<String> {
     })
 }
 
+/// Check whether the host of `origin` is `base_host` or a subdomain of it. IP addresses must match
+/// exactly. Unparsable origins never match.
+pub fn origin_matches_base_host(origin: &str, base_host: &Host) -> bool {
+    let url = match Url::parse(origin) {
+        Ok(url) => url,
+        Err(_) => return false,
+    };
+    match (base_host, url.host()) {
+        (Host::Domain(base), Some(Host::Domain(look))) => {
+            // a fairly long-winded way of saying
+            // `origin == base_domain || origin.ends_with('.' + base_domain);`
+            let mut rev_input = base.chars().rev();
+            let mut rev_host = look.chars().rev();
+            loop {
+                match (rev_input.next(), rev_host.next()) {
+                    (Some(ref a), Some(ref b)) if a == b => continue,
+                    (None, None) => return true, // exactly equal
+                    (None, Some(ref h)) => return *h == '.',
+                    _ => return false,
+                }
+            }
+        }
+        // ip addresses must match exactly.
+        (Host::Ipv4(base), Some(Host::Ipv4(look))) => *base == look,
+        (Host::Ipv6(base), Some(Host::Ipv6(look))) => *base == look,
+        // all "mismatches" in domain types are false.
+        _ => false,
+    }
+}
+
 pub fn system_time_millis_from_row(row: &Row<'_>, col_name: &str) -> Result<time::SystemTime> {
     let time_ms = row.get::<_, Option<i64>>(col_name)?.unwrap_or_default() as u64;
     Ok(time::UNIX_EPOCH + time::Duration::from_millis(time_ms))
@@ -4,7 +4,17 @@
 
 package mozilla.appservices.places
 
+import mozilla.appservices.places.uniffi.BookmarkBatchResult
+import mozilla.appservices.places.uniffi.BookmarkDuplicatesPlan
 import mozilla.appservices.places.uniffi.BookmarkItem
+import mozilla.appservices.places.uniffi.BookmarkKeyword
+import mozilla.appservices.places.uniffi.BookmarkOperation
+import mozilla.appservices.places.uniffi.BookmarksBackupFormat
+import mozilla.appservices.places.uniffi.BookmarksImportMode
+import mozilla.appservices.places.uniffi.BookmarksMigrationResult
+import mozilla.appservices.places.uniffi.BookmarksRepairMetrics
+import mozilla.appservices.places.uniffi.MergeDuplicatesResult
+import mozilla.appservices.places.uniffi.TagInfo
 
 /**
  * Enumeration of the ids of the roots of the bookmarks tree.
@@ -91,6 +101,50 @@ interface ReadableBookmarksConnection : InterruptibleConnection {
      */
     fun getBookmarkUrlForKeyword(keyword: String): Url?
 
+    /**
+     * Returns all search keywords and their bookmarked URLs, sorted by keyword.
+     *
+     * @throws OperationInterrupted if this database implements [InterruptibleConnection] and
+     * has its `interrupt()` method called on another thread.
+     */
+    fun getBookmarkKeywords(): List<BookmarkKeyword>
+
+    /**
+     * Returns the search keyword for a bookmarked URL, if one is set.
+     *
+     * @throws OperationInterrupted if this database implements [InterruptibleConnection] and
+     * has its `interrupt()` method called on another thread.
+     */
+    fun getBookmarkKeywordForUrl(url: Url): String?
+
+    /**
+     * Expands address bar input that starts with a search keyword, like "w firefox",
+     * into the URL to load. `%s` in the keyword's URL is replaced with the escaped
+     * text after the keyword, and `%S` with the text as typed.
+     *
+     * @param input The text typed in the address bar.
+     * @return The URL to load, or null if the input doesn't start with a keyword.
+     *
+     * @throws OperationInterrupted if this database implements [InterruptibleConnection] and
+     * has its `interrupt()` method called on another thread.
+     */
+    fun expandBookmarkKeyword(input: String): Url?
+
+    /**
+     * Finds duplicate bookmarks and folders, for [WritableBookmarksConnection.mergeDuplicateBookmarks].
+     * Bookmarks are duplicates if they have the same URL, ignoring the fragment and a
+     * trailing slash. Folders are duplicates if they have the same title and parent.
+     *
+     * @param matchTitle Whether duplicate bookmarks must also have the same title.
+     * @param matchParent Whether duplicate bookmarks must also be in the same folder,
+     * after merging duplicate folders.
+     * @return The duplicates. The oldest item in each group is kept.
+     *
+     * @throws OperationInterrupted if this database implements [InterruptibleConnection] and
+     * has its `interrupt()` method called on another thread.
+     */
+    fun findDuplicateBookmarks(matchTitle: Boolean = false, matchParent: Boolean = false): BookmarkDuplicatesPlan
+
     /**
      * Returns the list of bookmarks that match the provided search string.
      *
@@ -119,6 +173,37 @@ interface ReadableBookmarksConnection : InterruptibleConnection {
      * has its `interrupt()` method called on another thread.
      */
     fun getRecentBookmarks(limit: Int): List<BookmarkItem>
+
+    /**
+     * Returns the tags for the provided URL, most recently modified first.
+     *
+     * @param url The URL to look up.
+     * @return The tags for the URL, or an empty list if it has none.
+     *
+     * @throws OperationInterrupted if this database implements [InterruptibleConnection] and
+     * has its `interrupt()` method called on another thread.
+     */
+    fun getTagsForUrl(url: Url): List<String>
+
+    /**
+     * Returns the URLs with the provided tag.
+     *
+     * @param tag The tag to look up.
+     * @return The URLs with the tag, or an empty list if it isn't used.
+     *
+     * @throws OperationInterrupted if this database implements [InterruptibleConnection] and
+     * has its `interrupt()` method called on another thread.
+     */
+    fun getUrlsWithTag(tag: String): List<Url>
+
+    /**
+     * Returns all tags that are in use, sorted by tag, along with the
+     * number of URLs that have each tag.
+     *
+     * @throws OperationInterrupted if this database implements [InterruptibleConnection] and
+     * has its `interrupt()` method called on another thread.
+     */
+    fun getAllTags(): List<TagInfo>
 }
 
 /**
@@ -145,6 +230,71 @@ interface WritableBookmarksConnection : ReadableBookmarksConnection {
      */
     fun deleteAllBookmarks()
 
+    /**
+     * Set the search keyword for a bookmarked URL, replacing its old keyword. If
+     * another URL had the keyword, it's moved to this one. Keywords are lowercased,
+     * and the bookmarks are uploaded again on the next sync.
+     *
+     * @param url The bookmarked URL, which may contain `%s`.
+     * @param keyword The keyword, which can't be empty or contain whitespace.
+     *
+     * @throws InvalidBookmarkOperation If the keyword is empty or contains whitespace.
+     */
+    fun setBookmarkKeyword(url: Url, keyword: String)
+
+    /**
+     * Remove a search keyword.
+     *
+     * @return Whether or not the keyword existed.
+     */
+    fun clearBookmarkKeyword(keyword: String): Boolean
+
+    /**
+     * Merge the duplicates found by [ReadableBookmarksConnection.findDuplicateBookmarks].
+     * The children of duplicate folders are moved into the folder that's kept, then
+     * duplicate bookmarks are deleted. Tags and keywords are kept. The deletions are
+     * synced like any others.
+     *
+     * @param plan The duplicates to merge, which can be trimmed first.
+     * @return How many bookmarks and folders were removed.
+     */
+    fun mergeDuplicateBookmarks(plan: BookmarkDuplicatesPlan): MergeDuplicatesResult
+
+    /**
+     * Check the bookmark tree for items that are in the wrong place, like items
+     * whose parent doesn't exist or isn't a folder, or folders in a cycle, and move
+     * them to the unfiled root. Children whose positions have gaps or duplicates
+     * are renumbered. Repaired items are uploaded on the next sync.
+     *
+     * This is also done by [WritableHistoryConnection.runMaintenance].
+     *
+     * @return How many problems of each kind were repaired.
+     */
+    fun checkAndRepairBookmarks(): BookmarksRepairMetrics
+
+    /**
+     * Apply a list of inserts, moves, updates and deletes in one transaction, so
+     * that either all or none of them are applied.
+     *
+     * @param operations The operations, which are applied in order.
+     * @return The GUIDs of the inserted items, and a token for [undoBookmarkOperations].
+     *
+     * @throws UnknownBookmarkItem If an operation refers to a bookmark that doesn't exist.
+     * @throws InvalidBookmarkOperation If an operation is invalid, like moving a bookmark
+     * into a non-folder.
+     */
+    fun applyBookmarkOperations(operations: List<BookmarkOperation>): BookmarkBatchResult
+
+    /**
+     * Undo a batch from [applyBookmarkOperations], restoring the changed bookmarks,
+     * including deleted ones with their GUIDs and positions. Only the 50 most recent
+     * batches on this connection can be undone, and each one only once. An undo that
+     * fails can be tried again.
+     *
+     * @throws InvalidBookmarkOperation If the token is unknown.
+     */
+    fun undoBookmarkOperations(undoToken: ULong)
+
     /**
      * Create a bookmark folder, returning its guid.
      *
@@ -229,4 +379,81 @@ interface WritableBookmarksConnection : ReadableBookmarksConnection {
      * folder node.
      */
     fun updateBookmark(guid: Guid, parentGuid: Guid?, position: UInt?, title: String?, url: Url?)
+
+    /**
+     * Add a tag to a URL. Any bookmarks for the URL will be uploaded on
+     * the next sync.
+     *
+     * @param url The URL to tag. It doesn't need to be bookmarked.
+     * @param tag The tag to add. Leading and trailing whitespace is removed.
+     *
+     * @throws UrlParseFailed If `url` does not refer to a valid URL.
+     * @throws PlacesApiException If `tag` is empty or too long.
+     */
+    fun tagUrl(url: Url, tag: String)
+
+    /**
+     * Remove a tag from a URL.
+     *
+     * @param url The URL to untag.
+     * @param tag The tag to remove.
+     */
+    fun untagUrl(url: Url, tag: String)
+
+    /**
+     * Remove all tags from a URL.
+     *
+     * @param url The URL to untag.
+     */
+    fun removeAllTagsFromUrl(url: Url)
+
+    /**
+     * Remove a tag from every URL that has it.
+     *
+     * @param tag The tag to remove.
+     */
+    fun removeTag(tag: String)
+
+    /**
+     * Rename a tag on every URL that has it. If `newTag` is already in use,
+     * the two tags are merged.
+     *
+     * @param oldTag The tag to rename.
+     * @param newTag The new name for the tag.
+     *
+     * @throws PlacesApiException If either tag is empty or too long.
+     */
+    fun renameTag(oldTag: String, newTag: String)
+
+    /**
+     * Write all bookmarks, including their tags, to a backup file.
+     *
+     * @param path The path of the file to write. An existing file is overwritten.
+     * @param format Whether to write a Netscape bookmarks HTML file or a Firefox
+     * JSON backup.
+     */
+    fun exportBookmarks(path: String, format: BookmarksBackupFormat)
+
+    /**
+     * Restore bookmarks from a backup file.
+     *
+     * With [BookmarksImportMode.ReplaceAll], every existing bookmark and tag is
+     * removed first. The next sync replaces the server's bookmarks: everything
+     * the server has is deleted, and the restored bookmarks are uploaded. With
+     * [BookmarksImportMode.MergeIntoFolder], the backup is added to the given
+     * folder, and existing bookmarks are left alone.
+     *
+     * @param path The path of the file to read.
+     * @param format The format of the file.
+     * @param mode How to combine the backup with the existing bookmarks.
+     * @return Counts of the items that were and weren't restored.
+     *
+     * @throws PlacesApiException If the file can't be read or parsed.
+     * @throws InvalidParent If `mode` refers to a folder that doesn't exist.
+     */
+    fun importBookmarks(
+        path: String,
+        format: BookmarksBackupFormat,
+        mode: BookmarksImportMode,
+    ): BookmarksMigrationResult
 }
@@ -4,27 +4,52 @@
 
 package mozilla.appservices.places
 
+import mozilla.appservices.places.uniffi.BookmarkBatchResult
+import mozilla.appservices.places.uniffi.BookmarkDuplicatesPlan
 import mozilla.appservices.places.uniffi.BookmarkItem
+import mozilla.appservices.places.uniffi.BookmarkKeyword
+import mozilla.appservices.places.uniffi.BookmarkOperation
 import mozilla.appservices.places.uniffi.BookmarkPosition
 import mozilla.appservices.places.uniffi.BookmarkUpdateInfo
+import mozilla.appservices.places.uniffi.BookmarksBackupFormat
+import mozilla.appservices.places.uniffi.BookmarksImportMode
+import mozilla.appservices.places.uniffi.BookmarksMigrationResult
+import mozilla.appservices.places.uniffi.BookmarksRepairMetrics
 import mozilla.appservices.places.uniffi.ConnectionType
+import mozilla.appservices.places.uniffi.DeleteHistoryForDomainResult
 import mozilla.appservices.places.uniffi.DocumentType
+import mozilla.appservices.places.uniffi.FrecencySettings
 import mozilla.appservices.places.uniffi.FrecencyThresholdOption
+import mozilla.appservices.places.uniffi.FulltextSearchResult
+import mozilla.appservices.places.uniffi.HistoryGroup
+import mozilla.appservices.places.uniffi.HistoryGroupOptions
 import mozilla.appservices.places.uniffi.HistoryHighlight
 import mozilla.appservices.places.uniffi.HistoryHighlightWeights
 import mozilla.appservices.places.uniffi.HistoryMetadata
 import mozilla.appservices.places.uniffi.HistoryMetadataObservation
+import mozilla.appservices.places.uniffi.HistoryTimeRange
 import mozilla.appservices.places.uniffi.HistoryVisitInfo
 import mozilla.appservices.places.uniffi.HistoryVisitInfosWithBound
+import mozilla.appservices.places.uniffi.IconObservation
 import mozilla.appservices.places.uniffi.InsertableBookmark
 import mozilla.appservices.places.uniffi.InsertableBookmarkFolder
 import mozilla.appservices.places.uniffi.InsertableBookmarkItem
 import mozilla.appservices.places.uniffi.InsertableBookmarkSeparator
+import mozilla.appservices.places.uniffi.MergeDuplicatesResult
+import mozilla.appservices.places.uniffi.PageIcon
 import mozilla.appservices.places.uniffi.PlacesApiException
+import mozilla.appservices.places.uniffi.PlacesObserver
+import mozilla.appservices.places.uniffi.RecalculateFrecenciesMetrics
 import mozilla.appservices.places.uniffi.SearchResult
 import mozilla.appservices.places.uniffi.SqlInterruptHandle
+import mozilla.appservices.places.uniffi.TagInfo
+import mozilla.appservices.places.uniffi.TopDomain
+import mozilla.appservices.places.uniffi.TopDomainsOrder
 import mozilla.appservices.places.uniffi.TopFrecentSiteInfo
+import mozilla.appservices.places.uniffi.VisitCountBucket
+import mozilla.appservices.places.uniffi.VisitCountInterval
 import mozilla.appservices.places.uniffi.VisitObservation
+import mozilla.appservices.places.uniffi.VisitTransitionCount
 import mozilla.appservices.places.uniffi.placesApiNew
 import mozilla.appservices.sync15.SyncTelemetryPing
 import mozilla.telemetry.glean.private.CounterMetricType
@@ -44,8 +69,9 @@ typealias Guid = String
  * where necessary).
  *
  * @param path an absolute path to a file that will be used for the internal database.
+ * @param frecencySettings the weights used to rank history, or `null` to use the defaults.
  */
-class PlacesApi(path: String) : PlacesManager, AutoCloseable {
+class PlacesApi(path: String, frecencySettings: FrecencySettings? = null) : PlacesManager, AutoCloseable {
     // References to our "api" object and the single writer connection.
     private var api: UniffiPlacesApi
     private var writeConn: PlacesWriterConnection
@@ -54,7 +80,7 @@ class PlacesApi(path: String) : PlacesManager, AutoCloseable {
         // as per https://github.com/mozilla/uniffi-rs/pull/1063, there was some
         // pushback on allowing this to actually be a constructor, so it's a global
         // function instead :(
-        api = placesApiNew(path)
+        api = placesApiNew(path, frecencySettings)
 
         val uniffiConnection = api.newConnection(ConnectionType.READ_WRITE)
         writeConn = PlacesWriterConnection(uniffiConnection, this)
@@ -70,6 +96,14 @@ class PlacesApi(path: String) : PlacesManager, AutoCloseable {
         this.api.registerWithSyncManager()
     }
 
+    override fun setFrecencySettings(settings: FrecencySettings) {
+        this.api.setFrecencySettings(settings)
+    }
+
+    override fun setObserver(observer: PlacesObserver?) {
+        this.api.setObserver(observer)
+    }
+
     override fun openReader(): PlacesReaderConnection {
         val conn = api.newConnection(ConnectionType.READ_ONLY)
         return PlacesReaderConnection(conn)
@@ -162,6 +196,18 @@ open class PlacesReaderConnection internal constructor(conn: UniffiPlacesConnect
         return this.conn.getTopFrecentSiteInfos(numItems, frecencyThreshold)
     }
 
+    override fun getIconForPage(pageUrl: Url, preferredWidth: UInt): PageIcon? {
+        return readQueryCounters.measure {
+            this.conn.getIconForPage(pageUrl, preferredWidth)
+        }
+    }
+
+    override fun searchHistoryFulltext(query: String, limit: Int, timeRange: HistoryTimeRange?): List<FulltextSearchResult> {
+        return readQueryCounters.measure {
+            this.conn.searchHistoryFulltext(query, limit, timeRange)
+        }
+    }
+
     override fun getVisited(urls: List<String>): List<Boolean> {
         return this.conn.getVisited(urls)
     }
@@ -193,6 +239,50 @@ open class PlacesReaderConnection internal constructor(conn: UniffiPlacesConnect
         return this.conn.getVisitCount(visitTransitionSet(excludeTypes))
     }
 
+    override fun getVisitCountsByInterval(
+        start: Long,
+        end: Long,
+        interval: VisitCountInterval,
+        utcOffsetMinutes: Int,
+        excludeTypes: List<VisitType>,
+        localOnly: Boolean,
+    ): List<VisitCountBucket> {
+        return readQueryCounters.measure {
+            this.conn.getVisitCountsByInterval(
+                start,
+                end,
+                interval,
+                utcOffsetMinutes,
+                visitTransitionSet(excludeTypes),
+                localOnly,
+            )
+        }
+    }
+
+    override fun getTopDomains(
+        start: Long,
+        end: Long,
+        order: TopDomainsOrder,
+        limit: Int,
+        excludeTypes: List<VisitType>,
+        localOnly: Boolean,
+    ): List<TopDomain> {
+        return readQueryCounters.measure {
+            this.conn.getTopDomains(start, end, order, limit.toUInt(), visitTransitionSet(excludeTypes), localOnly)
+        }
+    }
+
+    override fun getVisitTransitionCounts(
+        start: Long,
+        end: Long,
+        excludeTypes: List<VisitType>,
+        localOnly: Boolean,
+    ): List<VisitTransitionCount> {
+        return readQueryCounters.measure {
+            this.conn.getVisitTransitionCounts(start, end, visitTransitionSet(excludeTypes), localOnly)
+        }
+    }
+
     override suspend fun getLatestHistoryMetadataForUrl(url: Url): HistoryMetadata? {
         return readQueryCounters.measure {
             this.conn.getLatestHistoryMetadataForUrl(url)
@@ -211,6 +301,16 @@ open class PlacesReaderConnection internal constructor(conn: UniffiPlacesConnect
         }
     }
 
+    override suspend fun getHistoryGroups(
+        start: Long,
+        end: Long,
+        options: HistoryGroupOptions,
+    ): List<HistoryGroup> {
+        return readQueryCounters.measure {
+            this.conn.getHistoryGroups(start, end, options)
+        }
+    }
+
     override suspend fun queryHistoryMetadata(query: String, limit: Int): List<HistoryMetadata> {
         return readQueryCounters.measure {
             this.conn.queryHistoryMetadata(query, limit)
@@ -250,6 +350,26 @@ open class PlacesReaderConnection internal constructor(conn: UniffiPlacesConnect
         return this.conn.bookmarksGetUrlForKeyword(keyword)
     }
 
+    override fun getBookmarkKeywords(): List<BookmarkKeyword> {
+        return readQueryCounters.measure {
+            this.conn.bookmarksGetKeywords()
+        }
+    }
+
+    override fun getBookmarkKeywordForUrl(url: Url): String? {
+        return this.conn.bookmarksGetKeywordForUrl(url)
+    }
+
+    override fun expandBookmarkKeyword(input: String): Url? {
+        return this.conn.bookmarksExpandKeyword(input)
+    }
+
+    override fun findDuplicateBookmarks(matchTitle: Boolean, matchParent: Boolean): BookmarkDuplicatesPlan {
+        return readQueryCounters.measure {
+            this.conn.bookmarksFindDuplicates(matchTitle, matchParent)
+        }
+    }
+
     override fun searchBookmarks(query: String, limit: Int): List<BookmarkItem> {
         return readQueryCounters.measure {
             this.conn.bookmarksSearch(query, limit)
@@ -262,6 +382,24 @@ open class PlacesReaderConnection internal constructor(conn: UniffiPlacesConnect
         }
     }
 
+    override fun getTagsForUrl(url: Url): List<String> {
+        return readQueryCounters.measure {
+            this.conn.getTagsForUrl(url)
+        }
+    }
+
+    override fun getUrlsWithTag(tag: String): List<Url> {
+        return readQueryCounters.measure {
+            this.conn.getUrlsWithTag(tag)
+        }
+    }
+
+    override fun getAllTags(): List<TagInfo> {
+        return readQueryCounters.measure {
+            this.conn.getAllTags()
+        }
+    }
+
     private val readQueryCounters: PlacesManagerCounterMetrics by lazy {
         PlacesManagerCounterMetrics(
             PlacesManagerMetrics.readQueryCount,
@@ -297,6 +435,18 @@ class PlacesWriterConnection internal constructor(conn: UniffiPlacesConnection,
         }
     }
 
+    override fun setIconForPage(icon: IconObservation) {
+        return writeQueryCounters.measure {
+            this.conn.setIconForPage(icon)
+        }
+    }
+
+    override fun notePageContent(url: Url, text: String) {
+        return writeQueryCounters.measure {
+            this.conn.notePageContent(url, text)
+        }
+    }
+
     override fun deleteVisitsFor(url: String) {
         return writeQueryCounters.measure {
             this.conn.deleteVisitsFor(url)
@@ -319,6 +469,12 @@ class PlacesWriterConnection internal constructor(conn: UniffiPlacesConnection,
         }
     }
 
+    override fun deleteHistoryForDomain(domain: String, includeSubdomains: Boolean): DeleteHistoryForDomainResult {
+        return writeQueryCounters.measure {
+            this.conn.deleteHistoryForDomain(domain, includeSubdomains)
+        }
+    }
+
     override fun wipeLocal() {
         this.conn.wipeLocalHistory()
     }
@@ -340,11 +496,35 @@ class PlacesWriterConnection internal constructor(conn: UniffiPlacesConnection,
             PlacesManagerMetrics.runMaintenanceChkPntTime.measure {
                 this.conn.runMaintenanceCheckpoint()
             }
+
+            val repairMetrics = this.conn.bookmarksCheckAndRepair()
+            recordBookmarksRepaired(repairMetrics)
             pruneMetrics
         }
         PlacesManagerMetrics.dbSizeAfterMaintenance.accumulateSamples(listOf(pruneMetrics.dbSizeAfter.toLong() / 1024))
     }
 
+    private fun recordBookmarksRepaired(metrics: BookmarksRepairMetrics) {
+        mapOf(
+            "invalid_root_children" to metrics.numInvalidRootChildren,
+            "orphans" to metrics.numOrphans,
+            "non_folder_children" to metrics.numNonFolderChildren,
+            "cycles" to metrics.numCycles,
+            "folders_renumbered" to metrics.numFoldersRenumbered,
+        ).forEach { (label, count) ->
+            // Counters can only be incremented by a positive amount.
+            if (count > 0U) {
+                PlacesManagerMetrics.bookmarksRepaired[label].add(count.toInt())
+            }
+        }
+    }
+
+    override fun recalculateFrecencies(recalculateAll: Boolean, maxPages: UInt): RecalculateFrecenciesMetrics {
+        return writeQueryCounters.measure {
+            this.conn.runMaintenanceRecalculateFrecencies(recalculateAll, maxPages)
+        }
+    }
+
     override fun pruneDestructively() {
         this.conn.pruneDestructively()
     }
@@ -361,12 +541,94 @@ class PlacesWriterConnection internal constructor(conn: UniffiPlacesConnection,
         }
     }
 
+    override fun setBookmarkKeyword(url: Url, keyword: String) {
+        return writeQueryCounters.measure {
+            this.conn.bookmarksSetKeyword(url, keyword)
+        }
+    }
+
+    override fun clearBookmarkKeyword(keyword: String): Boolean {
+        return writeQueryCounters.measure {
+            this.conn.bookmarksClearKeyword(keyword)
+        }
+    }
+
+    override fun mergeDuplicateBookmarks(plan: BookmarkDuplicatesPlan): MergeDuplicatesResult {
+        return writeQueryCounters.measure {
+            this.conn.bookmarksMergeDuplicates(plan)
+        }
+    }
+
+    override fun checkAndRepairBookmarks(): BookmarksRepairMetrics {
+        return writeQueryCounters.measure {
+            this.conn.bookmarksCheckAndRepair()
+        }
+    }
+
+    override fun applyBookmarkOperations(operations: List<BookmarkOperation>): BookmarkBatchResult {
+        return writeQueryCounters.measure {
+            this.conn.bookmarksApplyBatch(operations)
+        }
+    }
+
+    override fun undoBookmarkOperations(undoToken: ULong) {
+        return writeQueryCounters.measure {
+            this.conn.bookmarksUndoBatch(undoToken)
+        }
+    }
+
     override fun deleteBookmarkNode(guid: Guid): Boolean {
         return writeQueryCounters.measure {
             this.conn.bookmarksDelete(guid)
         }
     }
 
+    override fun tagUrl(url: Url, tag: String) {
+        return writeQueryCounters.measure {
+            this.conn.tagUrl(url, tag)
+        }
+    }
+
+    override fun untagUrl(url: Url, tag: String) {
+        return writeQueryCounters.measure {
+            this.conn.untagUrl(url, tag)
+        }
+    }
+
+    override fun removeAllTagsFromUrl(url: Url) {
+        return writeQueryCounters.measure {
+            this.conn.removeAllTagsFromUrl(url)
+        }
+    }
+
+    override fun removeTag(tag: String) {
+        return writeQueryCounters.measure {
+            this.conn.removeTag(tag)
+        }
+    }
+
+    override fun renameTag(oldTag: String, newTag: String) {
+        return writeQueryCounters.measure {
+            this.conn.renameTag(oldTag, newTag)
+        }
+    }
+
+    override fun exportBookmarks(path: String, format: BookmarksBackupFormat) {
+        return writeQueryCounters.measure {
+            this.conn.bookmarksExport(path, format)
+        }
+    }
+
+    override fun importBookmarks(
+        path: String,
+        format: BookmarksBackupFormat,
+        mode: BookmarksImportMode,
+    ): BookmarksMigrationResult {
+        return writeQueryCounters.measure {
+            this.conn.bookmarksImport(path, format, mode)
+        }
+    }
+
     override suspend fun noteHistoryMetadataObservation(observation: HistoryMetadataObservation) {
         // Different types of `HistoryMetadataObservation` are flattened out into a list of values.
         // The other side of this (rust code) is going to deal with missing/absent values. We're just
@@ -565,6 +827,25 @@ interface PlacesManager {
      * but those are handled internally in the Rust code.
      */
     fun resetBookmarkSyncMetadata()
+
+    /**
+     * Changes the weights used to rank history for all connections.
+     *
+     * Existing frecencies are recalculated as pages are visited or changed. To recalculate
+     * all of them, call [WritableHistoryConnection.recalculateFrecencies] with
+     * `recalculateAll = true`.
+     */
+    fun setFrecencySettings(settings: FrecencySettings)
+
+    /**
+     * Sets the observer that's told about changes to history and bookmarks, after they're
+     * committed on any connection. Pass `null` to remove it.
+     *
+     * The observer is called on the thread that made the changes, once per write, with
+     * all of the changes from that write. After a sync or an import, it's called with a
+     * single `PlacesEvent.ManyChanges`.
+     */
+    fun setObserver(observer: PlacesObserver?)
 }
 
 interface InterruptibleConnection : AutoCloseable {
@@ -603,6 +884,20 @@ interface ReadableHistoryMetadataConnection : InterruptibleConnection {
      */
     suspend fun getHistoryMetadataBetween(start: Long, end: Long): List<HistoryMetadata>
 
+    /**
+     * Groups [HistoryMetadata] updated between [start] and [end] into [HistoryGroup]s of related
+     * pages, for showing "Recently visited" sections. Pages with the same search term are
+     * grouped together, and pages reached by following links join the group of the page that
+     * linked to them, unless they were visited more than [HistoryGroupOptions.maxGap] after
+     * the rest of the group.
+     *
+     * @param start A `start` timestamp.
+     * @param end An `end` timestamp.
+     * @param options Controls how groups are formed and how many are returned.
+     * @return A `List` of [HistoryGroup], most recently updated first.
+     */
+    suspend fun getHistoryGroups(start: Long, end: Long, options: HistoryGroupOptions): List<HistoryGroup>
+
     /**
      * Searches through [HistoryMetadata] by [query], matching records by [HistoryMetadata.url],
      * [HistoryMetadata.title] and [HistoryMetadata.searchTerm].
@@ -686,6 +981,31 @@ interface ReadableHistoryConnection : InterruptibleConnection {
      */
     fun getTopFrecentSiteInfos(numItems: Int, frecencyThreshold: FrecencyThresholdOption): List<TopFrecentSiteInfo>
 
+    /**
+     * Returns the icon for a page, falling back to the root icon (`/favicon.ico`) of the
+     * page's origin if the page doesn't have one.
+     *
+     * @param pageUrl the page to get the icon for.
+     * @param preferredWidth the width to aim for, in pixels. The smallest icon at least this
+     * wide is returned, or the largest icon if there isn't one. The default of 0 returns the
+     * largest icon.
+     * @return the icon, or null if there isn't one.
+     */
+    fun getIconForPage(pageUrl: Url, preferredWidth: UInt = 0U): PageIcon?
+
+    /**
+     * Searches the titles, URLs and text of pages in history. Matches in titles rank
+     * highest, then matches in URLs, then matches in page text.
+     *
+     * @param query the words to search for. Each word matches as a prefix, and all of them
+     * must match.
+     * @param limit the maximum number of results to return.
+     * @param timeRange if set, only pages visited in this range are returned.
+     * @return the matching pages, best matches first, each with a snippet of the text
+     * which matched.
+     */
+    fun searchHistoryFulltext(query: String, limit: Int, timeRange: HistoryTimeRange? = null): List<FulltextSearchResult>
+
     /**
      * Maps a list of page URLs to a list of booleans indicating if each URL was visited.
      *
@@ -763,6 +1083,54 @@ interface ReadableHistoryConnection : InterruptibleConnection {
      * @param excludeTypes List of visit types to exclude.
      */
     fun getVisitCount(excludeTypes: List<VisitType> = listOf()): Long
+
+    /**
+     * Count the visits in each hour or day between [start] and [end], oldest first.
+     * Hours and days without visits are left out.
+     *
+     * @param interval Whether to count visits per hour or per day.
+     * @param utcOffsetMinutes The offset of the user's time zone from UTC, so that
+     * days start at local midnight.
+     * @param excludeTypes List of visit types to exclude.
+     * @param localOnly Whether to skip visits from other devices.
+     */
+    fun getVisitCountsByInterval(
+        start: Long,
+        end: Long,
+        interval: VisitCountInterval,
+        utcOffsetMinutes: Int = 0,
+        excludeTypes: List<VisitType> = listOf(),
+        localOnly: Boolean = false,
+    ): List<VisitCountBucket>
+
+    /**
+     * Return the [limit] domains with the most visits, or the highest frecency,
+     * among the domains visited between [start] and [end].
+     *
+     * @param excludeTypes List of visit types to exclude.
+     * @param localOnly Whether to skip visits from other devices.
+     */
+    fun getTopDomains(
+        start: Long,
+        end: Long,
+        order: TopDomainsOrder = TopDomainsOrder.VISITS,
+        limit: Int = 10,
+        excludeTypes: List<VisitType> = listOf(),
+        localOnly: Boolean = false,
+    ): List<TopDomain>
+
+    /**
+     * Count the visits of each type between [start] and [end], most common first.
+     *
+     * @param excludeTypes List of visit types to exclude.
+     * @param localOnly Whether to skip visits from other devices.
+     */
+    fun getVisitTransitionCounts(
+        start: Long,
+        end: Long,
+        excludeTypes: List<VisitType> = listOf(),
+        localOnly: Boolean = false,
+    ): List<VisitTransitionCount>
 }
 
 interface WritableHistoryConnection : ReadableHistoryConnection {
@@ -771,6 +1139,18 @@ interface WritableHistoryConnection : ReadableHistoryConnection {
      */
     fun noteObservation(data: VisitObservation)
 
+    /**
+     * Store an icon found on a page, which must already be in history. See [IconObservation].
+     */
+    fun setIconForPage(icon: IconObservation)
+
+    /**
+     * Store the text of a page (for example, a reader-mode extract) for
+     * [searchHistoryFulltext]. The page must already be in history, and its text is
+     * removed when its visits are deleted.
+     */
+    fun notePageContent(url: Url, text: String)
+
     /**
      * Deletes all history visits, without recording tombstones.
      *
@@ -787,6 +1167,7 @@ interface WritableHistoryConnection : ReadableHistoryConnection {
      * - Requesting that the indices in our tables be optimized.
      * - Expiring irrelevant history visits.
      * - Periodic repair or deletion of corrupted records.
+     * - Repairing the bookmark tree. See [WritableBookmarksConnection.checkAndRepairBookmarks].
      * - Deleting older visits when the database exceeds dbSizeLimit
      * - etc.
      *
@@ -802,6 +1183,20 @@ interface WritableHistoryConnection : ReadableHistoryConnection {
      */
     fun runMaintenance(dbSizeLimit: UInt = 0U)
 
+    /**
+     * Recalculates stale frecencies, in batches that are saved as they finish, so this
+     * can be interrupted without losing the work already done. It's intended to be run
+     * during idle time, and can be called again until [RecalculateFrecenciesMetrics.finished]
+     * is true.
+     *
+     * @param recalculateAll Mark every frecency as stale first, for example after
+     * calling [PlacesManager.setFrecencySettings].
+     * @param maxPages The maximum number of frecencies to recalculate. The default of 0
+     * means no limit.
+     * @return How many frecencies were recalculated and changed, and whether any are left.
+     */
+    fun recalculateFrecencies(recalculateAll: Boolean = false, maxPages: UInt = 0U): RecalculateFrecenciesMetrics
+
     /**
      * Aggressively prune history visits. These deletions are not intended
      * to be synced, however due to the way history sync works, this can
@@ -868,6 +1263,19 @@ interface WritableHistoryConnection : ReadableHistoryConnection {
      */
     fun deleteVisitsBetween(startTime: Long, endTime: Long)
 
+    /**
+     * "Forget about this site": deletes the visits, metadata, input history and keywords of
+     * every page on a domain. Pages are matched regardless of their scheme and port.
+     *
+     * Like [deleteVisitsFor], deletions of synced pages and visits will be synced, and
+     * bookmarked pages are kept without their history.
+     *
+     * @param domain A host, like `example.com`, or a URL.
+     * @param includeSubdomains Whether to also delete pages on subdomains, like `www.example.com`.
+     * @return How many pages and visits were removed.
+     */
+    fun deleteHistoryForDomain(domain: String, includeSubdomains: Boolean): DeleteHistoryForDomainResult
+
     /**
      * Delete the single visit that occurred at the provided timestamp.
      *
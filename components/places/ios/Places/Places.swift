@@ -34,10 +34,12 @@ public class PlacesAPI {
      *
      * - Parameter path: an absolute path to a file that will be used for the internal database.
      *
+     * - Parameter frecencySettings: the weights used to rank history, or `nil` to use the defaults.
+     *
      * - Throws: `PlacesApiError` if initializing the database failed.
      */
-    public init(path: String) throws {
-        try api = placesApiNew(dbPath: path)
+    public init(path: String, frecencySettings: FrecencySettings? = nil) throws {
+        try api = placesApiNew(dbPath: path, frecencySettings: frecencySettings)
 
         let uniffiConn = try api.newConnection(connType: ConnectionType.readWrite)
         writeConn = try PlacesWriteConnection(conn: uniffiConn)
@@ -174,6 +176,32 @@ public class PlacesAPI {
             self.api.registerWithSyncManager()
         }
     }
+
+    /**
+     * Change the weights used to rank history for all connections. Existing
+     * frecencies are recalculated as pages change, or by
+     * `PlacesWriteConnection.recalculateFrecencies(recalculateAll: true)`.
+     */
+    open func setFrecencySettings(settings: FrecencySettings) {
+        queue.sync {
+            self.api.setFrecencySettings(settings: settings)
+        }
+    }
+
+    /**
+     * Set the observer that's told about changes to history and bookmarks,
+     * after they're committed on any connection. Pass `nil` to remove it.
+     *
+     * The observer is called once per write, with all of the changes from that
+     * write, on the queue of the connection that made them. It shouldn't make
+     * synchronous calls on that connection, and should dispatch any UI work
+     * to the main queue.
+     */
+    open func setObserver(observer: PlacesObserver?) {
+        queue.sync {
+            self.api.setObserver(observer: observer)
+        }
+    }
 }
 
 /**
@@ -333,6 +361,56 @@ public class PlacesReadConnection {
         }
     }
 
+    /**
+     * Returns all search keywords and their bookmarked URLs, sorted by keyword.
+     */
+    open func getBookmarkKeywords() throws -> [BookmarkKeyword] {
+        return try queue.sync {
+            try self.checkApi()
+            return try self.conn.bookmarksGetKeywords()
+        }
+    }
+
+    /**
+     * Returns the search keyword for a bookmarked URL, if one is set.
+     */
+    open func getBookmarkKeywordForURL(url: Url) throws -> String? {
+        return try queue.sync {
+            try self.checkApi()
+            return try self.conn.bookmarksGetKeywordForUrl(url: url)
+        }
+    }
+
+    /**
+     * Expands address bar input that starts with a search keyword, like "w firefox",
+     * into the URL to load. `%s` in the keyword's URL is replaced with the escaped
+     * text after the keyword, and `%S` with the text as typed.
+     *
+     * - Returns: The URL to load, or nil if the input doesn't start with a keyword.
+     */
+    open func expandBookmarkKeyword(input: String) throws -> Url? {
+        return try queue.sync {
+            try self.checkApi()
+            return try self.conn.bookmarksExpandKeyword(input: input)
+        }
+    }
+
+    /**
+     * Finds duplicate bookmarks and folders, for `mergeDuplicateBookmarks`. Bookmarks
+     * are duplicates if they have the same URL, ignoring the fragment and a trailing
+     * slash, and optionally the same title or parent. Folders are duplicates if they
+     * have the same title and parent. The oldest item in each group is kept.
+     */
+    open func findDuplicateBookmarks(
+        matchTitle: Bool = false,
+        matchParent: Bool = false
+    ) throws -> BookmarkDuplicatesPlan {
+        return try queue.sync {
+            try self.checkApi()
+            return try self.conn.bookmarksFindDuplicates(matchTitle: matchTitle, matchParent: matchParent)
+        }
+    }
+
     /**
      * Returns the list of bookmarks that match the provided search string.
      *
@@ -398,6 +476,37 @@ public class PlacesReadConnection {
         }
     }
 
+    /**
+     * Returns the tags for the provided URL, most recently modified first.
+     */
+    open func getTagsForUrl(url: Url) throws -> [String] {
+        return try queue.sync {
+            try self.checkApi()
+            return try self.conn.getTagsForUrl(url: url)
+        }
+    }
+
+    /**
+     * Returns the URLs with the provided tag.
+     */
+    open func getUrlsWithTag(tag: String) throws -> [Url] {
+        return try queue.sync {
+            try self.checkApi()
+            return try self.conn.getUrlsWithTag(tag: tag)
+        }
+    }
+
+    /**
+     * Returns all tags that are in use, sorted by tag, along with the
+     * number of URLs that have each tag.
+     */
+    open func getAllTags() throws -> [TagInfo] {
+        return try queue.sync {
+            try self.checkApi()
+            return try self.conn.getAllTags()
+        }
+    }
+
     open func getLatestHistoryMetadataForUrl(url: Url) throws -> HistoryMetadata? {
         return try queue.sync {
             try self.checkApi()
@@ -419,6 +528,21 @@ public class PlacesReadConnection {
         }
     }
 
+    /**
+     * Groups history metadata updated between `start` and `end` into groups of
+     * related pages, most recently updated first.
+     */
+    open func getHistoryGroups(
+        start: Int64,
+        end: Int64,
+        options: HistoryGroupOptions = HistoryGroupOptions()
+    ) throws -> [HistoryGroup] {
+        return try queue.sync {
+            try self.checkApi()
+            return try self.conn.getHistoryGroups(start: start, end: end, options: options)
+        }
+    }
+
     open func getHighlights(weights: HistoryHighlightWeights, limit: Int32) throws -> [HistoryHighlight] {
         return try queue.sync {
             try self.checkApi()
@@ -474,6 +598,76 @@ public class PlacesReadConnection {
         }
     }
 
+    /**
+     * Counts the visits in each hour or day between `start` and `end`, oldest first.
+     * Days start at midnight in the time zone `utcOffsetMinutes` ahead of UTC.
+     */
+    open func getVisitCountsByInterval(
+        start: PlacesTimestamp,
+        end: PlacesTimestamp,
+        interval: VisitCountInterval,
+        utcOffsetMinutes: Int32 = 0,
+        excludeTypes: VisitTransitionSet = 0,
+        localOnly: Bool = false
+    ) throws -> [VisitCountBucket] {
+        return try queue.sync {
+            try self.checkApi()
+            return try self.conn.getVisitCountsByInterval(
+                startDate: start,
+                endDate: end,
+                interval: interval,
+                utcOffsetMinutes: utcOffsetMinutes,
+                excludeTypes: excludeTypes,
+                localOnly: localOnly
+            )
+        }
+    }
+
+    /**
+     * Returns the `limit` domains with the most visits, or the highest frecency,
+     * among the domains visited between `start` and `end`.
+     */
+    open func getTopDomains(
+        start: PlacesTimestamp,
+        end: PlacesTimestamp,
+        order: TopDomainsOrder = .visits,
+        limit: UInt32 = 10,
+        excludeTypes: VisitTransitionSet = 0,
+        localOnly: Bool = false
+    ) throws -> [TopDomain] {
+        return try queue.sync {
+            try self.checkApi()
+            return try self.conn.getTopDomains(
+                startDate: start,
+                endDate: end,
+                order: order,
+                limit: limit,
+                excludeTypes: excludeTypes,
+                localOnly: localOnly
+            )
+        }
+    }
+
+    /**
+     * Counts the visits of each type between `start` and `end`, most common first.
+     */
+    open func getVisitTransitionCounts(
+        start: PlacesTimestamp,
+        end: PlacesTimestamp,
+        excludeTypes: VisitTransitionSet = 0,
+        localOnly: Bool = false
+    ) throws -> [VisitTransitionCount] {
+        return try queue.sync {
+            try self.checkApi()
+            return try self.conn.getVisitTransitionCounts(
+                startDate: start,
+                endDate: end,
+                excludeTypes: excludeTypes,
+                localOnly: localOnly
+            )
+        }
+    }
+
     open func getVisitPageWithBound(
         bound: Int64,
         offset: Int64,
@@ -509,6 +703,28 @@ public class PlacesReadConnection {
         }
     }
 
+    open func getIconForPage(pageUrl: Url, preferredWidth: UInt32 = 0) throws -> PageIcon? {
+        return try queue.sync {
+            try self.checkApi()
+            return try self.conn.getIconForPage(pageUrl: pageUrl, preferredWidth: preferredWidth)
+        }
+    }
+
+    /**
+     * Searches the titles, URLs and text of pages in history, best matches first.
+     * If `timeRange` is given, only pages visited in that range are returned.
+     */
+    open func searchHistoryFulltext(
+        query: String,
+        limit: Int32,
+        timeRange: HistoryTimeRange? = nil
+    ) throws -> [FulltextSearchResult] {
+        return try queue.sync {
+            try self.checkApi()
+            return try self.conn.searchHistoryFulltext(query: query, limit: limit, timeRange: timeRange)
+        }
+    }
+
     /**
      * Attempt to interrupt a long-running operation which may be
      * happening concurrently. If the operation is interrupted,
@@ -534,6 +750,7 @@ public class PlacesWriteConnection: PlacesReadConnection {
      * - `VACUUM`ing.
      * - Requesting that the indices in our tables be optimized.
      * - Periodic repair or deletion of corrupted records.
+     * - Repairing the bookmark tree. See `checkAndRepairBookmarks`.
      * - Deleting older visits when the database exceeds dbSizeLimit
      * - etc.
      *
@@ -547,6 +764,8 @@ public class PlacesWriteConnection: PlacesReadConnection {
      *   that either the disk or memory is constrained then it halves the amount.
      *   The default of 0 disables pruning.
      *
+     * - Returns: How many problems of each kind were repaired in the bookmark tree.
+     *
      * - Throws:
      *     - `PlacesConnectionError.connUseAfterAPIClosed`: if the PlacesAPI that returned this connection
      *                                                      object has been closed. This indicates API
@@ -558,13 +777,56 @@ public class PlacesWriteConnection: PlacesReadConnection {
      *                               operation. (If this occurs, please let us know).
      *
      */
-    open func runMaintenance(dbSizeLimit: UInt32 = 0) throws {
+    @discardableResult
+    open func runMaintenance(dbSizeLimit: UInt32 = 0) throws -> BookmarksRepairMetrics {
         return try queue.sync {
             try self.checkApi()
             _ = try self.conn.runMaintenancePrune(dbSizeLimit: dbSizeLimit)
             try self.conn.runMaintenanceVacuum()
             try self.conn.runMaintenanceOptimize()
             try self.conn.runMaintenanceCheckpoint()
+            return try self.conn.bookmarksCheckAndRepair()
+        }
+    }
+
+    /**
+     * Check the bookmark tree for items that are in the wrong place, like
+     * items whose parent doesn't exist or isn't a folder, or folders in a
+     * cycle, and move them to the unfiled root. Children whose positions have
+     * gaps or duplicates are renumbered. Repaired items are uploaded on the
+     * next sync.
+     *
+     * This is also done by `runMaintenance`.
+     *
+     * - Returns: How many problems of each kind were repaired.
+     */
+    @discardableResult
+    open func checkAndRepairBookmarks() throws -> BookmarksRepairMetrics {
+        return try queue.sync {
+            try self.checkApi()
+            return try self.conn.bookmarksCheckAndRepair()
+        }
+    }
+
+    /**
+     * Recalculate stale frecencies, in batches that are saved as they finish.
+     * Call it again until the result's `finished` is true.
+     *
+     * - Parameter recalculateAll: Mark every frecency as stale first, for
+     *                             example after changing the frecency settings.
+     * - Parameter maxPages: The maximum number of frecencies to recalculate,
+     *                       or 0 for no limit.
+     */
+    open func recalculateFrecencies(
+        recalculateAll: Bool = false,
+        maxPages: UInt32 = 0
+    ) throws -> RecalculateFrecenciesMetrics {
+        return try queue.sync {
+            try self.checkApi()
+            return try self.conn.runMaintenanceRecalculateFrecencies(
+                recalculateAll: recalculateAll,
+                maxPages: maxPages
+            )
         }
     }
 
@@ -597,6 +859,160 @@ public class PlacesWriteConnection: PlacesReadConnection {
         }
     }
 
+    /**
+     * Sets the search keyword for a bookmarked URL, replacing its old keyword. If
+     * another URL had the keyword, it's moved to this one. Keywords are lowercased,
+     * and the bookmarks for both URLs will be uploaded on the next sync.
+     *
+     * - Throws:
+     *     - `PlacesApiError.invalidBookmarkOperation`: If `keyword` is empty or
+     *                                                  contains whitespace.
+     *     - `PlacesApiError.unexpected`: If `url` isn't bookmarked.
+     */
+    open func setBookmarkKeyword(url: Url, keyword: String) throws {
+        try queue.sync {
+            try self.checkApi()
+            try self.conn.bookmarksSetKeyword(url: url, keyword: keyword)
+        }
+    }
+
+    /**
+     * Removes a search keyword.
+     *
+     * - Returns: Whether or not the keyword existed.
+     */
+    @discardableResult
+    open func clearBookmarkKeyword(keyword: String) throws -> Bool {
+        return try queue.sync {
+            try self.checkApi()
+            return try self.conn.bookmarksClearKeyword(keyword: keyword)
+        }
+    }
+
+    /**
+     * Merges the duplicates found by `findDuplicateBookmarks`, which can be trimmed
+     * first. The children of duplicate folders are moved into the folder that's kept,
+     * then duplicate bookmarks are deleted. Tags and keywords are kept, and the
+     * deletions are synced like any others.
+     */
+    @discardableResult
+    open func mergeDuplicateBookmarks(plan: BookmarkDuplicatesPlan) throws -> MergeDuplicatesResult {
+        return try queue.sync {
+            try self.checkApi()
+            return try self.conn.bookmarksMergeDuplicates(plan: plan)
+        }
+    }
+
+    /**
+     * Applies a list of inserts, moves, updates and deletes in one transaction, so
+     * that either all or none of them are applied.
+     *
+     * - Returns: The GUIDs of the inserted items, and a token for `undoBookmarkOperations`.
+     * - Throws:
+     *     - `PlacesApiError.unknownBookmarkItem`: If an operation refers to a bookmark that
+     *                                             doesn't exist.
+     *     - `PlacesApiError.invalidBookmarkOperation`: If an operation is invalid, like moving
+     *                                                  a bookmark into a non-folder.
+     */
+    @discardableResult
+    open func applyBookmarkOperations(operations: [BookmarkOperation]) throws -> BookmarkBatchResult {
+        return try queue.sync {
+            try self.checkApi()
+            return try self.conn.bookmarksApplyBatch(operations: operations)
+        }
+    }
+
+    /**
+     * Undoes a batch from `applyBookmarkOperations`, restoring the changed bookmarks,
+     * including deleted ones with their GUIDs and positions. Only the 50 most recent
+     * batches on this connection can be undone, and each one only once. An undo that
+     * fails can be tried again.
+     */
+    open func undoBookmarkOperations(undoToken: UInt64) throws {
+        try queue.sync {
+            try self.checkApi()
+            try self.conn.bookmarksUndoBatch(undoToken: undoToken)
+        }
+    }
+
+    /**
+     * Add a tag to a URL. Any bookmarks for the URL will be uploaded on the
+     * next sync.
+     *
+     * - Throws:
+     *     - `PlacesApiError.urlParseFailed`: If `url` is not a valid URL.
+     *     - `PlacesApiError.unexpected`: If `tag` is empty or too long.
+     */
+    open func tagUrl(url: Url, tag: String) throws {
+        try queue.sync {
+            try self.checkApi()
+            try self.conn.tagUrl(url: url, tag: tag)
+        }
+    }
+
+    open func untagUrl(url: Url, tag: String) throws {
+        try queue.sync {
+            try self.checkApi()
+            try self.conn.untagUrl(url: url, tag: tag)
+        }
+    }
+
+    open func removeAllTagsFromUrl(url: Url) throws {
+        try queue.sync {
+            try self.checkApi()
+            try self.conn.removeAllTagsFromUrl(url: url)
+        }
+    }
+
+    /**
+     * Remove a tag from every URL that has it.
+     */
+    open func removeTag(tag: String) throws {
+        try queue.sync {
+            try self.checkApi()
+            try self.conn.removeTag(tag: tag)
+        }
+    }
+
+    /**
+     * Rename a tag on every URL that has it. If `newTag` is already in use,
+     * the two tags are merged.
+     */
+    open func renameTag(oldTag: String, newTag: String) throws {
+        try queue.sync {
+            try self.checkApi()
+            try self.conn.renameTag(oldTag: oldTag, newTag: newTag)
+        }
+    }
+
+    /**
+     * Write all bookmarks, including their tags, to a Netscape bookmarks
+     * HTML file or a Firefox JSON backup at `path`.
+     */
+    open func exportBookmarks(path: String, format: BookmarksBackupFormat) throws {
+        try queue.sync {
+            try self.checkApi()
+            try self.conn.bookmarksExport(path: path, format: format)
+        }
+    }
+
+    /**
+     * Restore bookmarks from the backup file at `path`, either replacing all
+     * existing bookmarks or merging the backup into a folder.
+     *
+     * - Returns: Counts of the items that were and weren't restored.
+     */
+    open func importBookmarks(
+        path: String,
+        format: BookmarksBackupFormat,
+        mode: BookmarksImportMode
+    ) throws -> BookmarksMigrationResult {
+        return try queue.sync {
+            try self.checkApi()
+            return try self.conn.bookmarksImport(path: path, format: format, mode: mode)
+        }
+    }
+
     /**
      * Create a bookmark folder, returning its guid.
      *
@@ -870,6 +1286,24 @@ public class PlacesWriteConnection: PlacesReadConnection {
         }
     }
 
+    /**
+     * Forget a site: delete the visits, metadata, input history and keywords of
+     * every page on `domain`, and its subdomains if `includeSubdomains` is true.
+     * Bookmarked pages are kept without their history.
+     */
+    open func deleteHistoryForDomain(
+        domain: String,
+        includeSubdomains: Bool
+    ) throws -> DeleteHistoryForDomainResult {
+        return try queue.sync {
+            try self.checkApi()
+            return try self.conn.deleteHistoryForDomain(
+                domain: domain,
+                includeSubdomains: includeSubdomains
+            )
+        }
+    }
+
     open func deleteVisit(url: Url, timestamp: PlacesTimestamp) throws {
         try queue.sync {
             try self.checkApi()
@@ -898,6 +1332,24 @@ public class PlacesWriteConnection: PlacesReadConnection {
         }
     }
 
+    open func setIconForPage(icon: IconObservation) throws {
+        try queue.sync {
+            try self.checkApi()
+            try self.conn.setIconForPage(icon: icon)
+        }
+    }
+
+    /**
+     * Stores the text of a page (for example, a reader-mode extract) for
+     * `searchHistoryFulltext`. The page must already be in history.
+     */
+    open func notePageContent(url: Url, text: String) throws {
+        try queue.sync {
+            try self.checkApi()
+            try self.conn.notePageContent(url: url, text: text)
+        }
+    }
+
     open func acceptResult(searchString: String, url: String) throws {
         return try queue.sync {
             try self.checkApi()
@@ -918,4 +1370,32 @@ public class PlacesWriteConnection: PlacesReadConnection {
             return try self.conn.placesHistoryImportFromIos(dbPath: path, lastSyncTimestamp: lastSyncTimestamp)
         }
     }
+
+    open func migrateHistoryFromChromium(path: String) throws -> HistoryMigrationResult {
+        return try queue.sync {
+            try self.checkApi()
+            return try self.conn.placesHistoryImportFromChromium(dbPath: path)
+        }
+    }
+
+    open func migrateBookmarksFromChromium(path: String) throws -> BookmarksMigrationResult {
+        return try queue.sync {
+            try self.checkApi()
+            return try self.conn.placesBookmarksImportFromChromium(path: path)
+        }
+    }
+
+    open func migrateHistoryFromSafari(path: String) throws -> HistoryMigrationResult {
+        return try queue.sync {
+            try self.checkApi()
+            return try self.conn.placesHistoryImportFromSafari(dbPath: path)
+        }
+    }
+
+    open func migrateBookmarksFromSafari(path: String) throws -> BookmarksMigrationResult {
+        return try queue.sync {
+            try self.checkApi()
+            return try self.conn.placesBookmarksImportFromSafari(path: path)
+        }
+    }
 }
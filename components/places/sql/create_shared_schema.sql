@@ -273,3 +273,48 @@ CREATE TABLE IF NOT EXISTS moz_places_metadata_search_queries (
     id INTEGER PRIMARY KEY,
     term TEXT NOT NULL UNIQUE
 );
+
+----------------------------------------------------------------------
+--------------------Favicons------------------------------------------
+----------------------------------------------------------------------
+
+-- These tables store favicons for pages. None of this data is synced.
+--
+-- An icon URL can have several size variants, one row per `width`. Root icons
+-- are the `/favicon.ico` of an origin, and are used for any page on that
+-- origin which doesn't have an icon of its own.
+CREATE TABLE IF NOT EXISTS moz_icons (
+    id INTEGER PRIMARY KEY,
+    icon_url TEXT NOT NULL,
+    icon_url_hash INTEGER NOT NULL,
+    width INTEGER NOT NULL DEFAULT 0,
+    root INTEGER NOT NULL DEFAULT 0,
+    mime_type TEXT,
+    expire_ms INTEGER NOT NULL DEFAULT 0,
+    data BLOB NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS moz_icons_urlhashindex ON moz_icons(icon_url_hash, width);
+
+CREATE TABLE IF NOT EXISTS moz_icons_to_pages (
+    page_id INTEGER NOT NULL REFERENCES moz_places(id) ON DELETE CASCADE,
+    icon_id INTEGER NOT NULL REFERENCES moz_icons(id) ON DELETE CASCADE,
+    PRIMARY KEY(page_id, icon_id)
+) WITHOUT ROWID;
+
+CREATE INDEX IF NOT EXISTS moz_icons_to_pages_iconindex ON moz_icons_to_pages(icon_id);
+
+----------------------------------------------------------------------
+--------------------Full-text search----------------------------------
+----------------------------------------------------------------------
+
+-- A full-text index over history. The rowid is the `moz_places` id of the
+-- page. `title` and `url` are kept in sync with `moz_places` by the triggers
+-- in `create_shared_triggers.sql`, and `content` holds any page text the
+-- application has given us (for example, a reader-mode extract).
+CREATE VIRTUAL TABLE IF NOT EXISTS moz_places_fts USING fts5(
+    title,
+    url,
+    content,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
@@ -44,3 +44,23 @@ CREATE TEMP TABLE moz_updateoriginsupdate_temp (
     frecency_delta INTEGER NOT NULL,
     PRIMARY KEY (prefix, host)
 ) WITHOUT ROWID;
+
+-- This table collects changes for the API's observer. The triggers in
+-- create_shared_triggers.sql add a row for each change, merging repeated
+-- changes to the same item, and `PlacesDb::take_notification()` removes them
+-- once they're committed. See `observer.rs` for the `kind` values.
+CREATE TEMP TABLE moz_places_events_temp (
+    id INTEGER PRIMARY KEY,
+    kind INTEGER NOT NULL,
+    guid TEXT NOT NULL,
+    url TEXT,
+    title TEXT,
+    parent_guid TEXT,
+    old_parent_guid TEXT,
+    position INTEGER,
+    old_position INTEGER,
+    frecency INTEGER,
+    visit_date INTEGER,
+    visit_type INTEGER,
+    UNIQUE(kind, guid)
+);
@@ -351,3 +351,108 @@ BEGIN
         SELECT id FROM moz_places_metadata pm WHERE pm.search_query_id = OLD.search_query_id
     );
 END;
+
+-- These triggers keep the titles and URLs in the full-text index up to date.
+-- Removing a page from `moz_places` also removes it from the index.
+CREATE TEMP TRIGGER moz_places_afterinsert_trigger_fts
+AFTER INSERT ON moz_places
+FOR EACH ROW
+BEGIN
+    INSERT OR REPLACE INTO moz_places_fts(rowid, title, url, content)
+    VALUES (NEW.id, IFNULL(NEW.title, ''), NEW.url, '');
+END;
+
+CREATE TEMP TRIGGER moz_places_afterupdate_trigger_fts
+AFTER UPDATE OF title, url ON moz_places
+FOR EACH ROW
+BEGIN
+    UPDATE moz_places_fts SET
+        title = IFNULL(NEW.title, ''),
+        url = NEW.url
+    WHERE rowid = NEW.id;
+END;
+
+CREATE TEMP TRIGGER moz_places_afterdelete_trigger_fts
+AFTER DELETE ON moz_places
+FOR EACH ROW
+BEGIN
+    DELETE FROM moz_places_fts WHERE rowid = OLD.id;
+END;
+
+-- These triggers record changes for the API's observer, in
+-- moz_places_events_temp. Repeated changes to the same item are merged, and
+-- changes to pages and bookmarks that are removed afterward are dropped. The
+-- numbers are the `EventKind`s from observer.rs. Bookmark moves are recorded
+-- by `update_bookmark()` instead, because inserting and removing bookmarks
+-- also changes the positions of their siblings.
+CREATE TEMP TRIGGER moz_historyvisits_afterinsert_trigger_events
+AFTER INSERT ON moz_historyvisits FOR EACH ROW
+BEGIN
+    INSERT INTO moz_places_events_temp(kind, guid, url, visit_date, visit_type)
+    SELECT 1, guid, url, NEW.visit_date, NEW.visit_type -- EventKind::VisitAdded
+    FROM moz_places
+    WHERE id = NEW.place_id
+    ON CONFLICT(kind, guid) DO UPDATE SET
+        visit_date = excluded.visit_date,
+        visit_type = excluded.visit_type
+    WHERE excluded.visit_date > visit_date;
+END;
+
+CREATE TEMP TRIGGER moz_places_afterdelete_trigger_events
+AFTER DELETE ON moz_places FOR EACH ROW
+BEGIN
+    DELETE FROM moz_places_events_temp
+    WHERE guid = OLD.guid AND
+          kind IN (1, 3, 4); -- VisitAdded, TitleChanged, FrecencyChanged
+    INSERT OR REPLACE INTO moz_places_events_temp(kind, guid, url)
+    VALUES (2, OLD.guid, OLD.url); -- EventKind::PageRemoved
+END;
+
+CREATE TEMP TRIGGER moz_places_afterupdate_title_trigger_events
+AFTER UPDATE OF title ON moz_places FOR EACH ROW
+WHEN OLD.title IS NOT NEW.title
+BEGIN
+    INSERT INTO moz_places_events_temp(kind, guid, url, title)
+    VALUES (3, NEW.guid, NEW.url, NEW.title) -- EventKind::TitleChanged
+    ON CONFLICT(kind, guid) DO UPDATE SET
+        title = excluded.title;
+END;
+
+CREATE TEMP TRIGGER moz_places_afterupdate_frecency_trigger_events
+AFTER UPDATE OF frecency ON moz_places FOR EACH ROW
+WHEN OLD.frecency <> NEW.frecency
+BEGIN
+    INSERT INTO moz_places_events_temp(kind, guid, url, frecency)
+    VALUES (4, NEW.guid, NEW.url, NEW.frecency) -- EventKind::FrecencyChanged
+    ON CONFLICT(kind, guid) DO UPDATE SET
+        frecency = excluded.frecency;
+END;
+
+CREATE TEMP TRIGGER moz_bookmarks_afterinsert_trigger_events
+AFTER INSERT ON moz_bookmarks FOR EACH ROW
+BEGIN
+    INSERT OR REPLACE INTO moz_places_events_temp(kind, guid, url, parent_guid, position)
+    VALUES (5, -- EventKind::BookmarkInserted
+            NEW.guid,
+            (SELECT url FROM moz_places WHERE id = NEW.fk),
+            (SELECT guid FROM moz_bookmarks WHERE id = NEW.parent),
+            NEW.position);
+END;
+
+CREATE TEMP TRIGGER moz_bookmarks_afterdelete_trigger_events
+AFTER DELETE ON moz_bookmarks FOR EACH ROW
+BEGIN
+    -- Items that were inserted and removed in the same transaction aren't
+    -- reported at all.
+    INSERT OR REPLACE INTO moz_places_events_temp(kind, guid, url, parent_guid, position)
+    SELECT 7, -- EventKind::BookmarkRemoved
+           OLD.guid,
+           (SELECT url FROM moz_places WHERE id = OLD.fk),
+           (SELECT guid FROM moz_bookmarks WHERE id = OLD.parent),
+           OLD.position
+    WHERE NOT EXISTS(SELECT 1 FROM moz_places_events_temp
+                     WHERE kind = 5 AND guid = OLD.guid);
+    DELETE FROM moz_places_events_temp
+    WHERE guid = OLD.guid AND
+          kind IN (5, 6); -- BookmarkInserted, BookmarkMoved
+END;
@@ -6,6 +6,7 @@ use crate::db::PlacesDb;
 use crate::error::Result;
 use crate::ffi::{MatchReason as FfiMatchReason, SearchResult as FfiSearchResult};
 pub use crate::match_impl::{MatchBehavior, SearchBehavior};
+use crate::storage::icons::{self, PageIcon};
 use rusqlite::Row;
 use serde_derive::*;
 use sql_support::ConnExt;
@@ -205,9 +206,9 @@ pub struct SearchResult {
     /// title of the bookmark or page, origin, URL, or URL fragment.
     pub title: String,
 
-    /// The favicon URL.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub icon_url: Option<Url>,
+    /// The largest icon for the page, or its origin's root icon.
+    #[serde(skip)]
+    pub icon: Option<PageIcon>,
 
     /// A frecency score for this match.
     pub frecency: i64,
@@ -245,7 +246,7 @@ impl SearchResult {
             search_string,
             url,
             title,
-            icon_url: None,
+            icon: PageIcon::from_joined_row(row)?,
             frecency,
             reasons,
         })
@@ -273,7 +274,7 @@ impl SearchResult {
             search_string,
             url,
             title,
-            icon_url: None,
+            icon: PageIcon::from_joined_row(row)?,
             frecency,
             reasons,
         })
@@ -291,7 +292,7 @@ impl SearchResult {
             search_string,
             url,
             title: display_url,
-            icon_url: None,
+            icon: PageIcon::from_joined_row(row)?,
             frecency,
             reasons: vec![MatchReason::Origin],
         })
@@ -332,7 +333,7 @@ impl SearchResult {
             search_string,
             url,
             title: display_url,
-            icon_url: None,
+            icon: PageIcon::from_joined_row(row)?,
             frecency,
             reasons,
         })
@@ -344,6 +345,7 @@ impl From<SearchResult> for FfiSearchResult {
         Self {
             url: res.url,
             title: res.title,
+            icon: res.icon,
             frecency: res.frecency,
             reasons: res.reasons.into_iter().map(Into::into).collect::<Vec<_>>(),
         }
@@ -437,12 +439,79 @@ const ORIGIN_SQL: &str = "
     LIMIT 1
 ";
 
+const ADAPTIVE_SQL: &str = "
+    SELECT h.url as url,
+           h.title as title,
+           EXISTS(SELECT 1 FROM moz_bookmarks
+                  WHERE fk = h.id) AS bookmarked,
+           (SELECT title FROM moz_bookmarks
+            WHERE fk = h.id AND
+                  title NOT NULL
+            ORDER BY lastModified DESC
+            LIMIT 1) AS btitle,
+           NULL AS tags,
+           h.visit_count_local + h.visit_count_remote AS visit_count,
+           h.typed as typed,
+           h.id as id,
+           NULL AS open_count,
+           h.frecency as frecency,
+           :searchString AS searchString
+    FROM (
+      SELECT ROUND(MAX(use_count) * (1 + (input = :searchString)), 1) AS rank,
+             place_id
+      FROM moz_inputhistory
+      WHERE input BETWEEN :searchString AND :searchString || X'FFFF'
+      GROUP BY place_id
+    ) AS i
+    JOIN moz_places h ON h.id = i.place_id
+    WHERE AUTOCOMPLETE_MATCH(:searchString, h.url,
+                             IFNULL(btitle, h.title), tags,
+                             visit_count, h.typed, bookmarked,
+                             NULL, :matchBehavior, :searchBehavior)
+    ORDER BY rank DESC, h.frecency DESC
+    LIMIT :maxResults
+";
+const SUGGESTIONS_SQL: &str = "
+    SELECT h.url as url, h.title as title,
+           EXISTS(SELECT 1 FROM moz_bookmarks
+                  WHERE fk = h.id) AS bookmarked,
+           (SELECT title FROM moz_bookmarks
+            WHERE fk = h.id AND
+                  title NOT NULL
+            ORDER BY lastModified DESC
+            LIMIT 1) AS btitle,
+           NULL AS tags,
+           h.visit_count_local + h.visit_count_remote AS visit_count,
+           h.typed as typed,
+           h.id as id,
+           NULL AS open_count, h.frecency as frecency, :searchString AS searchString
+    FROM moz_places h
+    WHERE h.frecency > 0
+      AND AUTOCOMPLETE_MATCH(:searchString, h.url,
+                             IFNULL(btitle, h.title), tags,
+                             visit_count, h.typed,
+                             bookmarked, NULL,
+                             :matchBehavior, :searchBehavior)
+      AND (+h.visit_count_local > 0 OR +h.visit_count_remote > 0)
+    ORDER BY h.frecency DESC, h.id DESC
+    LIMIT :maxResults
+";
+
+// The icons are only looked up for the rows each query returns, after its `LIMIT`. We sort all
+// the matches afterwards, so it doesn't matter that the wrapping loses the order.
+lazy_static::lazy_static! {
+    static ref URL_WITH_ICONS_SQL: String = icons::with_page_icons(URL_SQL);
+    static ref ORIGIN_WITH_ICONS_SQL: String = icons::with_origin_icons(ORIGIN_SQL);
+    static ref ADAPTIVE_WITH_ICONS_SQL: String = icons::with_page_icons(ADAPTIVE_SQL);
+    static ref SUGGESTIONS_WITH_ICONS_SQL: String = icons::with_page_icons(SUGGESTIONS_SQL);
+}
+
 impl<'query> Matcher for OriginOrUrl<'query> {
     fn search(&self, conn: &PlacesDb, _: u32) -> Result<Vec<SearchResult>> {
         Ok(if looks_like_origin(self.query) {
             query_flat_rows_and_then(
                 conn,
-                ORIGIN_SQL,
+                ORIGIN_WITH_ICONS_SQL.as_str(),
                 &[
                     (":prefix", &rusqlite::types::Null as &dyn rusqlite::ToSql),
                     (":searchString", &self.query),
@@ -465,7 +534,7 @@ impl<'query> Matcher for OriginOrUrl<'query> {
             };
             query_flat_rows_and_then(
                 conn,
-                URL_SQL,
+                URL_WITH_ICONS_SQL.as_str(),
                 &[
                     (":searchString", &self.query as &dyn rusqlite::ToSql),
                     (":host", &host_str),
@@ -504,37 +573,7 @@ impl<'query> Matcher for Adaptive<'query> {
     fn search(&self, conn: &PlacesDb, max_results: u32) -> Result<Vec<SearchResult>> {
         query_flat_rows_and_then(
             conn,
-            "
-            SELECT h.url as url,
-                   h.title as title,
-                   EXISTS(SELECT 1 FROM moz_bookmarks
-                          WHERE fk = h.id) AS bookmarked,
-                   (SELECT title FROM moz_bookmarks
-                    WHERE fk = h.id AND
-                          title NOT NULL
-                    ORDER BY lastModified DESC
-                    LIMIT 1) AS btitle,
-                   NULL AS tags,
-                   h.visit_count_local + h.visit_count_remote AS visit_count,
-                   h.typed as typed,
-                   h.id as id,
-                   NULL AS open_count,
-                   h.frecency as frecency,
-                   :searchString AS searchString
-            FROM (
-              SELECT ROUND(MAX(use_count) * (1 + (input = :searchString)), 1) AS rank,
-                     place_id
-              FROM moz_inputhistory
-              WHERE input BETWEEN :searchString AND :searchString || X'FFFF'
-              GROUP BY place_id
-            ) AS i
-            JOIN moz_places h ON h.id = i.place_id
-            WHERE AUTOCOMPLETE_MATCH(:searchString, h.url,
-                                     IFNULL(btitle, h.title), tags,
-                                     visit_count, h.typed, bookmarked,
-                                     NULL, :matchBehavior, :searchBehavior)
-            ORDER BY rank DESC, h.frecency DESC
-            LIMIT :maxResults",
+            ADAPTIVE_WITH_ICONS_SQL.as_str(),
             &[
                 (":searchString", &self.query as &dyn rusqlite::ToSql),
                 (":matchBehavior", &self.match_behavior),
@@ -570,30 +609,7 @@ impl<'query> Matcher for Suggestions<'query> {
     fn search(&self, conn: &PlacesDb, max_results: u32) -> Result<Vec<SearchResult>> {
         query_flat_rows_and_then(
             conn,
-            "
-            SELECT h.url, h.title,
-                   EXISTS(SELECT 1 FROM moz_bookmarks
-                          WHERE fk = h.id) AS bookmarked,
-                   (SELECT title FROM moz_bookmarks
-                    WHERE fk = h.id AND
-                          title NOT NULL
-                    ORDER BY lastModified DESC
-                    LIMIT 1) AS btitle,
-                   NULL AS tags,
-                   h.visit_count_local + h.visit_count_remote AS visit_count,
-                   h.typed as typed,
-                   h.id as id,
-                   NULL AS open_count, h.frecency, :searchString AS searchString
-            FROM moz_places h
-            WHERE h.frecency > 0
-              AND AUTOCOMPLETE_MATCH(:searchString, h.url,
-                                     IFNULL(btitle, h.title), tags,
-                                     visit_count, h.typed,
-                                     bookmarked, NULL,
-                                     :matchBehavior, :searchBehavior)
-              AND (+h.visit_count_local > 0 OR +h.visit_count_remote > 0)
-            ORDER BY h.frecency DESC, h.id DESC
-            LIMIT :maxResults",
+            SUGGESTIONS_WITH_ICONS_SQL.as_str(),
             &[
                 (":searchString", &self.query as &dyn rusqlite::ToSql),
                 (":matchBehavior", &self.match_behavior),
@@ -744,7 +760,7 @@ mod tests {
                 search_string: "example".into(),
                 url: Url::parse("http://example.com/").unwrap(),
                 title: "example.com/".into(),
-                icon_url: None,
+                icon: None,
                 frecency: 1999,
                 reasons: vec![MatchReason::Origin],
             }]
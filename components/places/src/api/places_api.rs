@@ -5,7 +5,9 @@
 use crate::bookmark_sync::BookmarksSyncEngine;
 use crate::db::db::{PlacesDb, SharedPlacesDb};
 use crate::error::*;
+use crate::frecency::FrecencySettings;
 use crate::history_sync::HistorySyncEngine;
+use crate::observer::{PlacesObserver, SharedObserver};
 use crate::storage::{
     self, bookmarks::bookmark_sync, delete_meta, get_meta, history::history_sync, put_meta,
 };
@@ -130,9 +132,19 @@ pub struct SyncState {
 /// For uniffi we need to expose our `Arc` returning constructor as a global function :(
 /// https://github.com/mozilla/uniffi-rs/pull/1063 would fix this, but got some pushback
 /// meaning we are forced into this unfortunate workaround.
+///
+/// If `frecency_settings` is passed, they replace the settings of the API,
+/// even if it was already open.
 #[handle_error(crate::Error)]
-pub fn places_api_new(db_name: impl AsRef<Path>) -> ApiResult<Arc<PlacesApi>> {
-    PlacesApi::new(db_name)
+pub fn places_api_new(
+    db_name: impl AsRef<Path>,
+    frecency_settings: Option<FrecencySettings>,
+) -> ApiResult<Arc<PlacesApi>> {
+    let api = PlacesApi::new(db_name)?;
+    if let Some(settings) = frecency_settings {
+        api.set_frecency_settings(settings);
+    }
+    Ok(api)
 }
 
 /// The entry-point to the places API. This object gives access to database
@@ -152,6 +164,8 @@ pub struct PlacesApi {
     // - The outer mutex synchronizes the `get_sync_connection()` operation.  If multiple threads
     //   ran that at the same time there would be issues.
     sync_connection: Mutex<Weak<SharedPlacesDb>>,
+    frecency_settings: Arc<Mutex<FrecencySettings>>,
+    observer: Arc<SharedObserver>,
     id: usize,
 }
 
@@ -180,11 +194,15 @@ impl PlacesApi {
                 // We always create a new read-write connection for an initial open so
                 // we can create the schema and/or do version upgrades.
                 let coop_tx_lock = Arc::new(Mutex::new(()));
+                let frecency_settings = Arc::new(Mutex::new(FrecencySettings::default()));
+                let observer = Arc::new(SharedObserver::default());
                 let connection = PlacesDb::open(
                     &db_name,
                     ConnectionType::ReadWrite,
                     id,
                     coop_tx_lock.clone(),
+                    frecency_settings.clone(),
+                    observer.clone(),
                 )?;
                 let new = PlacesApi {
                     db_name: db_name.clone(),
@@ -193,6 +211,8 @@ impl PlacesApi {
                     sync_connection: Mutex::new(Weak::new()),
                     id,
                     coop_tx_lock,
+                    frecency_settings,
+                    observer,
                 };
                 let arc = Arc::new(new);
                 target.insert(db_name, Arc::downgrade(&arc));
@@ -216,6 +236,8 @@ impl PlacesApi {
                     ConnectionType::ReadOnly,
                     self.id,
                     self.coop_tx_lock.clone(),
+                    self.frecency_settings.clone(),
+                    self.observer.clone(),
                 )
             }
             ConnectionType::ReadWrite => {
@@ -252,6 +274,8 @@ impl PlacesApi {
                     ConnectionType::Sync,
                     self.id,
                     self.coop_tx_lock.clone(),
+                    self.frecency_settings.clone(),
+                    self.observer.clone(),
                 )?));
                 register_interrupt(Arc::<SharedPlacesDb>::downgrade(&db));
                 // Store a weakref for next time
@@ -276,6 +300,20 @@ impl PlacesApi {
         Ok(())
     }
 
+    /// Change the settings used to calculate frecencies. This affects all
+    /// connections, including ones that are already open. Existing frecencies
+    /// aren't recalculated until their pages change, or until
+    /// `run_maintenance_recalculate_frecencies()` is called.
+    pub fn set_frecency_settings(&self, settings: FrecencySettings) {
+        *self.frecency_settings.lock() = settings;
+    }
+
+    /// Sets the observer that's told about changes to history and bookmarks,
+    /// after they're committed on any connection. Pass `None` to remove it.
+    pub fn set_observer(&self, observer: Option<Box<dyn PlacesObserver>>) {
+        self.observer.set(observer);
+    }
+
     fn get_disk_persisted_state(&self, conn: &PlacesDb) -> Result<Option<String>> {
         get_meta::<String>(conn, GLOBAL_STATE_META_KEY)
     }
@@ -374,6 +412,9 @@ impl PlacesApi {
         self.set_disk_persisted_state(&conn.lock(), &disk_cached_state)?;
         sync_state.mem_cached_state.replace(mem_cached_state);
         sync_state.disk_cached_state.replace(disk_cached_state);
+        // The engine only notifies once it's applied its changes, so make
+        // sure nothing is left over if the sync failed partway.
+        conn.notify_observer();
 
         // for b/w compat reasons, we do some dances with the result.
         if let Err(e) = result.result {
@@ -435,6 +476,9 @@ impl PlacesApi {
         }
         sync_state.mem_cached_state.replace(mem_cached_state);
         sync_state.disk_cached_state.replace(disk_cached_state);
+        // The engines only notify once they've applied their changes, so
+        // make sure nothing is left over if the sync failed partway.
+        conn.notify_observer();
 
         Ok(result)
     }
@@ -445,6 +489,7 @@ impl PlacesApi {
         let conn = self.get_sync_connection()?;
 
         storage::bookmarks::delete_everything(&conn.lock())?;
+        conn.notify_observer();
         Ok(())
     }
 
@@ -454,6 +499,7 @@ impl PlacesApi {
         let conn = self.get_sync_connection()?;
 
         bookmark_sync::reset(&conn.lock(), &EngineSyncAssociation::Disconnected)?;
+        conn.notify_observer();
         Ok(())
     }
 
@@ -464,6 +510,7 @@ impl PlacesApi {
         let conn = self.get_sync_connection()?;
 
         history_sync::reset(&conn.lock(), &EngineSyncAssociation::Disconnected)?;
+        conn.notify_observer();
         Ok(())
     }
 }
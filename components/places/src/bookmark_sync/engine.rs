@@ -10,7 +10,7 @@ use super::record::{
 use super::{SyncedBookmarkKind, SyncedBookmarkValidity};
 use crate::db::{GlobalChangeCounterTracker, PlacesDb, SharedPlacesDb};
 use crate::error::*;
-use crate::frecency::{calculate_frecency, DEFAULT_FRECENCY_SETTINGS};
+use crate::frecency::calculate_frecency;
 use crate::storage::{
     bookmarks::{
         bookmark_sync::{create_synced_bookmark_roots, reset},
@@ -819,6 +819,7 @@ fn push_synced_items(
 pub(crate) fn update_frecencies(db: &PlacesDb, scope: &SqlInterruptScope) -> Result<()> {
     let mut tx = db.begin_transaction()?;
 
+    let settings = db.frecency_settings();
     let mut frecencies = Vec::with_capacity(MAX_FRECENCIES_TO_RECALCULATE_PER_CHUNK);
     loop {
         let sql = format!(
@@ -834,8 +835,7 @@ pub(crate) fn update_frecencies(db: &PlacesDb, scope: &SqlInterruptScope) -> Res
             // Frecency recalculation runs several statements, so check to
             // make sure we aren't interrupted before each calculation.
             scope.err_if_interrupted()?;
-            let frecency =
-                calculate_frecency(db, &DEFAULT_FRECENCY_SETTINGS, place_id, Some(false))?;
+            let frecency = calculate_frecency(db, &settings, place_id, Some(false))?;
             frecencies.push((place_id, frecency));
         }
         if frecencies.is_empty() {
@@ -935,7 +935,12 @@ impl SyncEngine for BookmarksSyncEngine {
         // Merge.
         let mut merger = Merger::with_telemetry(&conn, &self.scope, timestamp, telem);
         merger.merge()?;
-        Ok(fetch_outgoing_records(&conn, &self.scope)?)
+        let outgoing = fetch_outgoing_records(&conn, &self.scope)?;
+        drop(conn);
+        // Tell the observer about the merged changes now, in case the sync
+        // fails before `sync_finished`.
+        self.db.notify_observer();
+        Ok(outgoing)
     }
 
     fn set_uploaded(
@@ -951,6 +956,8 @@ impl SyncEngine for BookmarksSyncEngine {
     fn sync_finished(&self) -> anyhow::Result<()> {
         let conn = self.db.lock();
         conn.pragma_update(None, "wal_checkpoint", "PASSIVE")?;
+        drop(conn);
+        self.db.notify_observer();
         Ok(())
     }
 
@@ -986,6 +993,8 @@ impl SyncEngine for BookmarksSyncEngine {
     fn reset(&self, assoc: &EngineSyncAssociation) -> anyhow::Result<()> {
         let conn = self.db.lock();
         reset(&conn, assoc)?;
+        drop(conn);
+        self.db.notify_observer();
         Ok(())
     }
 
@@ -1023,6 +1032,8 @@ impl SyncEngine for BookmarksSyncEngine {
         conn.execute_batch(&sql)?;
         create_synced_bookmark_roots(&conn)?;
         tx.commit()?;
+        drop(conn);
+        self.db.notify_observer();
         Ok(())
     }
 }
@@ -3211,8 +3222,7 @@ mod tests {
     fn test_apply_complex_bookmark_keywords() -> Result<()> {
         use crate::storage::bookmarks::bookmarks_get_url_for_keyword;
 
-        // We don't provide an API for setting keywords locally, but we'll
-        // still round-trip and fix up keywords on the server.
+        // We should round-trip and fix up keywords on the server.
 
         let api = new_mem_api();
         let writer = api.open_connection(ConnectionType::ReadWrite)?;
@@ -5,6 +5,8 @@
 use super::schema;
 use crate::api::places_api::ConnectionType;
 use crate::error::*;
+use crate::frecency::FrecencySettings;
+use crate::observer::SharedObserver;
 use interrupt_support::{SqlInterruptHandle, SqlInterruptScope};
 use lazy_static::lazy_static;
 use parking_lot::Mutex;
@@ -124,6 +126,11 @@ pub struct PlacesDb {
     interrupt_handle: Arc<SqlInterruptHandle>,
     api_id: usize,
     pub(super) coop_tx_lock: Arc<Mutex<()>>,
+    // Shared by all connections from the same API, so that changing the
+    // settings at runtime affects every connection.
+    frecency_settings: Arc<Mutex<FrecencySettings>>,
+    // Also shared by all connections from the same API.
+    observer: Arc<SharedObserver>,
 }
 
 impl PlacesDb {
@@ -132,6 +139,8 @@ impl PlacesDb {
         conn_type: ConnectionType,
         api_id: usize,
         coop_tx_lock: Arc<Mutex<()>>,
+        frecency_settings: Arc<Mutex<FrecencySettings>>,
+        observer: Arc<SharedObserver>,
     ) -> Self {
         Self {
             interrupt_handle: Arc::new(SqlInterruptHandle::new(&db)),
@@ -140,6 +149,8 @@ impl PlacesDb {
             // The API sets this explicitly.
             api_id,
             coop_tx_lock,
+            frecency_settings,
+            observer,
         }
     }
 
@@ -148,10 +159,19 @@ impl PlacesDb {
         conn_type: ConnectionType,
         api_id: usize,
         coop_tx_lock: Arc<Mutex<()>>,
+        frecency_settings: Arc<Mutex<FrecencySettings>>,
+        observer: Arc<SharedObserver>,
     ) -> Result<Self> {
         let initializer = PlacesInitializer { api_id, conn_type };
         let conn = open_database_with_flags(path, conn_type.rusqlite_flags(), &initializer)?;
-        Ok(Self::with_connection(conn, conn_type, api_id, coop_tx_lock))
+        Ok(Self::with_connection(
+            conn,
+            conn_type,
+            api_id,
+            coop_tx_lock,
+            frecency_settings,
+            observer,
+        ))
     }
 
     #[cfg(test)]
@@ -171,6 +191,8 @@ impl PlacesDb {
             conn_type,
             0,
             Arc::new(Mutex::new(())),
+            Arc::new(Mutex::new(FrecencySettings::default())),
+            Arc::default(),
         ))
     }
 
@@ -200,6 +222,16 @@ impl PlacesDb {
     pub fn api_id(&self) -> usize {
         self.api_id
     }
+
+    /// Returns the settings used to calculate frecencies for this connection.
+    pub fn frecency_settings(&self) -> FrecencySettings {
+        self.frecency_settings.lock().clone()
+    }
+
+    #[inline]
+    pub(crate) fn observer(&self) -> &SharedObserver {
+        &self.observer
+    }
 }
 
 impl Drop for PlacesDb {
@@ -245,6 +277,16 @@ impl SharedPlacesDb {
     pub fn begin_interrupt_scope(&self) -> Result<SqlInterruptScope> {
         Ok(self.interrupt_handle.begin_interrupt_scope()?)
     }
+
+    /// Tells the observer about changes committed on this connection. The
+    /// connection is unlocked before calling the observer, so that it can
+    /// make calls of its own.
+    pub fn notify_observer(&self) {
+        let notification = self.db.lock().take_notification();
+        if let Some(notification) = notification {
+            notification.send();
+        }
+    }
 }
 
 // Deref to a Mutex<PlacesDb>, which is how we will use SharedPlacesDb most of the time
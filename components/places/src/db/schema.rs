@@ -11,7 +11,7 @@ use crate::types::SyncStatus;
 use rusqlite::Connection;
 use sql_support::ConnExt;
 
-pub const VERSION: u32 = 17;
+pub const VERSION: u32 = 19;
 
 // Shared schema and temp tables for the read-write and Sync connections.
 const CREATE_SHARED_SCHEMA_SQL: &str = include_str!("../../sql/create_shared_schema.sql");
@@ -288,6 +288,18 @@ pub fn upgrade_from(db: &Connection, from: u32) -> rusqlite::Result<()> {
                 (),
             )?;
         }
+        17 => {
+            // Add the favicon tables
+            db.execute_batch(CREATE_SHARED_SCHEMA_SQL)?;
+        }
+        18 => {
+            // Add the full-text index, and index the pages we already have.
+            db.execute_batch(CREATE_SHARED_SCHEMA_SQL)?;
+            db.execute_batch(
+                "INSERT OR REPLACE INTO moz_places_fts(rowid, title, url, content)
+                 SELECT id, IFNULL(title, ''), url, '' FROM moz_places",
+            )?;
+        }
         // Add more migrations here...
 
         // Any other from value indicates that something very wrong happened
@@ -773,6 +785,33 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_upgrade_schema_18_19() {
+        let db_file = MigratedDatabaseFile::new(PlacesInitializer::new_for_test(), CREATE_V15_DB);
+
+        db_file.upgrade_to(18);
+        db_file
+            .open()
+            .execute(
+                "INSERT INTO moz_places(guid, url, title) VALUES ('abcdefghijkl', 'https://example.com/', 'Example')",
+                [],
+            )
+            .unwrap();
+        db_file.upgrade_to(19);
+        let db = db_file.open();
+
+        // Test the existing page was added to the full-text index
+        assert_eq!(
+            db.query_row(
+                "SELECT title, url FROM moz_places_fts WHERE moz_places_fts MATCH 'example'",
+                [],
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)),
+            )
+            .unwrap(),
+            ("Example".to_string(), "https://example.com/".to_string()),
+        );
+    }
+
     #[test]
     fn test_gh5464() {
         // Test the gh-5464 error case: A user with the `v16` schema, but with `user_version` set
@@ -818,6 +857,9 @@ mod tests {
             "moz_keywords",
             "moz_places_metadata",
             "moz_places_metadata_search_queries",
+            "moz_icons",
+            "moz_icons_to_pages",
+            "moz_places_fts",
         ];
         #[derive(Debug, Ord, PartialOrd, Eq, PartialEq)]
         struct ColumnInfo {
@@ -108,6 +108,11 @@ pub enum Error {
     #[error("Can not import from database version {0}")]
     UnsupportedDatabaseVersion(i64),
 
+    // A file from another browser that we can't read, like a corrupt Safari
+    // `Bookmarks.plist`.
+    #[error("Can not import from invalid file: {0}")]
+    InvalidImportFile(String),
+
     #[error("Error opening database: {0}")]
     OpenDatabaseError(#[from] sql_support::open_database::Error),
 
@@ -147,11 +152,20 @@ pub enum InvalidPlaceInfo {
     // Like Urls, a tag is considered private info, so the value isn't in the error.
     #[error("The tag value is invalid")]
     InvalidTag,
+    // Same for keywords.
+    #[error("The keyword value is invalid")]
+    InvalidKeyword,
     #[error("Cannot change the '{0}' property of a bookmark of type {1:?}")]
     IllegalChange(&'static str, BookmarkType),
 
     #[error("Cannot update the bookmark root {0:?}")]
     CannotUpdateRoot(BookmarkRootGuid),
+
+    #[error("Icon data is too large ({0} bytes)")]
+    IconTooLarge(usize),
+
+    #[error("No bookmark batch to undo for token {0}")]
+    NoSuchUndoToken(u64),
 }
 
 // Error types used when we can't continue due to corruption.
@@ -204,6 +218,12 @@ impl GetErrorHandling for Error {
                     InvalidPlaceInfo::CannotUpdateRoot(..) => {
                         PlacesApiError::InvalidBookmarkOperation { reason: label }
                     }
+                    InvalidPlaceInfo::InvalidKeyword => {
+                        PlacesApiError::InvalidBookmarkOperation { reason: label }
+                    }
+                    InvalidPlaceInfo::NoSuchUndoToken(..) => {
+                        PlacesApiError::InvalidBookmarkOperation { reason: label }
+                    }
                     _ => PlacesApiError::UnexpectedPlacesException { reason: label },
                 })
                 .report_error("places-invalid-place-info")
@@ -241,6 +261,14 @@ impl GetErrorHandling for Error {
                 })
                 .log_info()
             }
+            Error::InvalidImportFile(_) => {
+                // The user's other browser wrote a file we can't read, which
+                // isn't a bug in our code.
+                ErrorHandling::convert(PlacesApiError::UnexpectedPlacesException {
+                    reason: self.to_string(),
+                })
+                .log_warning()
+            }
             Error::Corruption(e) => {
                 ErrorHandling::convert(PlacesApiError::UnexpectedPlacesException {
                     reason: e.to_string(),
@@ -6,19 +6,41 @@
 
 use crate::api::matcher::{self, search_frecent, SearchParams};
 pub use crate::api::places_api::places_api_new;
+use crate::error::InvalidPlaceInfo;
 pub use crate::error::Result;
 pub use crate::error::{ApiResult, PlacesApiError};
-pub use crate::import::common::HistoryMigrationResult;
-use crate::import::import_ios_history;
+pub use crate::frecency::FrecencySettings;
+pub use crate::import::common::{BookmarksMigrationResult, HistoryMigrationResult};
+use crate::import::{
+    import_chromium_bookmarks, import_chromium_history, import_ios_history,
+    import_safari_bookmarks, import_safari_history,
+};
+pub use crate::observer::{PlacesEvent, PlacesObserver};
 use crate::storage;
 use crate::storage::bookmarks;
+pub use crate::storage::bookmarks::backup::{BookmarksBackupFormat, BookmarksImportMode};
+pub use crate::storage::bookmarks::batch::BookmarkOperation;
+use crate::storage::bookmarks::batch::BookmarkUndoLog;
+pub use crate::storage::bookmarks::duplicates::{
+    BookmarkDuplicates, BookmarkDuplicatesPlan, FolderDuplicates, MergeDuplicatesResult,
+};
+pub use crate::storage::bookmarks::keywords::BookmarkKeyword;
+pub use crate::storage::bookmarks::repair::BookmarksRepairMetrics;
 pub use crate::storage::bookmarks::BookmarkPosition;
+pub use crate::storage::fulltext::{FulltextSearchResult, HistoryTimeRange};
+pub use crate::storage::history::stats::{
+    TopDomain, TopDomainsOrder, VisitCountBucket, VisitCountInterval, VisitTransitionCount,
+};
+pub use crate::storage::history::DeleteHistoryForDomainResult;
+pub use crate::storage::history_metadata::groups::{HistoryGroup, HistoryGroupOptions};
 pub use crate::storage::history_metadata::{
     DocumentType, HistoryHighlight, HistoryHighlightWeights, HistoryMetadata,
     HistoryMetadataObservation,
 };
-pub use crate::storage::RunMaintenanceMetrics;
-use crate::storage::{history, history_metadata};
+pub use crate::storage::icons::{IconObservation, PageIcon};
+pub use crate::storage::tags::TagInfo;
+use crate::storage::{fulltext, history, history_metadata, icons, tags};
+pub use crate::storage::{RecalculateFrecenciesMetrics, RunMaintenanceMetrics};
 use crate::types::VisitTransitionSet;
 use crate::ConnectionType;
 use crate::UniffiCustomTypeConverter;
@@ -178,6 +200,8 @@ impl PlacesApi {
 pub struct PlacesConnection {
     db: Mutex<PlacesDb>,
     interrupt_handle: Arc<SqlInterruptHandle>,
+    // Undos for `bookmarks_apply_batch`, which last as long as the connection.
+    bookmark_undos: Mutex<BookmarkUndoLog>,
 }
 
 impl PlacesConnection {
@@ -185,6 +209,7 @@ impl PlacesConnection {
         Self {
             interrupt_handle: db.new_interrupt_handle(),
             db: Mutex::new(db),
+            bookmark_undos: Mutex::new(BookmarkUndoLog::default()),
         }
     }
 
@@ -194,7 +219,15 @@ impl PlacesConnection {
         F: FnOnce(&PlacesDb) -> crate::error::Result<T>,
     {
         let conn = self.db.lock();
-        f(&conn)
+        let result = f(&conn);
+        // Tell the observer about any changes after unlocking the connection,
+        // so that it can make calls of its own.
+        let notification = conn.take_notification();
+        drop(conn);
+        if let Some(notification) = notification {
+            notification.send();
+        }
+        result
     }
 
     // pass the SqlInterruptHandle as an object through Uniffi
@@ -229,6 +262,23 @@ impl PlacesConnection {
         self.with_conn(|conn| history_metadata::get_since(conn, start.as_millis_i64()))
     }
 
+    #[handle_error(crate::Error)]
+    pub fn get_history_groups(
+        &self,
+        start: PlacesTimestamp,
+        end: PlacesTimestamp,
+        options: HistoryGroupOptions,
+    ) -> ApiResult<Vec<HistoryGroup>> {
+        self.with_conn(|conn| {
+            history_metadata::groups::get_groups(
+                conn,
+                start.as_millis_i64(),
+                end.as_millis_i64(),
+                &options,
+            )
+        })
+    }
+
     #[handle_error(crate::Error)]
     pub fn query_history_metadata(
         &self,
@@ -312,6 +362,71 @@ impl PlacesConnection {
         self.with_conn(|conn| history::get_visit_infos(conn, start_date, end_date, exclude_types))
     }
 
+    #[handle_error(crate::Error)]
+    pub fn get_visit_counts_by_interval(
+        &self,
+        start_date: PlacesTimestamp,
+        end_date: PlacesTimestamp,
+        interval: VisitCountInterval,
+        utc_offset_minutes: i32,
+        exclude_types: VisitTransitionSet,
+        local_only: bool,
+    ) -> ApiResult<Vec<VisitCountBucket>> {
+        self.with_conn(|conn| {
+            history::stats::get_visit_counts_by_interval(
+                conn,
+                start_date,
+                end_date,
+                interval,
+                utc_offset_minutes,
+                exclude_types,
+                local_only,
+            )
+        })
+    }
+
+    #[handle_error(crate::Error)]
+    pub fn get_top_domains(
+        &self,
+        start_date: PlacesTimestamp,
+        end_date: PlacesTimestamp,
+        order: TopDomainsOrder,
+        limit: u32,
+        exclude_types: VisitTransitionSet,
+        local_only: bool,
+    ) -> ApiResult<Vec<TopDomain>> {
+        self.with_conn(|conn| {
+            history::stats::get_top_domains(
+                conn,
+                start_date,
+                end_date,
+                order,
+                limit,
+                exclude_types,
+                local_only,
+            )
+        })
+    }
+
+    #[handle_error(crate::Error)]
+    pub fn get_visit_transition_counts(
+        &self,
+        start_date: PlacesTimestamp,
+        end_date: PlacesTimestamp,
+        exclude_types: VisitTransitionSet,
+        local_only: bool,
+    ) -> ApiResult<Vec<VisitTransitionCount>> {
+        self.with_conn(|conn| {
+            history::stats::get_visit_transition_counts(
+                conn,
+                start_date,
+                end_date,
+                exclude_types,
+                local_only,
+            )
+        })
+    }
+
     #[handle_error(crate::Error)]
     pub fn get_visit_count(&self, exclude_types: VisitTransitionSet) -> ApiResult<i64> {
         self.with_conn(|conn| history::get_visit_count(conn, exclude_types))
@@ -372,6 +487,15 @@ impl PlacesConnection {
         })
     }
 
+    #[handle_error(crate::Error)]
+    pub fn delete_history_for_domain(
+        &self,
+        domain: String,
+        include_subdomains: bool,
+    ) -> ApiResult<DeleteHistoryForDomainResult> {
+        self.with_conn(|conn| history::delete_history_for_domain(conn, &domain, include_subdomains))
+    }
+
     #[handle_error(crate::Error)]
     pub fn delete_visits_between(
         &self,
@@ -412,6 +536,35 @@ impl PlacesConnection {
         })
     }
 
+    #[handle_error(crate::Error)]
+    pub fn set_icon_for_page(&self, icon: IconObservation) -> ApiResult<()> {
+        self.with_conn(|conn| icons::set_icon_for_page(conn, icon))
+    }
+
+    #[handle_error(crate::Error)]
+    pub fn get_icon_for_page(
+        &self,
+        page_url: Url,
+        preferred_width: u32,
+    ) -> ApiResult<Option<PageIcon>> {
+        self.with_conn(|conn| icons::get_icon_for_page(conn, &page_url, preferred_width))
+    }
+
+    #[handle_error(crate::Error)]
+    pub fn note_page_content(&self, url: Url, text: String) -> ApiResult<()> {
+        self.with_conn(|conn| fulltext::note_page_content(conn, &url, &text))
+    }
+
+    #[handle_error(crate::Error)]
+    pub fn search_history_fulltext(
+        &self,
+        query: String,
+        limit: i32,
+        time_range: Option<HistoryTimeRange>,
+    ) -> ApiResult<Vec<FulltextSearchResult>> {
+        self.with_conn(|conn| fulltext::search_history_fulltext(conn, &query, limit, time_range))
+    }
+
     // XXX - We probably need to document/name this a little better as it's specifically for
     // history and NOT bookmarks...
     #[handle_error(crate::Error)]
@@ -424,10 +577,10 @@ impl PlacesConnection {
     // further syncing of older data
     #[handle_error(crate::Error)]
     pub fn delete_everything_history(&self) -> ApiResult<()> {
-        history::delete_everything(&self.db.lock())
+        self.with_conn(history::delete_everything)
     }
 
-    // XXX - This just calls wipe_local under the hood...
+    // XXX - This just calls wipe_local under the hood (and deletes expired icons)...
     // should probably have this go away?
     #[handle_error(crate::Error)]
     pub fn prune_destructively(&self) -> ApiResult<()> {
@@ -454,6 +607,22 @@ impl PlacesConnection {
         self.with_conn(storage::run_maintenance_checkpoint)
     }
 
+    #[handle_error(crate::Error)]
+    pub fn run_maintenance_recalculate_frecencies(
+        &self,
+        recalculate_all: bool,
+        max_pages: u32,
+    ) -> ApiResult<RecalculateFrecenciesMetrics> {
+        self.with_conn(|conn| {
+            storage::run_maintenance_recalculate_frecencies(conn, recalculate_all, max_pages)
+        })
+    }
+
+    #[handle_error(crate::Error)]
+    pub fn bookmarks_check_and_repair(&self) -> ApiResult<BookmarksRepairMetrics> {
+        self.with_conn(bookmarks::repair::check_and_repair)
+    }
+
     #[handle_error(crate::Error)]
     pub fn query_autocomplete(&self, search: String, limit: i32) -> ApiResult<Vec<SearchResult>> {
         self.with_conn(|conn| {
@@ -563,6 +732,78 @@ impl PlacesConnection {
         self.with_conn(|conn| bookmarks::bookmarks_get_url_for_keyword(conn, keyword.as_str()))
     }
 
+    #[handle_error(crate::Error)]
+    pub fn bookmarks_set_keyword(&self, url: Url, keyword: String) -> ApiResult<()> {
+        self.with_conn(|conn| bookmarks::keywords::set_keyword(conn, &url, &keyword))
+    }
+
+    #[handle_error(crate::Error)]
+    pub fn bookmarks_clear_keyword(&self, keyword: String) -> ApiResult<bool> {
+        self.with_conn(|conn| bookmarks::keywords::clear_keyword(conn, &keyword))
+    }
+
+    #[handle_error(crate::Error)]
+    pub fn bookmarks_get_keywords(&self) -> ApiResult<Vec<BookmarkKeyword>> {
+        self.with_conn(bookmarks::keywords::get_keywords)
+    }
+
+    #[handle_error(crate::Error)]
+    pub fn bookmarks_get_keyword_for_url(&self, url: Url) -> ApiResult<Option<String>> {
+        self.with_conn(|conn| bookmarks::keywords::get_keyword_for_url(conn, &url))
+    }
+
+    #[handle_error(crate::Error)]
+    pub fn bookmarks_expand_keyword(&self, input: String) -> ApiResult<Option<Url>> {
+        self.with_conn(|conn| bookmarks::keywords::expand_keyword(conn, &input))
+    }
+
+    #[handle_error(crate::Error)]
+    pub fn bookmarks_find_duplicates(
+        &self,
+        match_title: bool,
+        match_parent: bool,
+    ) -> ApiResult<BookmarkDuplicatesPlan> {
+        self.with_conn(|conn| {
+            bookmarks::duplicates::find_duplicates(conn, match_title, match_parent)
+        })
+    }
+
+    #[handle_error(crate::Error)]
+    pub fn bookmarks_merge_duplicates(
+        &self,
+        plan: BookmarkDuplicatesPlan,
+    ) -> ApiResult<MergeDuplicatesResult> {
+        self.with_conn(|conn| bookmarks::duplicates::merge_duplicates(conn, plan))
+    }
+
+    #[handle_error(crate::Error)]
+    pub fn bookmarks_apply_batch(
+        &self,
+        operations: Vec<BookmarkOperation>,
+    ) -> ApiResult<BookmarkBatchResult> {
+        let batch = self.with_conn(|conn| bookmarks::batch::apply_operations(conn, operations))?;
+        let undo_token = self.bookmark_undos.lock().add(batch.undo);
+        Ok(BookmarkBatchResult {
+            inserted_guids: batch.inserted_guids,
+            undo_token,
+        })
+    }
+
+    #[handle_error(crate::Error)]
+    pub fn bookmarks_undo_batch(&self, undo_token: u64) -> ApiResult<()> {
+        self.with_conn(|conn| {
+            let mut undos = self.bookmark_undos.lock();
+            let undo = undos
+                .get(undo_token)
+                .ok_or(InvalidPlaceInfo::NoSuchUndoToken(undo_token))?;
+            bookmarks::batch::undo_operations(conn, undo)?;
+            // Only forget the undo once it's committed, so that it can be
+            // retried if it fails.
+            undos.take(undo_token);
+            Ok(())
+        })
+    }
+
     #[handle_error(crate::Error)]
     pub fn bookmarks_insert(&self, data: InsertableBookmarkItem) -> ApiResult<Guid> {
         self.with_conn(|conn| bookmarks::insert_bookmark(conn, data))
@@ -573,6 +814,61 @@ impl PlacesConnection {
         self.with_conn(|conn| bookmarks::update_bookmark_from_info(conn, item))
     }
 
+    #[handle_error(crate::Error)]
+    pub fn bookmarks_export(&self, path: String, format: BookmarksBackupFormat) -> ApiResult<()> {
+        self.with_conn(|conn| bookmarks::backup::export_bookmarks(conn, &path, format))
+    }
+
+    #[handle_error(crate::Error)]
+    pub fn bookmarks_import(
+        &self,
+        path: String,
+        format: BookmarksBackupFormat,
+        mode: BookmarksImportMode,
+    ) -> ApiResult<BookmarksMigrationResult> {
+        self.with_conn(|conn| bookmarks::backup::import_bookmarks(conn, &path, format, mode))
+    }
+
+    #[handle_error(crate::Error)]
+    pub fn tag_url(&self, url: Url, tag: String) -> ApiResult<()> {
+        self.with_conn(|conn| tags::tag_url(conn, &url, &tag))
+    }
+
+    #[handle_error(crate::Error)]
+    pub fn untag_url(&self, url: Url, tag: String) -> ApiResult<()> {
+        self.with_conn(|conn| tags::untag_url(conn, &url, &tag))
+    }
+
+    #[handle_error(crate::Error)]
+    pub fn remove_all_tags_from_url(&self, url: Url) -> ApiResult<()> {
+        self.with_conn(|conn| tags::remove_all_tags_from_url(conn, &url))
+    }
+
+    #[handle_error(crate::Error)]
+    pub fn remove_tag(&self, tag: String) -> ApiResult<()> {
+        self.with_conn(|conn| tags::remove_tag(conn, &tag))
+    }
+
+    #[handle_error(crate::Error)]
+    pub fn rename_tag(&self, old_tag: String, new_tag: String) -> ApiResult<()> {
+        self.with_conn(|conn| tags::rename_tag(conn, &old_tag, &new_tag))
+    }
+
+    #[handle_error(crate::Error)]
+    pub fn get_tags_for_url(&self, url: Url) -> ApiResult<Vec<String>> {
+        self.with_conn(|conn| tags::get_tags_for_url(conn, &url))
+    }
+
+    #[handle_error(crate::Error)]
+    pub fn get_urls_with_tag(&self, tag: String) -> ApiResult<Vec<Url>> {
+        self.with_conn(|conn| tags::get_urls_with_tag(conn, &tag))
+    }
+
+    #[handle_error(crate::Error)]
+    pub fn get_all_tags(&self) -> ApiResult<Vec<TagInfo>> {
+        self.with_conn(tags::get_all_tags)
+    }
+
     #[handle_error(crate::Error)]
     pub fn places_history_import_from_ios(
         &self,
@@ -581,6 +877,38 @@ impl PlacesConnection {
     ) -> ApiResult<HistoryMigrationResult> {
         self.with_conn(|conn| import_ios_history(conn, &db_path, last_sync_timestamp))
     }
+
+    #[handle_error(crate::Error)]
+    pub fn places_history_import_from_chromium(
+        &self,
+        db_path: String,
+    ) -> ApiResult<HistoryMigrationResult> {
+        self.with_conn(|conn| import_chromium_history(conn, &db_path))
+    }
+
+    #[handle_error(crate::Error)]
+    pub fn places_bookmarks_import_from_chromium(
+        &self,
+        path: String,
+    ) -> ApiResult<BookmarksMigrationResult> {
+        self.with_conn(|conn| import_chromium_bookmarks(conn, &path))
+    }
+
+    #[handle_error(crate::Error)]
+    pub fn places_history_import_from_safari(
+        &self,
+        db_path: String,
+    ) -> ApiResult<HistoryMigrationResult> {
+        self.with_conn(|conn| import_safari_history(conn, &db_path))
+    }
+
+    #[handle_error(crate::Error)]
+    pub fn places_bookmarks_import_from_safari(
+        &self,
+        path: String,
+    ) -> ApiResult<BookmarksMigrationResult> {
+        self.with_conn(|conn| import_safari_bookmarks(conn, &path))
+    }
 }
 
 impl AsRef<SqlInterruptHandle> for PlacesConnection {
@@ -589,6 +917,11 @@ impl AsRef<SqlInterruptHandle> for PlacesConnection {
     }
 }
 
+pub struct BookmarkBatchResult {
+    pub inserted_guids: Vec<Guid>,
+    pub undo_token: u64,
+}
+
 #[derive(Clone, PartialEq, Eq)]
 pub struct HistoryVisitInfo {
     pub url: Url,
@@ -609,6 +942,7 @@ pub struct HistoryVisitInfosWithBound {
 pub struct TopFrecentSiteInfo {
     pub url: Url,
     pub title: Option<String>,
+    pub icon: Option<PageIcon>,
 }
 
 pub enum FrecencyThresholdOption {
@@ -637,6 +971,7 @@ impl FrecencyThresholdOption {
 pub struct SearchResult {
     pub url: Url,
     pub title: String,
+    pub icon: Option<PageIcon>,
     pub frecency: i64,
     pub reasons: Vec<MatchReason>,
 }
@@ -90,6 +90,10 @@ impl SyncEngine for HistorySyncEngine {
         // interrupted we'll re-download and re-apply them, but that will be fine in practice.
         let conn = self.db.lock();
         do_apply_incoming(&conn, &self.scope, inbound, telem)?;
+        drop(conn);
+        // Tell the observer about the applied changes now, in case the sync
+        // fails before `sync_finished`.
+        self.db.notify_observer();
         Ok(())
     }
 
@@ -110,6 +114,7 @@ impl SyncEngine for HistorySyncEngine {
     }
 
     fn sync_finished(&self) -> anyhow::Result<()> {
+        self.db.notify_observer();
         Ok(())
     }
 
@@ -145,11 +150,13 @@ impl SyncEngine for HistorySyncEngine {
 
     fn reset(&self, assoc: &EngineSyncAssociation) -> anyhow::Result<()> {
         reset(&self.db.lock(), assoc)?;
+        self.db.notify_observer();
         Ok(())
     }
 
     fn wipe(&self) -> anyhow::Result<()> {
         delete_everything(&self.db.lock())?;
+        self.db.notify_observer();
         Ok(())
     }
 }
@@ -0,0 +1,275 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use super::WINDOWS_EPOCH_OFFSET_MS;
+use crate::error::Result;
+use crate::import::common::{
+    insert_imported_bookmarks, parse_bookmark_url, sanitize_bookmark_timestamp,
+    BookmarksMigrationResult, ImportedRoot,
+};
+use crate::storage::bookmarks::json_tree::{BookmarkNode, BookmarkTreeNode, FolderNode};
+use crate::storage::bookmarks::BookmarkRootGuid;
+use crate::PlacesDb;
+use serde_derive::Deserialize;
+use std::time::Instant;
+use types::Timestamp;
+
+// The parts of Chromium's `Bookmarks` JSON file that we import.
+#[derive(Deserialize)]
+struct BookmarksFile {
+    roots: Roots,
+}
+
+#[derive(Deserialize)]
+struct Roots {
+    bookmark_bar: Option<Node>,
+    other: Option<Node>,
+    synced: Option<Node>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Node {
+    Url {
+        name: Option<String>,
+        url: String,
+        date_added: Option<String>,
+    },
+    Folder {
+        name: Option<String>,
+        #[serde(default)]
+        children: Vec<Node>,
+        date_added: Option<String>,
+        date_modified: Option<String>,
+    },
+}
+
+/// Chromium writes its times as strings of microseconds since the Windows epoch.
+fn parse_time(value: Option<&str>) -> Option<Timestamp> {
+    let millis = value?.parse::<i64>().ok()? / 1000 - WINDOWS_EPOCH_OFFSET_MS;
+    sanitize_bookmark_timestamp(Timestamp(u64::try_from(millis).ok()?))
+}
+
+/// Converts the children of a Chromium folder, skipping (and counting) any
+/// bookmarks with URLs we can't store.
+fn convert_children(children: Vec<Node>, num_failed: &mut u32) -> Vec<BookmarkTreeNode> {
+    children
+        .into_iter()
+        .filter_map(|child| match child {
+            Node::Url {
+                name,
+                url,
+                date_added,
+            } => match parse_bookmark_url(&url) {
+                Some(url) => Some(
+                    BookmarkNode {
+                        guid: None,
+                        date_added: parse_time(date_added.as_deref()),
+                        last_modified: None,
+                        title: name,
+                        url,
+                    }
+                    .into(),
+                ),
+                None => {
+                    *num_failed += 1;
+                    None
+                }
+            },
+            Node::Folder {
+                name,
+                children,
+                date_added,
+                date_modified,
+            } => Some(
+                FolderNode {
+                    guid: None,
+                    date_added: parse_time(date_added.as_deref()),
+                    last_modified: parse_time(date_modified.as_deref()),
+                    title: name,
+                    children: convert_children(children, num_failed),
+                }
+                .into(),
+            ),
+        })
+        .collect()
+}
+
+fn root_children(root: Option<Node>, num_failed: &mut u32) -> Vec<BookmarkTreeNode> {
+    match root {
+        Some(Node::Folder { children, .. }) => convert_children(children, num_failed),
+        _ => Vec::new(),
+    }
+}
+
+/// Imports bookmarks from a Chromium (Chrome, Edge, Brave, etc) `Bookmarks`
+/// JSON file.
+///
+/// Bookmarks are appended to our roots: the bookmarks bar to the toolbar,
+/// "Other bookmarks" to unfiled, and "Mobile bookmarks" to mobile. Bookmarks
+/// with URLs we can't store are skipped, and counted as failures.
+pub fn import(
+    conn: &PlacesDb,
+    path: impl AsRef<std::path::Path>,
+) -> Result<BookmarksMigrationResult> {
+    let import_start = Instant::now();
+    let file = std::fs::File::open(crate::util::unurl_path(path))?;
+    let BookmarksFile { roots } = serde_json::from_reader(std::io::BufReader::new(file))?;
+    let mut num_failed = 0;
+    let roots = vec![
+        ImportedRoot {
+            root: BookmarkRootGuid::Toolbar,
+            children: root_children(roots.bookmark_bar, &mut num_failed),
+        },
+        ImportedRoot {
+            root: BookmarkRootGuid::Unfiled,
+            children: root_children(roots.other, &mut num_failed),
+        },
+        ImportedRoot {
+            root: BookmarkRootGuid::Mobile,
+            children: root_children(roots.synced, &mut num_failed),
+        },
+    ];
+    insert_imported_bookmarks(conn, roots, num_failed, import_start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::places_api::test::new_mem_connection;
+    use crate::tests::assert_json_tree;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(
+            parse_time(Some("13245000000000000")),
+            Some(Timestamp(1_600_526_400_000))
+        );
+        assert_eq!(parse_time(Some("0")), None);
+        assert_eq!(parse_time(Some("soon")), None);
+        assert_eq!(parse_time(None), None);
+    }
+
+    #[test]
+    fn test_import() {
+        let _ = env_logger::try_init();
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("Bookmarks");
+        std::fs::write(
+            &path,
+            json!({
+                "checksum": "0",
+                "roots": {
+                    "bookmark_bar": {
+                        "type": "folder",
+                        "name": "Bookmarks bar",
+                        "children": [
+                            {
+                                "type": "url",
+                                "name": "Example",
+                                "url": "https://example.com",
+                                "date_added": "13245000000000000",
+                            },
+                            {
+                                "type": "folder",
+                                "name": "Folder",
+                                "children": [
+                                    {
+                                        "type": "url",
+                                        "name": "Mozilla",
+                                        "url": "https://www.mozilla.org/",
+                                    },
+                                    {
+                                        "type": "url",
+                                        "name": "Invalid",
+                                        "url": "not a url",
+                                    },
+                                ],
+                            },
+                        ],
+                    },
+                    "other": {
+                        "type": "folder",
+                        "name": "Other bookmarks",
+                        "children": [
+                            {
+                                "type": "url",
+                                "name": "Other",
+                                "url": "https://example.org/",
+                            },
+                        ],
+                    },
+                    "synced": {
+                        "type": "folder",
+                        "name": "Mobile bookmarks",
+                        "children": [],
+                    },
+                },
+                "version": 1,
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        let conn = new_mem_connection();
+        let result = import(&conn, &path).expect("should import");
+        assert_eq!(result.num_total, 5);
+        assert_eq!(result.num_succeeded, 4);
+        assert_eq!(result.num_failed, 1);
+
+        assert_json_tree(
+            &conn,
+            &BookmarkRootGuid::Toolbar.into(),
+            json!({
+                "guid": &BookmarkRootGuid::Toolbar.as_guid(),
+                "children": [
+                    {
+                        "title": "Example",
+                        "url": "https://example.com/",
+                        "date_added": 1_600_526_400_000u64,
+                    },
+                    {
+                        "title": "Folder",
+                        "children": [
+                            {
+                                "title": "Mozilla",
+                                "url": "https://www.mozilla.org/",
+                            },
+                        ],
+                    },
+                ],
+            }),
+        );
+        assert_json_tree(
+            &conn,
+            &BookmarkRootGuid::Unfiled.into(),
+            json!({
+                "guid": &BookmarkRootGuid::Unfiled.as_guid(),
+                "children": [
+                    {
+                        "title": "Other",
+                        "url": "https://example.org/",
+                    },
+                ],
+            }),
+        );
+    }
+
+    #[test]
+    fn test_import_invalid_file() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("Bookmarks");
+        std::fs::write(&path, "{\"roots\": 1}").unwrap();
+        let conn = new_mem_connection();
+        assert!(matches!(
+            import(&conn, &path),
+            Err(crate::Error::JsonError(_))
+        ));
+        assert!(matches!(
+            import(&conn, tmpdir.path().join("Missing")),
+            Err(crate::Error::IoError(_))
+        ));
+    }
+}
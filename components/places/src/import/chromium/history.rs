@@ -0,0 +1,237 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use super::WINDOWS_EPOCH_OFFSET_MS;
+use crate::error::Result;
+use crate::import::common::{import_staged_history, HistoryMigrationResult, MAX_IMPORTED_VISITS};
+use crate::types::VisitTransition;
+use crate::PlacesDb;
+use rusqlite::functions::{Context, FunctionFlags};
+
+// Chromium's page transitions, from `ui/base/page_transition_types.h`. The
+// core type is in the low byte, and the high bits are qualifiers.
+const CORE_MASK: i64 = 0xFF;
+const LINK: i64 = 0;
+const TYPED: i64 = 1;
+const AUTO_BOOKMARK: i64 = 2;
+const AUTO_SUBFRAME: i64 = 3;
+const MANUAL_SUBFRAME: i64 = 4;
+const GENERATED: i64 = 5;
+const AUTO_TOPLEVEL: i64 = 6;
+const FORM_SUBMIT: i64 = 7;
+const RELOAD: i64 = 8;
+const KEYWORD: i64 = 9;
+const KEYWORD_GENERATED: i64 = 10;
+const CLIENT_REDIRECT: i64 = 0x4000_0000;
+const SERVER_REDIRECT: i64 = 0x8000_0000;
+
+/// Maps a Chromium page transition to a visit transition.
+///
+/// Chromium doesn't record whether a server redirect was permanent, so all
+/// redirects are temporary. Searches and keywords are typed visits, like they
+/// are for us.
+pub fn visit_transition(transition: i64) -> VisitTransition {
+    if transition & (CLIENT_REDIRECT | SERVER_REDIRECT) != 0 {
+        return VisitTransition::RedirectTemporary;
+    }
+    match transition & CORE_MASK {
+        LINK | AUTO_TOPLEVEL | FORM_SUBMIT => VisitTransition::Link,
+        TYPED | GENERATED | KEYWORD | KEYWORD_GENERATED => VisitTransition::Typed,
+        AUTO_BOOKMARK => VisitTransition::Bookmark,
+        AUTO_SUBFRAME => VisitTransition::Embed,
+        MANUAL_SUBFRAME => VisitTransition::FramedLink,
+        RELOAD => VisitTransition::Reload,
+        _ => VisitTransition::Link,
+    }
+}
+
+/// Imports history from a Chromium (Chrome, Edge, Brave, etc) `History`
+/// database.
+///
+/// As with the iOS import, only the most recent `MAX_IMPORTED_VISITS` visits
+/// are imported. Chromium doesn't tell us which visits came from other
+/// devices, so they're all imported as local visits.
+///
+/// ### Basic process
+///
+/// - Attach the Chromium database.
+/// - Stage the most recent visits, and the pages they're for, in temp tables.
+/// - Add any entries to moz_places that are needed, and add the visits.
+/// - Update frecency for new items.
+/// - Cleanup (detach the Chromium database, etc).
+pub fn import(
+    conn: &PlacesDb,
+    path: impl AsRef<std::path::Path>,
+) -> Result<HistoryMigrationResult> {
+    let url = crate::util::ensure_url_path(path)?;
+    conn.create_scalar_function(
+        "chromium_visit_type",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx: &Context<'_>| -> rusqlite::Result<u8> {
+            Ok(visit_transition(ctx.get::<i64>(0).unwrap_or(LINK)) as u8)
+        },
+    )?;
+    import_staged_history(
+        conn,
+        &url,
+        "chromium",
+        COUNT_CHROMIUM_HISTORY_VISITS,
+        &FILL_STAGING,
+    )
+}
+
+const COUNT_CHROMIUM_HISTORY_VISITS: &str = "SELECT COUNT(*) FROM chromium.visits";
+
+lazy_static::lazy_static! {
+    static ref FILL_STAGING: String = format!(
+        "INSERT INTO temp.importVisits(import_place_id, visit_date, visit_type, is_local)
+            SELECT
+                url,
+                sanitize_timestamp(visit_time / 1000 - {windows_epoch_offset}),
+                chromium_visit_type(transition),
+                1
+            FROM chromium.visits
+            ORDER BY visit_time DESC
+            LIMIT {max_visits};
+
+        INSERT OR IGNORE INTO temp.importPlaces(id, url, url_hash, title)
+            SELECT
+                u.id,
+                validate_url(u.url),
+                hash(validate_url(u.url)),
+                sanitize_utf8(u.title)
+            FROM chromium.urls u
+            WHERE u.id IN (SELECT import_place_id FROM temp.importVisits)
+            AND validate_url(u.url) IS NOT NULL;",
+        windows_epoch_offset = WINDOWS_EPOCH_OFFSET_MS,
+        max_visits = MAX_IMPORTED_VISITS,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::places_api::test::new_mem_connection;
+    use crate::storage::history::get_visit_infos;
+    use crate::types::VisitTransitionSet;
+    use rusqlite::Connection;
+    use types::Timestamp;
+
+    #[test]
+    fn test_visit_transition() {
+        assert_eq!(visit_transition(LINK), VisitTransition::Link);
+        assert_eq!(visit_transition(TYPED), VisitTransition::Typed);
+        assert_eq!(visit_transition(GENERATED), VisitTransition::Typed);
+        assert_eq!(visit_transition(AUTO_BOOKMARK), VisitTransition::Bookmark);
+        assert_eq!(visit_transition(AUTO_SUBFRAME), VisitTransition::Embed);
+        assert_eq!(
+            visit_transition(MANUAL_SUBFRAME),
+            VisitTransition::FramedLink
+        );
+        assert_eq!(visit_transition(RELOAD), VisitTransition::Reload);
+        // Qualifiers other than redirects don't change the type.
+        assert_eq!(
+            visit_transition(TYPED | 0x1000_0000),
+            VisitTransition::Typed
+        );
+        assert_eq!(
+            visit_transition(LINK | SERVER_REDIRECT | 0x2000_0000),
+            VisitTransition::RedirectTemporary
+        );
+        assert_eq!(
+            visit_transition(LINK | CLIENT_REDIRECT),
+            VisitTransition::RedirectTemporary
+        );
+        assert_eq!(visit_transition(0xFE), VisitTransition::Link);
+    }
+
+    fn to_chromium_time(ts: Timestamp) -> i64 {
+        (ts.as_millis_i64() + WINDOWS_EPOCH_OFFSET_MS) * 1000
+    }
+
+    #[test]
+    fn test_import() {
+        let _ = env_logger::try_init();
+        let tmpdir = tempfile::tempdir().unwrap();
+        let history_path = tmpdir.path().join("History");
+        let chromium = Connection::open(&history_path).unwrap();
+        chromium
+            .execute_batch(
+                "CREATE TABLE urls(id INTEGER PRIMARY KEY, url LONGVARCHAR, title LONGVARCHAR,
+                                   visit_count INTEGER DEFAULT 0 NOT NULL);
+                 CREATE TABLE visits(id INTEGER PRIMARY KEY, url INTEGER NOT NULL,
+                                     visit_time INTEGER NOT NULL, from_visit INTEGER,
+                                     transition INTEGER DEFAULT 0 NOT NULL);
+                 INSERT INTO urls(id, url, title) VALUES
+                    (1, 'https://example.com/', 'Example'),
+                    (2, 'https://www.mozilla.org/', NULL),
+                    (3, 'not a url', 'Invalid');",
+            )
+            .unwrap();
+        // Visits in the future are imported at the current time, so use fixed times.
+        let latest = Timestamp(1_600_000_000_000);
+        let earlier = Timestamp(latest.0 - 10_000);
+        for (url_id, visit_time, transition) in [
+            (1, earlier, TYPED),
+            (1, latest, LINK | CLIENT_REDIRECT),
+            (2, latest, AUTO_BOOKMARK),
+            (3, latest, LINK),
+        ] {
+            chromium
+                .execute(
+                    "INSERT INTO visits(url, visit_time, transition) VALUES (?, ?, ?)",
+                    (url_id, to_chromium_time(visit_time), transition),
+                )
+                .unwrap();
+        }
+        drop(chromium);
+
+        let conn = new_mem_connection();
+        let result = import(&conn, &history_path).expect("should import");
+        assert_eq!(result.num_total, 4);
+        assert_eq!(result.num_succeeded, 3);
+        assert_eq!(result.num_failed, 1);
+
+        let visits = get_visit_infos(
+            &conn,
+            Timestamp(earlier.0 - 1),
+            latest,
+            VisitTransitionSet::empty(),
+        )
+        .unwrap();
+        let mut visits = visits
+            .into_iter()
+            .map(|v| (v.url.to_string(), v.title, v.timestamp, v.visit_type))
+            .collect::<Vec<_>>();
+        visits.sort_by(|a, b| (&a.0, a.2).cmp(&(&b.0, b.2)));
+        assert_eq!(
+            visits,
+            vec![
+                (
+                    "https://example.com/".to_string(),
+                    Some("Example".to_string()),
+                    earlier,
+                    VisitTransition::Typed
+                ),
+                (
+                    "https://example.com/".to_string(),
+                    Some("Example".to_string()),
+                    latest,
+                    VisitTransition::RedirectTemporary
+                ),
+                (
+                    "https://www.mozilla.org/".to_string(),
+                    None,
+                    latest,
+                    VisitTransition::Bookmark
+                ),
+            ]
+        );
+
+        // Importing again doesn't duplicate the visits.
+        let result = import(&conn, &history_path).expect("should import again");
+        assert_eq!(result.num_succeeded, 0);
+    }
+}
@@ -4,9 +4,14 @@
 
 use crate::db::PlacesDb;
 use crate::error::*;
+use crate::observer::note_many_changes;
+use crate::storage::bookmarks::json_tree::{insert_tree_in_tx, BookmarkTreeNode, FolderNode};
+use crate::storage::bookmarks::BookmarkRootGuid;
+use crate::storage::{delete_pending_temp_tables, update_all_frecencies_at_once};
 use rusqlite::{named_params, Connection};
 use serde::Serialize;
 use sql_support::ConnExt;
+use std::time::Instant;
 use types::Timestamp;
 use url::Url;
 
@@ -171,6 +176,236 @@ pub struct HistoryMigrationResult {
     pub total_duration: u64,
 }
 
+/// The result of importing bookmarks from another browser or a backup. The
+/// counts include folders and separators.
+#[derive(Serialize, PartialEq, Eq, Debug, Clone, Default)]
+pub struct BookmarksMigrationResult {
+    pub num_total: u32,
+    pub num_succeeded: u32,
+    pub num_failed: u32,
+    pub total_duration: u64,
+}
+
+/// Like the iOS import, we only import the most recent visits from other
+/// browsers. Older visits make the import slow, and add little to frecency.
+pub const MAX_IMPORTED_VISITS: u32 = 10000;
+
+/// Imports history from another browser's database, for importers which
+/// can express their source schema as SQL.
+///
+/// The database is attached as `db_alias`. `count_visits_sql` counts the
+/// visits we could import, and `fill_staging_sql` fills the two staging
+/// tables created by `CREATE_HISTORY_STAGING_TABLES`: `temp.importPlaces`,
+/// with a row for each page (using the source database's ids), and
+/// `temp.importVisits`, with a row for each visit to import, which refers to
+/// its page by `import_place_id`. Visits to pages without a valid URL are
+/// dropped, and count as failures.
+///
+/// Importers should register any SQL functions they need before calling this.
+pub fn import_staged_history(
+    conn: &PlacesDb,
+    db_file_url: &Url,
+    db_alias: &'static str,
+    count_visits_sql: &str,
+    fill_staging_sql: &str,
+) -> Result<HistoryMigrationResult> {
+    let scope = conn.begin_interrupt_scope()?;
+    define_history_migration_functions(conn)?;
+    let import_start = Instant::now();
+    log::info!("Attaching database {}", db_file_url);
+    let auto_detach = attached_database(conn, db_file_url, db_alias)?;
+    let tx = conn.begin_transaction()?;
+    let num_total = select_count(conn, count_visits_sql)?.min(MAX_IMPORTED_VISITS);
+    let num_visits_before = select_count(conn, "SELECT COUNT(*) FROM main.moz_historyvisits")?;
+    log::info!("The number of visits to import is: {:?}", num_total);
+
+    log::info!("Creating and populating staging tables");
+    tx.execute_batch(CREATE_HISTORY_STAGING_TABLES)?;
+    tx.execute_batch(fill_staging_sql)?;
+    scope.err_if_interrupted()?;
+
+    log::info!("Updating old titles that may be missing, but now are available");
+    tx.execute_batch(UPDATE_PLACES_TITLES_FROM_STAGING)?;
+    scope.err_if_interrupted()?;
+
+    log::info!("Populating missing entries in moz_places");
+    tx.execute_batch(FILL_MOZ_PLACES_FROM_STAGING)?;
+    scope.err_if_interrupted()?;
+
+    log::info!("Inserting the history visits");
+    tx.execute_batch(INSERT_HISTORY_VISITS_FROM_STAGING)?;
+    scope.err_if_interrupted()?;
+
+    log::info!("Insert all new entries into stale frecencies");
+    let now = Timestamp::now().as_millis();
+    tx.execute(ADD_TO_STALE_FRECENCIES, &[(":now", &now)])?;
+    tx.execute_batch(DROP_HISTORY_STAGING_TABLES)?;
+    note_many_changes(conn)?;
+    tx.commit()?;
+    log::info!("Successfully imported history visits!");
+
+    let num_succeeded = select_count(conn, "SELECT COUNT(*) FROM main.moz_historyvisits")?
+        .saturating_sub(num_visits_before);
+    let num_failed = num_total.saturating_sub(num_succeeded);
+
+    // As with the iOS import, we update the frecencies in their own
+    // transaction, so that readers can see the imported visits sooner.
+    log::info!("Updating all frecencies");
+    update_all_frecencies_at_once(conn, &scope)?;
+    log::info!("Frecencies updated!");
+    auto_detach.execute_now()?;
+
+    Ok(HistoryMigrationResult {
+        num_total,
+        num_succeeded,
+        num_failed,
+        total_duration: import_start.elapsed().as_millis() as u64,
+    })
+}
+
+const CREATE_HISTORY_STAGING_TABLES: &str = "
+    CREATE TEMP TABLE IF NOT EXISTS temp.importPlaces(
+        id INTEGER PRIMARY KEY,
+        url TEXT NOT NULL,
+        url_hash INTEGER NOT NULL,
+        title TEXT
+    );
+    CREATE TEMP TABLE IF NOT EXISTS temp.importVisits(
+        import_place_id INTEGER NOT NULL,
+        visit_date INTEGER NOT NULL,
+        visit_type INTEGER NOT NULL,
+        is_local TINYINT NOT NULL
+    );
+";
+
+const DROP_HISTORY_STAGING_TABLES: &str = "
+    DROP TABLE temp.importPlaces;
+    DROP TABLE temp.importVisits;
+";
+
+const UPDATE_PLACES_TITLES_FROM_STAGING: &str = "
+    UPDATE main.moz_places
+    SET title = IFNULL((SELECT t.title
+                        FROM temp.importPlaces t
+                        WHERE t.url_hash = main.moz_places.url_hash AND t.url = main.moz_places.url), title)
+";
+
+const FILL_MOZ_PLACES_FROM_STAGING: &str = "
+    INSERT OR IGNORE INTO main.moz_places(guid, url, url_hash, title, frecency, sync_change_counter)
+        SELECT
+            IFNULL(
+                (SELECT p.guid FROM main.moz_places p WHERE p.url_hash = t.url_hash AND p.url = t.url),
+                generate_guid()
+            ),
+            t.url,
+            t.url_hash,
+            t.title,
+            -1,
+            1
+        FROM temp.importPlaces t
+";
+
+// Visits we already have are skipped, so that importing the same database
+// twice doesn't duplicate them.
+const INSERT_HISTORY_VISITS_FROM_STAGING: &str = "
+    INSERT OR IGNORE INTO main.moz_historyvisits(from_visit, place_id, visit_date, visit_type, is_local)
+        SELECT
+            NULL, -- We don't rebuild redirect chains.
+            p.id,
+            v.visit_date,
+            v.visit_type,
+            v.is_local
+        FROM temp.importVisits v
+        JOIN temp.importPlaces t ON v.import_place_id = t.id
+        JOIN main.moz_places p ON p.url_hash = t.url_hash AND p.url = t.url
+        WHERE NOT EXISTS(SELECT 1 FROM main.moz_historyvisits e
+                         WHERE e.place_id = p.id AND e.visit_date = v.visit_date)
+";
+
+const ADD_TO_STALE_FRECENCIES: &str = "
+    INSERT OR IGNORE INTO main.moz_places_stale_frecencies(place_id, stale_at)
+    SELECT
+        p.id,
+        :now
+    FROM main.moz_places p
+    WHERE p.frecency = -1
+";
+
+/// Bookmarks imported from another browser, to be appended to one of our roots.
+pub struct ImportedRoot {
+    pub root: BookmarkRootGuid,
+    pub children: Vec<BookmarkTreeNode>,
+}
+
+/// Inserts bookmarks imported from another browser, appending them to the
+/// roots they belong in. `num_failed` is the number of items the importer
+/// couldn't convert, which are counted in the result. Everything is inserted
+/// in one transaction, so a failure doesn't leave a partial import behind.
+pub fn insert_imported_bookmarks(
+    conn: &PlacesDb,
+    roots: Vec<ImportedRoot>,
+    num_failed: u32,
+    import_start: Instant,
+) -> Result<BookmarksMigrationResult> {
+    let scope = conn.begin_interrupt_scope()?;
+    let tx = conn.begin_transaction()?;
+    let mut num_succeeded = 0;
+    for ImportedRoot { root, children } in roots {
+        if children.is_empty() {
+            continue;
+        }
+        let num_items = children.iter().map(count_tree_items).sum::<u32>();
+        insert_tree_in_tx(
+            conn,
+            FolderNode {
+                guid: Some(root.as_guid()),
+                children,
+                ..Default::default()
+            },
+        )?;
+        num_succeeded += num_items;
+        scope.err_if_interrupted()?;
+    }
+    if num_succeeded > 0 {
+        note_many_changes(conn)?;
+    }
+    delete_pending_temp_tables(conn)?;
+    tx.commit()?;
+    Ok(BookmarksMigrationResult {
+        num_total: num_succeeded + num_failed,
+        num_succeeded,
+        num_failed,
+        total_duration: import_start.elapsed().as_millis() as u64,
+    })
+}
+
+/// Counts the items in `node`, including `node` itself.
+pub(crate) fn count_tree_items(node: &BookmarkTreeNode) -> u32 {
+    match node {
+        BookmarkTreeNode::Folder { f } => 1 + f.children.iter().map(count_tree_items).sum::<u32>(),
+        _ => 1,
+    }
+}
+
+/// Parses a bookmarked URL from another browser, returning `None` if it isn't
+/// one we can store.
+pub fn parse_bookmark_url(href: &str) -> Option<Url> {
+    if href.len() > crate::storage::URL_LENGTH_MAX {
+        return None;
+    }
+    Url::parse(href).ok()
+}
+
+/// Returns `ts` if it's a plausible time for a bookmark to have been added or
+/// modified, or `None` to use the current time instead.
+pub fn sanitize_bookmark_timestamp(ts: Timestamp) -> Option<Timestamp> {
+    if Timestamp::EARLIEST <= ts && ts <= *NOW {
+        Some(ts)
+    } else {
+        None
+    }
+}
+
 pub fn define_history_migration_functions(c: &Connection) -> Result<()> {
     use rusqlite::functions::FunctionFlags;
     c.create_scalar_function(
@@ -9,6 +9,7 @@ use crate::history_sync::engine::LAST_SYNC_META_KEY;
 use crate::import::common::{
     attached_database, define_history_migration_functions, select_count, HistoryMigrationResult,
 };
+use crate::observer::note_many_changes;
 use crate::storage::{put_meta, update_all_frecencies_at_once};
 use crate::PlacesDb;
 use types::Timestamp;
@@ -89,6 +90,7 @@ fn do_import(
     // Once the migration is done, we also migrate the sync timestamp if we have one
     // this prevents us from having to do a **full** sync
     put_meta(conn, LAST_SYNC_META_KEY, &last_sync_timestamp)?;
+    note_many_changes(conn)?;
 
     tx.commit()?;
     log::info!("Successfully imported history visits!");
@@ -0,0 +1,13 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+pub mod bookmarks;
+pub mod history;
+mod plist;
+pub use bookmarks::import as import_bookmarks;
+pub use history::import as import_history;
+
+/// Safari stores history times as seconds since 2001-01-01, the Core Data epoch.
+/// This is the number of seconds between that and the Unix epoch.
+const CORE_DATA_EPOCH_OFFSET_SECS: i64 = 978_307_200;
@@ -0,0 +1,260 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use super::plist::{self, Value};
+use crate::error::Result;
+use crate::import::common::{
+    insert_imported_bookmarks, parse_bookmark_url, BookmarksMigrationResult, ImportedRoot,
+};
+use crate::storage::bookmarks::json_tree::{BookmarkNode, BookmarkTreeNode, FolderNode};
+use crate::storage::bookmarks::BookmarkRootGuid;
+use crate::PlacesDb;
+use std::time::Instant;
+
+// The titles Safari gives its special top-level folders.
+const BOOKMARKS_BAR: &str = "BookmarksBar";
+const BOOKMARKS_MENU: &str = "BookmarksMenu";
+const READING_LIST: &str = "com.apple.ReadingList";
+
+/// Converts a Safari bookmark, skipping (and counting) any with URLs we can't
+/// store. Proxies (Safari's "History" item) aren't bookmarks, so are skipped
+/// without counting them.
+fn convert_item(item: &Value, num_failed: &mut u32) -> Option<BookmarkTreeNode> {
+    match item.get("WebBookmarkType")?.as_str()? {
+        "WebBookmarkTypeLeaf" => {
+            let url = match item
+                .get("URLString")
+                .and_then(Value::as_str)
+                .and_then(parse_bookmark_url)
+            {
+                Some(url) => url,
+                None => {
+                    *num_failed += 1;
+                    return None;
+                }
+            };
+            let title = item
+                .get("URIDictionary")
+                .and_then(|d| d.get("title"))
+                .and_then(Value::as_str)
+                .map(ToOwned::to_owned);
+            Some(
+                BookmarkNode {
+                    guid: None,
+                    date_added: None,
+                    last_modified: None,
+                    title,
+                    url,
+                }
+                .into(),
+            )
+        }
+        "WebBookmarkTypeList" => Some(
+            FolderNode {
+                title: item
+                    .get("Title")
+                    .and_then(Value::as_str)
+                    .map(ToOwned::to_owned),
+                children: convert_children(item, num_failed),
+                ..Default::default()
+            }
+            .into(),
+        ),
+        _ => None,
+    }
+}
+
+fn convert_children(folder: &Value, num_failed: &mut u32) -> Vec<BookmarkTreeNode> {
+    folder
+        .get("Children")
+        .and_then(Value::as_array)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|child| convert_item(child, num_failed))
+        .collect()
+}
+
+/// Imports bookmarks from a Safari `Bookmarks.plist` file.
+///
+/// Bookmarks are appended to our roots: the favorites bar to the toolbar, the
+/// bookmarks menu to the menu, and everything else at the top level to
+/// unfiled. The reading list isn't imported. Bookmarks with URLs we can't
+/// store are skipped, and counted as failures.
+pub fn import(
+    conn: &PlacesDb,
+    path: impl AsRef<std::path::Path>,
+) -> Result<BookmarksMigrationResult> {
+    let import_start = Instant::now();
+    let data = std::fs::read(crate::util::unurl_path(path))?;
+    let top = plist::parse(&data)?;
+    let mut num_failed = 0;
+    let mut toolbar = Vec::new();
+    let mut menu = Vec::new();
+    let mut unfiled = Vec::new();
+    for item in top
+        .get("Children")
+        .and_then(Value::as_array)
+        .unwrap_or_default()
+    {
+        match item.get("Title").and_then(Value::as_str) {
+            Some(BOOKMARKS_BAR) => toolbar.extend(convert_children(item, &mut num_failed)),
+            Some(BOOKMARKS_MENU) => menu.extend(convert_children(item, &mut num_failed)),
+            Some(READING_LIST) => continue,
+            _ => unfiled.extend(convert_item(item, &mut num_failed)),
+        }
+    }
+    let roots = vec![
+        ImportedRoot {
+            root: BookmarkRootGuid::Toolbar,
+            children: toolbar,
+        },
+        ImportedRoot {
+            root: BookmarkRootGuid::Menu,
+            children: menu,
+        },
+        ImportedRoot {
+            root: BookmarkRootGuid::Unfiled,
+            children: unfiled,
+        },
+    ];
+    insert_imported_bookmarks(conn, roots, num_failed, import_start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::places_api::test::new_mem_connection;
+    use crate::tests::assert_json_tree;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn dict<const N: usize>(entries: [(&str, Value); N]) -> Value {
+        Value::Dictionary(
+            entries
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v))
+                .collect::<HashMap<_, _>>(),
+        )
+    }
+
+    fn string(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    fn folder<const N: usize>(title: &str, children: [Value; N]) -> Value {
+        dict([
+            ("WebBookmarkType", string("WebBookmarkTypeList")),
+            ("Title", string(title)),
+            ("Children", Value::Array(children.to_vec())),
+        ])
+    }
+
+    fn leaf(title: &str, url: &str) -> Value {
+        dict([
+            ("WebBookmarkType", string("WebBookmarkTypeLeaf")),
+            ("URLString", string(url)),
+            ("URIDictionary", dict([("title", string(title))])),
+        ])
+    }
+
+    #[test]
+    fn test_import() {
+        let _ = env_logger::try_init();
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("Bookmarks.plist");
+        let bookmarks = folder(
+            "",
+            [
+                dict([
+                    ("WebBookmarkType", string("WebBookmarkTypeProxy")),
+                    ("Title", string("History")),
+                ]),
+                folder(
+                    BOOKMARKS_BAR,
+                    [
+                        leaf("Example", "https://example.com"),
+                        folder(
+                            "Folder",
+                            [
+                                leaf("Mozilla", "https://www.mozilla.org/"),
+                                leaf("Invalid", "not a url"),
+                            ],
+                        ),
+                    ],
+                ),
+                folder(BOOKMARKS_MENU, [leaf("Menu", "https://example.org/")]),
+                folder(READING_LIST, [leaf("Article", "https://example.net/")]),
+                leaf("Top level", "https://example.edu/"),
+            ],
+        );
+        std::fs::write(&path, plist::to_bytes(&bookmarks)).unwrap();
+
+        let conn = new_mem_connection();
+        let result = import(&conn, &path).expect("should import");
+        assert_eq!(result.num_total, 6);
+        assert_eq!(result.num_succeeded, 5);
+        assert_eq!(result.num_failed, 1);
+
+        assert_json_tree(
+            &conn,
+            &BookmarkRootGuid::Toolbar.into(),
+            json!({
+                "guid": &BookmarkRootGuid::Toolbar.as_guid(),
+                "children": [
+                    {
+                        "title": "Example",
+                        "url": "https://example.com/",
+                    },
+                    {
+                        "title": "Folder",
+                        "children": [
+                            {
+                                "title": "Mozilla",
+                                "url": "https://www.mozilla.org/",
+                            },
+                        ],
+                    },
+                ],
+            }),
+        );
+        assert_json_tree(
+            &conn,
+            &BookmarkRootGuid::Menu.into(),
+            json!({
+                "guid": &BookmarkRootGuid::Menu.as_guid(),
+                "children": [
+                    {
+                        "title": "Menu",
+                        "url": "https://example.org/",
+                    },
+                ],
+            }),
+        );
+        assert_json_tree(
+            &conn,
+            &BookmarkRootGuid::Unfiled.into(),
+            json!({
+                "guid": &BookmarkRootGuid::Unfiled.as_guid(),
+                "children": [
+                    {
+                        "title": "Top level",
+                        "url": "https://example.edu/",
+                    },
+                ],
+            }),
+        );
+    }
+
+    #[test]
+    fn test_import_invalid_file() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("Bookmarks.plist");
+        std::fs::write(&path, "<plist version=\"1.0\"></plist>").unwrap();
+        let conn = new_mem_connection();
+        assert!(matches!(
+            import(&conn, &path),
+            Err(crate::Error::InvalidImportFile(_))
+        ));
+    }
+}
@@ -0,0 +1,186 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use super::CORE_DATA_EPOCH_OFFSET_SECS;
+use crate::error::Result;
+use crate::import::common::{import_staged_history, HistoryMigrationResult, MAX_IMPORTED_VISITS};
+use crate::types::VisitTransition;
+use crate::PlacesDb;
+
+/// Imports history from a Safari `History.db` database.
+///
+/// As with the iOS import, only the most recent `MAX_IMPORTED_VISITS` visits
+/// are imported. Visits which Safari synced from other devices are imported
+/// as remote visits.
+///
+/// Safari records which visits were reached by a redirect, but not what kind
+/// of redirect it was, so those are imported as temporary redirects, and all
+/// other visits as links.
+pub fn import(
+    conn: &PlacesDb,
+    path: impl AsRef<std::path::Path>,
+) -> Result<HistoryMigrationResult> {
+    let url = crate::util::ensure_url_path(path)?;
+    import_staged_history(
+        conn,
+        &url,
+        "safari",
+        COUNT_SAFARI_HISTORY_VISITS,
+        &FILL_STAGING,
+    )
+}
+
+const COUNT_SAFARI_HISTORY_VISITS: &str = "SELECT COUNT(*) FROM safari.history_visits";
+
+lazy_static::lazy_static! {
+    // Safari's visit times are seconds since 2001, as a `REAL`. Pages don't
+    // have titles; we use the title of their most recent visit.
+    static ref FILL_STAGING: String = format!(
+        "INSERT INTO temp.importVisits(import_place_id, visit_date, visit_type, is_local)
+            SELECT
+                history_item,
+                sanitize_float_timestamp((visit_time + {core_data_epoch_offset}) * 1000),
+                CASE WHEN redirect_source IS NULL THEN {link} ELSE {redirect} END,
+                origin = 0
+            FROM safari.history_visits
+            ORDER BY visit_time DESC
+            LIMIT {max_visits};
+
+        INSERT OR IGNORE INTO temp.importPlaces(id, url, url_hash, title)
+            SELECT
+                h.id,
+                validate_url(h.url),
+                hash(validate_url(h.url)),
+                (SELECT sanitize_utf8(v.title) FROM safari.history_visits v
+                 WHERE v.history_item = h.id AND v.title IS NOT NULL
+                 ORDER BY v.visit_time DESC
+                 LIMIT 1)
+            FROM safari.history_items h
+            WHERE h.id IN (SELECT import_place_id FROM temp.importVisits)
+            AND validate_url(h.url) IS NOT NULL;",
+        core_data_epoch_offset = CORE_DATA_EPOCH_OFFSET_SECS,
+        link = VisitTransition::Link as u8,
+        redirect = VisitTransition::RedirectTemporary as u8,
+        max_visits = MAX_IMPORTED_VISITS,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::places_api::test::new_mem_connection;
+    use crate::storage::history::get_visit_infos;
+    use crate::types::VisitTransitionSet;
+    use rusqlite::Connection;
+    use types::Timestamp;
+
+    fn to_safari_time(ts: Timestamp) -> f64 {
+        ts.as_millis_i64() as f64 / 1000.0 - CORE_DATA_EPOCH_OFFSET_SECS as f64
+    }
+
+    #[test]
+    fn test_import() {
+        let _ = env_logger::try_init();
+        let tmpdir = tempfile::tempdir().unwrap();
+        let history_path = tmpdir.path().join("History.db");
+        let safari = Connection::open(&history_path).unwrap();
+        safari
+            .execute_batch(
+                "CREATE TABLE history_items(id INTEGER PRIMARY KEY AUTOINCREMENT,
+                                            url TEXT NOT NULL UNIQUE,
+                                            visit_count INTEGER NOT NULL);
+                 CREATE TABLE history_visits(id INTEGER PRIMARY KEY AUTOINCREMENT,
+                                             history_item INTEGER NOT NULL REFERENCES history_items(id),
+                                             visit_time REAL NOT NULL,
+                                             title TEXT NULL,
+                                             redirect_source INTEGER NULL UNIQUE,
+                                             redirect_destination INTEGER NULL UNIQUE,
+                                             origin INTEGER NOT NULL DEFAULT 0);
+                 INSERT INTO history_items(id, url, visit_count) VALUES
+                    (1, 'http://example.com/', 1),
+                    (2, 'https://example.com/', 2),
+                    (3, 'not a url', 1);",
+            )
+            .unwrap();
+        // Visits in the future are imported at the current time, so use fixed times.
+        let latest = Timestamp(1_600_000_000_000);
+        let earlier = Timestamp(latest.0 - 10_000);
+        for (id, item, visit_time, title, redirect_source, origin) in [
+            (1, 1, earlier, None, None, 0),
+            (2, 2, earlier, Some("Old title"), Some(1), 0),
+            (3, 2, latest, Some("Example"), None, 1),
+            (4, 3, latest, Some("Invalid"), None, 0),
+        ] {
+            safari
+                .execute(
+                    "INSERT INTO history_visits(id, history_item, visit_time, title, redirect_source, origin)
+                     VALUES (?, ?, ?, ?, ?, ?)",
+                    rusqlite::params![
+                        id,
+                        item,
+                        to_safari_time(visit_time),
+                        title,
+                        redirect_source,
+                        origin
+                    ],
+                )
+                .unwrap();
+        }
+        drop(safari);
+
+        let conn = new_mem_connection();
+        let result = import(&conn, &history_path).expect("should import");
+        assert_eq!(result.num_total, 4);
+        assert_eq!(result.num_succeeded, 3);
+        assert_eq!(result.num_failed, 1);
+
+        let visits = get_visit_infos(
+            &conn,
+            Timestamp(earlier.0 - 1),
+            latest,
+            VisitTransitionSet::empty(),
+        )
+        .unwrap();
+        let mut visits = visits
+            .into_iter()
+            .map(|v| (v.url.to_string(), v.title, v.timestamp, v.visit_type))
+            .collect::<Vec<_>>();
+        visits.sort_by(|a, b| (&a.0, a.2).cmp(&(&b.0, b.2)));
+        assert_eq!(
+            visits,
+            vec![
+                (
+                    "http://example.com/".to_string(),
+                    None,
+                    earlier,
+                    VisitTransition::Link
+                ),
+                (
+                    "https://example.com/".to_string(),
+                    Some("Example".to_string()),
+                    earlier,
+                    VisitTransition::RedirectTemporary
+                ),
+                (
+                    "https://example.com/".to_string(),
+                    Some("Example".to_string()),
+                    latest,
+                    VisitTransition::Link
+                ),
+            ]
+        );
+        let is_local = conn
+            .query_row(
+                "SELECT is_local FROM moz_historyvisits WHERE visit_date = ?",
+                [latest],
+                |row| row.get::<_, bool>(0),
+            )
+            .unwrap();
+        assert!(!is_local);
+
+        // Importing again doesn't duplicate the visits.
+        let result = import(&conn, &history_path).expect("should import again");
+        assert_eq!(result.num_succeeded, 0);
+    }
+}
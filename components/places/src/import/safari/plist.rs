@@ -0,0 +1,424 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A reader for binary property lists, which is the format Safari uses for
+//! `Bookmarks.plist`. It reads the types that file uses; UIDs and sets aren't
+//! supported.
+//!
+//! The format is described in Apple's `CFBinaryPList.c`: a `bplist00` header,
+//! the objects, an offset table giving where each object starts, and a 32 byte
+//! trailer describing the offset table.
+
+use crate::error::{Error, Result};
+use std::cell::Cell;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Integer(i64),
+    Real(f64),
+    /// Seconds since 2001-01-01.
+    Date(f64),
+    Data(Vec<u8>),
+    String(String),
+    Array(Vec<Value>),
+    Dictionary(HashMap<String, Value>),
+}
+
+impl Value {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(a) => Some(a),
+            _ => None,
+        }
+    }
+
+    /// Looks up `key`, if this is a dictionary.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Dictionary(d) => d.get(key),
+            _ => None,
+        }
+    }
+}
+
+const MAGIC: &[u8] = b"bplist00";
+const TRAILER_LEN: usize = 32;
+// Property lists can nest, but not deeply, so this is plenty and keeps
+// malicious files from overflowing the stack.
+const MAX_DEPTH: usize = 64;
+// Objects can be referenced more than once - strings usually are - and each
+// reference is read separately. Real bookmark files only read a few objects
+// per bookmark, so this is plenty, and keeps files which reference the same
+// objects over and over from taking forever and using all our memory.
+const MAX_OBJECTS_READ: usize = 1 << 20;
+
+fn invalid(reason: &str) -> Error {
+    Error::InvalidImportFile(format!("Invalid property list: {}", reason))
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    offsets: Vec<usize>,
+    ref_size: usize,
+    objects_read: Cell<usize>,
+}
+
+/// Reads a big-endian unsigned integer of `size` bytes.
+fn read_uint(data: &[u8], size: usize) -> Result<u64> {
+    if size == 0 || size > 8 || data.len() < size {
+        return Err(invalid("bad integer"));
+    }
+    Ok(data[..size]
+        .iter()
+        .fold(0u64, |acc, &b| (acc << 8) | u64::from(b)))
+}
+
+pub fn parse(data: &[u8]) -> Result<Value> {
+    if data.len() < MAGIC.len() + TRAILER_LEN || !data.starts_with(MAGIC) {
+        return Err(invalid("not a binary property list"));
+    }
+    let trailer = &data[data.len() - TRAILER_LEN..];
+    let offset_size = usize::from(trailer[6]);
+    let ref_size = usize::from(trailer[7]);
+    let num_objects = read_uint(&trailer[8..], 8)? as usize;
+    let top_object = read_uint(&trailer[16..], 8)? as usize;
+    let table_offset = read_uint(&trailer[24..], 8)? as usize;
+
+    let table_len = num_objects
+        .checked_mul(offset_size)
+        .ok_or_else(|| invalid("bad offset table"))?;
+    let table = data
+        .get(table_offset..)
+        .and_then(|t| t.get(..table_len))
+        .ok_or_else(|| invalid("bad offset table"))?;
+    let offsets = table
+        .chunks(offset_size.max(1))
+        .map(|chunk| read_uint(chunk, offset_size).map(|o| o as usize))
+        .collect::<Result<Vec<_>>>()?;
+    let reader = Reader {
+        data,
+        offsets,
+        ref_size,
+        objects_read: Cell::new(0),
+    };
+    reader.read_object(top_object, 0)
+}
+
+impl<'a> Reader<'a> {
+    fn read_object(&self, index: usize, depth: usize) -> Result<Value> {
+        if depth > MAX_DEPTH {
+            return Err(invalid("too deeply nested"));
+        }
+        let objects_read = self.objects_read.get() + 1;
+        if objects_read > MAX_OBJECTS_READ {
+            return Err(invalid("too many objects"));
+        }
+        self.objects_read.set(objects_read);
+        let offset = *self
+            .offsets
+            .get(index)
+            .ok_or_else(|| invalid("bad object reference"))?;
+        let marker = *self
+            .data
+            .get(offset)
+            .ok_or_else(|| invalid("bad object offset"))?;
+        let (kind, info) = (marker >> 4, usize::from(marker & 0x0F));
+        match kind {
+            0x0 => match info {
+                0x8 => Ok(Value::Bool(false)),
+                0x9 => Ok(Value::Bool(true)),
+                _ => Err(invalid("unsupported object")),
+            },
+            0x1 => {
+                let size = 1 << info;
+                let n = read_uint(self.bytes(offset + 1, size)?, size)?;
+                Ok(Value::Integer(n as i64))
+            }
+            0x2 => {
+                let size = 1 << info;
+                let bytes = self.bytes(offset + 1, size)?;
+                let n = read_uint(bytes, size)?;
+                match size {
+                    4 => Ok(Value::Real(f64::from(f32::from_bits(n as u32)))),
+                    8 => Ok(Value::Real(f64::from_bits(n))),
+                    _ => Err(invalid("bad real")),
+                }
+            }
+            0x3 => {
+                let n = read_uint(self.bytes(offset + 1, 8)?, 8)?;
+                Ok(Value::Date(f64::from_bits(n)))
+            }
+            0x4 => {
+                let (len, start) = self.read_length(offset, info)?;
+                Ok(Value::Data(self.bytes(start, len)?.to_vec()))
+            }
+            0x5 => {
+                let (len, start) = self.read_length(offset, info)?;
+                // "ASCII" strings are really Latin-1 in practice.
+                Ok(Value::String(
+                    self.bytes(start, len)?
+                        .iter()
+                        .map(|&b| char::from(b))
+                        .collect(),
+                ))
+            }
+            0x6 => {
+                let (len, start) = self.read_length(offset, info)?;
+                let units = self
+                    .bytes(
+                        start,
+                        len.checked_mul(2).ok_or_else(|| invalid("bad string"))?,
+                    )?
+                    .chunks(2)
+                    .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                    .collect::<Vec<_>>();
+                Ok(Value::String(String::from_utf16_lossy(&units)))
+            }
+            0xA => {
+                let (len, start) = self.read_length(offset, info)?;
+                let refs = self.read_refs(start, len)?;
+                let items = refs
+                    .into_iter()
+                    .map(|r| self.read_object(r, depth + 1))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(Value::Array(items))
+            }
+            0xD => {
+                let (len, start) = self.read_length(offset, info)?;
+                let refs = self.read_refs(
+                    start,
+                    len.checked_mul(2)
+                        .ok_or_else(|| invalid("bad dictionary"))?,
+                )?;
+                let (keys, values) = refs.split_at(len);
+                let mut dict = HashMap::with_capacity(len);
+                for (&k, &v) in keys.iter().zip(values) {
+                    let key = match self.read_object(k, depth + 1)? {
+                        Value::String(s) => s,
+                        _ => return Err(invalid("dictionary key isn't a string")),
+                    };
+                    dict.insert(key, self.read_object(v, depth + 1)?);
+                }
+                Ok(Value::Dictionary(dict))
+            }
+            _ => Err(invalid("unsupported object")),
+        }
+    }
+
+    fn bytes(&self, start: usize, len: usize) -> Result<&'a [u8]> {
+        start
+            .checked_add(len)
+            .and_then(|end| self.data.get(start..end))
+            .ok_or_else(|| invalid("object out of bounds"))
+    }
+
+    /// Reads the length of an object whose marker is at `offset`, returning it
+    /// and the offset of the object's contents. Lengths of 15 or more are
+    /// written as an integer object after the marker.
+    fn read_length(&self, offset: usize, info: usize) -> Result<(usize, usize)> {
+        if info != 0xF {
+            return Ok((info, offset + 1));
+        }
+        let marker = *self
+            .data
+            .get(offset + 1)
+            .ok_or_else(|| invalid("bad length"))?;
+        if marker >> 4 != 0x1 {
+            return Err(invalid("bad length"));
+        }
+        let size = 1 << (marker & 0x0F);
+        let len = read_uint(self.bytes(offset + 2, size)?, size)? as usize;
+        Ok((len, offset + 2 + size))
+    }
+
+    fn read_refs(&self, start: usize, count: usize) -> Result<Vec<usize>> {
+        let len = count
+            .checked_mul(self.ref_size)
+            .ok_or_else(|| invalid("bad references"))?;
+        self.bytes(start, len)?
+            .chunks(self.ref_size.max(1))
+            .map(|chunk| read_uint(chunk, self.ref_size).map(|r| r as usize))
+            .collect()
+    }
+}
+
+/// Writes a binary property list, for tests. Objects are written depth first,
+/// with one byte references and two byte offsets, which is plenty for a test.
+#[cfg(test)]
+pub fn to_bytes(value: &Value) -> Vec<u8> {
+    fn write_length(out: &mut Vec<u8>, kind: u8, len: usize) {
+        if len < 0xF {
+            out.push(kind << 4 | len as u8);
+        } else {
+            out.push(kind << 4 | 0xF);
+            out.push(0x11);
+            out.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+    }
+
+    fn flatten(value: &Value, objects: &mut Vec<Vec<u8>>) -> u8 {
+        let index = objects.len();
+        objects.push(Vec::new());
+        let mut out = Vec::new();
+        match value {
+            Value::Bool(b) => out.push(if *b { 0x09 } else { 0x08 }),
+            Value::Integer(n) => {
+                out.push(0x13);
+                out.extend_from_slice(&n.to_be_bytes());
+            }
+            Value::Real(r) => {
+                out.push(0x23);
+                out.extend_from_slice(&r.to_bits().to_be_bytes());
+            }
+            Value::Date(d) => {
+                out.push(0x33);
+                out.extend_from_slice(&d.to_bits().to_be_bytes());
+            }
+            Value::Data(d) => {
+                write_length(&mut out, 0x4, d.len());
+                out.extend_from_slice(d);
+            }
+            Value::String(s) => {
+                let units = s.encode_utf16().collect::<Vec<_>>();
+                write_length(&mut out, 0x6, units.len());
+                for unit in units {
+                    out.extend_from_slice(&unit.to_be_bytes());
+                }
+            }
+            Value::Array(items) => {
+                write_length(&mut out, 0xA, items.len());
+                for item in items {
+                    out.push(flatten(item, objects));
+                }
+            }
+            Value::Dictionary(dict) => {
+                write_length(&mut out, 0xD, dict.len());
+                let mut keys = Vec::new();
+                let mut values = Vec::new();
+                for (k, v) in dict {
+                    keys.push(flatten(&Value::String(k.clone()), objects));
+                    values.push(flatten(v, objects));
+                }
+                out.extend(keys);
+                out.extend(values);
+            }
+        }
+        objects[index] = out;
+        index as u8
+    }
+
+    let mut objects = Vec::new();
+    flatten(value, &mut objects);
+    let mut out = MAGIC.to_vec();
+    let mut offsets = Vec::new();
+    for object in &objects {
+        offsets.push(out.len() as u16);
+        out.extend_from_slice(object);
+    }
+    let table_offset = out.len() as u64;
+    for offset in offsets {
+        out.extend_from_slice(&offset.to_be_bytes());
+    }
+    out.extend_from_slice(&[0; 6]);
+    out.push(2);
+    out.push(1);
+    out.extend_from_slice(&(objects.len() as u64).to_be_bytes());
+    out.extend_from_slice(&0u64.to_be_bytes());
+    out.extend_from_slice(&table_offset.to_be_bytes());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let value = Value::Dictionary(HashMap::from([
+            ("bool".to_string(), Value::Bool(true)),
+            ("integer".to_string(), Value::Integer(-2)),
+            ("real".to_string(), Value::Real(1.5)),
+            ("date".to_string(), Value::Date(621_692_790.0)),
+            ("data".to_string(), Value::Data(vec![1, 2, 3])),
+            (
+                "string".to_string(),
+                Value::String("Ünïcödé 🦊".to_string()),
+            ),
+            (
+                "array".to_string(),
+                Value::Array(vec![
+                    Value::String("a long string which needs an integer length".to_string()),
+                    Value::Array(vec![]),
+                ]),
+            ),
+        ]));
+        assert_eq!(parse(&to_bytes(&value)).unwrap(), value);
+    }
+
+    #[test]
+    fn test_ascii_string() {
+        // {"k": "v"}, with ASCII strings, as written by `plutil`.
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&[0xD1, 0x01, 0x02, 0x51, b'k', 0x51, b'v']);
+        data.extend_from_slice(&[8, 11, 13]);
+        data.extend_from_slice(&[0, 0, 0, 0, 0, 0, 1, 1]);
+        data.extend_from_slice(&3u64.to_be_bytes());
+        data.extend_from_slice(&0u64.to_be_bytes());
+        data.extend_from_slice(&15u64.to_be_bytes());
+        assert_eq!(
+            parse(&data).unwrap(),
+            Value::Dictionary(HashMap::from([(
+                "k".to_string(),
+                Value::String("v".to_string())
+            )]))
+        );
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(parse(b"").is_err());
+        assert!(parse(b"<?xml version=\"1.0\" encoding=\"UTF-8\"?><plist></plist>").is_err());
+        let mut data = to_bytes(&Value::Array(vec![Value::Bool(true)]));
+        // Point the top object's first reference at an object which doesn't exist.
+        data[9] = 5;
+        assert!(parse(&data).is_err());
+        // A reference cycle.
+        data[9] = 0;
+        assert!(parse(&data).is_err());
+    }
+
+    #[test]
+    fn test_shared_references() {
+        // Each array holds two references to the next, and the last object is
+        // `true`, so reading every reference would read 2^41 objects.
+        const NUM_ARRAYS: u8 = 40;
+        let mut data = MAGIC.to_vec();
+        let mut offsets = Vec::new();
+        for i in 0..NUM_ARRAYS {
+            offsets.push(data.len() as u8);
+            data.extend_from_slice(&[0xA2, i + 1, i + 1]);
+        }
+        offsets.push(data.len() as u8);
+        data.push(0x09);
+        let table_offset = data.len() as u64;
+        data.extend_from_slice(&offsets);
+        data.extend_from_slice(&[0, 0, 0, 0, 0, 0, 1, 1]);
+        data.extend_from_slice(&(offsets.len() as u64).to_be_bytes());
+        data.extend_from_slice(&0u64.to_be_bytes());
+        data.extend_from_slice(&table_offset.to_be_bytes());
+        assert!(matches!(
+            parse(&data),
+            Err(Error::InvalidImportFile(reason)) if reason.contains("too many objects")
+        ));
+    }
+}
@@ -0,0 +1,656 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Tells the app about changes to history and bookmarks, so that it can
+//! update its UI without polling.
+//!
+//! Triggers record each change in a temp table on the connection that made
+//! it. Because the table is part of the transaction, changes that are rolled
+//! back are never reported. After each call, the connection takes what's in
+//! the table and passes it to the observer in one batch.
+
+use crate::api::places_api::ConnectionType;
+use crate::db::PlacesDb;
+use crate::error::*;
+use crate::types::VisitTransition;
+use parking_lot::Mutex;
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ValueRef};
+use rusqlite::Row;
+use sql_support::ConnExt;
+use std::fmt;
+use std::sync::Arc;
+use sync_guid::Guid as SyncGuid;
+use types::Timestamp;
+use url::Url;
+
+/// Batches with more events than this are reported as `ManyChanges`.
+const MAX_EVENTS_PER_NOTIFICATION: usize = 250;
+
+/// Implemented by the app to hear about changes after they're committed.
+pub trait PlacesObserver: Send + Sync {
+    /// Called with the changes from each write to the database, in the order
+    /// they were first made. This is called on the thread that made the
+    /// changes, after the connection is released.
+    fn on_events(&self, events: Vec<PlacesEvent>);
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PlacesEvent {
+    /// One or more visits were added to a page. Only the most recent one is
+    /// reported.
+    VisitAdded {
+        url: Url,
+        guid: SyncGuid,
+        visit_date: Timestamp,
+        visit_type: VisitTransition,
+    },
+    PageRemoved {
+        url: Url,
+        guid: SyncGuid,
+    },
+    TitleChanged {
+        url: Url,
+        guid: SyncGuid,
+        title: Option<String>,
+    },
+    FrecencyChanged {
+        url: Url,
+        guid: SyncGuid,
+        frecency: i64,
+    },
+    BookmarkInserted {
+        guid: SyncGuid,
+        parent_guid: SyncGuid,
+        position: u32,
+        /// `None` for folders and separators.
+        url: Option<Url>,
+    },
+    BookmarkMoved {
+        guid: SyncGuid,
+        old_parent_guid: SyncGuid,
+        old_position: u32,
+        parent_guid: SyncGuid,
+        position: u32,
+        url: Option<Url>,
+    },
+    BookmarkRemoved {
+        guid: SyncGuid,
+        /// `None` if the parent was removed too.
+        parent_guid: Option<SyncGuid>,
+        position: u32,
+        url: Option<Url>,
+    },
+    /// Too much changed to report individually, for example after a sync or
+    /// an import. Observers should reload everything they show.
+    ManyChanges,
+}
+
+// The values of `moz_places_events_temp.kind`, which are also used by the
+// triggers in create_shared_triggers.sql.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum EventKind {
+    VisitAdded = 1,
+    PageRemoved = 2,
+    TitleChanged = 3,
+    FrecencyChanged = 4,
+    BookmarkInserted = 5,
+    BookmarkMoved = 6,
+    BookmarkRemoved = 7,
+    ManyChanges = 8,
+}
+
+impl EventKind {
+    fn from_primitive(p: u8) -> Option<Self> {
+        match p {
+            1 => Some(EventKind::VisitAdded),
+            2 => Some(EventKind::PageRemoved),
+            3 => Some(EventKind::TitleChanged),
+            4 => Some(EventKind::FrecencyChanged),
+            5 => Some(EventKind::BookmarkInserted),
+            6 => Some(EventKind::BookmarkMoved),
+            7 => Some(EventKind::BookmarkRemoved),
+            8 => Some(EventKind::ManyChanges),
+            _ => None,
+        }
+    }
+}
+
+impl FromSql for EventKind {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let v = value.as_i64()?;
+        if v < 0 || v > i64::from(u8::max_value()) {
+            return Err(FromSqlError::OutOfRange(v));
+        }
+        EventKind::from_primitive(v as u8).ok_or(FromSqlError::OutOfRange(v))
+    }
+}
+
+/// Holds the observer for an API. It's shared by all of the API's
+/// connections, so that changes made on any of them are reported.
+#[derive(Default)]
+pub struct SharedObserver(Mutex<Option<Arc<dyn PlacesObserver>>>);
+
+impl SharedObserver {
+    pub fn set(&self, observer: Option<Box<dyn PlacesObserver>>) {
+        *self.0.lock() = observer.map(Arc::from);
+    }
+
+    fn get(&self) -> Option<Arc<dyn PlacesObserver>> {
+        self.0.lock().clone()
+    }
+}
+
+impl fmt::Debug for SharedObserver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedObserver")
+            .field("has_observer", &self.0.lock().is_some())
+            .finish()
+    }
+}
+
+/// Changes taken from a connection, to pass to the observer once the
+/// connection is released.
+pub struct Notification {
+    observer: Arc<dyn PlacesObserver>,
+    events: Vec<PlacesEvent>,
+}
+
+impl Notification {
+    pub fn send(self) {
+        self.observer.on_events(self.events);
+    }
+}
+
+impl PlacesDb {
+    /// Takes the changes committed on this connection since the last call,
+    /// and returns them with the observer to send them to, if there is one.
+    /// Callers should release the connection before sending them, in case the
+    /// observer wants to use it.
+    pub fn take_notification(&self) -> Option<Notification> {
+        let events = match take_events(self) {
+            Ok(events) => events,
+            Err(e) => {
+                log::warn!("Failed to take changes for the observer: {}", e);
+                return None;
+            }
+        };
+        if events.is_empty() {
+            return None;
+        }
+        Some(Notification {
+            observer: self.observer().get()?,
+            events,
+        })
+    }
+}
+
+/// Records that many items changed in the current transaction, so that the
+/// observer is told to reload everything, instead of about each change.
+pub(crate) fn note_many_changes(db: &PlacesDb) -> Result<()> {
+    db.execute_cached(
+        "INSERT OR IGNORE INTO moz_places_events_temp(kind, guid) VALUES (:kind, '')",
+        &[(":kind", &(EventKind::ManyChanges as u8))],
+    )?;
+    Ok(())
+}
+
+/// Records that a bookmark was moved from `old_parent_guid` and
+/// `old_position` to where it is now. Moving an item that was inserted in
+/// the same transaction just updates where it was inserted.
+pub(crate) fn note_bookmark_moved(
+    db: &PlacesDb,
+    guid: &SyncGuid,
+    old_parent_guid: &SyncGuid,
+    old_position: u32,
+) -> Result<()> {
+    let inserted = db.execute_cached(
+        "UPDATE moz_places_events_temp SET
+             parent_guid = (SELECT p.guid FROM moz_bookmarks b
+                            JOIN moz_bookmarks p ON p.id = b.parent
+                            WHERE b.guid = :guid),
+             position = (SELECT position FROM moz_bookmarks WHERE guid = :guid)
+         WHERE kind = :inserted AND
+               guid = :guid",
+        &[
+            (":guid", guid as &dyn rusqlite::ToSql),
+            (":inserted", &(EventKind::BookmarkInserted as u8)),
+        ],
+    )?;
+    if inserted > 0 {
+        return Ok(());
+    }
+    // If the item moves more than once, we report where it started and
+    // where it ended up.
+    db.execute_cached(
+        "INSERT INTO moz_places_events_temp(kind, guid, url, parent_guid, position,
+                                            old_parent_guid, old_position)
+         SELECT :moved, b.guid, h.url, p.guid, b.position, :old_parent_guid, :old_position
+         FROM moz_bookmarks b
+         JOIN moz_bookmarks p ON p.id = b.parent
+         LEFT JOIN moz_places h ON h.id = b.fk
+         WHERE b.guid = :guid
+         ON CONFLICT(kind, guid) DO UPDATE SET
+             parent_guid = excluded.parent_guid,
+             position = excluded.position,
+             url = excluded.url",
+        &[
+            (":guid", guid as &dyn rusqlite::ToSql),
+            (":moved", &(EventKind::BookmarkMoved as u8)),
+            (":old_parent_guid", old_parent_guid),
+            (":old_position", &old_position),
+        ],
+    )?;
+    Ok(())
+}
+
+fn take_events(db: &PlacesDb) -> Result<Vec<PlacesEvent>> {
+    // Read-only connections can't change anything, so they don't have the
+    // table.
+    if db.conn_type() == ConnectionType::ReadOnly {
+        return Ok(Vec::new());
+    }
+    let rows = db.query_rows_and_then(
+        "SELECT kind, guid, url, title, parent_guid, old_parent_guid, position,
+                old_position, frecency, visit_date, visit_type
+         FROM moz_places_events_temp
+         ORDER BY id",
+        [],
+        EventRow::from_row,
+    )?;
+    if rows.is_empty() {
+        return Ok(Vec::new());
+    }
+    db.execute_cached("DELETE FROM moz_places_events_temp", [])?;
+
+    // Changes made by Sync are always applied in bulk.
+    if db.conn_type() == ConnectionType::Sync
+        || rows.len() > MAX_EVENTS_PER_NOTIFICATION
+        || rows.iter().any(|row| row.kind == EventKind::ManyChanges)
+    {
+        return Ok(vec![PlacesEvent::ManyChanges]);
+    }
+    Ok(rows.into_iter().filter_map(EventRow::into_event).collect())
+}
+
+struct EventRow {
+    kind: EventKind,
+    guid: SyncGuid,
+    url: Option<String>,
+    title: Option<String>,
+    parent_guid: Option<SyncGuid>,
+    old_parent_guid: Option<SyncGuid>,
+    position: Option<u32>,
+    old_position: Option<u32>,
+    frecency: Option<i64>,
+    visit_date: Option<Timestamp>,
+    visit_type: Option<u8>,
+}
+
+impl EventRow {
+    fn from_row(row: &Row<'_>) -> Result<Self> {
+        Ok(Self {
+            kind: row.get("kind")?,
+            guid: row.get("guid")?,
+            url: row.get("url")?,
+            title: row.get("title")?,
+            parent_guid: row.get("parent_guid")?,
+            old_parent_guid: row.get("old_parent_guid")?,
+            position: row.get("position")?,
+            old_position: row.get("old_position")?,
+            frecency: row.get("frecency")?,
+            visit_date: row.get("visit_date")?,
+            visit_type: row.get("visit_type")?,
+        })
+    }
+
+    /// Converts the row to an event, or returns `None` for rows that we
+    /// can't report, like pages with invalid URLs and the roots.
+    fn into_event(self) -> Option<PlacesEvent> {
+        let url = match self.url.as_deref().map(Url::parse).transpose() {
+            Ok(url) => url,
+            Err(e) => {
+                log::warn!("Not reporting change to item with invalid URL: {}", e);
+                return None;
+            }
+        };
+        let guid = self.guid;
+        Some(match self.kind {
+            EventKind::VisitAdded => PlacesEvent::VisitAdded {
+                url: url?,
+                guid,
+                visit_date: self.visit_date?,
+                visit_type: self.visit_type.and_then(VisitTransition::from_primitive)?,
+            },
+            EventKind::PageRemoved => PlacesEvent::PageRemoved { url: url?, guid },
+            EventKind::TitleChanged => PlacesEvent::TitleChanged {
+                url: url?,
+                guid,
+                title: self.title,
+            },
+            EventKind::FrecencyChanged => PlacesEvent::FrecencyChanged {
+                url: url?,
+                guid,
+                frecency: self.frecency?,
+            },
+            EventKind::BookmarkInserted => PlacesEvent::BookmarkInserted {
+                guid,
+                parent_guid: self.parent_guid?,
+                position: self.position?,
+                url,
+            },
+            EventKind::BookmarkMoved => PlacesEvent::BookmarkMoved {
+                guid,
+                old_parent_guid: self.old_parent_guid?,
+                old_position: self.old_position?,
+                parent_guid: self.parent_guid?,
+                position: self.position?,
+                url,
+            },
+            EventKind::BookmarkRemoved => PlacesEvent::BookmarkRemoved {
+                guid,
+                parent_guid: self.parent_guid,
+                position: self.position?,
+                url,
+            },
+            EventKind::ManyChanges => PlacesEvent::ManyChanges,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::places_api::test::new_mem_connections;
+    use crate::history_sync::HistorySyncEngine;
+    use crate::observation::VisitObservation;
+    use crate::storage::bookmarks::{
+        delete_bookmark, insert_bookmark, update_bookmark_from_info, BookmarkPosition,
+        BookmarkRootGuid, BookmarkUpdateInfo, InsertableBookmark, InsertableFolder,
+    };
+    use crate::storage::history::{apply_observation, apply_observation_direct, delete_visits_for};
+    use pretty_assertions::assert_eq;
+    use sync15::engine::SyncEngine;
+
+    #[derive(Clone, Default)]
+    struct RecordingObserver(Arc<Mutex<Vec<PlacesEvent>>>);
+
+    impl PlacesObserver for RecordingObserver {
+        fn on_events(&self, events: Vec<PlacesEvent>) {
+            self.0.lock().extend(events);
+        }
+    }
+
+    impl RecordingObserver {
+        fn take(&self) -> Vec<PlacesEvent> {
+            std::mem::take(&mut *self.0.lock())
+        }
+    }
+
+    fn notify(db: &PlacesDb) {
+        if let Some(notification) = db.take_notification() {
+            notification.send();
+        }
+    }
+
+    #[test]
+    fn test_history_events() -> Result<()> {
+        let conns = new_mem_connections();
+        let conn = &conns.write;
+        let observer = RecordingObserver::default();
+        conns.api.set_observer(Some(Box::new(observer.clone())));
+
+        let url = Url::parse("https://example.com/")?;
+        for at in [1000, 2000] {
+            apply_observation(
+                conn,
+                VisitObservation::new(url.clone())
+                    .with_at(Timestamp(at))
+                    .with_visit_type(VisitTransition::Link)
+                    .with_title("Example".to_owned()),
+            )?;
+            notify(conn);
+        }
+        let events = observer.take();
+        let guid = match &events[0] {
+            PlacesEvent::VisitAdded { guid, .. } => guid.clone(),
+            event => panic!("Unexpected event {:?}", event),
+        };
+        // Each write is reported separately, but the title only changed in
+        // the first one.
+        assert_eq!(
+            events[..2],
+            [
+                PlacesEvent::VisitAdded {
+                    url: url.clone(),
+                    guid: guid.clone(),
+                    visit_date: Timestamp(1000),
+                    visit_type: VisitTransition::Link,
+                },
+                PlacesEvent::TitleChanged {
+                    url: url.clone(),
+                    guid: guid.clone(),
+                    title: Some("Example".to_owned()),
+                },
+            ]
+        );
+        assert!(matches!(events[2], PlacesEvent::FrecencyChanged { .. }));
+        assert_eq!(
+            events[3],
+            PlacesEvent::VisitAdded {
+                url: url.clone(),
+                guid: guid.clone(),
+                visit_date: Timestamp(2000),
+                visit_type: VisitTransition::Link,
+            }
+        );
+        assert!(!events[3..]
+            .iter()
+            .any(|event| matches!(event, PlacesEvent::TitleChanged { .. })));
+
+        // Changes that are rolled back aren't reported.
+        let tx = conn.begin_transaction()?;
+        apply_observation_direct(
+            conn,
+            VisitObservation::new(url.clone()).with_visit_type(VisitTransition::Typed),
+        )?;
+        tx.rollback()?;
+        assert!(conn.take_notification().is_none());
+
+        // Removing the page drops the changes to it from the same write.
+        delete_visits_for(conn, &guid)?;
+        notify(conn);
+        assert_eq!(observer.take(), [PlacesEvent::PageRemoved { url, guid }]);
+
+        // Nothing is reported once the observer is removed.
+        conns.api.set_observer(None);
+        apply_observation(
+            conn,
+            VisitObservation::new(Url::parse("https://example.org/")?)
+                .with_visit_type(VisitTransition::Link),
+        )?;
+        assert!(conn.take_notification().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_bookmark_events() -> Result<()> {
+        let conns = new_mem_connections();
+        let conn = &conns.write;
+        let observer = RecordingObserver::default();
+        conns.api.set_observer(Some(Box::new(observer.clone())));
+        let bookmark_events = || {
+            observer
+                .take()
+                .into_iter()
+                .filter(|event| {
+                    matches!(
+                        event,
+                        PlacesEvent::BookmarkInserted { .. }
+                            | PlacesEvent::BookmarkMoved { .. }
+                            | PlacesEvent::BookmarkRemoved { .. }
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let url = Url::parse("https://example.com/")?;
+        let folder_guid = insert_bookmark(
+            conn,
+            InsertableFolder {
+                parent_guid: BookmarkRootGuid::Unfiled.into(),
+                position: BookmarkPosition::Append,
+                date_added: None,
+                last_modified: None,
+                guid: Some("folderAAAAAA".into()),
+                title: Some("A".into()),
+                children: vec![],
+            }
+            .into(),
+        )?;
+        let bookmark_guid = insert_bookmark(
+            conn,
+            InsertableBookmark {
+                parent_guid: BookmarkRootGuid::Toolbar.into(),
+                position: BookmarkPosition::Append,
+                date_added: None,
+                last_modified: None,
+                guid: Some("bookmarkAAAA".into()),
+                url: url.clone(),
+                title: None,
+            }
+            .into(),
+        )?;
+        notify(conn);
+        assert_eq!(
+            bookmark_events(),
+            [
+                PlacesEvent::BookmarkInserted {
+                    guid: folder_guid.clone(),
+                    parent_guid: BookmarkRootGuid::Unfiled.into(),
+                    position: 0,
+                    url: None,
+                },
+                PlacesEvent::BookmarkInserted {
+                    guid: bookmark_guid.clone(),
+                    parent_guid: BookmarkRootGuid::Toolbar.into(),
+                    position: 0,
+                    url: Some(url.clone()),
+                },
+            ]
+        );
+
+        update_bookmark_from_info(
+            conn,
+            BookmarkUpdateInfo {
+                guid: bookmark_guid.clone(),
+                title: Some("Example".into()),
+                url: None,
+                parent_guid: Some(folder_guid.clone()),
+                position: None,
+            },
+        )?;
+        notify(conn);
+        assert_eq!(
+            bookmark_events(),
+            [PlacesEvent::BookmarkMoved {
+                guid: bookmark_guid.clone(),
+                old_parent_guid: BookmarkRootGuid::Toolbar.into(),
+                old_position: 0,
+                parent_guid: folder_guid.clone(),
+                position: 0,
+                url: Some(url.clone()),
+            }]
+        );
+
+        // Changing only the title isn't a move.
+        update_bookmark_from_info(
+            conn,
+            BookmarkUpdateInfo {
+                guid: bookmark_guid.clone(),
+                title: Some("Another title".into()),
+                url: None,
+                parent_guid: None,
+                position: None,
+            },
+        )?;
+        notify(conn);
+        assert!(bookmark_events().is_empty());
+
+        // Removing a folder also removes its children.
+        delete_bookmark(conn, &folder_guid)?;
+        notify(conn);
+        let events = bookmark_events();
+        assert_eq!(events.len(), 2);
+        assert!(events.contains(&PlacesEvent::BookmarkRemoved {
+            guid: folder_guid,
+            parent_guid: Some(BookmarkRootGuid::Unfiled.into()),
+            position: 0,
+            url: None,
+        }));
+        assert!(events.iter().any(|event| matches!(
+            event,
+            PlacesEvent::BookmarkRemoved { guid, url: Some(removed_url), .. }
+                if *guid == bookmark_guid && *removed_url == url
+        )));
+        Ok(())
+    }
+
+    #[test]
+    fn test_many_changes() -> Result<()> {
+        let conns = new_mem_connections();
+        let conn = &conns.write;
+        let observer = RecordingObserver::default();
+        conns.api.set_observer(Some(Box::new(observer.clone())));
+
+        let tx = conn.begin_transaction()?;
+        apply_observation_direct(
+            conn,
+            VisitObservation::new(Url::parse("https://example.com/")?)
+                .with_visit_type(VisitTransition::Link),
+        )?;
+        note_many_changes(conn)?;
+        tx.commit()?;
+        notify(conn);
+        assert_eq!(observer.take(), [PlacesEvent::ManyChanges]);
+
+        // Large batches are also reported as `ManyChanges`.
+        let tx = conn.begin_transaction()?;
+        for i in 0..=MAX_EVENTS_PER_NOTIFICATION {
+            apply_observation_direct(
+                conn,
+                VisitObservation::new(Url::parse(&format!("https://example.com/{}", i))?)
+                    .with_visit_type(VisitTransition::Link),
+            )?;
+        }
+        tx.commit()?;
+        notify(conn);
+        assert_eq!(observer.take(), [PlacesEvent::ManyChanges]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_connection_events() -> Result<()> {
+        let conns = new_mem_connections();
+        let observer = RecordingObserver::default();
+        conns.api.set_observer(Some(Box::new(observer.clone())));
+        apply_observation(
+            &conns.write,
+            VisitObservation::new(Url::parse("https://example.com/")?)
+                .with_visit_type(VisitTransition::Link),
+        )?;
+        notify(&conns.write);
+        observer.take();
+
+        // Wiping history on the Sync connection reports the removed pages
+        // right away, instead of with the next write on that connection.
+        let engine = HistorySyncEngine::new(conns.api.get_sync_connection()?)?;
+        engine.wipe().expect("should wipe history");
+        assert_eq!(observer.take(), [PlacesEvent::ManyChanges]);
+
+        conns.api.reset_history().expect("should reset history");
+        assert!(observer.take().is_empty());
+        Ok(())
+    }
+}
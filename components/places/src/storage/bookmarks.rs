@@ -10,6 +10,7 @@ use crate::bookmark_sync::engine::{
 };
 use crate::db::PlacesDb;
 use crate::error::*;
+use crate::observer::note_bookmark_moved;
 use crate::types::{BookmarkType, SyncStatus};
 use rusqlite::{self, Connection, Row};
 #[cfg(test)]
@@ -23,9 +24,14 @@ use url::Url;
 
 pub use root_guid::{BookmarkRootGuid, USER_CONTENT_ROOTS};
 
+pub mod backup;
+pub mod batch;
 mod conversions;
+pub mod duplicates;
 pub mod fetch;
 pub mod json_tree;
+pub mod keywords;
+pub mod repair;
 mod root_guid;
 
 fn create_root(
@@ -719,6 +725,9 @@ fn update_bookmark_in_tx(
             (":id", &raw.row_id),
         ],
     )?;
+    if parent_id != existing_parent_id || position != raw.position {
+        note_bookmark_moved(db, &raw.guid, existing_parent_guid, raw.position)?;
+    }
 
     let sql_counter = "
         UPDATE moz_bookmarks SET syncChangeCounter = syncChangeCounter + 1
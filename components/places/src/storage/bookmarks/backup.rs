@@ -0,0 +1,620 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Exporting bookmarks to, and restoring them from, files in the formats
+//! desktop uses: the Netscape bookmarks HTML format, which every browser can
+//! read, and Firefox's `.json` backups, which keep guids.
+
+use super::json_tree::{fetch_tree, BookmarkTreeNode, FetchDepth, FolderNode};
+use super::{insert_bookmark_in_tx, BookmarkRootGuid, InsertableItem, USER_CONTENT_ROOTS};
+use crate::db::PlacesDb;
+use crate::error::*;
+use crate::import::common::{count_tree_items, BookmarksMigrationResult};
+use crate::observer::note_many_changes;
+use crate::storage::tags::{tag_url_in_tx, validate_tag};
+use sql_support::ConnExt;
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+use sync_guid::Guid as SyncGuid;
+use url::Url;
+
+mod html;
+mod json;
+
+/// The file formats we can export bookmarks to and import them from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BookmarksBackupFormat {
+    /// The Netscape bookmarks HTML format, which all browsers can import.
+    Html,
+    /// Firefox's `.json` bookmark backups.
+    Json,
+}
+
+/// How imported bookmarks are combined with the existing ones.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BookmarksImportMode {
+    /// Replace all bookmarks with the imported ones, as desktop does when
+    /// restoring a backup. Guids in the file are kept.
+    ReplaceAll,
+    /// Add the imported bookmarks to an existing folder, with a subfolder for
+    /// each of the file's roots. The imported bookmarks get new guids.
+    MergeIntoFolder { parent_guid: SyncGuid },
+}
+
+/// The contents of a backup file.
+struct Backup {
+    /// The user content roots.
+    roots: Vec<BackupRoot>,
+    /// The tags for each bookmarked URL.
+    tags: HashMap<Url, Vec<String>>,
+    /// The number of items in the file we couldn't read, like bookmarks with
+    /// invalid URLs.
+    num_failed: u32,
+}
+
+/// A user content root in a backup file, and the items in it.
+struct BackupRoot {
+    guid: BookmarkRootGuid,
+    children: Vec<BookmarkTreeNode>,
+}
+
+impl Backup {
+    fn root_mut(&mut self, root: BookmarkRootGuid) -> &mut BackupRoot {
+        let index = match self.roots.iter().position(|r| r.guid == root) {
+            Some(index) => index,
+            None => {
+                self.roots.push(BackupRoot {
+                    guid: root,
+                    children: Vec::new(),
+                });
+                self.roots.len() - 1
+            }
+        };
+        &mut self.roots[index]
+    }
+}
+
+/// The title we give a root when it isn't a root, like the root folders in
+/// HTML files and the subfolders created by `MergeIntoFolder`.
+fn root_title(root: BookmarkRootGuid) -> &'static str {
+    match root {
+        BookmarkRootGuid::Root => "",
+        BookmarkRootGuid::Menu => "Bookmarks Menu",
+        BookmarkRootGuid::Toolbar => "Bookmarks Toolbar",
+        BookmarkRootGuid::Unfiled => "Other Bookmarks",
+        BookmarkRootGuid::Mobile => "Mobile Bookmarks",
+    }
+}
+
+/// Writes all bookmarks, with their tags, to `path`.
+pub fn export_bookmarks(
+    db: &PlacesDb,
+    path: impl AsRef<std::path::Path>,
+    format: BookmarksBackupFormat,
+) -> Result<()> {
+    let tree = match fetch_tree(db, BookmarkRootGuid::Root.guid(), &FetchDepth::Deepest)? {
+        Some((BookmarkTreeNode::Folder { f }, _, _)) => f,
+        _ => return Err(Corruption::InvalidLocalRoots.into()),
+    };
+    let tags = fetch_all_tags(db)?;
+    let contents = match format {
+        BookmarksBackupFormat::Html => html::write(&tree, &tags),
+        BookmarksBackupFormat::Json => json::write(&tree, &tags)?,
+    };
+    std::fs::write(crate::util::unurl_path(path), contents)?;
+    Ok(())
+}
+
+fn fetch_all_tags(db: &PlacesDb) -> Result<HashMap<Url, Vec<String>>> {
+    let rows = db.query_rows_and_then(
+        "SELECT h.url, t.tag
+         FROM moz_tags_relation r
+         JOIN moz_tags t ON t.id = r.tag_id
+         JOIN moz_places h ON h.id = r.place_id
+         ORDER BY t.tag",
+        [],
+        |row| -> rusqlite::Result<_> { Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)) },
+    )?;
+    let mut tags: HashMap<Url, Vec<String>> = HashMap::new();
+    for (url, tag) in rows {
+        if let Ok(url) = Url::parse(&url) {
+            tags.entry(url).or_default().push(tag);
+        }
+    }
+    Ok(tags)
+}
+
+/// Imports bookmarks, with their tags, from `path`.
+///
+/// With `ReplaceAll`, existing bookmarks and tags are deleted as if the user
+/// deleted them, so that, like on desktop, the next sync replaces the server's
+/// bookmarks instead of merging with them: everything the server has gets a
+/// tombstone, and the restored bookmarks are all uploaded. We don't reset the
+/// bookmark sync state, because the first sync after a reset merges the
+/// server's bookmarks back in. Items with a guid we already used, or which
+/// isn't valid, get a new one.
+///
+/// Items we can't read, like bookmarks with invalid URLs, are skipped, and
+/// counted as failures.
+pub fn import_bookmarks(
+    db: &PlacesDb,
+    path: impl AsRef<std::path::Path>,
+    format: BookmarksBackupFormat,
+    mode: BookmarksImportMode,
+) -> Result<BookmarksMigrationResult> {
+    let import_start = Instant::now();
+    let contents = std::fs::read_to_string(crate::util::unurl_path(path))?;
+    let backup = match format {
+        BookmarksBackupFormat::Html => html::parse(&contents),
+        BookmarksBackupFormat::Json => json::parse(&contents)?,
+    };
+    let num_succeeded = backup
+        .roots
+        .iter()
+        .flat_map(|root| &root.children)
+        .map(count_tree_items)
+        .sum::<u32>();
+
+    let tx = db.begin_transaction()?;
+    match mode {
+        BookmarksImportMode::ReplaceAll => replace_all(db, backup.roots)?,
+        BookmarksImportMode::MergeIntoFolder { parent_guid } => {
+            merge_into_folder(db, &parent_guid, backup.roots)?
+        }
+    }
+    for (url, tags) in backup.tags {
+        for tag in tags {
+            if validate_tag(&tag).ensure_valid().is_ok() {
+                tag_url_in_tx(db, &url, &tag)?;
+            }
+        }
+    }
+    crate::storage::delete_pending_temp_tables(db)?;
+    note_many_changes(db)?;
+    tx.commit()?;
+
+    Ok(BookmarksMigrationResult {
+        num_total: num_succeeded + backup.num_failed,
+        num_succeeded,
+        num_failed: backup.num_failed,
+        total_duration: import_start.elapsed().as_millis() as u64,
+    })
+}
+
+fn replace_all(db: &PlacesDb, roots: Vec<BackupRoot>) -> Result<()> {
+    // The tombstones trigger records deletions of synced items. We can't wipe
+    // the server from here, so we also write tombstones for everything else
+    // in the synced tree, like items we couldn't apply locally, so that the
+    // next sync deletes those from the server too. Restored items which reuse
+    // a guid take its tombstone back out when they're inserted.
+    let root_guids = format!(
+        "('{}', '{}', '{}', '{}', '{}')",
+        BookmarkRootGuid::Root.as_str(),
+        BookmarkRootGuid::Menu.as_str(),
+        BookmarkRootGuid::Mobile.as_str(),
+        BookmarkRootGuid::Toolbar.as_str(),
+        BookmarkRootGuid::Unfiled.as_str(),
+    );
+    db.execute_batch(&format!(
+        "DELETE FROM moz_bookmarks
+         WHERE guid NOT IN {root_guids};
+
+         INSERT OR IGNORE INTO moz_bookmarks_deleted(guid, dateRemoved)
+         SELECT guid, now()
+         FROM moz_bookmarks_synced
+         WHERE NOT isDeleted AND
+               guid NOT IN {root_guids};
+
+         DELETE FROM moz_tags_relation;
+         DELETE FROM moz_tags;"
+    ))?;
+    // The roots changed even if the backup doesn't have anything in them.
+    for root in USER_CONTENT_ROOTS {
+        db.execute_cached(
+            "UPDATE moz_bookmarks SET syncChangeCounter = syncChangeCounter + 1
+             WHERE guid = :guid",
+            &[(":guid", root.guid())],
+        )?;
+    }
+    let mut seen = HashSet::new();
+    for root in roots {
+        for mut child in root.children {
+            fix_guids(&mut child, &mut seen);
+            let mut item: InsertableItem = child.into();
+            item.set_parent_guid(root.guid.as_guid());
+            insert_bookmark_in_tx(db, item)?;
+        }
+    }
+    Ok(())
+}
+
+fn merge_into_folder(db: &PlacesDb, parent_guid: &SyncGuid, roots: Vec<BackupRoot>) -> Result<()> {
+    for root in roots {
+        if root.children.is_empty() {
+            continue;
+        }
+        let mut folder = BookmarkTreeNode::from(FolderNode {
+            title: Some(root_title(root.guid).to_owned()),
+            children: root.children,
+            ..Default::default()
+        });
+        clear_guids(&mut folder);
+        let mut item: InsertableItem = folder.into();
+        item.set_parent_guid(parent_guid.clone());
+        insert_bookmark_in_tx(db, item)?;
+    }
+    Ok(())
+}
+
+fn guid_mut(node: &mut BookmarkTreeNode) -> &mut Option<SyncGuid> {
+    match node {
+        BookmarkTreeNode::Bookmark { b } => &mut b.guid,
+        BookmarkTreeNode::Separator { s } => &mut s.guid,
+        BookmarkTreeNode::Folder { f } => &mut f.guid,
+    }
+}
+
+/// Removes any guids we can't insert: invalid ones, roots, and duplicates.
+fn fix_guids(node: &mut BookmarkTreeNode, seen: &mut HashSet<SyncGuid>) {
+    let guid = guid_mut(node);
+    if let Some(g) = guid {
+        if !g.is_valid_for_places()
+            || !g.is_valid_for_sync_server()
+            || BookmarkRootGuid::from_guid(g).is_some()
+            || !seen.insert(g.clone())
+        {
+            *guid = None;
+        }
+    }
+    if let BookmarkTreeNode::Folder { f } = node {
+        for child in &mut f.children {
+            fix_guids(child, seen);
+        }
+    }
+}
+
+fn clear_guids(node: &mut BookmarkTreeNode) {
+    *guid_mut(node) = None;
+    if let BookmarkTreeNode::Folder { f } = node {
+        f.children.iter_mut().for_each(clear_guids);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::places_api::test::new_mem_connection;
+    use crate::storage::bookmarks::json_tree::insert_tree;
+    use crate::storage::bookmarks::BookmarkPosition;
+    use crate::storage::bookmarks::{get_raw_bookmark, insert_bookmark, InsertableBookmark};
+    use crate::storage::tags::{get_tags_for_url, tag_url};
+    use crate::tests::{assert_json_tree, insert_json_tree};
+    use crate::types::SyncStatus;
+    use serde_json::json;
+
+    fn insert_test_tree(conn: &PlacesDb) {
+        insert_json_tree(
+            conn,
+            json!({
+                "guid": &BookmarkRootGuid::Menu.as_guid(),
+                "children": [
+                    {
+                        "guid": "bookmark1___",
+                        "title": "Example & co",
+                        "url": "https://example.com/",
+                    },
+                    {
+                        "guid": "separator1__",
+                        "type": 3,
+                    },
+                    {
+                        "guid": "folder1_____",
+                        "title": "Folder",
+                        "children": [
+                            {
+                                "guid": "bookmark2___",
+                                "title": "Mozilla",
+                                "url": "https://www.mozilla.org/",
+                            },
+                        ],
+                    },
+                ],
+            }),
+        );
+        insert_json_tree(
+            conn,
+            json!({
+                "guid": &BookmarkRootGuid::Toolbar.as_guid(),
+                "children": [
+                    {
+                        "guid": "bookmark3___",
+                        "title": "Toolbar",
+                        "url": "https://example.org/",
+                    },
+                ],
+            }),
+        );
+        tag_url(conn, &Url::parse("https://example.com/").unwrap(), "tag1").unwrap();
+        tag_url(conn, &Url::parse("https://example.com/").unwrap(), "tag2").unwrap();
+    }
+
+    fn check_roundtrip(format: BookmarksBackupFormat, keeps_guids: bool) {
+        let _ = env_logger::try_init();
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("bookmarks");
+        let conn = new_mem_connection();
+        insert_test_tree(&conn);
+        export_bookmarks(&conn, &path, format).expect("should export");
+
+        // Restoring the backup over different bookmarks replaces them.
+        let conn = new_mem_connection();
+        insert_bookmark(
+            &conn,
+            InsertableBookmark {
+                parent_guid: BookmarkRootGuid::Unfiled.as_guid(),
+                position: BookmarkPosition::Append,
+                date_added: None,
+                last_modified: None,
+                guid: None,
+                url: Url::parse("https://example.net/").unwrap(),
+                title: None,
+            }
+            .into(),
+        )
+        .unwrap();
+        let result = import_bookmarks(&conn, &path, format, BookmarksImportMode::ReplaceAll)
+            .expect("should import");
+        assert_eq!(result.num_total, 5);
+        assert_eq!(result.num_succeeded, 5);
+        assert_eq!(result.num_failed, 0);
+
+        let guid = |g: &str| if keeps_guids { Some(g) } else { None };
+        assert_json_tree(
+            &conn,
+            &BookmarkRootGuid::Menu.into(),
+            json!({
+                "guid": &BookmarkRootGuid::Menu.as_guid(),
+                "children": [
+                    {
+                        "guid": guid("bookmark1___"),
+                        "title": "Example & co",
+                        "url": "https://example.com/",
+                    },
+                    {
+                        "guid": guid("separator1__"),
+                        "type": 3,
+                    },
+                    {
+                        "guid": guid("folder1_____"),
+                        "title": "Folder",
+                        "children": [
+                            {
+                                "guid": guid("bookmark2___"),
+                                "title": "Mozilla",
+                                "url": "https://www.mozilla.org/",
+                            },
+                        ],
+                    },
+                ],
+            }),
+        );
+        assert_json_tree(
+            &conn,
+            &BookmarkRootGuid::Toolbar.into(),
+            json!({
+                "guid": &BookmarkRootGuid::Toolbar.as_guid(),
+                "children": [
+                    {
+                        "guid": guid("bookmark3___"),
+                        "title": "Toolbar",
+                        "url": "https://example.org/",
+                    },
+                ],
+            }),
+        );
+        assert_json_tree(
+            &conn,
+            &BookmarkRootGuid::Unfiled.into(),
+            json!({
+                "guid": &BookmarkRootGuid::Unfiled.as_guid(),
+                "children": [],
+            }),
+        );
+        let mut tags =
+            get_tags_for_url(&conn, &Url::parse("https://example.com/").unwrap()).unwrap();
+        tags.sort();
+        assert_eq!(tags, vec!["tag1".to_string(), "tag2".to_string()]);
+    }
+
+    #[test]
+    fn test_json_roundtrip() {
+        check_roundtrip(BookmarksBackupFormat::Json, true);
+    }
+
+    #[test]
+    fn test_html_roundtrip() {
+        check_roundtrip(BookmarksBackupFormat::Html, false);
+    }
+
+    #[test]
+    fn test_replace_all_sync_state() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("bookmarks.json");
+        let conn = new_mem_connection();
+        insert_test_tree(&conn);
+        export_bookmarks(&conn, &path, BookmarksBackupFormat::Json).unwrap();
+
+        // Pretend everything has been synced, then add an item which isn't in
+        // the backup.
+        conn.execute_batch(&format!(
+            "UPDATE moz_bookmarks SET syncChangeCounter = 0, syncStatus = {}",
+            SyncStatus::Normal as u8
+        ))
+        .unwrap();
+        insert_json_tree(
+            &conn,
+            json!({
+                "guid": &BookmarkRootGuid::Unfiled.as_guid(),
+                "children": [
+                    {
+                        "guid": "bookmark4___",
+                        "url": "https://example.net/",
+                    },
+                ],
+            }),
+        );
+        conn.execute_batch(&format!(
+            "UPDATE moz_bookmarks SET syncChangeCounter = 0, syncStatus = {}",
+            SyncStatus::Normal as u8
+        ))
+        .unwrap();
+        tag_url(&conn, &Url::parse("https://example.net/").unwrap(), "old").unwrap();
+        // An item the server has which we haven't applied locally.
+        conn.execute(
+            "INSERT INTO moz_bookmarks_synced(guid, parentGuid, kind)
+             VALUES ('remote1_____', 'unfiled_____', 1)",
+            [],
+        )
+        .unwrap();
+
+        import_bookmarks(
+            &conn,
+            &path,
+            BookmarksBackupFormat::Json,
+            BookmarksImportMode::ReplaceAll,
+        )
+        .unwrap();
+
+        // The deleted item and the server's item have tombstones, and the
+        // restored ones don't.
+        let tombstones = conn
+            .query_rows_and_then(
+                "SELECT guid FROM moz_bookmarks_deleted ORDER BY guid",
+                [],
+                |row| row.get::<_, String>(0),
+            )
+            .unwrap();
+        assert_eq!(
+            tombstones,
+            vec!["bookmark4___".to_string(), "remote1_____".to_string()]
+        );
+        // Tags which aren't in the backup are gone.
+        assert!(
+            get_tags_for_url(&conn, &Url::parse("https://example.net/").unwrap())
+                .unwrap()
+                .is_empty()
+        );
+        let mut tags =
+            get_tags_for_url(&conn, &Url::parse("https://example.com/").unwrap()).unwrap();
+        tags.sort();
+        assert_eq!(tags, vec!["tag1".to_string(), "tag2".to_string()]);
+        // The restored items will be uploaded as new items.
+        let restored = get_raw_bookmark(&conn, &SyncGuid::from("bookmark2___"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(restored._sync_status, SyncStatus::New);
+        assert_eq!(restored._sync_change_counter, 1);
+        for root in USER_CONTENT_ROOTS {
+            let root = get_raw_bookmark(&conn, root.guid()).unwrap().unwrap();
+            assert!(root._sync_change_counter > 0);
+        }
+    }
+
+    #[test]
+    fn test_merge_into_folder() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("bookmarks.json");
+        let conn = new_mem_connection();
+        insert_test_tree(&conn);
+        export_bookmarks(&conn, &path, BookmarksBackupFormat::Json).unwrap();
+
+        insert_tree(
+            &conn,
+            FolderNode {
+                guid: Some(BookmarkRootGuid::Unfiled.as_guid()),
+                children: vec![FolderNode {
+                    guid: Some(SyncGuid::from("imported____")),
+                    title: Some("Imported".to_string()),
+                    ..Default::default()
+                }
+                .into()],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let result = import_bookmarks(
+            &conn,
+            &path,
+            BookmarksBackupFormat::Json,
+            BookmarksImportMode::MergeIntoFolder {
+                parent_guid: SyncGuid::from("imported____"),
+            },
+        )
+        .expect("should merge");
+        // The folders we created for the roots aren't counted.
+        assert_eq!(result.num_succeeded, 5);
+
+        // The existing bookmarks are still there.
+        assert!(get_raw_bookmark(&conn, &SyncGuid::from("bookmark1___"))
+            .unwrap()
+            .is_some());
+        assert_json_tree(
+            &conn,
+            &SyncGuid::from("imported____"),
+            json!({
+                "guid": "imported____",
+                "title": "Imported",
+                "children": [
+                    {
+                        "title": "Bookmarks Menu",
+                        "children": [
+                            {
+                                "title": "Example & co",
+                                "url": "https://example.com/",
+                            },
+                            {
+                                "type": 3,
+                            },
+                            {
+                                "title": "Folder",
+                                "children": [
+                                    {
+                                        "title": "Mozilla",
+                                        "url": "https://www.mozilla.org/",
+                                    },
+                                ],
+                            },
+                        ],
+                    },
+                    {
+                        "title": "Bookmarks Toolbar",
+                        "children": [
+                            {
+                                "title": "Toolbar",
+                                "url": "https://example.org/",
+                            },
+                        ],
+                    },
+                ],
+            }),
+        );
+
+        // Merging into something that isn't a folder fails, and doesn't
+        // import anything. (The other bookmark is the original.)
+        assert!(import_bookmarks(
+            &conn,
+            &path,
+            BookmarksBackupFormat::Json,
+            BookmarksImportMode::MergeIntoFolder {
+                parent_guid: SyncGuid::from("bookmark1___"),
+            },
+        )
+        .is_err());
+        assert_eq!(
+            conn.query_one::<u32>("SELECT COUNT(*) FROM moz_bookmarks WHERE title = 'Toolbar'")
+                .unwrap(),
+            2
+        );
+    }
+}
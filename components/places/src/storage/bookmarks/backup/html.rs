@@ -0,0 +1,681 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! The Netscape bookmarks HTML format. There's no spec, so we write what
+//! desktop's `BookmarkHTMLUtils.jsm` writes, and read it like desktop does:
+//! the top-level list is the menu, and folders at the top level with special
+//! attributes hold the contents of the other roots. Other browsers write the
+//! same format, but it's often not valid HTML, so the reader is forgiving.
+//!
+//! Times are in seconds.
+
+use super::{root_title, Backup};
+use crate::import::common::{parse_bookmark_url, sanitize_bookmark_timestamp};
+use crate::storage::bookmarks::json_tree::{
+    BookmarkNode, BookmarkTreeNode, FolderNode, SeparatorNode,
+};
+use crate::storage::bookmarks::BookmarkRootGuid;
+use std::collections::HashMap;
+use types::Timestamp;
+use url::Url;
+
+const HEADER: &str = r#"<!DOCTYPE NETSCAPE-Bookmark-file-1>
+<!-- This is an automatically generated file.
+     It will be read and overwritten.
+     DO NOT EDIT! -->
+<META HTTP-EQUIV="Content-Type" CONTENT="text/html; charset=UTF-8">
+<meta http-equiv="Content-Security-Policy"
+      content="default-src 'self'; script-src 'none'; img-src data: *; object-src 'none'"></meta>
+<TITLE>Bookmarks</TITLE>
+"#;
+
+// The attributes of the top-level folders which hold the contents of roots.
+// Desktop doesn't export the mobile root, so the mobile attribute is ours;
+// other browsers import the mobile folder as a normal folder.
+const ROOT_ATTRIBUTES: [(BookmarkRootGuid, &str); 3] = [
+    (BookmarkRootGuid::Toolbar, "PERSONAL_TOOLBAR_FOLDER"),
+    (BookmarkRootGuid::Unfiled, "UNFILED_BOOKMARKS_FOLDER"),
+    (BookmarkRootGuid::Mobile, "MOBILE_BOOKMARKS_FOLDER"),
+];
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn write_dates(out: &mut String, date_added: Option<Timestamp>, last_modified: Option<Timestamp>) {
+    if let Some(date_added) = date_added {
+        out.push_str(&format!(" ADD_DATE=\"{}\"", date_added.as_millis() / 1000));
+    }
+    if let Some(last_modified) = last_modified {
+        out.push_str(&format!(
+            " LAST_MODIFIED=\"{}\"",
+            last_modified.as_millis() / 1000
+        ));
+    }
+}
+
+fn write_list(
+    out: &mut String,
+    children: &[BookmarkTreeNode],
+    tags: &HashMap<Url, Vec<String>>,
+    depth: usize,
+) {
+    let indent = "    ".repeat(depth);
+    out.push_str(&format!("{}<DL><p>\n", indent));
+    for child in children {
+        write_node(out, child, tags, depth + 1);
+    }
+    out.push_str(&format!("{}</DL><p>\n", indent));
+}
+
+fn write_folder(
+    out: &mut String,
+    folder: &FolderNode,
+    title: &str,
+    attribute: Option<&str>,
+    tags: &HashMap<Url, Vec<String>>,
+    depth: usize,
+) {
+    let indent = "    ".repeat(depth);
+    out.push_str(&format!("{}<DT><H3", indent));
+    write_dates(out, folder.date_added, folder.last_modified);
+    if let Some(attribute) = attribute {
+        out.push_str(&format!(" {}=\"true\"", attribute));
+    }
+    out.push_str(&format!(">{}</H3>\n", escape(title)));
+    write_list(out, &folder.children, tags, depth);
+}
+
+fn write_node(
+    out: &mut String,
+    node: &BookmarkTreeNode,
+    tags: &HashMap<Url, Vec<String>>,
+    depth: usize,
+) {
+    let indent = "    ".repeat(depth);
+    match node {
+        BookmarkTreeNode::Bookmark { b } => {
+            out.push_str(&format!(
+                "{}<DT><A HREF=\"{}\"",
+                indent,
+                escape(b.url.as_str())
+            ));
+            write_dates(out, b.date_added, b.last_modified);
+            if let Some(tags) = tags.get(&b.url) {
+                out.push_str(&format!(" TAGS=\"{}\"", escape(&tags.join(","))));
+            }
+            out.push_str(&format!(
+                ">{}</A>\n",
+                escape(b.title.as_deref().unwrap_or_default())
+            ));
+        }
+        BookmarkTreeNode::Separator { .. } => out.push_str(&format!("{}<HR>\n", indent)),
+        BookmarkTreeNode::Folder { f } => write_folder(
+            out,
+            f,
+            f.title.as_deref().unwrap_or_default(),
+            None,
+            tags,
+            depth,
+        ),
+    }
+}
+
+/// Writes the tree starting at the Places root as HTML.
+pub(super) fn write(tree: &FolderNode, tags: &HashMap<Url, Vec<String>>) -> String {
+    let root = |guid: BookmarkRootGuid| {
+        tree.children.iter().find_map(|child| match child {
+            BookmarkTreeNode::Folder { f } if f.guid.as_ref() == Some(guid.guid()) => Some(f),
+            _ => None,
+        })
+    };
+    let mut out = HEADER.to_string();
+    out.push_str(&format!(
+        "<H1>{}</H1>\n\n<DL><p>\n",
+        root_title(BookmarkRootGuid::Menu)
+    ));
+    if let Some(menu) = root(BookmarkRootGuid::Menu) {
+        for child in &menu.children {
+            write_node(&mut out, child, tags, 1);
+        }
+    }
+    for (guid, attribute) in ROOT_ATTRIBUTES {
+        if let Some(folder) = root(guid).filter(|f| !f.children.is_empty()) {
+            write_folder(&mut out, folder, root_title(guid), Some(attribute), tags, 1);
+        }
+    }
+    out.push_str("</DL>\n");
+    out
+}
+
+#[derive(Debug, PartialEq)]
+enum Token<'a> {
+    /// A start tag, with its name and attribute names in upper case, and its
+    /// attribute values decoded.
+    Start(String, HashMap<String, String>),
+    End(String),
+    Text(&'a str),
+}
+
+fn is_tag_name_end(c: char) -> bool {
+    c.is_ascii_whitespace() || c == '>' || c == '/'
+}
+
+/// Reads a start tag, after its `<`, returning it and the rest of the input.
+fn read_start_tag(input: &str) -> (Token<'_>, &str) {
+    let name_end = input.find(is_tag_name_end).unwrap_or(input.len());
+    let name = input[..name_end].to_ascii_uppercase();
+    let mut rest = &input[name_end..];
+    let mut attributes = HashMap::new();
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == '/');
+        if rest.is_empty() {
+            break;
+        }
+        if let Some(after) = rest.strip_prefix('>') {
+            rest = after;
+            break;
+        }
+        let key_end = rest
+            .find(|c: char| c.is_ascii_whitespace() || c == '=' || c == '>')
+            .unwrap_or(rest.len());
+        let key = rest[..key_end].to_ascii_uppercase();
+        rest = rest[key_end..].trim_start();
+        let value = match rest.strip_prefix('=').map(str::trim_start) {
+            Some(after) => match after.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    let after = &after[1..];
+                    let end = after.find(quote).unwrap_or(after.len());
+                    rest = after.get(end + 1..).unwrap_or_default();
+                    &after[..end]
+                }
+                _ => {
+                    let end = after
+                        .find(|c: char| c.is_ascii_whitespace() || c == '>')
+                        .unwrap_or(after.len());
+                    rest = &after[end..];
+                    &after[..end]
+                }
+            },
+            None => "",
+        };
+        attributes.insert(key, decode_entities(value));
+    }
+    (Token::Start(name, attributes), rest)
+}
+
+fn tokenize(input: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut rest = input;
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("<!--") {
+            rest = after.find("-->").map_or("", |end| &after[end + 3..]);
+        } else if rest.starts_with("<!") || rest.starts_with("<?") {
+            rest = rest.find('>').map_or("", |end| &rest[end + 1..]);
+        } else if let Some(after) = rest.strip_prefix("</") {
+            let end = after.find('>').unwrap_or(after.len());
+            tokens.push(Token::End(after[..end].trim().to_ascii_uppercase()));
+            rest = after.get(end + 1..).unwrap_or_default();
+        } else if rest.starts_with('<') && rest[1..].starts_with(|c: char| c.is_ascii_alphabetic())
+        {
+            let (token, after) = read_start_tag(&rest[1..]);
+            tokens.push(token);
+            rest = after;
+        } else {
+            // Text runs up to the next `<`, and a `<` which doesn't start a
+            // tag is text.
+            let skip = usize::from(rest.starts_with('<'));
+            let end = rest[skip..].find('<').map_or(rest.len(), |end| end + skip);
+            tokens.push(Token::Text(&rest[..end]));
+            rest = &rest[end..];
+        }
+    }
+    tokens
+}
+
+fn decode_entity(name: &str) -> Option<char> {
+    match name {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some('\u{a0}'),
+        _ => {
+            let code = name.strip_prefix('#')?;
+            let code = match code.strip_prefix(|c| c == 'x' || c == 'X') {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => code.parse().ok()?,
+            };
+            char::from_u32(code)
+        }
+    }
+}
+
+/// Decodes the character references in `s`. Unknown entities are left as
+/// they are.
+fn decode_entities(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let decoded = rest
+            .find(';')
+            .filter(|end| *end <= 10)
+            .and_then(|end| Some((decode_entity(&rest[1..end])?, end)));
+        match decoded {
+            Some((c, end)) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn parse_date(attributes: &HashMap<String, String>, name: &str) -> Option<Timestamp> {
+    let seconds = attributes.get(name)?.trim().parse::<u64>().ok()?;
+    sanitize_bookmark_timestamp(Timestamp(seconds.checked_mul(1000)?))
+}
+
+fn parse_title(text: &str) -> Option<String> {
+    let title = decode_entities(text.trim());
+    if title.is_empty() {
+        None
+    } else {
+        Some(title)
+    }
+}
+
+/// A list we're reading.
+struct OpenList {
+    folder: FolderNode,
+    /// The root whose contents are in this list.
+    root: Option<BookmarkRootGuid>,
+    /// True for a list without a heading, whose items belong to the
+    /// enclosing list.
+    is_stray: bool,
+}
+
+/// The heading or link whose text we're reading.
+enum Current {
+    Heading(OpenList),
+    Link(HashMap<String, String>),
+}
+
+struct Parser {
+    backup: Backup,
+    open: Vec<OpenList>,
+    /// A folder whose heading we've read, and whose list should be next.
+    pending: Option<OpenList>,
+    current: Option<Current>,
+    text: String,
+}
+
+impl Parser {
+    fn push(&mut self, node: BookmarkTreeNode) {
+        match self.open.last_mut() {
+            Some(list) => list.folder.children.push(node),
+            None => self
+                .backup
+                .root_mut(BookmarkRootGuid::Menu)
+                .children
+                .push(node),
+        }
+    }
+
+    /// Adds a folder whose heading wasn't followed by a list.
+    fn flush_pending(&mut self) {
+        if let Some(list) = self.pending.take() {
+            self.close(list);
+        }
+    }
+
+    fn close(&mut self, list: OpenList) {
+        if let Some(root) = list.root {
+            self.backup
+                .root_mut(root)
+                .children
+                .extend(list.folder.children);
+        } else if list.is_stray {
+            for child in list.folder.children {
+                self.push(child);
+            }
+        } else {
+            self.push(list.folder.into());
+        }
+    }
+
+    fn start_tag(&mut self, name: &str, attributes: HashMap<String, String>) {
+        match name {
+            "H3" => {
+                self.flush_pending();
+                // Only folders in the top-level list can hold roots.
+                let root = if self.open.len() == 1 {
+                    ROOT_ATTRIBUTES
+                        .iter()
+                        .find(|(_, attribute)| attributes.contains_key(*attribute))
+                        .map(|(root, _)| *root)
+                } else {
+                    None
+                };
+                self.current = Some(Current::Heading(OpenList {
+                    folder: FolderNode {
+                        date_added: parse_date(&attributes, "ADD_DATE"),
+                        last_modified: parse_date(&attributes, "LAST_MODIFIED"),
+                        ..Default::default()
+                    },
+                    root,
+                    is_stray: false,
+                }));
+                self.text.clear();
+            }
+            "A" => {
+                self.flush_pending();
+                self.current = Some(Current::Link(attributes));
+                self.text.clear();
+            }
+            "HR" => {
+                self.flush_pending();
+                self.push(SeparatorNode::default().into());
+            }
+            "DL" => {
+                let list = self.pending.take().unwrap_or_else(|| OpenList {
+                    folder: FolderNode::default(),
+                    root: if self.open.is_empty() {
+                        Some(BookmarkRootGuid::Menu)
+                    } else {
+                        None
+                    },
+                    is_stray: !self.open.is_empty(),
+                });
+                self.open.push(list);
+            }
+            _ => {}
+        }
+    }
+
+    fn end_tag(&mut self, name: &str) {
+        match name {
+            "H3" => {
+                if let Some(Current::Heading(mut list)) = self.current.take() {
+                    list.folder.title = parse_title(&self.text);
+                    self.pending = Some(list);
+                }
+            }
+            "A" => {
+                if let Some(Current::Link(attributes)) = self.current.take() {
+                    let url = attributes
+                        .get("HREF")
+                        .and_then(|href| parse_bookmark_url(href.trim()));
+                    match url {
+                        Some(url) => self.add_bookmark(url, attributes),
+                        None => self.backup.num_failed += 1,
+                    }
+                }
+            }
+            "DL" => {
+                self.flush_pending();
+                if let Some(list) = self.open.pop() {
+                    self.close(list);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn add_bookmark(&mut self, url: Url, attributes: HashMap<String, String>) {
+        if let Some(tags) = attributes.get("TAGS") {
+            let url_tags = self.backup.tags.entry(url.clone()).or_default();
+            for tag in tags.split(',').map(str::trim) {
+                if !tag.is_empty() && !url_tags.iter().any(|t| t == tag) {
+                    url_tags.push(tag.to_string());
+                }
+            }
+        }
+        self.push(
+            BookmarkNode {
+                guid: None,
+                date_added: parse_date(&attributes, "ADD_DATE"),
+                last_modified: parse_date(&attributes, "LAST_MODIFIED"),
+                title: parse_title(&self.text),
+                url,
+            }
+            .into(),
+        );
+    }
+}
+
+/// Reads bookmarks from HTML. This never fails, but if the HTML isn't a
+/// bookmarks file, there won't be any bookmarks.
+pub(super) fn parse(contents: &str) -> Backup {
+    let mut parser = Parser {
+        backup: Backup {
+            roots: Vec::new(),
+            tags: HashMap::new(),
+            num_failed: 0,
+        },
+        open: Vec::new(),
+        pending: None,
+        current: None,
+        text: String::new(),
+    };
+    for token in tokenize(contents) {
+        match token {
+            Token::Start(name, attributes) => parser.start_tag(&name, attributes),
+            Token::End(name) => parser.end_tag(&name),
+            Token::Text(text) => {
+                if parser.current.is_some() {
+                    parser.text.push_str(text);
+                }
+            }
+        }
+    }
+    // Close anything left open by a truncated file.
+    parser.flush_pending();
+    while let Some(list) = parser.open.pop() {
+        parser.close(list);
+    }
+    parser.backup
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sync_guid::Guid as SyncGuid;
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize("<!-- <A> --><dt><a HREF=\"x?a=1&amp;b=2\" data=y empty>A &lt; B</A></dt>"),
+            vec![
+                Token::Start("DT".into(), HashMap::new()),
+                Token::Start(
+                    "A".into(),
+                    HashMap::from([
+                        ("HREF".into(), "x?a=1&b=2".into()),
+                        ("DATA".into(), "y".into()),
+                        ("EMPTY".into(), "".into()),
+                    ])
+                ),
+                Token::Text("A &lt; B"),
+                Token::End("A".into()),
+                Token::End("DT".into()),
+            ]
+        );
+        assert_eq!(
+            tokenize("1 < 2 é<"),
+            vec![Token::Text("1 "), Token::Text("< 2 é"), Token::Text("<")]
+        );
+    }
+
+    #[test]
+    fn test_decode_entities() {
+        assert_eq!(
+            decode_entities("&amp;&lt;&gt;&quot;&#39;&#x1F98A;&unknown;&;& done"),
+            "&<>\"'🦊&unknown;&;& done"
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        // A file like Chrome writes, which has a toolbar folder, and doesn't
+        // close its `<DT>`s or `<p>`s.
+        let contents = r#"<!DOCTYPE NETSCAPE-Bookmark-file-1>
+<!-- This is an automatically generated file.
+     It will be read and overwritten.
+     DO NOT EDIT! -->
+<META HTTP-EQUIV="Content-Type" CONTENT="text/html; charset=UTF-8">
+<TITLE>Bookmarks</TITLE>
+<H1>Bookmarks</H1>
+<DL><p>
+    <DT><H3 ADD_DATE="1600000000" LAST_MODIFIED="1600000100" PERSONAL_TOOLBAR_FOLDER="true">Bookmarks bar</H3>
+    <DL><p>
+        <DT><A HREF="https://example.com/" ADD_DATE="1600000000" TAGS="a,b">Example &amp; co</A>
+        <DD>A description, which we ignore.
+        <DT><H3>Folder</H3>
+        <DL><p>
+            <DT><A HREF="not a url">Invalid</A>
+            <HR>
+            <DT><A HREF="https://www.mozilla.org/">  </A>
+        </DL><p>
+        <DT><H3>Empty</H3>
+    </DL><p>
+    <DT><A HREF="https://example.org/">Menu</A>
+</DL><p>
+"#;
+        let backup = parse(contents);
+        assert_eq!(backup.num_failed, 1);
+        assert_eq!(backup.roots.len(), 2);
+        let toolbar = &backup.roots[0];
+        assert_eq!(toolbar.guid, BookmarkRootGuid::Toolbar);
+        assert_eq!(
+            toolbar.children,
+            vec![
+                BookmarkNode {
+                    guid: None,
+                    date_added: Some(Timestamp(1_600_000_000_000)),
+                    last_modified: None,
+                    title: Some("Example & co".into()),
+                    url: Url::parse("https://example.com/").unwrap(),
+                }
+                .into(),
+                FolderNode {
+                    title: Some("Folder".into()),
+                    children: vec![
+                        SeparatorNode::default().into(),
+                        BookmarkNode {
+                            guid: None,
+                            date_added: None,
+                            last_modified: None,
+                            title: None,
+                            url: Url::parse("https://www.mozilla.org/").unwrap(),
+                        }
+                        .into(),
+                    ],
+                    ..Default::default()
+                }
+                .into(),
+                FolderNode {
+                    title: Some("Empty".into()),
+                    ..Default::default()
+                }
+                .into(),
+            ]
+        );
+        let menu = &backup.roots[1];
+        assert_eq!(menu.guid, BookmarkRootGuid::Menu);
+        assert_eq!(
+            menu.children,
+            vec![BookmarkNode {
+                guid: None,
+                date_added: None,
+                last_modified: None,
+                title: Some("Menu".into()),
+                url: Url::parse("https://example.org/").unwrap(),
+            }
+            .into()]
+        );
+        assert_eq!(
+            backup.tags,
+            HashMap::from([(
+                Url::parse("https://example.com/").unwrap(),
+                vec!["a".to_string(), "b".to_string()]
+            )])
+        );
+    }
+
+    #[test]
+    fn test_write() {
+        let tree = FolderNode {
+            guid: Some(BookmarkRootGuid::Root.as_guid()),
+            children: vec![
+                FolderNode {
+                    guid: Some(BookmarkRootGuid::Menu.as_guid()),
+                    children: vec![
+                        BookmarkNode {
+                            guid: Some(SyncGuid::from("bookmark1___")),
+                            date_added: Some(Timestamp(1_600_000_000_000)),
+                            last_modified: Some(Timestamp(1_600_000_001_000)),
+                            title: Some("<Example>".into()),
+                            url: Url::parse("https://example.com/?a=1&b=\"2\"").unwrap(),
+                        }
+                        .into(),
+                        SeparatorNode::default().into(),
+                    ],
+                    ..Default::default()
+                }
+                .into(),
+                FolderNode {
+                    guid: Some(BookmarkRootGuid::Toolbar.as_guid()),
+                    children: vec![FolderNode {
+                        title: Some("Folder".into()),
+                        ..Default::default()
+                    }
+                    .into()],
+                    ..Default::default()
+                }
+                .into(),
+                FolderNode {
+                    guid: Some(BookmarkRootGuid::Unfiled.as_guid()),
+                    ..Default::default()
+                }
+                .into(),
+            ],
+            ..Default::default()
+        };
+        let tags = HashMap::from([(
+            Url::parse("https://example.com/?a=1&b=\"2\"").unwrap(),
+            vec!["a".to_string(), "b".to_string()],
+        )]);
+        let expected = format!(
+            "{}{}",
+            HEADER,
+            r#"<H1>Bookmarks Menu</H1>
+
+<DL><p>
+    <DT><A HREF="https://example.com/?a=1&amp;b=%222%22" ADD_DATE="1600000000" LAST_MODIFIED="1600000001" TAGS="a,b">&lt;Example&gt;</A>
+    <HR>
+    <DT><H3 PERSONAL_TOOLBAR_FOLDER="true">Bookmarks Toolbar</H3>
+    <DL><p>
+        <DT><H3>Folder</H3>
+        <DL><p>
+        </DL><p>
+    </DL><p>
+</DL>
+"#
+        );
+        assert_eq!(write(&tree, &tags), expected);
+    }
+}
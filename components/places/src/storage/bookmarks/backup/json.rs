@@ -0,0 +1,399 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Firefox's `.json` bookmark backups, as written by desktop's
+//! `BookmarkJSONUtils.jsm`. Times are in microseconds.
+
+use super::Backup;
+use crate::error::*;
+use crate::import::common::{parse_bookmark_url, sanitize_bookmark_timestamp};
+use crate::storage::bookmarks::json_tree::{
+    BookmarkNode, BookmarkTreeNode, FolderNode, SeparatorNode,
+};
+use crate::storage::bookmarks::BookmarkRootGuid;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use sync_guid::Guid as SyncGuid;
+use types::Timestamp;
+use url::Url;
+
+const TYPE_BOOKMARK: &str = "text/x-moz-place";
+const TYPE_FOLDER: &str = "text/x-moz-place-container";
+const TYPE_SEPARATOR: &str = "text/x-moz-place-separator";
+
+const TYPE_CODE_BOOKMARK: u8 = 1;
+const TYPE_CODE_FOLDER: u8 = 2;
+const TYPE_CODE_SEPARATOR: u8 = 3;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Node {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    guid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    index: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    date_added: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_modified: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    type_code: Option<u8>,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    node_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    root: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uri: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tags: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    children: Vec<Node>,
+}
+
+/// The names desktop uses for its roots in the `root` property.
+fn root_name(root: BookmarkRootGuid) -> &'static str {
+    match root {
+        BookmarkRootGuid::Root => "placesRoot",
+        BookmarkRootGuid::Menu => "bookmarksMenuFolder",
+        BookmarkRootGuid::Toolbar => "toolbarFolder",
+        BookmarkRootGuid::Unfiled => "unfiledBookmarksFolder",
+        BookmarkRootGuid::Mobile => "mobileFolder",
+    }
+}
+
+fn root_from_name(name: &str) -> Option<BookmarkRootGuid> {
+    [
+        BookmarkRootGuid::Root,
+        BookmarkRootGuid::Menu,
+        BookmarkRootGuid::Toolbar,
+        BookmarkRootGuid::Unfiled,
+        BookmarkRootGuid::Mobile,
+    ]
+    .into_iter()
+    .find(|root| root_name(*root) == name)
+}
+
+fn to_micros(ts: Option<Timestamp>) -> Option<i64> {
+    ts.map(|ts| ts.as_millis_i64() * 1000)
+}
+
+fn from_micros(micros: Option<i64>) -> Option<Timestamp> {
+    sanitize_bookmark_timestamp(Timestamp(u64::try_from(micros? / 1000).ok()?))
+}
+
+fn convert_folder(f: &FolderNode, index: u32, tags: &HashMap<Url, Vec<String>>) -> Node {
+    Node {
+        guid: f.guid.as_ref().map(ToString::to_string),
+        // Desktop always writes a title for folders, even an empty one.
+        title: Some(f.title.clone().unwrap_or_default()),
+        index: Some(index),
+        date_added: to_micros(f.date_added),
+        last_modified: to_micros(f.last_modified),
+        type_code: Some(TYPE_CODE_FOLDER),
+        node_type: Some(TYPE_FOLDER.into()),
+        root: f
+            .guid
+            .as_ref()
+            .and_then(BookmarkRootGuid::from_guid)
+            .map(|root| root_name(root).into()),
+        children: f
+            .children
+            .iter()
+            .enumerate()
+            .map(|(i, child)| convert_node(child, i as u32, tags))
+            .collect(),
+        ..Default::default()
+    }
+}
+
+fn convert_node(node: &BookmarkTreeNode, index: u32, tags: &HashMap<Url, Vec<String>>) -> Node {
+    match node {
+        BookmarkTreeNode::Bookmark { b } => Node {
+            guid: b.guid.as_ref().map(ToString::to_string),
+            title: b.title.clone(),
+            index: Some(index),
+            date_added: to_micros(b.date_added),
+            last_modified: to_micros(b.last_modified),
+            type_code: Some(TYPE_CODE_BOOKMARK),
+            node_type: Some(TYPE_BOOKMARK.into()),
+            uri: Some(b.url.to_string()),
+            tags: tags.get(&b.url).map(|tags| tags.join(",")),
+            ..Default::default()
+        },
+        BookmarkTreeNode::Separator { s } => Node {
+            guid: s.guid.as_ref().map(ToString::to_string),
+            index: Some(index),
+            date_added: to_micros(s.date_added),
+            last_modified: to_micros(s.last_modified),
+            type_code: Some(TYPE_CODE_SEPARATOR),
+            node_type: Some(TYPE_SEPARATOR.into()),
+            ..Default::default()
+        },
+        BookmarkTreeNode::Folder { f } => convert_folder(f, index, tags),
+    }
+}
+
+/// Writes the tree starting at the Places root as a backup.
+pub(super) fn write(tree: &FolderNode, tags: &HashMap<Url, Vec<String>>) -> Result<String> {
+    let mut root = convert_folder(tree, 0, tags);
+    // Desktop's root has an empty title.
+    root.title = Some(String::new());
+    Ok(serde_json::to_string(&root)?)
+}
+
+/// Converts the children of a folder from a backup. Items with invalid URLs,
+/// or types we don't know about, are counted as failures.
+fn convert_children(
+    children: Vec<Node>,
+    tags: &mut HashMap<Url, Vec<String>>,
+    num_failed: &mut u32,
+) -> Vec<BookmarkTreeNode> {
+    children
+        .into_iter()
+        .filter_map(|child| convert_backup_node(child, tags, num_failed))
+        .collect()
+}
+
+fn convert_backup_node(
+    node: Node,
+    tags: &mut HashMap<Url, Vec<String>>,
+    num_failed: &mut u32,
+) -> Option<BookmarkTreeNode> {
+    let type_code = match node.node_type.as_deref() {
+        Some(TYPE_BOOKMARK) => TYPE_CODE_BOOKMARK,
+        Some(TYPE_FOLDER) => TYPE_CODE_FOLDER,
+        Some(TYPE_SEPARATOR) => TYPE_CODE_SEPARATOR,
+        _ => node.type_code.unwrap_or_default(),
+    };
+    let guid = node.guid.map(SyncGuid::from);
+    let date_added = from_micros(node.date_added);
+    let last_modified = from_micros(node.last_modified);
+    Some(match type_code {
+        TYPE_CODE_BOOKMARK => {
+            let url = match node.uri.as_deref().and_then(parse_bookmark_url) {
+                Some(url) => url,
+                None => {
+                    *num_failed += 1;
+                    return None;
+                }
+            };
+            if let Some(node_tags) = node.tags {
+                let url_tags = tags.entry(url.clone()).or_default();
+                for tag in node_tags.split(',').map(str::trim) {
+                    if !tag.is_empty() && !url_tags.iter().any(|t| t == tag) {
+                        url_tags.push(tag.to_string());
+                    }
+                }
+            }
+            BookmarkNode {
+                guid,
+                date_added,
+                last_modified,
+                title: node.title,
+                url,
+            }
+            .into()
+        }
+        TYPE_CODE_FOLDER => FolderNode {
+            guid,
+            date_added,
+            last_modified,
+            title: node.title,
+            children: convert_children(node.children, tags, num_failed),
+        }
+        .into(),
+        TYPE_CODE_SEPARATOR => SeparatorNode {
+            guid,
+            date_added,
+            last_modified,
+        }
+        .into(),
+        _ => {
+            *num_failed += 1;
+            return None;
+        }
+    })
+}
+
+/// Reads a backup. Items outside of the user content roots are added to
+/// unfiled; desktop's tags folder, which older backups include, is skipped
+/// since the bookmarks in it are also in the other roots.
+pub(super) fn parse(contents: &str) -> Result<Backup> {
+    let places_root: Node = serde_json::from_str(contents)?;
+    let is_places_root = places_root.root.as_deref() == Some(root_name(BookmarkRootGuid::Root))
+        || places_root.guid.as_deref() == Some(BookmarkRootGuid::Root.as_str());
+    if !is_places_root {
+        return Err(Error::InvalidImportFile(
+            "Bookmarks backup doesn't start at the root".into(),
+        ));
+    }
+    let mut backup = Backup {
+        roots: Vec::new(),
+        tags: HashMap::new(),
+        num_failed: 0,
+    };
+    for child in places_root.children {
+        let root = child
+            .root
+            .as_deref()
+            .and_then(root_from_name)
+            .or_else(|| child.guid.as_deref().and_then(BookmarkRootGuid::well_known));
+        if child.root.as_deref() == Some("tagsFolder") {
+            continue;
+        }
+        match root {
+            Some(root) if root != BookmarkRootGuid::Root => {
+                let children =
+                    convert_children(child.children, &mut backup.tags, &mut backup.num_failed);
+                backup.root_mut(root).children.extend(children);
+            }
+            _ => {
+                if let Some(node) =
+                    convert_backup_node(child, &mut backup.tags, &mut backup.num_failed)
+                {
+                    backup
+                        .root_mut(BookmarkRootGuid::Unfiled)
+                        .children
+                        .push(node);
+                }
+            }
+        }
+    }
+    Ok(backup)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_desktop_backup() {
+        // Trimmed from a backup written by desktop.
+        let contents = json!({
+            "guid": "root________",
+            "title": "",
+            "index": 0,
+            "dateAdded": 1_600_000_000_000_000i64,
+            "lastModified": 1_600_000_000_000_000i64,
+            "id": 1,
+            "typeCode": 2,
+            "type": "text/x-moz-place-container",
+            "root": "placesRoot",
+            "children": [
+                {
+                    "guid": "menu________",
+                    "title": "menu",
+                    "index": 0,
+                    "id": 2,
+                    "typeCode": 2,
+                    "type": "text/x-moz-place-container",
+                    "root": "bookmarksMenuFolder",
+                    "children": [
+                        {
+                            "guid": "bookmark1___",
+                            "title": "Example",
+                            "index": 0,
+                            "dateAdded": 1_600_000_000_000_000i64,
+                            "lastModified": 1_600_000_001_000_000i64,
+                            "id": 7,
+                            "typeCode": 1,
+                            "tags": "a, b,,a",
+                            "iconUri": "https://example.com/favicon.ico",
+                            "type": "text/x-moz-place",
+                            "uri": "https://example.com/",
+                        },
+                        {
+                            "guid": "livemark1___",
+                            "title": "Feed",
+                            "typeCode": 4,
+                            "type": "text/x-moz-place-livemark",
+                        },
+                        {
+                            "guid": "bookmark2___",
+                            "title": "Invalid",
+                            "typeCode": 1,
+                            "type": "text/x-moz-place",
+                            "uri": "not a url",
+                        },
+                    ],
+                },
+                {
+                    "guid": "tags________",
+                    "title": "tags",
+                    "typeCode": 2,
+                    "type": "text/x-moz-place-container",
+                    "root": "tagsFolder",
+                    "children": [
+                        {
+                            "title": "a",
+                            "typeCode": 2,
+                            "type": "text/x-moz-place-container",
+                        },
+                    ],
+                },
+                {
+                    "guid": "mobile______",
+                    "title": "mobile",
+                    "typeCode": 2,
+                    "type": "text/x-moz-place-container",
+                    "root": "mobileFolder",
+                    "children": [
+                        {
+                            "guid": "separator1__",
+                            "typeCode": 3,
+                            "type": "text/x-moz-place-separator",
+                        },
+                    ],
+                },
+            ],
+        })
+        .to_string();
+        let backup = parse(&contents).expect("should parse");
+        assert_eq!(backup.num_failed, 2);
+        assert_eq!(backup.roots.len(), 2);
+
+        let menu = &backup.roots[0];
+        assert_eq!(menu.guid, BookmarkRootGuid::Menu);
+        assert_eq!(
+            menu.children,
+            vec![BookmarkNode {
+                guid: Some("bookmark1___".into()),
+                date_added: Some(Timestamp(1_600_000_000_000)),
+                last_modified: Some(Timestamp(1_600_000_001_000)),
+                title: Some("Example".into()),
+                url: Url::parse("https://example.com/").unwrap(),
+            }
+            .into()]
+        );
+        let mobile = &backup.roots[1];
+        assert_eq!(mobile.guid, BookmarkRootGuid::Mobile);
+        assert_eq!(
+            mobile.children,
+            vec![SeparatorNode {
+                guid: Some("separator1__".into()),
+                ..Default::default()
+            }
+            .into()]
+        );
+        assert_eq!(
+            backup.tags,
+            HashMap::from([(
+                Url::parse("https://example.com/").unwrap(),
+                vec!["a".to_string(), "b".to_string()]
+            )])
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(matches!(parse("1"), Err(Error::JsonError(_))));
+        assert!(matches!(
+            parse(r#"{"type": "text/x-moz-place-container", "children": []}"#),
+            Err(Error::InvalidImportFile(_))
+        ));
+    }
+}
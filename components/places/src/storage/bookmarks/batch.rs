@@ -0,0 +1,442 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Applying several bookmark changes at once, like moving or deleting a
+//! multi-selection, in a single transaction, and undoing them.
+
+use super::json_tree::{fetch_tree, FetchDepth};
+use super::{
+    delete_bookmark_in_tx, get_raw_bookmark, insert_bookmark_in_tx, update_bookmark_in_tx,
+    BookmarkPosition, BookmarkUpdateInfo, InsertableItem, RawBookmark, UpdatableBookmark,
+    UpdatableFolder, UpdatableItem, UpdatableSeparator, UpdateTreeLocation,
+};
+use crate::db::PlacesDb;
+use crate::error::*;
+use crate::types::BookmarkType;
+use std::collections::BTreeMap;
+use sync_guid::Guid as SyncGuid;
+
+#[derive(Debug, Clone)]
+pub enum BookmarkOperation {
+    Insert {
+        item: InsertableItem,
+    },
+    Move {
+        guid: SyncGuid,
+        parent_guid: SyncGuid,
+        position: BookmarkPosition,
+    },
+    Update {
+        info: BookmarkUpdateInfo,
+    },
+    Delete {
+        guid: SyncGuid,
+    },
+}
+
+/// Undoes one operation.
+#[derive(Debug)]
+enum UndoStep {
+    /// Deletes an inserted item.
+    Delete { guid: SyncGuid },
+    /// Inserts a deleted item, with its children, at its old position and
+    /// with its old guids.
+    Insert { item: InsertableItem },
+    /// Moves an item back, and restores its title and URL.
+    Update { guid: SyncGuid, item: UpdatableItem },
+}
+
+/// Restores the bookmarks changed by [`apply_operations`] to how they were
+/// before.
+#[derive(Debug, Default)]
+pub struct BookmarkUndo {
+    /// In the order the operations were applied.
+    steps: Vec<UndoStep>,
+}
+
+/// The result of [`apply_operations`].
+#[derive(Debug, Default)]
+pub struct BookmarkBatch {
+    /// The guids of the inserted items, in order.
+    pub inserted_guids: Vec<SyncGuid>,
+    pub undo: BookmarkUndo,
+}
+
+/// Keeps the undos for the most recent batches, so that apps can refer to
+/// them with a token instead of holding on to them.
+#[derive(Debug, Default)]
+pub struct BookmarkUndoLog {
+    next_token: u64,
+    undos: BTreeMap<u64, BookmarkUndo>,
+}
+
+impl BookmarkUndoLog {
+    /// How many undos we keep before we forget the oldest.
+    const MAX_UNDOS: usize = 50;
+
+    /// Adds an undo, and returns its token.
+    pub fn add(&mut self, undo: BookmarkUndo) -> u64 {
+        let token = self.next_token;
+        self.next_token += 1;
+        self.undos.insert(token, undo);
+        if self.undos.len() > Self::MAX_UNDOS {
+            self.undos.pop_first();
+        }
+        token
+    }
+
+    /// Returns an undo, if we still have it.
+    pub fn get(&self, token: u64) -> Option<&BookmarkUndo> {
+        self.undos.get(&token)
+    }
+
+    /// Removes and returns an undo, if we still have it.
+    pub fn take(&mut self, token: u64) -> Option<BookmarkUndo> {
+        self.undos.remove(&token)
+    }
+}
+
+/// Returns an item that restores the title, URL and location of `raw`.
+fn updatable_for_undo(raw: &RawBookmark) -> Result<UpdatableItem> {
+    let parent_guid = raw
+        .parent_guid
+        .clone()
+        .ok_or_else(|| Corruption::NonRootWithoutParent(raw.guid.to_string()))?;
+    let location = UpdateTreeLocation::Parent {
+        guid: parent_guid,
+        pos: BookmarkPosition::Specific { pos: raw.position },
+    };
+    let title = Some(raw.title.clone().unwrap_or_default());
+    Ok(match raw.bookmark_type {
+        BookmarkType::Bookmark => UpdatableBookmark {
+            location,
+            url: raw.url.clone(),
+            title,
+        }
+        .into(),
+        BookmarkType::Folder => UpdatableFolder { location, title }.into(),
+        BookmarkType::Separator => UpdatableSeparator { location }.into(),
+    })
+}
+
+/// Returns an item that moves an item of the given type.
+fn updatable_for_move(ty: BookmarkType, location: UpdateTreeLocation) -> UpdatableItem {
+    match ty {
+        BookmarkType::Bookmark => UpdatableBookmark {
+            location,
+            ..Default::default()
+        }
+        .into(),
+        BookmarkType::Folder => UpdatableFolder {
+            location,
+            ..Default::default()
+        }
+        .into(),
+        BookmarkType::Separator => UpdatableSeparator { location }.into(),
+    }
+}
+
+fn apply_operation(db: &PlacesDb, op: BookmarkOperation, batch: &mut BookmarkBatch) -> Result<()> {
+    match op {
+        BookmarkOperation::Insert { item } => {
+            let guid = insert_bookmark_in_tx(db, item)?;
+            batch.inserted_guids.push(guid.clone());
+            batch.undo.steps.push(UndoStep::Delete { guid });
+        }
+        BookmarkOperation::Move {
+            guid,
+            parent_guid,
+            position,
+        } => {
+            let raw = get_raw_bookmark(db, &guid)?
+                .ok_or_else(|| InvalidPlaceInfo::NoSuchGuid(guid.to_string()))?;
+            let item = updatable_for_move(
+                raw.bookmark_type,
+                UpdateTreeLocation::Parent {
+                    guid: parent_guid,
+                    pos: position,
+                },
+            );
+            let undo = updatable_for_undo(&raw)?;
+            update_bookmark_in_tx(db, &guid, &item, raw)?;
+            batch.undo.steps.push(UndoStep::Update { guid, item: undo });
+        }
+        BookmarkOperation::Update { info } => {
+            let raw = get_raw_bookmark(db, &info.guid)?
+                .ok_or_else(|| InvalidPlaceInfo::NoSuchGuid(info.guid.to_string()))?;
+            let (guid, item) = info.into_updatable(raw.bookmark_type)?;
+            let undo = updatable_for_undo(&raw)?;
+            update_bookmark_in_tx(db, &guid, &item, raw)?;
+            batch.undo.steps.push(UndoStep::Update { guid, item: undo });
+        }
+        BookmarkOperation::Delete { guid } => {
+            // Deleting an item that doesn't exist does nothing, like
+            // `delete_bookmark`.
+            if let Some((node, parent_guid, position)) =
+                fetch_tree(db, &guid, &FetchDepth::Deepest)?
+            {
+                let mut item: InsertableItem = node.into();
+                item.set_parent_guid(
+                    parent_guid
+                        .ok_or_else(|| Corruption::NonRootWithoutParent(guid.to_string()))?,
+                );
+                set_position(&mut item, BookmarkPosition::Specific { pos: position });
+                if delete_bookmark_in_tx(db, &guid)? {
+                    batch.undo.steps.push(UndoStep::Insert { item });
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn set_position(item: &mut InsertableItem, position: BookmarkPosition) {
+    match item {
+        InsertableItem::Bookmark { b } => b.position = position,
+        InsertableItem::Separator { s } => s.position = position,
+        InsertableItem::Folder { f } => f.position = position,
+    }
+}
+
+/// Applies a list of inserts, moves, updates and deletes in one transaction.
+/// If any of them fails, none of them are applied.
+///
+/// Returns the guids of the inserted items, and a [`BookmarkUndo`] for
+/// [`undo_operations`].
+pub fn apply_operations(db: &PlacesDb, ops: Vec<BookmarkOperation>) -> Result<BookmarkBatch> {
+    let mut batch = BookmarkBatch::default();
+    let tx = db.begin_transaction()?;
+    for op in ops {
+        apply_operation(db, op, &mut batch)?;
+    }
+    crate::storage::delete_pending_temp_tables(db)?;
+    tx.commit()?;
+    Ok(batch)
+}
+
+/// Undoes the operations from [`apply_operations`], restoring the changed
+/// items, including deleted ones, with their guids and positions. If the
+/// bookmarks were changed again in a way that conflicts, like deleting the
+/// folder a deleted item was in, nothing is undone and an error is returned.
+pub fn undo_operations(db: &PlacesDb, undo: &BookmarkUndo) -> Result<()> {
+    let tx = db.begin_transaction()?;
+    for step in undo.steps.iter().rev() {
+        match step {
+            UndoStep::Delete { guid } => {
+                delete_bookmark_in_tx(db, guid)?;
+            }
+            UndoStep::Insert { item } => {
+                insert_bookmark_in_tx(db, item.clone())?;
+            }
+            UndoStep::Update { guid, item } => {
+                let raw = get_raw_bookmark(db, guid)?
+                    .ok_or_else(|| InvalidPlaceInfo::NoSuchGuid(guid.to_string()))?;
+                update_bookmark_in_tx(db, guid, item, raw)?;
+            }
+        }
+    }
+    crate::storage::delete_pending_temp_tables(db)?;
+    tx.commit()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::places_api::test::new_mem_connection;
+    use crate::storage::bookmarks::{
+        delete_bookmark, insert_bookmark, BookmarkRootGuid, InsertableBookmark,
+    };
+    use crate::tests::{assert_json_tree, insert_json_tree};
+    use serde_json::{json, Value};
+    use url::Url;
+
+    fn initial_tree() -> Value {
+        json!({
+            "guid": String::from(BookmarkRootGuid::Unfiled.as_str()),
+            "children": [
+                {
+                    "guid": "bookmarkAAAA",
+                    "title": "A",
+                    "url": "https://example.com/a",
+                },
+                {
+                    "guid": "folderAAAAAA",
+                    "title": "Folder",
+                    "children": [
+                        {
+                            "guid": "bookmarkBBBB",
+                            "title": "B",
+                            "url": "https://example.com/b",
+                        },
+                        {
+                            "guid": "bookmarkCCCC",
+                            "title": "C",
+                            "url": "https://example.com/c",
+                        },
+                    ],
+                },
+                {
+                    "guid": "bookmarkDDDD",
+                    "title": "D",
+                    "url": "https://example.com/d",
+                },
+            ],
+        })
+    }
+
+    #[test]
+    fn test_apply_and_undo() -> Result<()> {
+        let conn = new_mem_connection();
+        insert_json_tree(&conn, initial_tree());
+
+        let batch = apply_operations(
+            &conn,
+            vec![
+                BookmarkOperation::Insert {
+                    item: InsertableBookmark {
+                        parent_guid: "folderAAAAAA".into(),
+                        position: BookmarkPosition::Specific { pos: 0 },
+                        date_added: None,
+                        last_modified: None,
+                        guid: Some("bookmarkEEEE".into()),
+                        url: Url::parse("https://example.com/e")?,
+                        title: Some("E".into()),
+                    }
+                    .into(),
+                },
+                BookmarkOperation::Move {
+                    guid: "bookmarkDDDD".into(),
+                    parent_guid: "folderAAAAAA".into(),
+                    position: BookmarkPosition::Append,
+                },
+                BookmarkOperation::Update {
+                    info: BookmarkUpdateInfo {
+                        guid: "bookmarkAAAA".into(),
+                        title: Some("New A".into()),
+                        url: None,
+                        parent_guid: None,
+                        position: None,
+                    },
+                },
+                BookmarkOperation::Delete {
+                    guid: "bookmarkBBBB".into(),
+                },
+            ],
+        )?;
+        assert_eq!(batch.inserted_guids, vec![SyncGuid::from("bookmarkEEEE")]);
+        assert_json_tree(
+            &conn,
+            &BookmarkRootGuid::Unfiled.into(),
+            json!({
+                "guid": String::from(BookmarkRootGuid::Unfiled.as_str()),
+                "children": [
+                    {
+                        "guid": "bookmarkAAAA",
+                        "title": "New A",
+                        "url": "https://example.com/a",
+                    },
+                    {
+                        "guid": "folderAAAAAA",
+                        "title": "Folder",
+                        "children": [
+                            {
+                                "guid": "bookmarkEEEE",
+                                "title": "E",
+                                "url": "https://example.com/e",
+                            },
+                            {
+                                "guid": "bookmarkCCCC",
+                                "title": "C",
+                                "url": "https://example.com/c",
+                            },
+                            {
+                                "guid": "bookmarkDDDD",
+                                "title": "D",
+                                "url": "https://example.com/d",
+                            },
+                        ],
+                    },
+                ],
+            }),
+        );
+
+        undo_operations(&conn, &batch.undo)?;
+        assert_json_tree(&conn, &BookmarkRootGuid::Unfiled.into(), initial_tree());
+        Ok(())
+    }
+
+    #[test]
+    fn test_undo_folder_delete() -> Result<()> {
+        let conn = new_mem_connection();
+        insert_json_tree(&conn, initial_tree());
+
+        let batch = apply_operations(
+            &conn,
+            vec![BookmarkOperation::Delete {
+                guid: "folderAAAAAA".into(),
+            }],
+        )?;
+        assert!(get_raw_bookmark(&conn, &"bookmarkCCCC".into())?.is_none());
+
+        // An undo that fails changes nothing, and can be tried again.
+        insert_bookmark(
+            &conn,
+            InsertableBookmark {
+                parent_guid: BookmarkRootGuid::Menu.into(),
+                position: BookmarkPosition::Append,
+                date_added: None,
+                last_modified: None,
+                guid: Some("bookmarkCCCC".into()),
+                url: Url::parse("https://example.com/c")?,
+                title: None,
+            }
+            .into(),
+        )?;
+        undo_operations(&conn, &batch.undo).expect_err("should fail to reuse a guid");
+        assert!(get_raw_bookmark(&conn, &"folderAAAAAA".into())?.is_none());
+        delete_bookmark(&conn, &"bookmarkCCCC".into())?;
+
+        undo_operations(&conn, &batch.undo)?;
+        assert_json_tree(&conn, &BookmarkRootGuid::Unfiled.into(), initial_tree());
+        Ok(())
+    }
+
+    #[test]
+    fn test_undo_log() {
+        let mut log = BookmarkUndoLog::default();
+        let first = log.add(BookmarkUndo::default());
+        for _ in 0..BookmarkUndoLog::MAX_UNDOS {
+            log.add(BookmarkUndo::default());
+        }
+        let last = log.add(BookmarkUndo::default());
+        assert!(log.take(first).is_none(), "should forget the oldest undo");
+        assert!(log.get(last).is_some());
+        assert!(log.take(last).is_some());
+        assert!(log.take(last).is_none(), "should only undo once");
+    }
+
+    #[test]
+    fn test_failed_batch_is_rolled_back() -> Result<()> {
+        let conn = new_mem_connection();
+        insert_json_tree(&conn, initial_tree());
+
+        apply_operations(
+            &conn,
+            vec![
+                BookmarkOperation::Delete {
+                    guid: "bookmarkAAAA".into(),
+                },
+                BookmarkOperation::Move {
+                    guid: "bookmarkDDDD".into(),
+                    parent_guid: "bookmarkBBBB".into(),
+                    position: BookmarkPosition::Append,
+                },
+            ],
+        )
+        .expect_err("should fail to move into a bookmark");
+        assert_json_tree(&conn, &BookmarkRootGuid::Unfiled.into(), initial_tree());
+        Ok(())
+    }
+}
@@ -0,0 +1,480 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Finding and merging duplicate bookmarks and folders, which users often
+//! end up with after importing from several browsers.
+//!
+//! Finding duplicates returns a plan, which the app can show to the user
+//! and trim before merging. Merging moves and deletes items the same way the
+//! rest of the bookmarks API does, so the changes sync like any others.
+
+use super::keywords::{get_keyword_for_url, set_keyword_in_tx};
+use super::{
+    delete_bookmark_in_tx, get_raw_bookmark, update_bookmark_in_tx, BookmarkPosition,
+    UpdatableBookmark, UpdatableFolder, UpdatableItem, UpdatableSeparator, UpdateTreeLocation,
+};
+use crate::db::PlacesDb;
+use crate::error::Result;
+use crate::storage::tags::{get_tags_for_url, tag_url_in_tx};
+use crate::types::BookmarkType;
+use sql_support::ConnExt;
+use std::collections::HashMap;
+use sync_guid::Guid as SyncGuid;
+use url::Url;
+
+/// Bookmarks for the same URL. The oldest one is kept.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BookmarkDuplicates {
+    /// The URL of the bookmark we keep.
+    pub url: Url,
+    pub keep_guid: SyncGuid,
+    pub duplicate_guids: Vec<SyncGuid>,
+}
+
+/// Folders with the same title in the same parent. The oldest one is kept,
+/// and the children of the others are moved into it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FolderDuplicates {
+    pub title: Option<String>,
+    pub keep_guid: SyncGuid,
+    pub duplicate_guids: Vec<SyncGuid>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BookmarkDuplicatesPlan {
+    pub bookmarks: Vec<BookmarkDuplicates>,
+    /// Parent folders come before their children.
+    pub folders: Vec<FolderDuplicates>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MergeDuplicatesResult {
+    pub num_bookmarks_removed: u32,
+    pub num_folders_removed: u32,
+}
+
+/// Returns the form of a URL we compare to find duplicates, which ignores the
+/// fragment and a trailing slash.
+fn normalize_url(url: &Url) -> String {
+    let mut url = url.clone();
+    url.set_fragment(None);
+    let mut normalized = String::from(url);
+    if normalized.ends_with('/') {
+        normalized.pop();
+    }
+    normalized
+}
+
+/// Finds duplicate bookmarks, which have the same URL after ignoring the
+/// fragment and a trailing slash, and, optionally, the same title or parent,
+/// and duplicate folders, which have the same title and parent.
+///
+/// Parents are compared as they will be after merging folders, so bookmarks
+/// in two duplicate folders can be duplicates of each other.
+pub fn find_duplicates(
+    db: &PlacesDb,
+    match_title: bool,
+    match_parent: bool,
+) -> Result<BookmarkDuplicatesPlan> {
+    let mut plan = BookmarkDuplicatesPlan::default();
+
+    // Maps the guids of duplicate folders to the folders they're merged into.
+    let mut merged_folders: HashMap<SyncGuid, SyncGuid> = HashMap::new();
+    // Folders are ordered by depth, so we've always seen a folder's parent,
+    // and merged it if it's a duplicate, before the folder itself.
+    let folders = db.query_rows_and_then_cached(
+        "WITH RECURSIVE folders(id, guid, parentGuid, title, dateAdded, level) AS (
+             SELECT id, guid, NULL, title, dateAdded, 0 FROM moz_bookmarks
+             WHERE guid = 'root________'
+             UNION ALL
+             SELECT b.id, b.guid, f.guid, b.title, b.dateAdded, f.level + 1
+             FROM moz_bookmarks b
+             JOIN folders f ON f.id = b.parent
+             WHERE b.type = :folder_type
+         )
+         SELECT guid, parentGuid, NULLIF(title, '') AS title FROM folders
+         /* The roots can't be merged. */
+         WHERE level > 1
+         ORDER BY level, dateAdded, id",
+        &[(":folder_type", &(BookmarkType::Folder as u8))],
+        |row| -> rusqlite::Result<_> {
+            Ok((
+                row.get::<_, SyncGuid>("guid")?,
+                row.get::<_, SyncGuid>("parentGuid")?,
+                row.get::<_, Option<String>>("title")?,
+            ))
+        },
+    )?;
+    let mut folder_groups: HashMap<(SyncGuid, Option<String>), usize> = HashMap::new();
+    for (guid, parent_guid, title) in folders {
+        let parent_guid = merged_folders
+            .get(&parent_guid)
+            .unwrap_or(&parent_guid)
+            .clone();
+        match folder_groups.get(&(parent_guid.clone(), title.clone())) {
+            Some(&index) => {
+                let group: &mut FolderDuplicates = &mut plan.folders[index];
+                merged_folders.insert(guid.clone(), group.keep_guid.clone());
+                group.duplicate_guids.push(guid);
+            }
+            None => {
+                folder_groups.insert((parent_guid, title.clone()), plan.folders.len());
+                plan.folders.push(FolderDuplicates {
+                    title,
+                    keep_guid: guid,
+                    duplicate_guids: Vec::new(),
+                });
+            }
+        }
+    }
+    plan.folders
+        .retain(|group| !group.duplicate_guids.is_empty());
+
+    let bookmarks = db.query_rows_and_then_cached(
+        "SELECT b.guid, p.guid AS parentGuid, NULLIF(b.title, '') AS title, h.url
+         FROM moz_bookmarks b
+         JOIN moz_bookmarks p ON p.id = b.parent
+         JOIN moz_places h ON h.id = b.fk
+         WHERE b.type = :bookmark_type
+         ORDER BY b.dateAdded, b.id",
+        &[(":bookmark_type", &(BookmarkType::Bookmark as u8))],
+        |row| -> rusqlite::Result<_> {
+            Ok((
+                row.get::<_, SyncGuid>("guid")?,
+                row.get::<_, SyncGuid>("parentGuid")?,
+                row.get::<_, Option<String>>("title")?,
+                row.get::<_, String>("url")?,
+            ))
+        },
+    )?;
+    let mut bookmark_groups: HashMap<(String, Option<String>, Option<SyncGuid>), usize> =
+        HashMap::new();
+    for (guid, parent_guid, title, url) in bookmarks {
+        let url = match Url::parse(&url) {
+            Ok(url) => url,
+            Err(e) => {
+                log::warn!("ignoring invalid url: {:?}", e);
+                continue;
+            }
+        };
+        let parent_guid = merged_folders
+            .get(&parent_guid)
+            .unwrap_or(&parent_guid)
+            .clone();
+        let key = (
+            normalize_url(&url),
+            if match_title { title } else { None },
+            if match_parent {
+                Some(parent_guid)
+            } else {
+                None
+            },
+        );
+        match bookmark_groups.get(&key) {
+            Some(&index) => plan.bookmarks[index].duplicate_guids.push(guid),
+            None => {
+                bookmark_groups.insert(key, plan.bookmarks.len());
+                plan.bookmarks.push(BookmarkDuplicates {
+                    url,
+                    keep_guid: guid,
+                    duplicate_guids: Vec::new(),
+                });
+            }
+        }
+    }
+    plan.bookmarks
+        .retain(|group| !group.duplicate_guids.is_empty());
+
+    Ok(plan)
+}
+
+/// Merges the duplicates in a plan from [`find_duplicates`], which the app
+/// may have changed. Folders are merged first, by moving the children of the
+/// duplicate folders into the folder we keep, then duplicate bookmarks are
+/// deleted. Tags and keywords for the URLs of deleted bookmarks are added to
+/// the URL of the bookmark we keep, if it doesn't have a keyword already.
+///
+/// Items that were deleted since the plan was made are skipped.
+pub fn merge_duplicates(
+    db: &PlacesDb,
+    plan: BookmarkDuplicatesPlan,
+) -> Result<MergeDuplicatesResult> {
+    let mut result = MergeDuplicatesResult::default();
+    let tx = db.begin_transaction()?;
+
+    for group in plan.folders {
+        match get_raw_bookmark(db, &group.keep_guid)? {
+            Some(keep) if keep.bookmark_type == BookmarkType::Folder => (),
+            _ => continue,
+        }
+        for guid in group.duplicate_guids {
+            if guid == group.keep_guid {
+                continue;
+            }
+            let duplicate = match get_raw_bookmark(db, &guid)? {
+                Some(raw) if raw.bookmark_type == BookmarkType::Folder => raw,
+                _ => continue,
+            };
+            let children = db.query_rows_and_then_cached(
+                "SELECT guid FROM moz_bookmarks WHERE parent = :parent ORDER BY position",
+                &[(":parent", &duplicate.row_id)],
+                |row| row.get::<_, SyncGuid>(0),
+            )?;
+            for child_guid in children {
+                let child = match get_raw_bookmark(db, &child_guid)? {
+                    Some(raw) => raw,
+                    None => continue,
+                };
+                let location = UpdateTreeLocation::Parent {
+                    guid: group.keep_guid.clone(),
+                    pos: BookmarkPosition::Append,
+                };
+                let item: UpdatableItem = match child.bookmark_type {
+                    BookmarkType::Bookmark => UpdatableBookmark {
+                        location,
+                        ..Default::default()
+                    }
+                    .into(),
+                    BookmarkType::Folder => UpdatableFolder {
+                        location,
+                        ..Default::default()
+                    }
+                    .into(),
+                    BookmarkType::Separator => UpdatableSeparator { location }.into(),
+                };
+                update_bookmark_in_tx(db, &child_guid, &item, child)?;
+            }
+            if delete_bookmark_in_tx(db, &guid)? {
+                result.num_folders_removed += 1;
+            }
+        }
+    }
+
+    for group in plan.bookmarks {
+        let keep_url = match get_raw_bookmark(db, &group.keep_guid)? {
+            Some(keep) if keep.bookmark_type == BookmarkType::Bookmark => match keep.url {
+                Some(url) => url,
+                None => continue,
+            },
+            _ => continue,
+        };
+        for guid in group.duplicate_guids {
+            if guid == group.keep_guid {
+                continue;
+            }
+            let duplicate_url = match get_raw_bookmark(db, &guid)? {
+                Some(raw) if raw.bookmark_type == BookmarkType::Bookmark => raw.url,
+                _ => continue,
+            };
+            // Tags and keywords belong to URLs, so they're only lost if the
+            // URLs are different.
+            if let Some(duplicate_url) = duplicate_url.filter(|url| *url != keep_url) {
+                for tag in get_tags_for_url(db, &duplicate_url)? {
+                    tag_url_in_tx(db, &keep_url, &tag)?;
+                }
+                if get_keyword_for_url(db, &keep_url)?.is_none() {
+                    if let Some(keyword) = get_keyword_for_url(db, &duplicate_url)? {
+                        set_keyword_in_tx(db, &keep_url, &keyword)?;
+                    }
+                }
+            }
+            if delete_bookmark_in_tx(db, &guid)? {
+                result.num_bookmarks_removed += 1;
+            }
+        }
+    }
+
+    crate::storage::delete_pending_temp_tables(db)?;
+    tx.commit()?;
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::places_api::test::new_mem_connection;
+    use crate::storage::bookmarks::keywords::set_keyword;
+    use crate::storage::bookmarks::BookmarkRootGuid;
+    use crate::storage::tags::tag_url;
+    use crate::tests::{assert_json_tree, insert_json_tree};
+    use crate::types::SyncStatus;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    fn guids(guids: &[&str]) -> Vec<SyncGuid> {
+        guids.iter().map(|&guid| guid.into()).collect()
+    }
+
+    fn insert_duplicates(conn: &PlacesDb) {
+        insert_json_tree(
+            conn,
+            json!({
+                "guid": String::from(BookmarkRootGuid::Menu.as_str()),
+                "children": [
+                    {
+                        "guid": "bookmarkAAAA",
+                        "title": "A",
+                        "url": "https://example.com/a",
+                    },
+                    {
+                        "guid": "folderAAAAAA",
+                        "title": "Imported",
+                        "children": [
+                            {
+                                "guid": "bookmarkBBBB",
+                                "title": "B",
+                                "url": "https://example.com/b",
+                            },
+                        ],
+                    },
+                    {
+                        "guid": "folderBBBBBB",
+                        "title": "Imported",
+                        "children": [
+                            {
+                                "guid": "bookmarkCCCC",
+                                "title": "B",
+                                "url": "https://example.com/b/",
+                            },
+                            {
+                                "guid": "bookmarkDDDD",
+                                "title": "D",
+                                "url": "https://example.com/d",
+                            },
+                        ],
+                    },
+                    {
+                        "guid": "bookmarkEEEE",
+                        "title": "Another A",
+                        "url": "https://example.com/a#top",
+                    },
+                ],
+            }),
+        );
+    }
+
+    #[test]
+    fn test_find_duplicates() -> Result<()> {
+        let conn = new_mem_connection();
+        insert_duplicates(&conn);
+
+        let plan = find_duplicates(&conn, false, false)?;
+        assert_eq!(
+            plan.folders,
+            vec![FolderDuplicates {
+                title: Some("Imported".into()),
+                keep_guid: "folderAAAAAA".into(),
+                duplicate_guids: guids(&["folderBBBBBB"]),
+            }]
+        );
+        assert_eq!(
+            plan.bookmarks,
+            vec![
+                BookmarkDuplicates {
+                    url: Url::parse("https://example.com/a")?,
+                    keep_guid: "bookmarkAAAA".into(),
+                    duplicate_guids: guids(&["bookmarkEEEE"]),
+                },
+                BookmarkDuplicates {
+                    url: Url::parse("https://example.com/b")?,
+                    keep_guid: "bookmarkBBBB".into(),
+                    duplicate_guids: guids(&["bookmarkCCCC"]),
+                },
+            ]
+        );
+
+        // B and C are in duplicate folders, so they still match by parent,
+        // but A and E have different titles.
+        let plan = find_duplicates(&conn, true, true)?;
+        assert_eq!(
+            plan.bookmarks
+                .iter()
+                .map(|group| group.keep_guid.as_str())
+                .collect::<Vec<_>>(),
+            vec!["bookmarkBBBB"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_duplicates() -> Result<()> {
+        let conn = new_mem_connection();
+        insert_duplicates(&conn);
+        let kept_url = Url::parse("https://example.com/a")?;
+        let removed_url = Url::parse("https://example.com/a#top")?;
+        tag_url(&conn, &removed_url, "later")?;
+        set_keyword(&conn, &removed_url, "a")?;
+        // Pretend everything was synced, so that we write tombstones.
+        conn.execute(
+            &format!(
+                "UPDATE moz_bookmarks SET syncStatus = {}",
+                SyncStatus::Normal as u8
+            ),
+            [],
+        )?;
+
+        let plan = find_duplicates(&conn, false, false)?;
+        assert_eq!(
+            merge_duplicates(&conn, plan)?,
+            MergeDuplicatesResult {
+                num_bookmarks_removed: 2,
+                num_folders_removed: 1,
+            }
+        );
+
+        assert_json_tree(
+            &conn,
+            &BookmarkRootGuid::Menu.into(),
+            json!({
+                "guid": String::from(BookmarkRootGuid::Menu.as_str()),
+                "children": [
+                    {
+                        "guid": "bookmarkAAAA",
+                        "title": "A",
+                        "url": "https://example.com/a",
+                    },
+                    {
+                        "guid": "folderAAAAAA",
+                        "title": "Imported",
+                        "children": [
+                            {
+                                "guid": "bookmarkBBBB",
+                                "title": "B",
+                                "url": "https://example.com/b",
+                            },
+                            {
+                                "guid": "bookmarkDDDD",
+                                "title": "D",
+                                "url": "https://example.com/d",
+                            },
+                        ],
+                    },
+                ],
+            }),
+        );
+        assert_eq!(
+            get_tags_for_url(&conn, &kept_url)?,
+            vec!["later".to_string()]
+        );
+        assert_eq!(get_keyword_for_url(&conn, &kept_url)?, Some("a".into()));
+
+        // The deleted items are synced as tombstones.
+        let tombstones: Vec<String> = conn.query_rows_and_then(
+            "SELECT guid FROM moz_bookmarks_deleted ORDER BY guid",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(
+            tombstones,
+            vec!["bookmarkCCCC", "bookmarkEEEE", "folderBBBBBB"]
+        );
+
+        // Merging again does nothing.
+        assert_eq!(
+            merge_duplicates(&conn, find_duplicates(&conn, false, false)?)?,
+            MergeDuplicatesResult::default()
+        );
+        Ok(())
+    }
+}
@@ -404,8 +404,16 @@ mod test_serialize {
 pub fn insert_tree(db: &PlacesDb, tree: FolderNode) -> Result<()> {
     // This API is strange - we don't add `tree`, but just use it for the parent.
     // It's only used for json importing, so we can live with a strange API :)
-    let parent = tree.guid.expect("inserting a tree without the root guid");
     let tx = db.begin_transaction()?;
+    insert_tree_in_tx(db, tree)?;
+    crate::storage::delete_pending_temp_tables(db)?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Like `insert_tree`, but for callers which insert several trees in their own transaction.
+pub(crate) fn insert_tree_in_tx(db: &PlacesDb, tree: FolderNode) -> Result<()> {
+    let parent = tree.guid.expect("inserting a tree without the root guid");
     for child in tree.children {
         let mut insertable: InsertableItem = child.into();
         assert!(
@@ -415,8 +423,6 @@ pub fn insert_tree(db: &PlacesDb, tree: FolderNode) -> Result<()> {
         insertable.set_parent_guid(parent.clone());
         crate::storage::bookmarks::insert_bookmark_in_tx(db, insertable)?;
     }
-    crate::storage::delete_pending_temp_tables(db)?;
-    tx.commit()?;
     Ok(())
 }
 
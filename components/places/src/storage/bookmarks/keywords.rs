@@ -0,0 +1,358 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Search keywords for bookmarked URLs, like "w" for
+//! `https://en.wikipedia.org/wiki/Special:Search?search=%s`.
+//!
+//! Keywords are stored per URL in `moz_keywords`, but Sync stores them on
+//! each bookmark record, so changing a keyword flags all the bookmarks for
+//! the old and new URLs for upload. Unlike Desktop, we don't support POST
+//! data for keywords.
+
+use crate::db::PlacesDb;
+use crate::error::{InvalidPlaceInfo, Result};
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use sql_support::ConnExt;
+use url::Url;
+
+/// The characters that `encodeURIComponent` escapes, which Desktop uses to
+/// substitute `%s` in keyword URLs. Non-ASCII characters are always escaped.
+const KEYWORD_PARAM_ENCODE_SET: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'$')
+    .add(b'%')
+    .add(b'&')
+    .add(b'+')
+    .add(b',')
+    .add(b'/')
+    .add(b':')
+    .add(b';')
+    .add(b'<')
+    .add(b'=')
+    .add(b'>')
+    .add(b'?')
+    .add(b'@')
+    .add(b'[')
+    .add(b'\\')
+    .add(b']')
+    .add(b'^')
+    .add(b'`')
+    .add(b'{')
+    .add(b'|')
+    .add(b'}');
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BookmarkKeyword {
+    pub keyword: String,
+    pub url: Url,
+}
+
+/// Returns the normalized form of a keyword, or an error if it's empty or
+/// contains whitespace, since it couldn't be typed in the address bar.
+fn normalize_keyword(keyword: &str) -> Result<String> {
+    let keyword = keyword.trim();
+    if keyword.is_empty() || keyword.contains(char::is_whitespace) {
+        return Err(InvalidPlaceInfo::InvalidKeyword.into());
+    }
+    Ok(keyword.to_lowercase())
+}
+
+/// Flags the bookmarks for a page, and for the page that currently has the
+/// keyword, for upload, so that Sync picks up their new keywords.
+fn bump_change_counters_for_keyword_pages(
+    db: &PlacesDb,
+    keyword: &str,
+    place_id: i64,
+) -> Result<()> {
+    db.execute_cached(
+        "UPDATE moz_bookmarks SET
+             syncChangeCounter = syncChangeCounter + 1
+         WHERE fk = :place_id OR
+               fk = (SELECT place_id FROM moz_keywords WHERE keyword = :keyword)",
+        rusqlite::named_params! {
+            ":place_id": place_id,
+            ":keyword": keyword,
+        },
+    )?;
+    Ok(())
+}
+
+/// Sets the keyword for a bookmarked URL, replacing its old keyword. If
+/// another URL had the keyword, it's moved to this one.
+///
+/// Keywords are lowercased, and can't be empty or contain whitespace. The
+/// URL may contain `%s`, which is replaced with the text typed after the
+/// keyword; see [`expand_keyword`].
+pub fn set_keyword(db: &PlacesDb, url: &Url, keyword: &str) -> Result<()> {
+    let tx = db.begin_transaction()?;
+    set_keyword_in_tx(db, url, keyword)?;
+    tx.commit()?;
+    Ok(())
+}
+
+pub(crate) fn set_keyword_in_tx(db: &PlacesDb, url: &Url, keyword: &str) -> Result<()> {
+    let keyword = normalize_keyword(keyword)?;
+    // Sync stores keywords on bookmarks, so we can't set a keyword for a URL
+    // that isn't bookmarked.
+    let place_id: i64 = match db.try_query_row(
+        "SELECT h.id FROM moz_places h
+         WHERE h.url_hash = hash(:url) AND h.url = :url AND
+               EXISTS(SELECT 1 FROM moz_bookmarks WHERE fk = h.id)",
+        &[(":url", &url.as_str())],
+        |row| row.get(0),
+        true,
+    )? {
+        Some(place_id) => place_id,
+        None => return Err(InvalidPlaceInfo::NoSuchUrl.into()),
+    };
+    let unchanged = db.exists(
+        "SELECT 1 FROM moz_keywords
+         WHERE place_id = :place_id AND keyword = :keyword",
+        rusqlite::named_params! {
+            ":place_id": place_id,
+            ":keyword": keyword,
+        },
+    )?;
+    if !unchanged {
+        bump_change_counters_for_keyword_pages(db, &keyword, place_id)?;
+        db.execute_cached(
+            "DELETE FROM moz_keywords
+             WHERE place_id = :place_id OR keyword = :keyword",
+            rusqlite::named_params! {
+                ":place_id": place_id,
+                ":keyword": keyword,
+            },
+        )?;
+        db.execute_cached(
+            "INSERT INTO moz_keywords(place_id, keyword)
+             VALUES(:place_id, :keyword)",
+            rusqlite::named_params! {
+                ":place_id": place_id,
+                ":keyword": keyword,
+            },
+        )?;
+    }
+    Ok(())
+}
+
+/// Removes a keyword. Returns `true` if the keyword existed.
+pub fn clear_keyword(db: &PlacesDb, keyword: &str) -> Result<bool> {
+    let keyword = normalize_keyword(keyword)?;
+    let tx = db.begin_transaction()?;
+    db.execute_cached(
+        "UPDATE moz_bookmarks SET
+             syncChangeCounter = syncChangeCounter + 1
+         WHERE fk = (SELECT place_id FROM moz_keywords WHERE keyword = :keyword)",
+        &[(":keyword", &keyword)],
+    )?;
+    let removed = db.execute_cached(
+        "DELETE FROM moz_keywords WHERE keyword = :keyword",
+        &[(":keyword", &keyword)],
+    )? > 0;
+    tx.commit()?;
+    Ok(removed)
+}
+
+/// Returns all keywords and their URLs, sorted by keyword.
+pub fn get_keywords(db: &PlacesDb) -> Result<Vec<BookmarkKeyword>> {
+    let rows = db.query_rows_and_then_cached(
+        "SELECT k.keyword, h.url FROM moz_keywords k
+         JOIN moz_places h ON h.id = k.place_id
+         ORDER BY k.keyword",
+        [],
+        |row| -> rusqlite::Result<_> {
+            Ok((
+                row.get::<_, String>("keyword")?,
+                row.get::<_, String>("url")?,
+            ))
+        },
+    )?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|(keyword, url)| match Url::parse(&url) {
+            Ok(url) => Some(BookmarkKeyword { keyword, url }),
+            Err(e) => {
+                // The keyword is PII, so we don't log it.
+                log::warn!("ignoring invalid url: {:?}", e);
+                None
+            }
+        })
+        .collect())
+}
+
+/// Returns the keyword for a URL, if it has one.
+pub fn get_keyword_for_url(db: &PlacesDb, url: &Url) -> Result<Option<String>> {
+    Ok(db.try_query_row(
+        "SELECT k.keyword FROM moz_keywords k
+         JOIN moz_places h ON h.id = k.place_id
+         WHERE h.url_hash = hash(:url) AND h.url = :url",
+        &[(":url", &url.as_str())],
+        |row| row.get::<_, String>("keyword"),
+        true,
+    )?)
+}
+
+/// Expands address bar input that starts with a keyword, like "w firefox",
+/// into the URL to load. Returns `None` if the first word isn't a keyword.
+///
+/// In the keyword's URL, `%s` is replaced with the rest of the input,
+/// escaped as a URL component, and `%S` with the rest of the input as typed.
+/// If the URL has neither, the keyword only matches when it's typed alone.
+pub fn expand_keyword(db: &PlacesDb, input: &str) -> Result<Option<Url>> {
+    let input = input.trim();
+    let (keyword, param) = match input.split_once(char::is_whitespace) {
+        Some((keyword, param)) => (keyword, param.trim_start()),
+        None => (input, ""),
+    };
+    if keyword.is_empty() {
+        return Ok(None);
+    }
+    let keyword = keyword.to_lowercase();
+    let url = match db.try_query_row(
+        "SELECT h.url FROM moz_keywords k
+         JOIN moz_places h ON h.id = k.place_id
+         WHERE k.keyword = :keyword",
+        &[(":keyword", &keyword)],
+        |row| row.get::<_, String>("url"),
+        true,
+    )? {
+        Some(url) => url,
+        None => return Ok(None),
+    };
+    let has_placeholder = url.contains("%s") || url.contains("%S");
+    if !has_placeholder && !param.is_empty() {
+        return Ok(None);
+    }
+    let encoded_param = utf8_percent_encode(param, KEYWORD_PARAM_ENCODE_SET).to_string();
+    let expanded = url.replace("%s", &encoded_param).replace("%S", param);
+    match Url::parse(&expanded) {
+        Ok(url) => Ok(Some(url)),
+        Err(e) => {
+            log::warn!("ignoring invalid keyword url: {:?}", e);
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::places_api::test::new_mem_connection;
+    use crate::storage::bookmarks::{
+        bookmarks_get_url_for_keyword, insert_bookmark, BookmarkPosition, BookmarkRootGuid,
+        InsertableBookmark, InsertableItem,
+    };
+    use pretty_assertions::assert_eq;
+
+    fn bookmark(conn: &PlacesDb, url: &Url) -> Result<()> {
+        insert_bookmark(
+            conn,
+            InsertableItem::Bookmark {
+                b: InsertableBookmark {
+                    parent_guid: BookmarkRootGuid::Unfiled.into(),
+                    position: BookmarkPosition::Append,
+                    date_added: None,
+                    last_modified: None,
+                    guid: None,
+                    url: url.clone(),
+                    title: None,
+                },
+            },
+        )?;
+        Ok(())
+    }
+
+    fn change_counter(conn: &PlacesDb, url: &Url) -> Result<i64> {
+        Ok(conn.query_row_and_then_cachable(
+            "SELECT b.syncChangeCounter FROM moz_bookmarks b
+             JOIN moz_places h ON h.id = b.fk
+             WHERE h.url = :url",
+            &[(":url", &url.as_str())],
+            |row| row.get(0),
+            false,
+        )?)
+    }
+
+    #[test]
+    fn test_set_and_clear_keyword() -> Result<()> {
+        let conn = new_mem_connection();
+        let search = Url::parse("https://example.com/search?q=%s")?;
+        let other = Url::parse("https://example.org/")?;
+        bookmark(&conn, &search)?;
+        bookmark(&conn, &other)?;
+        conn.execute("UPDATE moz_bookmarks SET syncChangeCounter = 0", [])?;
+
+        set_keyword(&conn, &search, " EX ")?;
+        assert_eq!(get_keyword_for_url(&conn, &search)?, Some("ex".to_string()));
+        assert_eq!(
+            bookmarks_get_url_for_keyword(&conn, "ex")?,
+            Some(search.clone())
+        );
+        assert_eq!(change_counter(&conn, &search)?, 1);
+
+        // Setting the same keyword again doesn't reupload the bookmark.
+        set_keyword(&conn, &search, "ex")?;
+        assert_eq!(change_counter(&conn, &search)?, 1);
+
+        // Moving the keyword to another URL changes both bookmarks.
+        set_keyword(&conn, &other, "ex")?;
+        assert_eq!(get_keyword_for_url(&conn, &search)?, None);
+        assert_eq!(change_counter(&conn, &search)?, 2);
+        assert_eq!(change_counter(&conn, &other)?, 1);
+
+        set_keyword(&conn, &search, "s")?;
+        assert_eq!(
+            get_keywords(&conn)?,
+            vec![
+                BookmarkKeyword {
+                    keyword: "ex".into(),
+                    url: other.clone(),
+                },
+                BookmarkKeyword {
+                    keyword: "s".into(),
+                    url: search.clone(),
+                },
+            ]
+        );
+
+        assert!(clear_keyword(&conn, "ex")?);
+        assert!(!clear_keyword(&conn, "ex")?);
+        assert_eq!(get_keyword_for_url(&conn, &other)?, None);
+        assert_eq!(change_counter(&conn, &other)?, 2);
+
+        set_keyword(&conn, &search, "two words").expect_err("should reject whitespace");
+        set_keyword(&conn, &Url::parse("https://example.net/")?, "net")
+            .expect_err("should reject URLs that aren't bookmarked");
+        Ok(())
+    }
+
+    #[test]
+    fn test_expand_keyword() -> Result<()> {
+        let conn = new_mem_connection();
+        let search = Url::parse("https://example.com/search?q=%s&raw=%S")?;
+        let home = Url::parse("https://example.org/")?;
+        bookmark(&conn, &search)?;
+        bookmark(&conn, &home)?;
+        set_keyword(&conn, &search, "s")?;
+        set_keyword(&conn, &home, "h")?;
+
+        assert_eq!(
+            expand_keyword(&conn, "S  fish & chips")?,
+            Some(Url::parse(
+                "https://example.com/search?q=fish%20%26%20chips&raw=fish & chips"
+            )?)
+        );
+        assert_eq!(
+            expand_keyword(&conn, "s")?,
+            Some(Url::parse("https://example.com/search?q=&raw=")?)
+        );
+        assert_eq!(expand_keyword(&conn, " h ")?, Some(home));
+        assert_eq!(expand_keyword(&conn, "h something")?, None);
+        assert_eq!(expand_keyword(&conn, "nope")?, None);
+        assert_eq!(expand_keyword(&conn, "")?, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,549 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Checking the local bookmark tree for corruption, and repairing it.
+//!
+//! Bookmark sync validates the tree it merges, but a local tree can still be
+//! corrupted by crashes, old versions or imports. This finds items that
+//! aren't where they should be in the tree and moves them into the unfiled
+//! root, and renumbers children whose positions aren't `0..n`. Repaired
+//! items and folders have their change counters bumped, so the fixed tree is
+//! uploaded on the next sync.
+
+use super::{BookmarkRootGuid, USER_CONTENT_ROOTS};
+use crate::db::PlacesDb;
+use crate::error::*;
+use crate::observer::note_many_changes;
+use crate::storage::RowId;
+use crate::types::BookmarkType;
+use sql_support::ConnExt;
+use std::collections::{BTreeMap, HashSet};
+use types::Timestamp;
+
+/// The number of problems found and repaired by [`check_and_repair`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BookmarksRepairMetrics {
+    /// Items in the root that aren't roots, and roots that weren't in the
+    /// root.
+    pub num_invalid_root_children: u32,
+    /// Items whose parent doesn't exist.
+    pub num_orphans: u32,
+    /// Items whose parent is a bookmark or a separator.
+    pub num_non_folder_children: u32,
+    /// Folders that were their own ancestors.
+    pub num_cycles: u32,
+    /// Folders whose children had gaps or duplicates in their positions,
+    /// including the ones left with gaps by moving items out of them.
+    pub num_folders_renumbered: u32,
+}
+
+impl BookmarksRepairMetrics {
+    fn num_repaired(&self) -> u32 {
+        self.num_invalid_root_children
+            + self.num_orphans
+            + self.num_non_folder_children
+            + self.num_cycles
+            + self.num_folders_renumbered
+    }
+}
+
+fn get_root_id(db: &PlacesDb, root: BookmarkRootGuid) -> Result<RowId> {
+    Ok(db
+        .try_query_one(
+            "SELECT id FROM moz_bookmarks WHERE guid = :guid",
+            &[(":guid", &root.as_guid())],
+            true,
+        )?
+        .ok_or_else(|| InvalidPlaceInfo::NoSuchGuid(root.as_str().to_string()))?)
+}
+
+fn bump_change_counter(db: &PlacesDb, id: RowId, now: Timestamp) -> Result<()> {
+    db.execute_cached(
+        "UPDATE moz_bookmarks SET
+             syncChangeCounter = syncChangeCounter + 1,
+             lastModified = :now
+         WHERE id = :id",
+        rusqlite::named_params! {
+            ":id": id,
+            ":now": now,
+        },
+    )?;
+    Ok(())
+}
+
+/// Moves an item to the end of the unfiled root, and bumps the change
+/// counters of the item and both parents. The old parent might not exist.
+fn move_to_unfiled(
+    db: &PlacesDb,
+    id: RowId,
+    old_parent_id: RowId,
+    unfiled_id: RowId,
+    now: Timestamp,
+) -> Result<()> {
+    db.execute_cached(
+        "UPDATE moz_bookmarks SET
+             parent = :unfiled_id,
+             position = (SELECT IFNULL(MAX(position) + 1, 0) FROM moz_bookmarks
+                         WHERE parent = :unfiled_id),
+             syncChangeCounter = syncChangeCounter + 1,
+             lastModified = :now
+         WHERE id = :id",
+        rusqlite::named_params! {
+            ":id": id,
+            ":unfiled_id": unfiled_id,
+            ":now": now,
+        },
+    )?;
+    bump_change_counter(db, old_parent_id, now)?;
+    bump_change_counter(db, unfiled_id, now)?;
+    Ok(())
+}
+
+fn fetch_ids_and_parents(
+    db: &PlacesDb,
+    sql: &str,
+    params: &[(&str, &dyn rusqlite::ToSql)],
+) -> Result<Vec<(RowId, RowId)>> {
+    db.query_rows_and_then(sql, params, |row| -> Result<_> {
+        Ok((row.get("id")?, row.get("parent")?))
+    })
+}
+
+/// Moves items in the root that aren't roots to the unfiled root, and puts
+/// roots that aren't in the root back.
+fn fix_root_children(
+    db: &PlacesDb,
+    root_id: RowId,
+    unfiled_id: RowId,
+    now: Timestamp,
+) -> Result<u32> {
+    let mut count = 0;
+    let children = db.query_rows_and_then(
+        "SELECT id, parent, guid FROM moz_bookmarks WHERE parent = :root_id",
+        &[(":root_id", &root_id)],
+        |row| -> Result<_> {
+            Ok((
+                row.get::<_, RowId>("id")?,
+                row.get::<_, RowId>("parent")?,
+                row.get::<_, String>("guid")?,
+            ))
+        },
+    )?;
+    for (id, parent_id, guid) in children {
+        let is_user_root = BookmarkRootGuid::well_known(&guid)
+            .map_or(false, |root| USER_CONTENT_ROOTS.contains(&root));
+        if !is_user_root {
+            move_to_unfiled(db, id, parent_id, unfiled_id, now)?;
+            count += 1;
+        }
+    }
+    for root in USER_CONTENT_ROOTS {
+        let moved = db.execute_cached(
+            "UPDATE moz_bookmarks SET
+                 parent = :root_id,
+                 position = (SELECT IFNULL(MAX(position) + 1, 0) FROM moz_bookmarks
+                             WHERE parent = :root_id),
+                 syncChangeCounter = syncChangeCounter + 1,
+                 lastModified = :now
+             WHERE guid = :guid AND parent IS NOT :root_id",
+            rusqlite::named_params! {
+                ":root_id": root_id,
+                ":guid": root.as_guid(),
+                ":now": now,
+            },
+        )?;
+        count += moved as u32;
+    }
+    Ok(count)
+}
+
+/// Moves items whose parent doesn't exist, which can happen if the database
+/// was written without foreign key checks, to the unfiled root.
+fn fix_orphans(db: &PlacesDb, unfiled_id: RowId, now: Timestamp) -> Result<u32> {
+    let orphans = fetch_ids_and_parents(
+        db,
+        "SELECT b.id, b.parent FROM moz_bookmarks b
+         WHERE b.guid <> :root_guid AND
+               NOT EXISTS(SELECT 1 FROM moz_bookmarks p WHERE p.id = b.parent)",
+        &[(
+            ":root_guid",
+            &BookmarkRootGuid::Root.as_guid() as &dyn rusqlite::ToSql,
+        )],
+    )?;
+    for &(id, parent_id) in &orphans {
+        move_to_unfiled(db, id, parent_id, unfiled_id, now)?;
+    }
+    Ok(orphans.len() as u32)
+}
+
+/// Moves items whose parent is a bookmark or a separator to the unfiled root.
+fn fix_non_folder_children(db: &PlacesDb, unfiled_id: RowId, now: Timestamp) -> Result<u32> {
+    let children = fetch_ids_and_parents(
+        db,
+        "SELECT b.id, b.parent FROM moz_bookmarks b
+         JOIN moz_bookmarks p ON p.id = b.parent
+         WHERE p.type <> :folder_type",
+        &[(
+            ":folder_type",
+            &BookmarkType::Folder as &dyn rusqlite::ToSql,
+        )],
+    )?;
+    for &(id, parent_id) in &children {
+        move_to_unfiled(db, id, parent_id, unfiled_id, now)?;
+    }
+    Ok(children.len() as u32)
+}
+
+/// Breaks cycles by moving one folder in each cycle to the unfiled root,
+/// which brings the rest of the cycle, and everything in it, along.
+///
+/// This must run after the other fixes, so that every item that can't be
+/// reached from the root is in, or descends from, a cycle.
+fn fix_cycles(db: &PlacesDb, unfiled_id: RowId, now: Timestamp) -> Result<u32> {
+    let unreachable: BTreeMap<RowId, RowId> = db.query_rows_into(
+        "WITH RECURSIVE
+         reachable(id) AS (
+             SELECT id FROM moz_bookmarks WHERE guid = :root_guid
+             UNION
+             SELECT b.id FROM moz_bookmarks b
+             JOIN reachable r ON b.parent = r.id
+         )
+         SELECT id, parent FROM moz_bookmarks
+         WHERE id NOT IN reachable",
+        &[(":root_guid", &BookmarkRootGuid::Root.as_guid())],
+        |row| -> Result<_> { Ok((row.get("id")?, row.get("parent")?)) },
+    )?;
+    let mut count = 0;
+    let mut resolved = HashSet::new();
+    for &start in unreachable.keys() {
+        // Follow the parents until we reach an item we've already resolved,
+        // or one we've already seen on this walk, which is in a cycle.
+        let mut path = Vec::new();
+        let mut id = start;
+        while !resolved.contains(&id) {
+            if path.contains(&id) {
+                move_to_unfiled(db, id, unreachable[&id], unfiled_id, now)?;
+                count += 1;
+                break;
+            }
+            path.push(id);
+            match unreachable.get(&id) {
+                Some(&parent_id) => id = parent_id,
+                None => break,
+            }
+        }
+        resolved.extend(path);
+    }
+    Ok(count)
+}
+
+/// Renumbers the children of folders whose positions have gaps or
+/// duplicates, keeping their order.
+fn fix_positions(db: &PlacesDb, now: Timestamp) -> Result<u32> {
+    let parent_ids = db.query_rows_and_then(
+        "SELECT parent FROM moz_bookmarks
+         WHERE parent NOT NULL
+         GROUP BY parent
+         HAVING MIN(position) <> 0 OR
+                MAX(position) <> COUNT(*) - 1 OR
+                COUNT(DISTINCT position) <> COUNT(*)",
+        [],
+        |row| -> Result<RowId> { Ok(row.get(0)?) },
+    )?;
+    for &parent_id in &parent_ids {
+        let child_ids = db.query_rows_and_then(
+            "SELECT id FROM moz_bookmarks
+             WHERE parent = :parent_id
+             ORDER BY position, id",
+            &[(":parent_id", &parent_id)],
+            |row| -> Result<RowId> { Ok(row.get(0)?) },
+        )?;
+        for (position, child_id) in child_ids.into_iter().enumerate() {
+            db.execute_cached(
+                "UPDATE moz_bookmarks SET position = :position
+                 WHERE id = :id AND position <> :position",
+                rusqlite::named_params! {
+                    ":id": child_id,
+                    ":position": position as u32,
+                },
+            )?;
+        }
+        bump_change_counter(db, parent_id, now)?;
+    }
+    Ok(parent_ids.len() as u32)
+}
+
+/// Checks the bookmark tree for items that are in the wrong place, or whose
+/// positions are wrong, and repairs them in a single transaction. Misplaced
+/// items are moved to the end of the unfiled root.
+///
+/// This is one of the `run_maintenance_*()` steps, and is intended to be run
+/// during idle time.
+pub fn check_and_repair(db: &PlacesDb) -> Result<BookmarksRepairMetrics> {
+    let tx = db.begin_transaction()?;
+    let now = Timestamp::now();
+    let root_id = get_root_id(db, BookmarkRootGuid::Root)?;
+    let unfiled_id = get_root_id(db, BookmarkRootGuid::Unfiled)?;
+    let mut metrics = BookmarksRepairMetrics {
+        num_invalid_root_children: fix_root_children(db, root_id, unfiled_id, now)?,
+        num_orphans: fix_orphans(db, unfiled_id, now)?,
+        num_non_folder_children: fix_non_folder_children(db, unfiled_id, now)?,
+        num_cycles: fix_cycles(db, unfiled_id, now)?,
+        ..BookmarksRepairMetrics::default()
+    };
+    metrics.num_folders_renumbered = fix_positions(db, now)?;
+    if metrics.num_repaired() > 0 {
+        log::warn!("Repaired the bookmark tree: {:?}", metrics);
+        note_many_changes(db)?;
+    }
+    tx.commit()?;
+    Ok(metrics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::places_api::test::new_mem_connection;
+    use crate::storage::bookmarks::get_raw_bookmark;
+    use crate::tests::{assert_json_tree, insert_json_tree};
+    use serde_json::json;
+
+    fn get_change_counter(db: &PlacesDb, guid: &str) -> u32 {
+        db.query_row(
+            "SELECT syncChangeCounter FROM moz_bookmarks WHERE guid = ?",
+            [guid],
+            |row| row.get(0),
+        )
+        .expect("should get change counter")
+    }
+
+    fn set_parent(db: &PlacesDb, guid: &str, parent_guid: &str, position: u32) {
+        db.execute(
+            "UPDATE moz_bookmarks SET
+                 parent = (SELECT id FROM moz_bookmarks WHERE guid = :parent_guid),
+                 position = :position
+             WHERE guid = :guid",
+            rusqlite::named_params! {
+                ":guid": guid,
+                ":parent_guid": parent_guid,
+                ":position": position,
+            },
+        )
+        .expect("should set parent");
+    }
+
+    #[test]
+    fn test_valid_tree() -> Result<()> {
+        let conn = new_mem_connection();
+        insert_json_tree(
+            &conn,
+            json!({
+                "guid": String::from(BookmarkRootGuid::Unfiled.as_str()),
+                "children": [
+                    {
+                        "guid": "folderAAAAAA",
+                        "title": "A",
+                        "children": [
+                            {
+                                "guid": "bookmarkBBBB",
+                                "title": "B",
+                                "url": "https://example.com/b",
+                            },
+                        ],
+                    },
+                ],
+            }),
+        );
+        assert_eq!(check_and_repair(&conn)?, BookmarksRepairMetrics::default());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fix_positions() -> Result<()> {
+        let conn = new_mem_connection();
+        insert_json_tree(
+            &conn,
+            json!({
+                "guid": String::from(BookmarkRootGuid::Unfiled.as_str()),
+                "children": [
+                    {
+                        "guid": "bookmarkAAAA",
+                        "title": "A",
+                        "url": "https://example.com/a",
+                    },
+                    {
+                        "guid": "bookmarkBBBB",
+                        "title": "B",
+                        "url": "https://example.com/b",
+                    },
+                    {
+                        "guid": "bookmarkCCCC",
+                        "title": "C",
+                        "url": "https://example.com/c",
+                    },
+                ],
+            }),
+        );
+        conn.execute(
+            "UPDATE moz_bookmarks SET position = position * 2 + 1
+             WHERE guid IN ('bookmarkAAAA', 'bookmarkBBBB', 'bookmarkCCCC')",
+            [],
+        )?;
+        let unfiled_counter = get_change_counter(&conn, BookmarkRootGuid::Unfiled.as_str());
+
+        let metrics = check_and_repair(&conn)?;
+        assert_eq!(
+            metrics,
+            BookmarksRepairMetrics {
+                num_folders_renumbered: 1,
+                ..BookmarksRepairMetrics::default()
+            }
+        );
+        for (guid, position) in [
+            ("bookmarkAAAA", 0),
+            ("bookmarkBBBB", 1),
+            ("bookmarkCCCC", 2),
+        ] {
+            let bm = get_raw_bookmark(&conn, &guid.into())?.expect("should exist");
+            assert_eq!(bm.position, position);
+        }
+        assert!(get_change_counter(&conn, BookmarkRootGuid::Unfiled.as_str()) > unfiled_counter);
+        Ok(())
+    }
+
+    #[test]
+    fn test_fix_misplaced_items() -> Result<()> {
+        let conn = new_mem_connection();
+        insert_json_tree(
+            &conn,
+            json!({
+                "guid": String::from(BookmarkRootGuid::Menu.as_str()),
+                "children": [
+                    {
+                        "guid": "bookmarkAAAA",
+                        "title": "A",
+                        "url": "https://example.com/a",
+                    },
+                    {
+                        "guid": "bookmarkBBBB",
+                        "title": "B",
+                        "url": "https://example.com/b",
+                    },
+                    {
+                        "guid": "folderCCCCCC",
+                        "title": "C",
+                        "children": [
+                            {
+                                "guid": "folderDDDDDD",
+                                "title": "D",
+                                "children": [
+                                    {
+                                        "guid": "bookmarkEEEE",
+                                        "title": "E",
+                                        "url": "https://example.com/e",
+                                    },
+                                ],
+                            },
+                        ],
+                    },
+                    {
+                        "guid": "bookmarkFFFF",
+                        "title": "F",
+                        "url": "https://example.com/f",
+                    },
+                    {
+                        "guid": "bookmarkGGGG",
+                        "title": "G",
+                        "url": "https://example.com/g",
+                    },
+                ],
+            }),
+        );
+        // A is in the root, B is in a bookmark, and C and D are in a cycle.
+        set_parent(&conn, "bookmarkAAAA", BookmarkRootGuid::Root.as_str(), 4);
+        set_parent(&conn, "bookmarkBBBB", "bookmarkFFFF", 0);
+        set_parent(&conn, "folderCCCCCC", "folderDDDDDD", 1);
+        // F's parent doesn't exist.
+        conn.execute_batch("PRAGMA foreign_keys = OFF")?;
+        conn.execute(
+            "UPDATE moz_bookmarks SET parent = 9999 WHERE guid = 'bookmarkFFFF'",
+            [],
+        )?;
+        conn.execute_batch("PRAGMA foreign_keys = ON")?;
+        let item_counter = get_change_counter(&conn, "bookmarkBBBB");
+
+        let metrics = check_and_repair(&conn)?;
+        assert_eq!(
+            metrics,
+            BookmarksRepairMetrics {
+                num_invalid_root_children: 1,
+                num_orphans: 1,
+                num_non_folder_children: 1,
+                num_cycles: 1,
+                // The menu, which the other items were moved out of.
+                num_folders_renumbered: 1,
+            }
+        );
+        assert_json_tree(
+            &conn,
+            &BookmarkRootGuid::Unfiled.into(),
+            json!({
+                "guid": String::from(BookmarkRootGuid::Unfiled.as_str()),
+                "children": [
+                    {
+                        "guid": "bookmarkAAAA",
+                        "title": "A",
+                        "url": "https://example.com/a",
+                    },
+                    {
+                        "guid": "bookmarkFFFF",
+                        "title": "F",
+                        "url": "https://example.com/f",
+                    },
+                    {
+                        "guid": "bookmarkBBBB",
+                        "title": "B",
+                        "url": "https://example.com/b",
+                    },
+                    {
+                        "guid": "folderCCCCCC",
+                        "title": "C",
+                        "children": [
+                            {
+                                "guid": "folderDDDDDD",
+                                "title": "D",
+                                "children": [
+                                    {
+                                        "guid": "bookmarkEEEE",
+                                        "title": "E",
+                                        "url": "https://example.com/e",
+                                    },
+                                ],
+                            },
+                        ],
+                    },
+                ],
+            }),
+        );
+        assert!(get_change_counter(&conn, "bookmarkBBBB") > item_counter);
+
+        // Running it again shouldn't find anything.
+        assert_eq!(check_and_repair(&conn)?, BookmarksRepairMetrics::default());
+        assert_json_tree(
+            &conn,
+            &BookmarkRootGuid::Menu.into(),
+            json!({
+                "guid": String::from(BookmarkRootGuid::Menu.as_str()),
+                "children": [
+                    {
+                        "guid": "bookmarkGGGG",
+                        "title": "G",
+                        "url": "https://example.com/g",
+                    },
+                ],
+            }),
+        );
+        Ok(())
+    }
+}
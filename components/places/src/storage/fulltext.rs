@@ -0,0 +1,381 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Full-text search over history.
+//!
+//! `moz_places_fts` is an FTS5 index over the title and URL of every page,
+//! which the `moz_places` triggers keep up to date, and any page text the
+//! application gives us with `note_page_content()`. Page text is history, so
+//! it's removed along with the page's visits, even when the page itself is
+//! kept because it's bookmarked.
+
+use crate::db::PlacesDb;
+use crate::error::{InvalidPlaceInfo, Result};
+use crate::storage::RowId;
+use crate::util::slice_up_to;
+use rusqlite::Row;
+use sql_support::ConnExt;
+use types::Timestamp;
+use url::Url;
+
+/// The most page text we'll index for a page, in bytes. Anything after this is dropped.
+pub const PAGE_CONTENT_LENGTH_MAX: usize = 100_000;
+
+/// How many tokens a snippet includes.
+const SNIPPET_TOKENS: u32 = 24;
+
+/// Only pages visited in this range are returned. Both ends are inclusive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HistoryTimeRange {
+    pub start: Timestamp,
+    pub end: Timestamp,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FulltextSearchResult {
+    pub url: Url,
+    pub title: Option<String>,
+    /// An excerpt of the title, URL or page text which best matched the query.
+    pub snippet: String,
+    pub last_visit: Timestamp,
+}
+
+impl FulltextSearchResult {
+    fn from_row(row: &Row<'_>) -> Result<Self> {
+        Ok(Self {
+            url: Url::parse(&row.get::<_, String>("url")?)?,
+            title: row.get("title")?,
+            snippet: row.get("snippet")?,
+            last_visit: row.get("last_visit")?,
+        })
+    }
+}
+
+/// Sets the text of a page, replacing any text we already have for it. The page must
+/// already be in history.
+pub fn note_page_content(db: &PlacesDb, url: &Url, text: &str) -> Result<()> {
+    let tx = db.begin_transaction()?;
+    let page_id = match db.try_query_one::<RowId, _>(
+        "SELECT id FROM moz_places WHERE url_hash = hash(:url) AND url = :url",
+        &[(":url", &url.as_str())],
+        true,
+    )? {
+        Some(page_id) => page_id,
+        None => return Err(InvalidPlaceInfo::NoSuchUrl.into()),
+    };
+    db.execute_cached(
+        "INSERT OR REPLACE INTO moz_places_fts(rowid, title, url, content)
+         SELECT id, IFNULL(title, ''), url, :content FROM moz_places WHERE id = :page_id",
+        rusqlite::named_params! {
+            ":content": slice_up_to(text.trim(), PAGE_CONTENT_LENGTH_MAX),
+            ":page_id": page_id,
+        },
+    )?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Removes the text of a page from the index, but keeps its title and URL. Used when we
+/// delete the visits to a page we can't remove.
+pub(crate) fn delete_page_content(db: &PlacesDb, page_id: RowId) -> Result<()> {
+    db.execute_cached(
+        "UPDATE moz_places_fts SET content = '' WHERE rowid = :page_id",
+        &[(":page_id", &page_id)],
+    )?;
+    Ok(())
+}
+
+/// Removes the text of every page from the index. Assumes the caller has set up a
+/// transaction.
+pub(crate) fn delete_all_page_content(db: &PlacesDb) -> Result<()> {
+    db.execute_cached(
+        "UPDATE moz_places_fts SET content = '' WHERE content != ''",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Turns a search string into an FTS5 query which matches pages with every word in the
+/// string, as a prefix. The words are quoted, so FTS5 syntax in the search string is
+/// treated as text. Returns `None` if there aren't any words to search for.
+fn to_fts_query(search: &str) -> Option<String> {
+    let terms = search
+        .split_whitespace()
+        .filter(|word| word.chars().any(char::is_alphanumeric))
+        .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+        .collect::<Vec<_>>();
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" "))
+    }
+}
+
+/// Searches the titles, URLs and text of visited pages, best matches first.
+///
+/// Matches in titles rank above matches in URLs, which rank above matches in page text.
+/// Pages which only have remote visits are included.
+pub fn search_history_fulltext(
+    db: &PlacesDb,
+    query: &str,
+    limit: i32,
+    time_range: Option<HistoryTimeRange>,
+) -> Result<Vec<FulltextSearchResult>> {
+    let fts_query = match to_fts_query(query) {
+        Some(fts_query) => fts_query,
+        None => return Ok(Vec::new()),
+    };
+    let HistoryTimeRange { start, end } = time_range.unwrap_or(HistoryTimeRange {
+        start: Timestamp(0),
+        end: Timestamp(i64::MAX as u64),
+    });
+    db.query_rows_and_then_cached(
+        &format!(
+            "SELECT h.url, h.title,
+                    MAX(h.last_visit_date_local, h.last_visit_date_remote) AS last_visit,
+                    snippet(moz_places_fts, -1, '', '', '…', {snippet_tokens}) AS snippet
+             FROM moz_places_fts
+             JOIN moz_places h ON h.id = moz_places_fts.rowid
+             WHERE moz_places_fts MATCH :query
+               AND EXISTS(SELECT 1 FROM moz_historyvisits v
+                          WHERE v.place_id = h.id
+                            AND v.visit_date BETWEEN :start AND :end)
+             ORDER BY bm25(moz_places_fts, 10.0, 5.0, 1.0), h.frecency DESC
+             LIMIT :limit",
+            snippet_tokens = SNIPPET_TOKENS,
+        ),
+        rusqlite::named_params! {
+            ":query": fts_query,
+            ":start": start,
+            ":end": end,
+            ":limit": limit,
+        },
+        FulltextSearchResult::from_row,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::places_api::test::new_mem_connection;
+    use crate::observation::VisitObservation;
+    use crate::storage::bookmarks::{
+        insert_bookmark, BookmarkPosition, BookmarkRootGuid, InsertableBookmark, InsertableItem,
+    };
+    use crate::storage::history::{
+        apply_observation, delete_visits_between, delete_visits_for, url_to_guid, wipe_local,
+    };
+    use crate::types::VisitTransition;
+
+    fn visit(db: &PlacesDb, url: &str, title: &str, at: Timestamp) -> Url {
+        let url = Url::parse(url).unwrap();
+        apply_observation(
+            db,
+            VisitObservation::new(url.clone())
+                .with_title(title.to_string())
+                .with_visit_type(VisitTransition::Link)
+                .with_at(at),
+        )
+        .expect("Should apply visit");
+        url
+    }
+
+    fn search_urls(db: &PlacesDb, query: &str) -> Vec<String> {
+        search_history_fulltext(db, query, 10, None)
+            .expect("Should search")
+            .into_iter()
+            .map(|result| result.url.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_to_fts_query() {
+        assert_eq!(to_fts_query("  "), None);
+        assert_eq!(to_fts_query("- ( \"\""), None);
+        assert_eq!(
+            to_fts_query("foo bar"),
+            Some("\"foo\"* \"bar\"*".to_string())
+        );
+        assert_eq!(
+            to_fts_query("NOT a\"b OR"),
+            Some("\"NOT\"* \"a\"\"b\"* \"OR\"*".to_string())
+        );
+    }
+
+    #[test]
+    fn test_search_titles_and_urls() {
+        let conn = new_mem_connection();
+        let now = Timestamp::now();
+        visit(&conn, "https://example.com/recipes", "Soup recipes", now);
+        visit(&conn, "https://soup.example.org/", "Kitchen", now);
+        visit(&conn, "https://example.net/", "Something else", now);
+
+        // Title matches rank above URL matches.
+        assert_eq!(
+            search_urls(&conn, "soup"),
+            vec!["https://example.com/recipes", "https://soup.example.org/"]
+        );
+        // Words are prefixes, and all of them must match.
+        assert_eq!(
+            search_urls(&conn, "kitch soup"),
+            vec!["https://soup.example.org/"]
+        );
+        assert_eq!(search_urls(&conn, "soup else"), Vec::<String>::new());
+        // FTS5 syntax is searched for as text, rather than failing.
+        assert_eq!(search_urls(&conn, "soup NOT"), Vec::<String>::new());
+        assert_eq!(search_urls(&conn, "\"soup*"), search_urls(&conn, "soup"));
+
+        // Changing the title updates the index.
+        conn.execute(
+            "UPDATE moz_places SET title = 'Stew' WHERE url = 'https://example.com/recipes'",
+            [],
+        )
+        .unwrap();
+        assert_eq!(
+            search_urls(&conn, "stew"),
+            vec!["https://example.com/recipes"]
+        );
+        assert_eq!(
+            search_urls(&conn, "soup"),
+            vec!["https://soup.example.org/"]
+        );
+    }
+
+    #[test]
+    fn test_page_content() {
+        let conn = new_mem_connection();
+        let now = Timestamp::now();
+        let url = visit(&conn, "https://example.com/article", "An article", now);
+        visit(&conn, "https://example.com/aubergine", "Aubergines", now);
+
+        note_page_content(
+            &conn,
+            &url,
+            "A long article which, somewhere in the middle, mentions aubergines.",
+        )
+        .expect("Should note content");
+        let results = search_history_fulltext(&conn, "aubergine", 10, None).unwrap();
+        assert_eq!(
+            results
+                .iter()
+                .map(|result| result.url.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "https://example.com/aubergine",
+                "https://example.com/article"
+            ]
+        );
+        assert_eq!(results[1].title.as_deref(), Some("An article"));
+        assert!(results[1].snippet.contains("mentions aubergines"));
+        assert_eq!(results[1].last_visit, now);
+
+        // New text replaces the old text.
+        note_page_content(&conn, &url, "Nothing about vegetables").unwrap();
+        assert_eq!(
+            search_urls(&conn, "aubergine"),
+            vec!["https://example.com/aubergine"]
+        );
+        assert_eq!(
+            search_urls(&conn, "vegetables"),
+            vec!["https://example.com/article"]
+        );
+
+        // We only index text for pages in history.
+        match note_page_content(&conn, &Url::parse("https://example.org/").unwrap(), "text")
+            .expect_err("Should not note content for an unknown page")
+        {
+            crate::Error::InvalidPlaceInfo(InvalidPlaceInfo::NoSuchUrl) => {}
+            e => panic!("Unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_time_range() {
+        let conn = new_mem_connection();
+        let now = Timestamp::now();
+        let earlier = Timestamp(now.0 - 10_000);
+        visit(&conn, "https://example.com/old", "Old page", earlier);
+        visit(&conn, "https://example.com/new", "New page", now);
+
+        let search = |start, end| -> Vec<String> {
+            search_history_fulltext(&conn, "page", 10, Some(HistoryTimeRange { start, end }))
+                .unwrap()
+                .into_iter()
+                .map(|result| result.url.to_string())
+                .collect()
+        };
+        assert_eq!(
+            search(Timestamp(earlier.0 - 1), Timestamp(earlier.0 + 1)),
+            vec!["https://example.com/old"]
+        );
+        assert_eq!(
+            search(Timestamp(now.0 - 1), now),
+            vec!["https://example.com/new"]
+        );
+        assert_eq!(search(earlier, now).len(), 2);
+        assert_eq!(search_urls(&conn, "page").len(), 2);
+    }
+
+    #[test]
+    fn test_index_pruned_with_history() {
+        let conn = new_mem_connection();
+        let now = Timestamp::now();
+        let page = visit(&conn, "https://example.com/page", "Page", now);
+        let bookmarked = visit(&conn, "https://example.com/bookmarked", "Bookmarked", now);
+        insert_bookmark(
+            &conn,
+            InsertableItem::Bookmark {
+                b: InsertableBookmark {
+                    parent_guid: BookmarkRootGuid::Unfiled.into(),
+                    position: BookmarkPosition::Append,
+                    date_added: None,
+                    last_modified: None,
+                    guid: None,
+                    url: bookmarked.clone(),
+                    title: None,
+                },
+            },
+        )
+        .expect("Should insert bookmark");
+        note_page_content(&conn, &page, "cabbage").unwrap();
+        note_page_content(&conn, &bookmarked, "cabbage").unwrap();
+
+        let fts_count = |query: &str| -> u32 {
+            conn.query_row(
+                "SELECT COUNT(*) FROM moz_places_fts WHERE moz_places_fts MATCH ?",
+                [query],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        assert_eq!(fts_count("cabbage"), 2);
+
+        // Deleting the visits removes the page, and the page text of the bookmarked page.
+        for url in [&page, &bookmarked] {
+            let guid = url_to_guid(&conn, url).unwrap().unwrap();
+            delete_visits_for(&conn, &guid).unwrap();
+        }
+        assert_eq!(fts_count("cabbage"), 0);
+        assert_eq!(fts_count("page"), 0);
+        assert_eq!(fts_count("bookmarked"), 1);
+        // The bookmarked page is no longer in history.
+        assert_eq!(search_urls(&conn, "bookmarked"), Vec::<String>::new());
+
+        // Deleting visits by time does the same.
+        visit(&conn, "https://example.com/bookmarked", "Bookmarked", now);
+        note_page_content(&conn, &bookmarked, "cabbage").unwrap();
+        delete_visits_between(&conn, Timestamp(now.0 - 1), now).unwrap();
+        assert_eq!(fts_count("cabbage"), 0);
+        assert_eq!(fts_count("bookmarked"), 1);
+
+        // And so does wiping history.
+        visit(&conn, "https://example.com/page", "Page", now);
+        visit(&conn, "https://example.com/bookmarked", "Bookmarked", now);
+        note_page_content(&conn, &bookmarked, "cabbage").unwrap();
+        wipe_local(&conn).unwrap();
+        assert_eq!(fts_count("cabbage"), 0);
+        assert_eq!(fts_count("page"), 0);
+        assert_eq!(fts_count("bookmarked"), 1);
+    }
+}
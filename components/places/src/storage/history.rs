@@ -3,6 +3,7 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 mod actions;
+pub mod stats;
 
 use super::{fetch_page_info, new_page_info, PageInfo, RowId};
 use crate::db::PlacesDb;
@@ -15,7 +16,7 @@ use crate::history_sync::engine::{
 };
 use crate::observation::VisitObservation;
 use crate::storage::{
-    delete_meta, delete_pending_temp_tables, get_meta, history_metadata, put_meta,
+    delete_meta, delete_pending_temp_tables, fulltext, get_meta, history_metadata, icons, put_meta,
 };
 use crate::types::{
     serialize_unknown_fields, SyncStatus, UnknownFields, VisitTransition, VisitTransitionSet,
@@ -158,7 +159,7 @@ pub fn apply_observation_direct(
 pub fn update_frecency(db: &PlacesDb, id: RowId, redirect_boost: Option<bool>) -> Result<()> {
     let score = frecency::calculate_frecency(
         db.conn(),
-        &frecency::DEFAULT_FRECENCY_SETTINGS,
+        &db.frecency_settings(),
         id.0, // TODO: calculate_frecency should take a RowId here.
         redirect_boost,
     )?;
@@ -285,6 +286,7 @@ fn delete_visits_for_in_tx(db: &PlacesDb, guid: &SyncGuid) -> Result<()> {
             insert_tombstones_for_all_page_visits(db, id)?;
             delete_all_visits_for_page(db, id)?;
             history_metadata::delete_all_metadata_for_page(db, id)?;
+            fulltext::delete_page_content(db, id)?;
         }
         Some(PageToClean {
             id,
@@ -307,8 +309,9 @@ fn delete_visits_for_in_tx(db: &PlacesDb, guid: &SyncGuid) -> Result<()> {
             // we still can't delete it; we must delete its visits. But we
             // don't need to write any tombstones for those deleted visits.
             delete_all_visits_for_page(db, id)?;
-            // and we need to delete all history metadata.
+            // and we need to delete all history metadata and page text.
             history_metadata::delete_all_metadata_for_page(db, id)?;
+            fulltext::delete_page_content(db, id)?;
         }
         Some(PageToClean {
             id,
@@ -386,6 +389,130 @@ pub fn delete_visits_between(db: &PlacesDb, start: Timestamp, end: Timestamp) ->
     Ok(())
 }
 
+/// What `delete_history_for_domain` removed.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DeleteHistoryForDomainResult {
+    /// Bookmarked pages lose their history, but aren't removed, so they
+    /// aren't counted here.
+    pub num_pages_removed: u32,
+    pub num_visits_removed: u32,
+}
+
+/// Forgets a site: deletes the visits, metadata, input history and keywords
+/// for every page on `domain`, and on its subdomains if `include_subdomains`
+/// is true. `domain` can be a host, like `example.com`, or a URL. Pages are
+/// matched regardless of their scheme and port.
+///
+/// Like `delete_visits_for`, this writes tombstones for synced pages and
+/// visits, and keeps bookmarked pages without their history.
+pub fn delete_history_for_domain(
+    db: &PlacesDb,
+    domain: &str,
+    include_subdomains: bool,
+) -> Result<DeleteHistoryForDomainResult> {
+    // Parsing the domain as part of a URL gives us the same lowercase,
+    // Punycoded host that's stored in `moz_origins`.
+    let domain = domain.trim();
+    let url = match Url::parse(domain) {
+        Ok(url) if url.has_host() => url,
+        _ => Url::parse(&format!("http://{}/", domain.trim_end_matches('.')))?,
+    };
+    let host = match url.host_str() {
+        Some(host) if !host.is_empty() => host.to_owned(),
+        _ => return Ok(DeleteHistoryForDomainResult::default()),
+    };
+    let subdomain_suffix = format!(".{}", host);
+
+    let tx = db.begin_transaction()?;
+
+    // `moz_origins` is much smaller than `moz_places`, so we can match the
+    // hosts here instead of in SQL.
+    let origin_ids = db
+        .query_rows_and_then("SELECT id, host FROM moz_origins", [], |row| -> Result<_> {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+        })?
+        .into_iter()
+        .filter(|(_, host_and_port)| {
+            let origin_host = strip_port(host_and_port);
+            origin_host == host
+                || (include_subdomains && origin_host.ends_with(subdomain_suffix.as_str()))
+        })
+        .map(|(id, _)| id)
+        .collect::<Vec<_>>();
+
+    let mut pages = Vec::new();
+    sql_support::each_chunk(&origin_ids, |chunk, _| -> Result<()> {
+        pages.extend(db.query_rows_and_then(
+            &format!(
+                "SELECT id, guid FROM moz_places WHERE origin_id IN ({})",
+                sql_support::repeat_sql_vars(chunk.len()),
+            ),
+            rusqlite::params_from_iter(chunk),
+            |row| -> Result<_> { Ok((row.get::<_, RowId>(0)?, row.get::<_, SyncGuid>(1)?)) },
+        )?);
+        Ok(())
+    })?;
+
+    let mut result = DeleteHistoryForDomainResult::default();
+    for (page_id, guid) in &pages {
+        result.num_visits_removed += db.query_row_and_then_cachable(
+            "SELECT COUNT(*) FROM moz_historyvisits WHERE place_id = :page_id",
+            &[(":page_id", page_id)],
+            |row| row.get::<_, u32>(0),
+            true,
+        )?;
+        db.execute_cached(
+            "DELETE FROM moz_inputhistory WHERE place_id = :page_id",
+            &[(":page_id", page_id)],
+        )?;
+        // Metadata for other pages that were reached from this one would
+        // still reveal the site.
+        db.execute_cached(
+            "DELETE FROM moz_places_metadata WHERE referrer_place_id = :page_id",
+            &[(":page_id", page_id)],
+        )?;
+        let removed_keyword = db.execute_cached(
+            "DELETE FROM moz_keywords WHERE place_id = :page_id",
+            &[(":page_id", page_id)],
+        )? > 0;
+        if removed_keyword {
+            // Keywords are synced as part of bookmarks, so upload the
+            // bookmarks for this page again, without the keyword.
+            db.execute_cached(
+                "UPDATE moz_bookmarks SET
+                     syncChangeCounter = syncChangeCounter + 1
+                 WHERE fk = :page_id",
+                &[(":page_id", page_id)],
+            )?;
+        }
+        delete_visits_for_in_tx(db, guid)?;
+    }
+
+    for (page_id, _) in &pages {
+        if db.exists(
+            "SELECT 1 FROM moz_places WHERE id = :page_id",
+            &[(":page_id", page_id)],
+        )? {
+            // Bookmarked pages lose their visits, so need a new frecency.
+            update_frecency(db, *page_id, None)?;
+        } else {
+            result.num_pages_removed += 1;
+        }
+    }
+    delete_pending_temp_tables(db)?;
+    tx.commit()?;
+    Ok(result)
+}
+
+/// Returns the host from a `moz_origins` host, which can include a port.
+fn strip_port(host_and_port: &str) -> &str {
+    match host_and_port.rsplit_once(':') {
+        // IPv6 hosts are in brackets, and contain colons.
+        Some((host, port)) if !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()) => host,
+        _ => host_and_port,
+    }
+}
+
 pub fn delete_place_visit_at_time(db: &PlacesDb, place: &Url, visit: Timestamp) -> Result<()> {
     delete_place_visit_at_time_by_href(db, place.as_str(), visit)
 }
@@ -402,6 +529,8 @@ pub fn delete_place_visit_at_time_by_href(
 }
 
 pub fn prune_destructively(db: &PlacesDb) -> Result<()> {
+    // `wipe_local` keeps the icons of the pages it keeps, but we don't need the stale ones.
+    icons::delete_expired_icons(db, Timestamp::now())?;
     // For now, just fall back to wipe_local until we decide how this should work.
     wipe_local(db)
 }
@@ -504,7 +633,6 @@ pub fn wipe_local(db: &PlacesDb) -> Result<()> {
 }
 
 fn wipe_local_in_tx(db: &PlacesDb) -> Result<()> {
-    use crate::frecency::DEFAULT_FRECENCY_SETTINGS;
     db.execute_all(&[
         "DELETE FROM moz_places WHERE foreign_count == 0",
         "DELETE FROM moz_places_metadata",
@@ -525,7 +653,7 @@ fn wipe_local_in_tx(db: &PlacesDb) -> Result<()> {
                                  ELSE {unvisited_bookmark_frec}
                             END),
                 sync_change_counter = 0"#,
-            unvisited_bookmark_frec = DEFAULT_FRECENCY_SETTINGS.unvisited_bookmark_bonus
+            unvisited_bookmark_frec = db.frecency_settings().unvisited_bookmark_bonus
         ),
     ])?;
 
@@ -537,6 +665,10 @@ fn wipe_local_in_tx(db: &PlacesDb) -> Result<()> {
         update_frecency(db, row_id, None)?;
     }
     delete_pending_temp_tables(db)?;
+    icons::delete_orphaned_icons(db)?;
+    // Deleted pages are removed from the full-text index by triggers, but the
+    // pages we keep still have their page text.
+    fulltext::delete_all_page_content(db)?;
     Ok(())
 }
 
@@ -705,6 +837,12 @@ fn cleanup_pages(db: &PlacesDb, pages: &[PageToClean]) -> Result<()> {
         update_frecency(db, id, None)?;
     }
 
+    // Pages we keep for their bookmarks, but which no longer have any visits,
+    // shouldn't keep their page text either.
+    for page in pages.iter().filter(|p| p.has_foreign && !p.has_visits) {
+        fulltext::delete_page_content(db, page.id)?;
+    }
+
     // Like desktop, we do "AND foreign_count = 0 AND last_visit_date ISNULL"
     // to creating orphans in case of async race conditions - in Desktop's
     // case, it reads the pages before starting a write transaction, so that
@@ -1338,6 +1476,29 @@ pub fn get_visited_urls(
     )?)
 }
 
+lazy_static::lazy_static! {
+    static ref TOP_FRECENT_SITES_SQL: String = format!(
+        "{sites_with_icons}
+        ORDER BY r.frecency DESC",
+        sites_with_icons = icons::with_page_icons(
+            "SELECT h.id, h.frecency, h.title, h.url
+            FROM moz_places h
+            WHERE EXISTS (
+                SELECT v.visit_type
+                FROM moz_historyvisits v
+                WHERE h.id = v.place_id
+                  AND (SUBSTR(h.url, 1, 6) == 'https:' OR SUBSTR(h.url, 1, 5) == 'http:')
+                  AND (h.last_visit_date_local + h.last_visit_date_remote) != 0
+                  AND ((1 << v.visit_type) & :allowed_types) != 0
+                  AND h.frecency >= :frecency_threshold AND
+                  NOT h.hidden
+            )
+            ORDER BY h.frecency DESC
+            LIMIT :limit"
+        )
+    );
+}
+
 pub fn get_top_frecent_site_infos(
     db: &PlacesDb,
     num_items: i32,
@@ -1355,20 +1516,7 @@ pub fn get_top_frecent_site_infos(
     .complement();
 
     let infos = db.query_rows_and_then_cached(
-        "SELECT h.frecency, h.title, h.url
-        FROM moz_places h
-        WHERE EXISTS (
-            SELECT v.visit_type
-            FROM moz_historyvisits v
-            WHERE h.id = v.place_id
-              AND (SUBSTR(h.url, 1, 6) == 'https:' OR SUBSTR(h.url, 1, 5) == 'http:')
-              AND (h.last_visit_date_local + h.last_visit_date_remote) != 0
-              AND ((1 << v.visit_type) & :allowed_types) != 0
-              AND h.frecency >= :frecency_threshold AND
-              NOT h.hidden
-        )
-        ORDER BY h.frecency DESC
-        LIMIT :limit",
+        TOP_FRECENT_SITES_SQL.as_str(),
         rusqlite::named_params! {
             ":limit": num_items,
             ":allowed_types": allowed_types,
@@ -2172,7 +2320,7 @@ mod tests {
             }
 
             if let Some(keyword) = page.keyword {
-                // We don't have a public API for inserting keywords, so just
+                // `set_keyword` only works for bookmarked pages, so just
                 // write to the database directly.
                 db.execute_cached(
                     "INSERT INTO moz_keywords(place_id, keyword)
@@ -2435,6 +2583,123 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_delete_history_for_domain() -> Result<()> {
+        use crate::storage::bookmarks::{
+            self, BookmarkPosition, BookmarkRootGuid, InsertableBookmark,
+        };
+
+        let _ = env_logger::try_init();
+        let mut db = PlacesDb::open_in_memory(ConnectionType::ReadWrite)?;
+        let now = Timestamp::now();
+        for href in &[
+            "https://example.com/",
+            "http://example.com:8080/a",
+            "https://www.example.com/b",
+            "https://sub.www.example.com/c",
+            "https://notexample.com/",
+            "https://example.org/",
+        ] {
+            get_custom_observed_page(&mut db, href, |o| o.with_at(now))?;
+        }
+        get_custom_observed_page(&mut db, "https://example.com/", |o| {
+            o.with_at(Timestamp(now.0 - 1000))
+        })?;
+
+        let set_synced = |href: &str| -> Result<()> {
+            db.execute_cached(
+                &format!(
+                    "UPDATE moz_places
+                         SET sync_status = {}
+                     WHERE url_hash = hash(:url) AND
+                           url = :url",
+                    (SyncStatus::Normal as u8)
+                ),
+                &[(":url", &href)],
+            )?;
+            Ok(())
+        };
+        set_synced("https://example.com/")?;
+        set_synced("https://www.example.com/b")?;
+
+        let bookmarked = Url::parse("https://www.example.com/b")?;
+        bookmarks::insert_bookmark(
+            &db,
+            InsertableBookmark {
+                parent_guid: BookmarkRootGuid::Unfiled.into(),
+                position: BookmarkPosition::Append,
+                date_added: None,
+                last_modified: None,
+                guid: None,
+                url: bookmarked.clone(),
+                title: Some("B".to_owned()),
+            }
+            .into(),
+        )?;
+        db.execute_cached(
+            "INSERT INTO moz_keywords(place_id, keyword)
+             SELECT id, 'b' FROM moz_places
+             WHERE url_hash = hash(:url) AND url = :url",
+            &[(":url", &bookmarked.as_str())],
+        )?;
+
+        let example_guid =
+            url_to_guid(&db, &Url::parse("https://example.com/")?)?.expect("should have the page");
+        assert_eq!(
+            delete_history_for_domain(&db, " EXAMPLE.com ", false)?,
+            DeleteHistoryForDomainResult {
+                num_pages_removed: 2,
+                num_visits_removed: 3,
+            }
+        );
+        assert!(url_to_guid(&db, &Url::parse("https://example.com/")?)?.is_none());
+        assert!(url_to_guid(&db, &Url::parse("http://example.com:8080/a")?)?.is_none());
+        assert!(url_to_guid(&db, &bookmarked)?.is_some());
+        let tombstones: Vec<SyncGuid> =
+            db.query_rows_and_then("SELECT guid FROM moz_places_tombstones", [], |row| {
+                row.get(0)
+            })?;
+        assert_eq!(tombstones, vec![example_guid]);
+
+        // Subdomains are matched, but not other domains that end the same
+        // way.
+        assert_eq!(
+            delete_history_for_domain(&db, "https://example.com/ignored", true)?,
+            DeleteHistoryForDomainResult {
+                num_pages_removed: 1,
+                num_visits_removed: 2,
+            }
+        );
+        assert!(url_to_guid(&db, &Url::parse("https://sub.www.example.com/c")?)?.is_none());
+
+        // The bookmarked page is kept, without its visits or keyword, and
+        // the bookmark will be uploaded again.
+        let (info, visits) = fetch_visits(&db, &bookmarked, 0)?.expect("should keep the page");
+        assert!(visits.is_empty());
+        assert_tombstones(&db, &[(info.row_id, now)]);
+        assert!(bookmarks::bookmarks_get_url_for_keyword(&db, "b")?.is_none());
+        let change_counter: i64 = db.query_row_and_then_cachable(
+            "SELECT syncChangeCounter FROM moz_bookmarks WHERE fk = :page_id",
+            &[(":page_id", &info.row_id)],
+            |row| row.get(0),
+            false,
+        )?;
+        assert_eq!(change_counter, 2);
+
+        let remaining: Vec<String> = db.query_rows_and_then(
+            "SELECT h.url FROM moz_historyvisits v
+             JOIN moz_places h ON h.id = v.place_id
+             ORDER BY h.url",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(
+            remaining,
+            vec!["https://example.org/", "https://notexample.com/"]
+        );
+        Ok(())
+    }
+
     fn assert_tombstones(c: &PlacesDb, expected: &[(RowId, Timestamp)]) {
         let mut expected: Vec<(RowId, Timestamp)> = expected.into();
         expected.sort();
@@ -0,0 +1,482 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Aggregate statistics about history visits, for summaries like "your
+//! browsing week". These are computed in SQL, so that apps don't need to
+//! fetch every visit.
+//!
+//! Every query counts the visits between `start` and `end`, skipping visits
+//! with a type in `exclude_types`, and remote visits if `local_only` is set.
+
+use super::strip_port;
+use crate::db::PlacesDb;
+use crate::error::Result;
+use crate::types::{VisitTransition, VisitTransitionSet};
+use sql_support::ConnExt;
+use std::collections::HashMap;
+use types::Timestamp;
+
+const HOUR_MS: i64 = 60 * 60 * 1000;
+const DAY_MS: i64 = 24 * HOUR_MS;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VisitCountInterval {
+    Hour,
+    Day,
+}
+
+impl VisitCountInterval {
+    fn as_millis(self) -> i64 {
+        match self {
+            VisitCountInterval::Hour => HOUR_MS,
+            VisitCountInterval::Day => DAY_MS,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VisitCountBucket {
+    /// The start of the hour or day.
+    pub start: Timestamp,
+    pub visit_count: i64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TopDomainsOrder {
+    /// By the number of visits in the time range.
+    Visits,
+    /// By the total frecency of the domain's origins that were visited in the
+    /// time range. Frecency itself isn't limited to the time range.
+    Frecency,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TopDomain {
+    /// The host, without a leading "www." or a port. Visits to all schemes,
+    /// ports and to "www." are counted together.
+    pub host: String,
+    pub visit_count: i64,
+    /// The total frecency of the origins that were visited in the time range.
+    pub frecency: i64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VisitTransitionCount {
+    pub visit_type: VisitTransition,
+    pub visit_count: i64,
+}
+
+/// Returns the number of visits in each hour or day, oldest first. Hours and
+/// days without visits are left out.
+///
+/// Days start at midnight in the time zone `utc_offset_minutes` ahead of UTC.
+pub fn get_visit_counts_by_interval(
+    db: &PlacesDb,
+    start: Timestamp,
+    end: Timestamp,
+    interval: VisitCountInterval,
+    utc_offset_minutes: i32,
+    exclude_types: VisitTransitionSet,
+    local_only: bool,
+) -> Result<Vec<VisitCountBucket>> {
+    db.query_rows_and_then_cached(
+        "SELECT ((visit_date + :offset) / :interval) * :interval - :offset AS bucket,
+                COUNT(*) AS visit_count
+         FROM moz_historyvisits
+         WHERE visit_date BETWEEN :start AND :end AND
+               ((1 << visit_type) & :allowed_types) != 0 AND
+               (is_local OR NOT :local_only)
+         GROUP BY bucket
+         ORDER BY bucket",
+        rusqlite::named_params! {
+            ":offset": i64::from(utc_offset_minutes) * 60 * 1000,
+            ":interval": interval.as_millis(),
+            ":start": start,
+            ":end": end,
+            ":allowed_types": exclude_types.complement(),
+            ":local_only": local_only,
+        },
+        |row| -> Result<_> {
+            Ok(VisitCountBucket {
+                start: Timestamp(row.get::<_, i64>("bucket")?.max(0) as u64),
+                visit_count: row.get("visit_count")?,
+            })
+        },
+    )
+}
+
+/// Returns the `limit` domains with the most visits, or the highest frecency,
+/// among the domains visited in the time range.
+pub fn get_top_domains(
+    db: &PlacesDb,
+    start: Timestamp,
+    end: Timestamp,
+    order: TopDomainsOrder,
+    limit: u32,
+    exclude_types: VisitTransitionSet,
+    local_only: bool,
+) -> Result<Vec<TopDomain>> {
+    // Origins are per scheme and port, so we count the visits for each origin,
+    // look up the frecencies of just the visited origins, and add them up for
+    // each domain as we read them, since SQL can't strip the port.
+    let origins = db.query_rows_and_then_cached(
+        "WITH origin_visits(origin_id, visit_count) AS (
+             SELECT h.origin_id, COUNT(*)
+             FROM moz_historyvisits v
+             JOIN moz_places h ON h.id = v.place_id
+             WHERE v.visit_date BETWEEN :start AND :end AND
+                   ((1 << v.visit_type) & :allowed_types) != 0 AND
+                   (v.is_local OR NOT :local_only)
+             GROUP BY h.origin_id
+         )
+         SELECT o.host, ov.visit_count, MAX(o.frecency, 0) AS frecency
+         FROM origin_visits ov
+         JOIN moz_origins o ON o.id = ov.origin_id",
+        rusqlite::named_params! {
+            ":start": start,
+            ":end": end,
+            ":allowed_types": exclude_types.complement(),
+            ":local_only": local_only,
+        },
+        |row| -> Result<_> {
+            Ok((
+                row.get::<_, String>("host")?,
+                row.get::<_, i64>("visit_count")?,
+                row.get::<_, i64>("frecency")?,
+            ))
+        },
+    )?;
+    let mut domains: HashMap<String, TopDomain> = HashMap::new();
+    for (host_and_port, visit_count, frecency) in origins {
+        let host = strip_port(&host_and_port);
+        let host = host.strip_prefix("www.").unwrap_or(host);
+        let domain = domains.entry(host.to_owned()).or_insert_with(|| TopDomain {
+            host: host.to_owned(),
+            visit_count: 0,
+            frecency: 0,
+        });
+        domain.visit_count += visit_count;
+        domain.frecency += frecency;
+    }
+    let mut domains = domains.into_values().collect::<Vec<_>>();
+    domains.sort_by(|a, b| {
+        let (a_key, b_key) = match order {
+            TopDomainsOrder::Visits => ((a.visit_count, a.frecency), (b.visit_count, b.frecency)),
+            TopDomainsOrder::Frecency => ((a.frecency, a.visit_count), (b.frecency, b.visit_count)),
+        };
+        b_key.cmp(&a_key).then_with(|| a.host.cmp(&b.host))
+    });
+    domains.truncate(limit as usize);
+    Ok(domains)
+}
+
+/// Returns the number of visits of each type, most common first. Types
+/// without visits are left out.
+pub fn get_visit_transition_counts(
+    db: &PlacesDb,
+    start: Timestamp,
+    end: Timestamp,
+    exclude_types: VisitTransitionSet,
+    local_only: bool,
+) -> Result<Vec<VisitTransitionCount>> {
+    let counts = db.query_rows_and_then_cached(
+        "SELECT visit_type, COUNT(*) AS visit_count
+         FROM moz_historyvisits
+         WHERE visit_date BETWEEN :start AND :end AND
+               ((1 << visit_type) & :allowed_types) != 0 AND
+               (is_local OR NOT :local_only)
+         GROUP BY visit_type
+         ORDER BY visit_count DESC, visit_type",
+        rusqlite::named_params! {
+            ":start": start,
+            ":end": end,
+            ":allowed_types": exclude_types.complement(),
+            ":local_only": local_only,
+        },
+        |row| -> Result<_> { Ok((row.get::<_, u8>("visit_type")?, row.get("visit_count")?)) },
+    )?;
+    Ok(counts
+        .into_iter()
+        .filter_map(|(visit_type, visit_count)| {
+            Some(VisitTransitionCount {
+                visit_type: VisitTransition::from_primitive(visit_type)?,
+                visit_count,
+            })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::places_api::test::new_mem_connection;
+    use crate::observation::VisitObservation;
+    use crate::storage::history::apply_observation;
+    use pretty_assertions::assert_eq;
+    use url::Url;
+
+    fn visit(
+        conn: &PlacesDb,
+        url: &str,
+        at: i64,
+        visit_type: VisitTransition,
+        is_remote: bool,
+    ) -> Result<()> {
+        apply_observation(
+            conn,
+            VisitObservation::new(Url::parse(url)?)
+                .with_at(Timestamp(at as u64))
+                .with_visit_type(visit_type)
+                .with_is_remote(is_remote),
+        )?;
+        Ok(())
+    }
+
+    // 2023-06-05T00:00:00Z
+    const MONDAY: i64 = 1_685_923_200_000;
+
+    fn add_visits(conn: &PlacesDb) -> Result<()> {
+        visit(
+            conn,
+            "https://example.com/",
+            MONDAY + HOUR_MS,
+            VisitTransition::Typed,
+            false,
+        )?;
+        visit(
+            conn,
+            "http://www.example.com/a",
+            MONDAY + 2 * HOUR_MS,
+            VisitTransition::Link,
+            false,
+        )?;
+        visit(
+            conn,
+            "https://example.com/b",
+            MONDAY + 2 * HOUR_MS + 1,
+            VisitTransition::Link,
+            true,
+        )?;
+        visit(
+            conn,
+            "https://mozilla.org/",
+            MONDAY + DAY_MS + HOUR_MS,
+            VisitTransition::Link,
+            false,
+        )?;
+        visit(
+            conn,
+            "https://mozilla.org/",
+            MONDAY + DAY_MS + 2 * HOUR_MS,
+            VisitTransition::Reload,
+            false,
+        )?;
+        // Outside of the range we query.
+        visit(
+            conn,
+            "https://example.net/",
+            MONDAY + 7 * DAY_MS,
+            VisitTransition::Typed,
+            false,
+        )?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_visit_counts_by_interval() -> Result<()> {
+        let conn = new_mem_connection();
+        add_visits(&conn)?;
+        let start = Timestamp(MONDAY as u64);
+        let end = Timestamp((MONDAY + 7 * DAY_MS - 1) as u64);
+        let counts = |interval, utc_offset_minutes, exclude_types, local_only| {
+            get_visit_counts_by_interval(
+                &conn,
+                start,
+                end,
+                interval,
+                utc_offset_minutes,
+                exclude_types,
+                local_only,
+            )
+            .unwrap()
+            .into_iter()
+            .map(|bucket| (bucket.start.as_millis() as i64 - MONDAY, bucket.visit_count))
+            .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            counts(
+                VisitCountInterval::Day,
+                0,
+                VisitTransitionSet::empty(),
+                false
+            ),
+            vec![(0, 3), (DAY_MS, 2)]
+        );
+        assert_eq!(
+            counts(
+                VisitCountInterval::Hour,
+                0,
+                VisitTransitionSet::empty(),
+                false
+            ),
+            vec![
+                (HOUR_MS, 1),
+                (2 * HOUR_MS, 2),
+                (DAY_MS + HOUR_MS, 1),
+                (DAY_MS + 2 * HOUR_MS, 1)
+            ]
+        );
+        assert_eq!(
+            counts(
+                VisitCountInterval::Day,
+                0,
+                VisitTransitionSet::single(VisitTransition::Reload),
+                true
+            ),
+            vec![(0, 2), (DAY_MS, 1)]
+        );
+        // In UTC-01:30, the first visit of each day was late the day before.
+        let offset = 90 * 60 * 1000;
+        assert_eq!(
+            counts(
+                VisitCountInterval::Day,
+                -90,
+                VisitTransitionSet::empty(),
+                false
+            ),
+            vec![(-DAY_MS + offset, 1), (offset, 3), (DAY_MS + offset, 1)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_top_domains() -> Result<()> {
+        let conn = new_mem_connection();
+        add_visits(&conn)?;
+        let start = Timestamp(MONDAY as u64);
+        let end = Timestamp((MONDAY + 7 * DAY_MS - 1) as u64);
+
+        let domains = get_top_domains(
+            &conn,
+            start,
+            end,
+            TopDomainsOrder::Visits,
+            10,
+            VisitTransitionSet::empty(),
+            false,
+        )?;
+        assert_eq!(
+            domains
+                .iter()
+                .map(|domain| (domain.host.as_str(), domain.visit_count))
+                .collect::<Vec<_>>(),
+            vec![("example.com", 3), ("mozilla.org", 2)]
+        );
+
+        let domains = get_top_domains(
+            &conn,
+            start,
+            end,
+            TopDomainsOrder::Visits,
+            1,
+            VisitTransitionSet::single(VisitTransition::Reload),
+            true,
+        )?;
+        assert_eq!(domains.len(), 1);
+        assert_eq!(domains[0].host, "example.com");
+        assert_eq!(domains[0].visit_count, 2);
+
+        let by_frecency = get_top_domains(
+            &conn,
+            start,
+            end,
+            TopDomainsOrder::Frecency,
+            10,
+            VisitTransitionSet::empty(),
+            false,
+        )?;
+        assert_eq!(by_frecency.len(), 2);
+        assert!(by_frecency[0].frecency >= by_frecency[1].frecency);
+
+        // Origins with a port are counted with the host.
+        visit(
+            &conn,
+            "http://mozilla.org:8080/",
+            MONDAY + 2 * DAY_MS,
+            VisitTransition::Link,
+            false,
+        )?;
+        visit(
+            &conn,
+            "http://mozilla.org:8080/a",
+            MONDAY + 2 * DAY_MS + 1,
+            VisitTransition::Link,
+            false,
+        )?;
+        let domains = get_top_domains(
+            &conn,
+            start,
+            end,
+            TopDomainsOrder::Visits,
+            10,
+            VisitTransitionSet::empty(),
+            false,
+        )?;
+        assert_eq!(
+            domains
+                .iter()
+                .map(|domain| (domain.host.as_str(), domain.visit_count))
+                .collect::<Vec<_>>(),
+            vec![("mozilla.org", 4), ("example.com", 3)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_visit_transition_counts() -> Result<()> {
+        let conn = new_mem_connection();
+        add_visits(&conn)?;
+        let start = Timestamp(MONDAY as u64);
+        let end = Timestamp((MONDAY + 7 * DAY_MS - 1) as u64);
+
+        assert_eq!(
+            get_visit_transition_counts(&conn, start, end, VisitTransitionSet::empty(), false)?,
+            vec![
+                VisitTransitionCount {
+                    visit_type: VisitTransition::Link,
+                    visit_count: 3,
+                },
+                VisitTransitionCount {
+                    visit_type: VisitTransition::Typed,
+                    visit_count: 1,
+                },
+                VisitTransitionCount {
+                    visit_type: VisitTransition::Reload,
+                    visit_count: 1,
+                },
+            ]
+        );
+        assert_eq!(
+            get_visit_transition_counts(
+                &conn,
+                start,
+                end,
+                VisitTransitionSet::single(VisitTransition::Typed),
+                true
+            )?,
+            vec![
+                VisitTransitionCount {
+                    visit_type: VisitTransition::Link,
+                    visit_count: 2,
+                },
+                VisitTransitionCount {
+                    visit_type: VisitTransition::Reload,
+                    visit_count: 1,
+                },
+            ]
+        );
+        Ok(())
+    }
+}
@@ -15,6 +15,8 @@ use url::Url;
 
 use lazy_static::lazy_static;
 
+pub mod groups;
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum DocumentType {
     Regular = 0,
@@ -0,0 +1,452 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Groups history metadata into "journeys": pages reached from the same
+//! search, or by following links from one page to the next. Both apps render
+//! these as "Recently visited" sections, so the heuristics live here.
+
+use super::{get_between, HistoryMetadata};
+use crate::db::PlacesDb;
+use crate::error::Result;
+use std::collections::HashMap;
+use url::Url;
+
+#[derive(Clone, Debug)]
+pub struct HistoryGroupOptions {
+    /// The longest time, in milliseconds, between the last view of a page and
+    /// following a link from it, or between searches for the same term, for
+    /// both to end up in the same group.
+    pub max_gap: i64,
+    /// Groups with fewer pages than this are left out.
+    pub min_group_size: u32,
+    /// The maximum number of groups to return.
+    pub limit: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HistoryGroup {
+    /// The search term for search groups, otherwise the title of the first
+    /// page in the group.
+    pub title: String,
+    pub search_term: Option<String>,
+    /// The pages in the group, most recently updated first. A page that was
+    /// viewed several times appears once, with its view times added up.
+    pub members: Vec<HistoryMetadata>,
+    pub total_view_time: i64,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+/// Returns groups of related metadata updated between `start` and `end`,
+/// most recently updated first.
+///
+/// Metadata with the same search term (ignoring case) joins the group for
+/// that term, as long as the group was last viewed within `max_gap` of it.
+/// Metadata without a search term joins the group of its referrer, as long as
+/// the referrer was viewed within `max_gap` of it. Otherwise, it starts a new
+/// group, so that visits separated by a long gap aren't grouped together.
+pub fn get_groups(
+    db: &PlacesDb,
+    start: i64,
+    end: i64,
+    options: &HistoryGroupOptions,
+) -> Result<Vec<HistoryGroup>> {
+    let entries = get_between(db, start, end)?;
+    Ok(group_entries(entries, options))
+}
+
+fn group_entries(
+    mut entries: Vec<HistoryMetadata>,
+    options: &HistoryGroupOptions,
+) -> Vec<HistoryGroup> {
+    // Oldest first, so that we see referrers before the pages they link to.
+    entries.sort_by_key(|entry| entry.created_at);
+
+    let mut builders: Vec<GroupBuilder> = Vec::new();
+    let mut by_search_term: HashMap<String, usize> = HashMap::new();
+    // The group that each page was last added to, and when it was last viewed.
+    let mut by_url: HashMap<String, (usize, i64)> = HashMap::new();
+
+    for entry in entries {
+        let search_term = entry
+            .search_term
+            .as_deref()
+            .map(str::trim)
+            .filter(|term| !term.is_empty())
+            .map(ToOwned::to_owned);
+        let index = match search_term {
+            Some(term) => {
+                let key = term.to_lowercase();
+                match by_search_term.get(&key) {
+                    Some(&index)
+                        if entry.created_at - builders[index].updated_at <= options.max_gap =>
+                    {
+                        index
+                    }
+                    _ => {
+                        builders.push(GroupBuilder::new(Some(term)));
+                        by_search_term.insert(key, builders.len() - 1);
+                        builders.len() - 1
+                    }
+                }
+            }
+            None => match entry
+                .referrer_url
+                .as_ref()
+                .and_then(|referrer_url| by_url.get(referrer_url))
+            {
+                Some(&(index, last_viewed))
+                    if entry.created_at - last_viewed <= options.max_gap =>
+                {
+                    index
+                }
+                _ => {
+                    builders.push(GroupBuilder::new(None));
+                    builders.len() - 1
+                }
+            },
+        };
+        by_url.insert(entry.url.clone(), (index, entry.updated_at));
+        builders[index].add(entry);
+    }
+
+    let mut groups = builders
+        .into_iter()
+        .filter(|builder| builder.members.len() >= options.min_group_size as usize)
+        .map(GroupBuilder::build)
+        .collect::<Vec<_>>();
+    groups.sort_by(|a, b| b.updated_at.cmp(&a.updated_at));
+    groups.truncate(options.limit as usize);
+    groups
+}
+
+struct GroupBuilder {
+    search_term: Option<String>,
+    members: Vec<HistoryMetadata>,
+    by_url: HashMap<String, usize>,
+    /// When any page in the group was last viewed.
+    updated_at: i64,
+}
+
+impl GroupBuilder {
+    fn new(search_term: Option<String>) -> Self {
+        Self {
+            search_term,
+            members: Vec::new(),
+            by_url: HashMap::new(),
+            updated_at: i64::MIN,
+        }
+    }
+
+    fn add(&mut self, entry: HistoryMetadata) {
+        self.updated_at = self.updated_at.max(entry.updated_at);
+        match self.by_url.get(&entry.url) {
+            Some(&index) => {
+                let member = &mut self.members[index];
+                member.total_view_time =
+                    member.total_view_time.saturating_add(entry.total_view_time);
+                member.updated_at = member.updated_at.max(entry.updated_at);
+            }
+            None => {
+                self.by_url.insert(entry.url.clone(), self.members.len());
+                self.members.push(entry);
+            }
+        }
+    }
+
+    fn build(mut self) -> HistoryGroup {
+        // Members are in the order they were first viewed, so the first one
+        // started the group.
+        let title = match &self.search_term {
+            Some(term) => term.clone(),
+            None => page_title(&self.members[0]),
+        };
+        let total_view_time = self
+            .members
+            .iter()
+            .map(|member| i64::from(member.total_view_time))
+            .sum();
+        let created_at = self.members[0].created_at;
+        let updated_at = self.updated_at;
+        self.members.sort_by(|a, b| b.updated_at.cmp(&a.updated_at));
+        HistoryGroup {
+            title,
+            search_term: self.search_term,
+            members: self.members,
+            total_view_time,
+            created_at,
+            updated_at,
+        }
+    }
+}
+
+/// Returns the title of a page, falling back to its host or URL.
+fn page_title(page: &HistoryMetadata) -> String {
+    match page.title.as_deref().map(str::trim) {
+        Some(title) if !title.is_empty() => title.to_owned(),
+        _ => Url::parse(&page.url)
+            .ok()
+            .and_then(|url| url.host_str().map(ToOwned::to_owned))
+            .unwrap_or_else(|| page.url.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::places_api::ConnectionType;
+    use crate::storage::history_metadata::{
+        apply_metadata_observation, DocumentType, HistoryMetadataObservation,
+    };
+    use pretty_assertions::assert_eq;
+
+    const MINUTE: i64 = 60 * 1000;
+
+    fn options() -> HistoryGroupOptions {
+        HistoryGroupOptions {
+            max_gap: 30 * MINUTE,
+            min_group_size: 2,
+            limit: 10,
+        }
+    }
+
+    fn entry(
+        url: &str,
+        referrer_url: Option<&str>,
+        search_term: Option<&str>,
+        created_at: i64,
+        total_view_time: i32,
+    ) -> HistoryMetadata {
+        HistoryMetadata {
+            url: url.to_owned(),
+            title: None,
+            preview_image_url: None,
+            created_at,
+            updated_at: created_at + i64::from(total_view_time),
+            total_view_time,
+            search_term: search_term.map(ToOwned::to_owned),
+            document_type: DocumentType::Regular,
+            referrer_url: referrer_url.map(ToOwned::to_owned),
+        }
+    }
+
+    fn member_urls(group: &HistoryGroup) -> Vec<&str> {
+        group
+            .members
+            .iter()
+            .map(|member| member.url.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_group_by_search_term() {
+        let entries = vec![
+            entry("https://example.com/cats", None, Some("Cats"), 0, 1000),
+            entry(
+                "https://example.com/cats/tabby",
+                Some("https://example.com/cats"),
+                None,
+                MINUTE,
+                2000,
+            ),
+            // The same search a little later, with different case and
+            // spacing, still joins the group.
+            entry(
+                "https://example.org/kittens",
+                None,
+                Some(" cats "),
+                20 * MINUTE,
+                3000,
+            ),
+            entry(
+                "https://example.net/dogs",
+                None,
+                Some("dogs"),
+                2 * MINUTE,
+                500,
+            ),
+            // The same search a day later starts a new group.
+            entry(
+                "https://example.com/cats",
+                None,
+                Some("CATS"),
+                24 * 60 * MINUTE,
+                1000,
+            ),
+            entry(
+                "https://example.org/cats",
+                None,
+                Some("cats"),
+                24 * 60 * MINUTE + 5 * MINUTE,
+                1000,
+            ),
+        ];
+        let groups = group_entries(entries, &options());
+        assert_eq!(groups.len(), 2);
+        let group = &groups[1];
+        assert_eq!(group.title, "Cats");
+        assert_eq!(group.search_term.as_deref(), Some("Cats"));
+        assert_eq!(
+            member_urls(group),
+            vec![
+                "https://example.org/kittens",
+                "https://example.com/cats/tabby",
+                "https://example.com/cats",
+            ]
+        );
+        assert_eq!(group.total_view_time, 6000);
+        assert_eq!(group.created_at, 0);
+        assert_eq!(group.updated_at, 20 * MINUTE + 3000);
+
+        let group = &groups[0];
+        assert_eq!(group.title, "CATS");
+        assert_eq!(
+            member_urls(group),
+            vec!["https://example.org/cats", "https://example.com/cats"]
+        );
+        assert_eq!(group.created_at, 24 * 60 * MINUTE);
+    }
+
+    #[test]
+    fn test_group_by_referrer_chain() {
+        let mut first = entry("https://news.example/", None, None, 0, 1000);
+        first.title = Some("Example News".to_owned());
+        let entries = vec![
+            first,
+            entry(
+                "https://news.example/story",
+                Some("https://news.example/"),
+                None,
+                10 * MINUTE,
+                1000,
+            ),
+            entry(
+                "https://other.example/",
+                Some("https://news.example/story"),
+                None,
+                20 * MINUTE,
+                1000,
+            ),
+            // Too long after the last view of its referrer, so this starts
+            // a new group, which is too small to return.
+            entry(
+                "https://later.example/",
+                Some("https://other.example/"),
+                None,
+                60 * MINUTE,
+                1000,
+            ),
+        ];
+        let groups = group_entries(entries, &options());
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].title, "Example News");
+        assert_eq!(groups[0].search_term, None);
+        assert_eq!(
+            member_urls(&groups[0]),
+            vec![
+                "https://other.example/",
+                "https://news.example/story",
+                "https://news.example/",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_group_merges_repeat_views() {
+        let entries = vec![
+            entry("https://example.com/", None, None, 0, 1000),
+            entry(
+                "https://example.com/a",
+                Some("https://example.com/"),
+                None,
+                MINUTE,
+                1000,
+            ),
+            entry(
+                "https://example.com/",
+                Some("https://example.com/a"),
+                None,
+                2 * MINUTE,
+                4000,
+            ),
+        ];
+        let groups = group_entries(entries, &options());
+        assert_eq!(groups.len(), 1);
+        assert_eq!(
+            member_urls(&groups[0]),
+            vec!["https://example.com/", "https://example.com/a"]
+        );
+        assert_eq!(groups[0].members[0].total_view_time, 5000);
+        assert_eq!(groups[0].total_view_time, 6000);
+        // With no title, the group is named after the host.
+        assert_eq!(groups[0].title, "example.com");
+    }
+
+    #[test]
+    fn test_group_order_and_limit() {
+        let entries = vec![
+            entry("https://a.example/", None, Some("a"), 0, 0),
+            entry("https://a.example/1", None, Some("a"), MINUTE, 0),
+            entry("https://b.example/", None, Some("b"), 2 * MINUTE, 0),
+            entry("https://b.example/1", None, Some("b"), 3 * MINUTE, 0),
+            entry("https://c.example/", None, Some("c"), 4 * MINUTE, 0),
+        ];
+        let titles = |options: &HistoryGroupOptions| {
+            group_entries(entries.clone(), options)
+                .into_iter()
+                .map(|group| group.title)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(titles(&options()), vec!["b", "a"]);
+        assert_eq!(
+            titles(&HistoryGroupOptions {
+                min_group_size: 1,
+                ..options()
+            }),
+            vec!["c", "b", "a"]
+        );
+        assert_eq!(
+            titles(&HistoryGroupOptions {
+                limit: 1,
+                ..options()
+            }),
+            vec!["b"]
+        );
+    }
+
+    #[test]
+    fn test_get_groups() {
+        let conn = PlacesDb::open_in_memory(ConnectionType::ReadWrite).expect("memory db");
+        for (url, referrer_url, search_term) in [
+            ("https://example.com/search", None, Some("rust")),
+            (
+                "https://www.rust-lang.org/",
+                Some("https://example.com/search"),
+                Some("rust"),
+            ),
+            ("https://example.org/", None, None),
+        ] {
+            apply_metadata_observation(
+                &conn,
+                HistoryMetadataObservation {
+                    url: url.to_owned(),
+                    view_time: Some(1000),
+                    search_term: search_term.map(ToOwned::to_owned),
+                    document_type: None,
+                    referrer_url: referrer_url.map(ToOwned::to_owned),
+                    title: None,
+                },
+            )
+            .unwrap();
+        }
+
+        let groups = get_groups(&conn, 0, i64::MAX, &options()).unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].title, "rust");
+        assert_eq!(groups[0].members.len(), 2);
+        assert_eq!(groups[0].total_view_time, 2000);
+
+        assert!(get_groups(&conn, 0, 1, &options()).unwrap().is_empty());
+    }
+}
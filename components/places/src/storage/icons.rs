@@ -0,0 +1,519 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Favicon storage.
+//!
+//! Icons are stored in `moz_icons`, one row for each size variant of an icon
+//! URL, and are associated with pages in `moz_icons_to_pages`. A page which
+//! doesn't have an icon of its own falls back to the root icon (the
+//! `/favicon.ico`) of its origin, if we have one.
+
+use crate::db::PlacesDb;
+use crate::error::{InvalidPlaceInfo, Result};
+use rusqlite::Row;
+use sql_support::ConnExt;
+use std::time::Duration;
+use types::Timestamp;
+use url::Url;
+
+/// The largest icon we'll store, matching desktop's `MAX_FAVICON_BUFFER_SIZE`.
+pub const ICON_DATA_MAX: usize = 65536;
+
+/// How long an icon is fresh for, if the observation doesn't say.
+pub const DEFAULT_ICON_EXPIRY: Duration = Duration::from_secs(60 * 60 * 24 * 7);
+
+/// An icon for a page, as observed by the consumer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IconObservation {
+    /// The page the icon was found on. It must already be in history.
+    pub page_url: Url,
+    pub icon_url: Url,
+    /// The width of this variant, in pixels. 0 if it's unknown.
+    pub width: u32,
+    pub data: Vec<u8>,
+    pub mime_type: Option<String>,
+    /// When the icon should be fetched again. Defaults to `DEFAULT_ICON_EXPIRY` from now.
+    pub expires: Option<Timestamp>,
+}
+
+/// An icon returned for a page.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PageIcon {
+    pub icon_url: Url,
+    pub width: u32,
+    pub data: Vec<u8>,
+    pub mime_type: Option<String>,
+    /// Expired icons are still returned, but the consumer should fetch them again.
+    pub expires: Timestamp,
+    /// Whether this is the root icon of the page's origin, rather than an icon of the page itself.
+    pub is_root: bool,
+}
+
+impl PageIcon {
+    fn from_row(row: &Row<'_>) -> Result<Self> {
+        Ok(Self {
+            icon_url: Url::parse(&row.get::<_, String>("icon_url")?)?,
+            width: row.get("width")?,
+            data: row.get("data")?,
+            mime_type: row.get("mime_type")?,
+            expires: row.get("expire_ms")?,
+            is_root: row.get("root")?,
+        })
+    }
+
+    /// Reads the icon added to a row by `with_page_icons()` or `with_origin_icons()`.
+    pub(crate) fn from_joined_row(row: &Row<'_>) -> Result<Option<Self>> {
+        let icon_url = match row.get::<_, Option<String>>("icon_url")? {
+            Some(icon_url) => icon_url,
+            None => return Ok(None),
+        };
+        Ok(Some(Self {
+            icon_url: Url::parse(&icon_url)?,
+            width: row.get("icon_width")?,
+            data: row.get("icon_data")?,
+            mime_type: row.get("icon_mime_type")?,
+            expires: row.get("icon_expire_ms")?,
+            is_root: row.get("icon_is_root")?,
+        }))
+    }
+}
+
+/// The root icon URL for a page, or `None` if the page doesn't have an origin which could
+/// have one.
+fn root_icon_url(page_url: &Url) -> Option<Url> {
+    match page_url.scheme() {
+        "http" | "https" => page_url.join("/favicon.ico").ok(),
+        _ => None,
+    }
+}
+
+fn is_root_icon_url(icon_url: &Url) -> bool {
+    icon_url.path() == "/favicon.ico" && icon_url.query().is_none()
+}
+
+/// Stores an icon for a page.
+///
+/// Size variants of the same icon URL are kept, but setting an icon with a different URL
+/// replaces the page's previous icons. Icons which are no longer used by any page are removed
+/// by `delete_orphaned_icons()`.
+pub fn set_icon_for_page(db: &PlacesDb, icon: IconObservation) -> Result<()> {
+    if icon.data.len() > ICON_DATA_MAX {
+        return Err(InvalidPlaceInfo::IconTooLarge(icon.data.len()).into());
+    }
+    let expires = icon
+        .expires
+        .or_else(|| Timestamp::now().checked_add(DEFAULT_ICON_EXPIRY))
+        .unwrap_or_default();
+    let tx = db.begin_transaction()?;
+    let page_id = match db.try_query_one::<i64, _>(
+        "SELECT id FROM moz_places WHERE url_hash = hash(:page_url) AND url = :page_url",
+        &[(":page_url", &icon.page_url.as_str())],
+        true,
+    )? {
+        Some(page_id) => page_id,
+        None => return Err(InvalidPlaceInfo::NoSuchUrl.into()),
+    };
+    let existing_id = db.try_query_one::<i64, _>(
+        "SELECT id FROM moz_icons
+         WHERE icon_url_hash = hash(:icon_url) AND icon_url = :icon_url AND width = :width",
+        rusqlite::named_params! {
+            ":icon_url": icon.icon_url.as_str(),
+            ":width": icon.width,
+        },
+        true,
+    )?;
+    let icon_id = match existing_id {
+        Some(icon_id) => {
+            db.execute_cached(
+                "UPDATE moz_icons
+                 SET data = :data, mime_type = :mime_type, expire_ms = :expires
+                 WHERE id = :id",
+                rusqlite::named_params! {
+                    ":data": icon.data,
+                    ":mime_type": icon.mime_type,
+                    ":expires": expires,
+                    ":id": icon_id,
+                },
+            )?;
+            icon_id
+        }
+        None => {
+            db.execute_cached(
+                "INSERT INTO moz_icons(icon_url, icon_url_hash, width, root, mime_type,
+                                       expire_ms, data)
+                 VALUES(:icon_url, hash(:icon_url), :width, :root, :mime_type, :expires, :data)",
+                rusqlite::named_params! {
+                    ":icon_url": icon.icon_url.as_str(),
+                    ":width": icon.width,
+                    ":root": is_root_icon_url(&icon.icon_url),
+                    ":mime_type": icon.mime_type,
+                    ":expires": expires,
+                    ":data": icon.data,
+                },
+            )?;
+            db.conn().last_insert_rowid()
+        }
+    };
+    db.execute_cached(
+        "DELETE FROM moz_icons_to_pages
+         WHERE page_id = :page_id AND
+               icon_id IN (SELECT id FROM moz_icons WHERE icon_url <> :icon_url)",
+        rusqlite::named_params! {
+            ":page_id": page_id,
+            ":icon_url": icon.icon_url.as_str(),
+        },
+    )?;
+    db.execute_cached(
+        "INSERT OR IGNORE INTO moz_icons_to_pages(page_id, icon_id)
+         VALUES(:page_id, :icon_id)",
+        rusqlite::named_params! {
+            ":page_id": page_id,
+            ":icon_id": icon_id,
+        },
+    )?;
+    tx.commit()?;
+    Ok(())
+}
+
+// All the size variants of the page's own icons, or of its root icon if it has none.
+fn get_icon_variants(db: &PlacesDb, page_url: &Url) -> Result<Vec<PageIcon>> {
+    let icons = db.query_rows_and_then_cached(
+        "SELECT i.icon_url, i.width, i.data, i.mime_type, i.expire_ms, 0 AS root
+         FROM moz_icons i
+         JOIN moz_icons_to_pages ip ON ip.icon_id = i.id
+         JOIN moz_places h ON h.id = ip.page_id
+         WHERE h.url_hash = hash(:page_url) AND h.url = :page_url
+         ORDER BY i.width",
+        &[(":page_url", &page_url.as_str())],
+        PageIcon::from_row,
+    )?;
+    if !icons.is_empty() {
+        return Ok(icons);
+    }
+    let root_url = match root_icon_url(page_url) {
+        Some(root_url) => root_url,
+        None => return Ok(icons),
+    };
+    db.query_rows_and_then_cached(
+        "SELECT icon_url, width, data, mime_type, expire_ms, root
+         FROM moz_icons
+         WHERE icon_url_hash = hash(:root_url) AND icon_url = :root_url AND root
+         ORDER BY width",
+        &[(":root_url", &root_url.as_str())],
+        PageIcon::from_row,
+    )
+}
+
+/// Gets the best icon for a page: the smallest variant at least `preferred_width` pixels wide,
+/// or the largest variant if none are that wide. Pass 0 for the largest variant.
+pub fn get_icon_for_page(
+    db: &PlacesDb,
+    page_url: &Url,
+    preferred_width: u32,
+) -> Result<Option<PageIcon>> {
+    let mut icons = get_icon_variants(db, page_url)?;
+    if preferred_width > 0 {
+        if let Some(index) = icons.iter().position(|i| i.width >= preferred_width) {
+            return Ok(Some(icons.swap_remove(index)));
+        }
+    }
+    Ok(icons.pop())
+}
+
+// Joins the largest icon of the page with the `moz_places` id `page_id` to each row of `sql`, or
+// the largest root icon at `root_url` if the page has none. Both are SQL expressions over the
+// columns of `sql`, which are available as `r`.
+fn with_icons(sql: &str, page_id: &str, root_url: &str) -> String {
+    format!(
+        "SELECT r.*,
+                icon.icon_url AS icon_url, icon.width AS icon_width, icon.data AS icon_data,
+                icon.mime_type AS icon_mime_type, icon.expire_ms AS icon_expire_ms,
+                NOT EXISTS(SELECT 1 FROM moz_icons_to_pages
+                           WHERE page_id = {page_id} AND icon_id = icon.id) AS icon_is_root
+         FROM ({sql}) r
+         LEFT JOIN moz_icons icon ON icon.id = IFNULL(
+             (SELECT ip.icon_id
+              FROM moz_icons_to_pages ip
+              JOIN moz_icons i ON i.id = ip.icon_id
+              WHERE ip.page_id = {page_id}
+              ORDER BY i.width DESC
+              LIMIT 1),
+             (SELECT id
+              FROM moz_icons
+              WHERE icon_url_hash = hash({root_url}) AND icon_url = {root_url} AND root
+              ORDER BY width DESC
+              LIMIT 1))"
+    )
+}
+
+/// Wraps `sql`, a query for pages which returns their `moz_places` id as `id`, so that each row
+/// also has the largest icon for the page, falling back to the root icon of its origin. Read it
+/// with `PageIcon::from_joined_row()`. The rows of `sql` are available as `r`, but their order
+/// isn't kept.
+pub(crate) fn with_page_icons(sql: &str) -> String {
+    with_icons(
+        sql,
+        "r.id",
+        "(SELECT o.prefix || o.host || '/favicon.ico'
+          FROM moz_places h
+          JOIN moz_origins o ON o.id = h.origin_id
+          WHERE h.id = r.id AND o.prefix IN ('http://', 'https://'))",
+    )
+}
+
+/// Like `with_page_icons()`, but for a query for origins which returns their URL, with a
+/// trailing slash, as `url`. Origins only have root icons.
+pub(crate) fn with_origin_icons(sql: &str) -> String {
+    with_icons(sql, "NULL", "r.url || 'favicon.ico'")
+}
+
+/// Deletes icons which aren't used by any page. Root icons are kept while we still know about
+/// their origin.
+pub fn delete_orphaned_icons(db: &PlacesDb) -> Result<()> {
+    db.execute_cached(
+        "DELETE FROM moz_icons
+         WHERE NOT EXISTS(SELECT 1 FROM moz_icons_to_pages
+                          WHERE icon_id = moz_icons.id)
+           AND NOT (root AND EXISTS(SELECT 1 FROM moz_origins o
+                                    WHERE moz_icons.icon_url =
+                                          o.prefix || o.host || '/favicon.ico'))",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Deletes icons which expired before `older_than`, along with any orphaned icons.
+pub fn delete_expired_icons(db: &PlacesDb, older_than: Timestamp) -> Result<()> {
+    let tx = db.begin_transaction()?;
+    db.execute_cached(
+        "DELETE FROM moz_icons WHERE expire_ms < :older_than",
+        &[(":older_than", &older_than)],
+    )?;
+    delete_orphaned_icons(db)?;
+    tx.commit()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::places_api::test::new_mem_connection;
+    use crate::observation::VisitObservation;
+    use crate::storage::history::{apply_observation, delete_visits_for, url_to_guid, wipe_local};
+    use crate::types::VisitTransition;
+
+    fn visit(conn: &PlacesDb, url: &str) -> Url {
+        let url = Url::parse(url).unwrap();
+        apply_observation(
+            conn,
+            VisitObservation::new(url.clone()).with_visit_type(VisitTransition::Link),
+        )
+        .unwrap();
+        url
+    }
+
+    fn observation(page_url: &Url, icon_url: &str, width: u32) -> IconObservation {
+        IconObservation {
+            page_url: page_url.clone(),
+            icon_url: Url::parse(icon_url).unwrap(),
+            width,
+            data: vec![width as u8; 4],
+            mime_type: Some("image/png".into()),
+            expires: None,
+        }
+    }
+
+    fn icon_count(conn: &PlacesDb) -> u32 {
+        conn.query_one("SELECT COUNT(*) FROM moz_icons").unwrap()
+    }
+
+    #[test]
+    fn test_size_variants() {
+        let conn = new_mem_connection();
+        let page = visit(&conn, "https://www.example.com/page");
+        for width in [16, 32, 64] {
+            set_icon_for_page(
+                &conn,
+                observation(&page, "https://www.example.com/icon.png", width),
+            )
+            .unwrap();
+        }
+        let best = |preferred| {
+            get_icon_for_page(&conn, &page, preferred)
+                .unwrap()
+                .map(|icon| icon.width)
+        };
+        assert_eq!(best(0), Some(64));
+        assert_eq!(best(16), Some(16));
+        assert_eq!(best(20), Some(32));
+        assert_eq!(best(128), Some(64));
+
+        let icon = get_icon_for_page(&conn, &page, 32).unwrap().unwrap();
+        assert_eq!(icon.data, vec![32; 4]);
+        assert_eq!(icon.mime_type.as_deref(), Some("image/png"));
+        assert!(!icon.is_root);
+        assert!(icon.expires > Timestamp::now());
+
+        // Setting an icon with a different URL replaces them all.
+        set_icon_for_page(
+            &conn,
+            observation(&page, "https://www.example.com/new.png", 32),
+        )
+        .unwrap();
+        let icon = get_icon_for_page(&conn, &page, 0).unwrap().unwrap();
+        assert_eq!(icon.icon_url.as_str(), "https://www.example.com/new.png");
+        delete_orphaned_icons(&conn).unwrap();
+        assert_eq!(icon_count(&conn), 1);
+    }
+
+    #[test]
+    fn test_root_fallback() {
+        let conn = new_mem_connection();
+        let home = visit(&conn, "https://www.example.com/");
+        let other = visit(&conn, "https://www.example.com/other");
+        set_icon_for_page(
+            &conn,
+            observation(&home, "https://www.example.com/favicon.ico", 0),
+        )
+        .unwrap();
+
+        let icon = get_icon_for_page(&conn, &other, 0).unwrap().unwrap();
+        assert!(icon.is_root);
+        assert_eq!(
+            icon.icon_url.as_str(),
+            "https://www.example.com/favicon.ico"
+        );
+        // Pages we don't know about can still use the root icon.
+        let unvisited = Url::parse("https://www.example.com/unvisited?q=1").unwrap();
+        assert!(get_icon_for_page(&conn, &unvisited, 0).unwrap().is_some());
+        // But other origins can't.
+        let elsewhere = Url::parse("https://example.org/").unwrap();
+        assert!(get_icon_for_page(&conn, &elsewhere, 0).unwrap().is_none());
+
+        // The root icon outlives the page it was found on, as long as the origin is around.
+        delete_visits_for(&conn, &url_to_guid(&conn, &home).unwrap().unwrap()).unwrap();
+        delete_orphaned_icons(&conn).unwrap();
+        assert!(get_icon_for_page(&conn, &other, 0).unwrap().is_some());
+        wipe_local(&conn).unwrap();
+        assert_eq!(icon_count(&conn), 0);
+    }
+
+    #[test]
+    fn test_results_have_icons() {
+        use crate::api::matcher::{search_frecent, MatchReason, SearchParams};
+        use crate::storage::history::get_top_frecent_site_infos;
+
+        let conn = new_mem_connection();
+        let page = visit(&conn, "https://www.example.com/page");
+        let home = visit(&conn, "https://www.example.com/");
+        let other = visit(&conn, "https://example.org/");
+        for width in [16, 32] {
+            set_icon_for_page(
+                &conn,
+                observation(&page, "https://www.example.com/icon.png", width),
+            )
+            .unwrap();
+        }
+        set_icon_for_page(
+            &conn,
+            observation(&home, "https://www.example.com/favicon.ico", 16),
+        )
+        .unwrap();
+        let search = |search_string: &str, limit| {
+            search_frecent(
+                &conn,
+                SearchParams {
+                    search_string: search_string.into(),
+                    limit,
+                },
+            )
+            .unwrap()
+        };
+
+        // Results get the largest icon for their page.
+        let results = search("example.com/page", 10);
+        assert_eq!(results.len(), 1);
+        let icon = results[0].icon.as_ref().unwrap();
+        assert_eq!(icon.icon_url.as_str(), "https://www.example.com/icon.png");
+        assert_eq!(icon.width, 32);
+        assert_eq!(icon.data, vec![32; 4]);
+        assert!(!icon.is_root);
+
+        // Origins get their root icon. The origin is the first match.
+        let results = search("www.example.com", 1);
+        assert_eq!(results[0].reasons, vec![MatchReason::Origin]);
+        let icon = results[0].icon.as_ref().unwrap();
+        assert_eq!(
+            icon.icon_url.as_str(),
+            "https://www.example.com/favicon.ico"
+        );
+        assert!(icon.is_root);
+
+        let infos = get_top_frecent_site_infos(&conn, 10, 0).unwrap();
+        assert_eq!(infos.len(), 3);
+        for info in infos {
+            let icon = info
+                .icon
+                .map(|icon| (icon.icon_url.to_string(), icon.is_root));
+            if info.url == page {
+                assert_eq!(
+                    icon,
+                    Some(("https://www.example.com/icon.png".to_string(), false))
+                );
+            } else if info.url == home {
+                // The root icon was found on the home page itself.
+                assert_eq!(
+                    icon,
+                    Some(("https://www.example.com/favicon.ico".to_string(), false))
+                );
+            } else {
+                assert_eq!(info.url, other);
+                assert_eq!(icon, None);
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_icons() {
+        let conn = new_mem_connection();
+        let unvisited = Url::parse("https://www.example.com/").unwrap();
+        set_icon_for_page(
+            &conn,
+            observation(&unvisited, "https://www.example.com/i.png", 16),
+        )
+        .expect_err("page isn't in history");
+
+        let page = visit(&conn, "https://www.example.com/");
+        let mut too_large = observation(&page, "https://www.example.com/i.png", 16);
+        too_large.data = vec![0; ICON_DATA_MAX + 1];
+        set_icon_for_page(&conn, too_large).expect_err("icon is too large");
+        assert_eq!(icon_count(&conn), 0);
+    }
+
+    #[test]
+    fn test_delete_expired_icons() {
+        let conn = new_mem_connection();
+        let page = visit(&conn, "https://www.example.com/");
+        let mut expired = observation(&page, "https://www.example.com/i.png", 16);
+        expired.expires = Some(Timestamp(1));
+        set_icon_for_page(&conn, expired).unwrap();
+        set_icon_for_page(
+            &conn,
+            observation(&page, "https://www.example.com/i.png", 32),
+        )
+        .unwrap();
+
+        // Expired icons are still returned until they're pruned.
+        assert_eq!(
+            get_icon_for_page(&conn, &page, 16).unwrap().unwrap().width,
+            16
+        );
+        delete_expired_icons(&conn, Timestamp::now()).unwrap();
+        assert_eq!(
+            get_icon_for_page(&conn, &page, 16).unwrap().unwrap().width,
+            32
+        );
+        assert_eq!(icon_count(&conn), 1);
+    }
+}
@@ -6,15 +6,18 @@
 // API and the database.
 
 pub mod bookmarks;
+pub mod fulltext;
 pub mod history;
 pub mod history_metadata;
+pub mod icons;
 pub mod tags;
 
 use crate::db::PlacesDb;
 use crate::error::{Error, InvalidPlaceInfo, Result};
 use crate::ffi::HistoryVisitInfo;
 use crate::ffi::TopFrecentSiteInfo;
-use crate::frecency::{calculate_frecency, DEFAULT_FRECENCY_SETTINGS};
+use crate::frecency::calculate_frecency;
+use crate::storage::icons::PageIcon;
 use crate::types::{SyncStatus, UnknownFields, VisitTransition};
 use interrupt_support::SqlInterruptScope;
 use rusqlite::types::{FromSql, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
@@ -225,6 +228,7 @@ impl TopFrecentSiteInfo {
         Ok(Self {
             url: Url::parse(&url)?,
             title: row.get("title")?,
+            icon: PageIcon::from_joined_row(row)?,
         })
     }
 }
@@ -250,6 +254,9 @@ pub fn run_maintenance_prune(conn: &PlacesDb, db_size_limit: u32) -> Result<RunM
     let should_prune = db_size_limit > 0 && db_size_before > db_size_limit;
     if should_prune {
         history::prune_older_visits(conn)?;
+        icons::delete_expired_icons(conn, Timestamp::now())?;
+    } else {
+        icons::delete_orphaned_icons(conn)?;
     }
     let db_size_after = conn.get_db_size()?;
     Ok(RunMaintenanceMetrics {
@@ -303,6 +310,94 @@ pub fn run_maintenance_checkpoint(conn: &PlacesDb) -> Result<()> {
     Ok(())
 }
 
+/// The number of frecencies that `run_maintenance_recalculate_frecencies()`
+/// recalculates in each transaction.
+const FRECENCY_RECALCULATION_BATCH_SIZE: u32 = 200;
+
+pub struct RecalculateFrecenciesMetrics {
+    pub num_recalculated: u32,
+    pub num_changed: u32,
+    pub finished: bool,
+}
+
+/// Run maintenance on the places DB (frecency step)
+///
+/// Recalculates stale frecencies in batches, each in its own transaction, so
+/// that an interrupted run keeps the batches it already finished. If
+/// `recalculate_all` is true, every frecency is marked as stale first, which is
+/// useful after changing the frecency settings.
+///
+/// At most `max_pages` frecencies are recalculated (pass in a 0 for no limit).
+/// The result says how many were recalculated, how many of those changed, and
+/// whether any stale frecencies are left for the next run.
+pub fn run_maintenance_recalculate_frecencies(
+    conn: &PlacesDb,
+    recalculate_all: bool,
+    max_pages: u32,
+) -> Result<RecalculateFrecenciesMetrics> {
+    let scope = conn.begin_interrupt_scope()?;
+    if recalculate_all {
+        conn.execute_cached(
+            "INSERT OR IGNORE INTO moz_places_stale_frecencies(place_id, stale_at)
+             SELECT id, now() FROM moz_places",
+            [],
+        )?;
+    }
+
+    let settings = conn.frecency_settings();
+    let mut num_recalculated = 0;
+    let mut num_changed = 0;
+    loop {
+        let batch_size = if max_pages > 0 {
+            FRECENCY_RECALCULATION_BATCH_SIZE.min(max_pages - num_recalculated)
+        } else {
+            FRECENCY_RECALCULATION_BATCH_SIZE
+        };
+        if batch_size == 0 {
+            break;
+        }
+        let tx = conn.begin_transaction()?;
+        let place_ids = tx.query_rows_and_then(
+            "SELECT place_id FROM moz_places_stale_frecencies
+             ORDER BY stale_at DESC
+             LIMIT :limit",
+            &[(":limit", &batch_size)],
+            |row| row.get::<_, i64>(0),
+        )?;
+        if place_ids.is_empty() {
+            break;
+        }
+        for place_id in &place_ids {
+            // Frecency recalculation runs several statements, so check to
+            // make sure we aren't interrupted before each calculation.
+            scope.err_if_interrupted()?;
+            let frecency = calculate_frecency(conn, &settings, *place_id, Some(false))?;
+            num_changed += tx.execute_cached(
+                "UPDATE moz_places SET frecency = :frecency
+                 WHERE id = :place_id AND frecency <> :frecency",
+                rusqlite::named_params! {
+                    ":frecency": frecency,
+                    ":place_id": place_id,
+                },
+            )? as u32;
+            tx.execute_cached(
+                "DELETE FROM moz_places_stale_frecencies WHERE place_id = :place_id",
+                &[(":place_id", place_id)],
+            )?;
+        }
+        tx.commit()?;
+        num_recalculated += place_ids.len() as u32;
+    }
+
+    let finished =
+        !conn.query_one::<bool>("SELECT EXISTS(SELECT 1 FROM moz_places_stale_frecencies)")?;
+    Ok(RecalculateFrecenciesMetrics {
+        num_recalculated,
+        num_changed,
+        finished,
+    })
+}
+
 pub fn update_all_frecencies_at_once(db: &PlacesDb, scope: &SqlInterruptScope) -> Result<()> {
     let tx = db.begin_transaction()?;
 
@@ -312,13 +407,14 @@ pub fn update_all_frecencies_at_once(db: &PlacesDb, scope: &SqlInterruptScope) -
         |r| r.get::<_, i64>(0),
     )?;
     scope.err_if_interrupted()?;
+    let settings = db.frecency_settings();
     let frecencies = need_frecency_update
         .iter()
         .map(|places_id| {
             scope.err_if_interrupted()?;
             Ok((
                 *places_id,
-                calculate_frecency(db, &DEFAULT_FRECENCY_SETTINGS, *places_id, Some(false))?,
+                calculate_frecency(db, &settings, *places_id, Some(false))?,
             ))
         })
         .collect::<Result<Vec<(i64, i32)>>>()?;
@@ -392,7 +488,10 @@ pub fn delete_pending_temp_tables(conn: &PlacesDb) -> Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::api::places_api::test::new_mem_connection;
+    use crate::api::places_api::test::{new_mem_api, new_mem_connection};
+    use crate::api::places_api::ConnectionType;
+    use crate::frecency::FrecencySettings;
+    use crate::observation::VisitObservation;
 
     #[test]
     fn test_meta() {
@@ -415,4 +514,59 @@ mod tests {
             .is_none());
         delete_meta(&conn, "foo").expect("delete non-existing should work");
     }
+
+    #[test]
+    fn test_recalculate_frecencies() {
+        let api = new_mem_api();
+        let conn = api
+            .open_connection(ConnectionType::ReadWrite)
+            .expect("should get a connection");
+        for i in 0..5 {
+            let url = Url::parse(&format!("https://example.com/{}", i)).unwrap();
+            history::apply_observation(
+                &conn,
+                VisitObservation::new(url).with_visit_type(VisitTransition::Typed),
+            )
+            .expect("should apply");
+        }
+        let get_frecencies = || -> Vec<i32> {
+            conn.query_rows_and_then("SELECT frecency FROM moz_places ORDER BY id", [], |row| {
+                row.get(0)
+            })
+            .expect("should get frecencies")
+        };
+        let old_frecencies = get_frecencies();
+
+        // Changing the settings on the API changes them for open connections,
+        // but doesn't recalculate anything yet.
+        api.set_frecency_settings(FrecencySettings {
+            typed_visit_bonus: 4000,
+            ..FrecencySettings::default()
+        });
+        assert_eq!(conn.frecency_settings().typed_visit_bonus, 4000);
+        assert_eq!(get_frecencies(), old_frecencies);
+
+        let metrics = run_maintenance_recalculate_frecencies(&conn, false, 0).unwrap();
+        assert_eq!(metrics.num_recalculated, 0);
+        assert!(metrics.finished);
+
+        let metrics = run_maintenance_recalculate_frecencies(&conn, true, 2).unwrap();
+        assert_eq!(metrics.num_recalculated, 2);
+        assert_eq!(metrics.num_changed, 2);
+        assert!(!metrics.finished);
+
+        let metrics = run_maintenance_recalculate_frecencies(&conn, false, 0).unwrap();
+        assert_eq!(metrics.num_recalculated, 3);
+        assert_eq!(metrics.num_changed, 3);
+        assert!(metrics.finished);
+
+        for (old, new) in old_frecencies.iter().zip(get_frecencies()) {
+            assert!(new > *old, "{} should be greater than {}", new, old);
+        }
+
+        // Recalculating again with the same settings doesn't change anything.
+        let metrics = run_maintenance_recalculate_frecencies(&conn, true, 0).unwrap();
+        assert_eq!(metrics.num_recalculated, 5);
+        assert_eq!(metrics.num_changed, 0);
+    }
 }
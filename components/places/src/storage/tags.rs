@@ -66,9 +66,14 @@ pub fn validate_tag(tag: &str) -> ValidatedTag<'_> {
 ///
 /// There is no success return value.
 pub fn tag_url(db: &PlacesDb, url: &Url, tag: &str) -> Result<()> {
-    let tag = validate_tag(tag).ensure_valid()?;
     let tx = db.begin_transaction()?;
+    tag_url_in_tx(db, url, tag)?;
+    tx.commit()?;
+    Ok(())
+}
 
+pub(crate) fn tag_url_in_tx(db: &PlacesDb, url: &Url, tag: &str) -> Result<()> {
+    let tag = validate_tag(tag).ensure_valid()?;
     // This function will not create a new place.
     // Fetch the place id, so we (a) avoid creating a new tag when we aren't
     // going to reference it and (b) to avoid a sub-query.
@@ -91,7 +96,6 @@ pub fn tag_url(db: &PlacesDb, url: &Url, tag: &str) -> Result<()> {
             (":place_id", &place_id),
         ],
     )?;
-    tx.commit()?;
     Ok(())
 }
 
@@ -158,6 +162,22 @@ pub fn remove_all_tags_from_url(db: &PlacesDb, url: &Url) -> Result<()> {
 ///
 /// There is no success return value.
 pub fn remove_tag(db: &PlacesDb, tag: &str) -> Result<()> {
+    let tx = db.begin_transaction()?;
+    delete_tag(db, tag)?;
+    tx.commit()?;
+    Ok(())
+}
+
+fn delete_tag(db: &PlacesDb, tag: &str) -> Result<()> {
+    // Delete the relations explicitly, rather than relying on the cascade, so
+    // it's obvious that the triggers which bump the change counters of the
+    // tagged bookmarks run.
+    db.execute_cached(
+        "DELETE FROM moz_tags_relation
+         WHERE tag_id = (SELECT id FROM moz_tags
+                         WHERE tag = :tag)",
+        &[(":tag", &tag)],
+    )?;
     db.execute_cached(
         "DELETE FROM moz_tags
          WHERE tag = :tag",
@@ -166,6 +186,96 @@ pub fn remove_tag(db: &PlacesDb, tag: &str) -> Result<()> {
     Ok(())
 }
 
+/// Rename a tag on all URLs.
+///
+/// # Arguments
+///
+/// * `conn` - A database connection on which to operate.
+///
+/// * `old_tag` - The tag to rename.
+///
+/// * `new_tag` - The new name for the tag. If a tag with this name already
+///   exists, the two tags are merged.
+///
+/// # Returns
+///
+/// There is no success return value - the operation is ignored if no URLs
+/// have the old tag.
+pub fn rename_tag(db: &PlacesDb, old_tag: &str, new_tag: &str) -> Result<()> {
+    let old_tag = validate_tag(old_tag).ensure_valid()?;
+    let new_tag = validate_tag(new_tag).ensure_valid()?;
+    if old_tag == new_tag {
+        return Ok(());
+    }
+    let tx = db.begin_transaction()?;
+    // Sync associates tags with bookmarks, so rather than renaming the tag
+    // in place, we move its relations, which bumps the change counters of
+    // the tagged bookmarks and uploads them with their new tags.
+    let has_urls = db.exists(
+        "SELECT 1 FROM moz_tags_relation
+         WHERE tag_id = (SELECT id FROM moz_tags
+                         WHERE tag = :old_tag)",
+        &[(":old_tag", &old_tag)],
+    )?;
+    if has_urls {
+        db.execute_cached(
+            "INSERT OR IGNORE INTO moz_tags(tag, lastModified)
+             VALUES(:new_tag, now())",
+            &[(":new_tag", &new_tag)],
+        )?;
+        db.execute_cached(
+            "UPDATE moz_tags SET lastModified = now()
+             WHERE tag = :new_tag",
+            &[(":new_tag", &new_tag)],
+        )?;
+        db.execute_cached(
+            "INSERT OR IGNORE INTO moz_tags_relation(tag_id, place_id)
+             SELECT (SELECT id FROM moz_tags WHERE tag = :new_tag), r.place_id
+             FROM moz_tags_relation r
+             JOIN moz_tags t ON t.id = r.tag_id
+             WHERE t.tag = :old_tag",
+            &[(":old_tag", &old_tag), (":new_tag", &new_tag)],
+        )?;
+    }
+    delete_tag(db, old_tag)?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// A tag, and how many URLs have it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TagInfo {
+    pub tag: String,
+    pub url_count: u32,
+}
+
+/// Retrieves all tags which are in use.
+///
+/// # Arguments
+///
+/// * `conn` - A database connection on which to operate.
+///
+/// # Returns
+///
+/// * A Vec<TagInfo> with each tag and the number of URLs which have it,
+///   sorted by tag.
+pub fn get_all_tags(db: &PlacesDb) -> Result<Vec<TagInfo>> {
+    db.query_rows_and_then_cached(
+        "SELECT t.tag, COUNT(*) AS url_count
+         FROM moz_tags t
+         JOIN moz_tags_relation r ON r.tag_id = t.id
+         GROUP BY t.id
+         ORDER BY t.tag",
+        [],
+        |row| -> Result<_> {
+            Ok(TagInfo {
+                tag: row.get("tag")?,
+                url_count: row.get("url_count")?,
+            })
+        },
+    )
+}
+
 /// Retrieves a list of URLs which have the specified tag.
 ///
 /// # Arguments
@@ -364,4 +474,100 @@ mod tests {
             .expect("should work")
             .expect("should exist");
     }
+
+    #[test]
+    fn test_rename_tag() {
+        let conn = new_mem_connection();
+        let url1 = Url::parse("http://example.com").expect("valid url");
+        let url2 = Url::parse("http://example2.com").expect("valid url");
+        new_page_info(&conn, &url1, None).expect("should create the page");
+        new_page_info(&conn, &url2, None).expect("should create the page");
+
+        tag_url(&conn, &url1, "old").expect("should work");
+        tag_url(&conn, &url2, "old").expect("should work");
+        tag_url(&conn, &url2, "existing").expect("should work");
+        assert_eq!(
+            get_all_tags(&conn).expect("should work"),
+            vec![
+                TagInfo {
+                    tag: "existing".into(),
+                    url_count: 1
+                },
+                TagInfo {
+                    tag: "old".into(),
+                    url_count: 2
+                },
+            ]
+        );
+
+        rename_tag(&conn, "old", "new").expect("should work");
+        check_urls_with_tag(&conn, "old", vec![]);
+        check_urls_with_tag(&conn, "new", vec![url1.clone(), url2.clone()]);
+
+        // Renaming to an existing tag merges them.
+        rename_tag(&conn, "new", " existing ").expect("should work");
+        check_tags_for_url(&conn, &url1, vec!["existing".to_string()]);
+        check_tags_for_url(&conn, &url2, vec!["existing".to_string()]);
+        assert_eq!(get_foreign_count(&conn, &url2), 1);
+        assert_eq!(
+            get_all_tags(&conn).expect("should work"),
+            vec![TagInfo {
+                tag: "existing".into(),
+                url_count: 2
+            }]
+        );
+
+        // Renaming a tag nobody has doesn't create the new one.
+        rename_tag(&conn, "unused", "other").expect("should work");
+        assert_eq!(get_all_tags(&conn).expect("should work").len(), 1);
+        rename_tag(&conn, "existing", "").expect_err("new tag is invalid");
+    }
+
+    #[test]
+    fn test_tag_changes_bump_sync_change_counter() {
+        use crate::storage::bookmarks::{
+            insert_bookmark, BookmarkPosition, BookmarkRootGuid, InsertableBookmark, InsertableItem,
+        };
+
+        let conn = new_mem_connection();
+        let url = Url::parse("http://example.com").expect("valid url");
+        insert_bookmark(
+            &conn,
+            InsertableItem::Bookmark {
+                b: InsertableBookmark {
+                    parent_guid: BookmarkRootGuid::Unfiled.into(),
+                    position: BookmarkPosition::Append,
+                    date_added: None,
+                    last_modified: None,
+                    guid: None,
+                    url: url.clone(),
+                    title: None,
+                },
+            },
+        )
+        .expect("should insert the bookmark");
+        let reset_counters = || {
+            conn.execute("UPDATE moz_bookmarks SET syncChangeCounter = 0", [])
+                .expect("should work");
+        };
+        let changed = || -> u32 {
+            conn.query_one(
+                "SELECT syncChangeCounter FROM moz_bookmarks
+                 WHERE fk = (SELECT id FROM moz_places WHERE url = 'http://example.com/')",
+            )
+            .expect("should work")
+        };
+
+        reset_counters();
+        tag_url(&conn, &url, "tag").expect("should work");
+        assert!(changed() > 0);
+
+        reset_counters();
+        rename_tag(&conn, "tag", "renamed").expect("should work");
+        assert!(changed() > 0);
+
+        reset_counters();
+        remove_tag(&conn, "renamed").expect("should work");
+        assert!(changed() > 0);
+    }
 }
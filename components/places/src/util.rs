@@ -25,7 +25,7 @@ pub fn slice_up_to(s: &str, max_len: usize) -> &str {
 ///
 /// Swift loves using file urls (the only support it has for file manipulation
 /// is through file urls), so it's handy to support them if possible.
-fn unurl_path(p: impl AsRef<Path>) -> PathBuf {
+pub fn unurl_path(p: impl AsRef<Path>) -> PathBuf {
     p.as_ref()
         .to_str()
         .and_then(|s| Url::parse(s).ok())
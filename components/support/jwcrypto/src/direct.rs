@@ -10,7 +10,7 @@ use crate::{
     error::{JwCryptoError, Result},
     Algorithm, CompactJwe, EncryptionAlgorithm, JweHeader, Jwk, JwkKeyParameters,
 };
-use rc_crypto::rand;
+use rc_crypto::{digest, hkdf, hmac, rand};
 
 impl Jwk {
     /// Create a new random key suitable for `Direct` symmetric encryption.
@@ -63,6 +63,20 @@ pub(crate) fn encrypt_to_jwe(
     aes::aes_gcm_encrypt(data, protected_header, &secret)
 }
 
+/// Compute a HMAC-SHA256 of `data`, with a key derived from a `Direct` key using HKDF. `info`
+/// goes into the derivation, so that digests computed for different purposes can't be compared.
+pub(crate) fn keyed_digest(jwk: &Jwk, data: &[u8], info: &[u8]) -> Result<Vec<u8>> {
+    let secret = match &jwk.key_parameters {
+        JwkKeyParameters::Direct { k } => base64::decode_config(k, base64::URL_SAFE_NO_PAD)?,
+        _ => return Err(JwCryptoError::IllegalState("Not a Direct key")),
+    };
+    let salt = hmac::SigningKey::new(&digest::SHA256, &[]);
+    let mut key = vec![0; 32];
+    hkdf::extract_and_expand(&salt, &secret, info, &mut key)?;
+    let signature = hmac::sign(&hmac::SigningKey::new(&digest::SHA256, &key), data)?;
+    Ok(signature.as_ref().to_vec())
+}
+
 pub(crate) fn decrypt_jwe(jwe: &CompactJwe, jwk: Jwk) -> Result<String> {
     let secret = match jwk.key_parameters {
         JwkKeyParameters::Direct { k } => base64::decode_config(k, base64::URL_SAFE_NO_PAD)?,
@@ -255,3 +269,16 @@ fn test_bad_key_type_direct() {
         Err(JwCryptoError::IllegalState(_))
     ));
 }
+
+#[test]
+fn test_keyed_digest() {
+    let jwk = Jwk::new_direct_from_bytes(None, "a_secret256bitkeya_secret256bitk".as_bytes());
+    let digest = keyed_digest(&jwk, b"data", b"info").unwrap();
+    assert_eq!(digest.len(), 32);
+    assert_eq!(keyed_digest(&jwk, b"data", b"info").unwrap(), digest);
+    assert_ne!(keyed_digest(&jwk, b"other data", b"info").unwrap(), digest);
+    assert_ne!(keyed_digest(&jwk, b"data", b"other info").unwrap(), digest);
+
+    let other_jwk = Jwk::new_direct_from_bytes(None, "a_wrong256bitkeya_wrong256bitkey".as_bytes());
+    assert_ne!(keyed_digest(&other_jwk, b"data", b"info").unwrap(), digest);
+}
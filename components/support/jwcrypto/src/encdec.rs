@@ -105,6 +105,15 @@ impl<E: From<EncryptorDecryptorError>> EncryptorDecryptor<E> {
         Ok(serde_json::from_str(&json).to_encdec_result(description)?)
     }
 
+    /// Compute a HMAC-SHA256 of some data, keyed with a key derived from the encryption key
+    ///
+    /// This lets consumers store a digest which can be looked up, but which (unlike a plain hash)
+    /// can't be brute-forced without the key.  `info` separates the keys derived for different
+    /// purposes, and is also used as the description for error reports.
+    pub fn keyed_digest(&self, data: &[u8], info: &str) -> Result<Vec<u8>, E> {
+        crate::direct::keyed_digest(&self.jwk, data, info.as_bytes()).to_encdec_result(info)
+    }
+
     // Create canary text.
     //
     // These are used to check if a key is still valid for a database.  Call this when opening a
@@ -0,0 +1,155 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Registrable domains ("eTLD+1"), using the Public Suffix List.
+//!
+//! This is what desktop uses (via `Services.eTLD`) to decide which hosts belong to the same
+//! site. We include the private domains section, so that eg, `a.github.io` and `b.github.io`
+//! are different sites. The list is compiled in from `public_suffix_list.dat`; see the README
+//! for where it comes from and how to update it.
+
+use std::collections::HashSet;
+use url::Host;
+
+const PUBLIC_SUFFIX_LIST: &str = include_str!("public_suffix_list.dat");
+
+#[derive(Default)]
+struct Rules {
+    /// Rules like `co.uk`.
+    normal: HashSet<String>,
+    /// Rules like `*.ck`, without the `*.`.
+    wildcard: HashSet<String>,
+    /// Rules like `!www.ck`, without the `!`.
+    exception: HashSet<String>,
+}
+
+lazy_static::lazy_static! {
+    static ref RULES: Rules = parse_rules(PUBLIC_SUFFIX_LIST);
+}
+
+/// Converts a domain from the list to the punycode form `Url` gives us.
+fn to_ascii(domain: &str) -> Option<String> {
+    match Host::parse(domain) {
+        Ok(Host::Domain(domain)) => Some(domain),
+        _ => None,
+    }
+}
+
+fn parse_rules(list: &str) -> Rules {
+    let mut rules = Rules::default();
+    for line in list.lines() {
+        // Only the first word of a line is the rule.
+        let rule = match line.split_whitespace().next() {
+            Some(rule) if !rule.starts_with("//") => rule,
+            _ => continue,
+        };
+        let (set, domain) = if let Some(domain) = rule.strip_prefix("*.") {
+            (&mut rules.wildcard, domain)
+        } else if let Some(domain) = rule.strip_prefix('!') {
+            (&mut rules.exception, domain)
+        } else {
+            (&mut rules.normal, rule)
+        };
+        if let Some(domain) = to_ascii(domain) {
+            set.insert(domain);
+        }
+    }
+    rules
+}
+
+/// Returns how many labels at the end of `labels` make up the public suffix.
+fn public_suffix_len(rules: &Rules, labels: &[&str]) -> usize {
+    let n = labels.len();
+    // An exception rule always wins, and means its leftmost label isn't part of the suffix.
+    if let Some(i) = (0..n).find(|&i| rules.exception.contains(&labels[i..].join("."))) {
+        return n - i - 1;
+    }
+    // Otherwise the rule with the most labels wins, and if no rule matches, the suffix is the
+    // last label.
+    (0..n)
+        .find(|&i| {
+            rules.normal.contains(&labels[i..].join("."))
+                || (i + 1 < n && rules.wildcard.contains(&labels[i + 1..].join(".")))
+        })
+        .map_or(1, |i| n - i)
+}
+
+/// Returns the registrable domain of a host - its public suffix plus one more label - or None
+/// if the host is itself a public suffix. `host` must already be lowercase and in punycode, like
+/// the domains given by `Url::host()`.
+pub fn registrable_domain(host: &str) -> Option<String> {
+    let host = host.strip_suffix('.').unwrap_or(host);
+    let labels = host.split('.').collect::<Vec<_>>();
+    if labels.iter().any(|label| label.is_empty()) {
+        return None;
+    }
+    let suffix_len = public_suffix_len(&RULES, &labels);
+    if suffix_len >= labels.len() {
+        return None;
+    }
+    Some(labels[labels.len() - suffix_len - 1..].join("."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registrable_domain() {
+        // Some of the test cases from https://publicsuffix.org/list/
+        for (host, expected) in [
+            ("com", None),
+            ("example.com", Some("example.com")),
+            ("www.example.com", Some("example.com")),
+            ("a.b.example.com", Some("example.com")),
+            ("uk.com", None),
+            ("example.uk.com", Some("example.uk.com")),
+            ("b.example.uk.com", Some("example.uk.com")),
+            ("ac.jp", None),
+            ("test.ac.jp", Some("test.ac.jp")),
+            ("www.test.ac.jp", Some("test.ac.jp")),
+            ("kyoto.jp", None),
+            ("test.kyoto.jp", Some("test.kyoto.jp")),
+            ("ide.kyoto.jp", None),
+            ("b.ide.kyoto.jp", Some("b.ide.kyoto.jp")),
+            ("c.kobe.jp", None),
+            ("b.c.kobe.jp", Some("b.c.kobe.jp")),
+            ("city.kobe.jp", Some("city.kobe.jp")),
+            ("www.city.kobe.jp", Some("city.kobe.jp")),
+            ("ck", None),
+            ("test.ck", None),
+            ("b.test.ck", Some("b.test.ck")),
+            ("www.ck", Some("www.ck")),
+            ("www.www.ck", Some("www.ck")),
+            ("us", None),
+            ("test.us", Some("test.us")),
+            ("www.test.us", Some("test.us")),
+            ("ak.us", None),
+            ("k12.ak.us", None),
+            ("test.k12.ak.us", Some("test.k12.ak.us")),
+            ("www.ibm.de", Some("ibm.de")),
+            ("login.ibm.de", Some("ibm.de")),
+            ("www.example.co.uk", Some("example.co.uk")),
+            ("github.io", None),
+            ("victim.github.io", Some("victim.github.io")),
+            ("www.victim.github.io", Some("victim.github.io")),
+            (
+                "xn--85x722f.xn--55qx5d.cn",
+                Some("xn--85x722f.xn--55qx5d.cn"),
+            ),
+            (
+                "www.xn--85x722f.xn--55qx5d.cn",
+                Some("xn--85x722f.xn--55qx5d.cn"),
+            ),
+            ("xn--55qx5d.cn", None),
+            // Hosts which aren't under a listed suffix.
+            ("localhost", None),
+            ("example.example", Some("example.example")),
+            ("www.example.com.", Some("example.com")),
+            ("a..com", None),
+        ] {
+            assert_eq!(registrable_domain(host).as_deref(), expected, "{}", host);
+        }
+    }
+}
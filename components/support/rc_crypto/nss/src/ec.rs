@@ -175,6 +175,40 @@ impl PrivateKey {
         self.curve
     }
 
+    /// ECDSA sign operation. The signature is the raw `r || s` encoding, as used by
+    /// WebCrypto and accepted by [`PublicKey::verify`].
+    pub fn sign(&self, message: &[u8], hash_algorithm: HashAlgorithm) -> Result<Vec<u8>> {
+        // The following code is adapted from:
+        // https://searchfox.org/mozilla-central/rev/b2716c233e9b4398fc5923cbe150e7f83c7c6c5b/dom/crypto/WebCryptoTask.cpp#1100
+        let hash = pk11::context::hash_buf(&hash_algorithm, message)?;
+        let hash = nss_sys::SECItem {
+            len: u32::try_from(hash.len())?,
+            data: hash.as_ptr() as *mut u8,
+            type_: 0,
+        };
+        let sig_len = unsafe { nss_sys::PK11_SignatureLen(self.as_mut_ptr()) };
+        if sig_len <= 0 {
+            return Err(ErrorKind::InternalError.into());
+        }
+        let mut sig_buf = vec![0u8; usize::try_from(sig_len)?];
+        let mut signature = nss_sys::SECItem {
+            len: u32::try_from(sig_buf.len())?,
+            data: sig_buf.as_mut_ptr(),
+            type_: 0,
+        };
+        map_nss_secstatus(|| unsafe {
+            nss_sys::PK11_SignWithMechanism(
+                self.as_mut_ptr(),
+                nss_sys::PK11_MapSignKeyType((*self.wrapped.as_ptr()).keyType),
+                ptr::null(),
+                &mut signature,
+                &hash,
+            )
+        })?;
+        sig_buf.truncate(usize::try_from(signature.len)?);
+        Ok(sig_buf)
+    }
+
     pub fn private_value(&self) -> Result<Vec<u8>> {
         let mut private_value = self.read_raw_attribute(nss_sys::CKA_VALUE.into()).unwrap();
         let private_key = unsafe { sec_item_as_slice(private_value.as_mut_ref())?.to_vec() };
@@ -30,6 +30,13 @@ pub fn pbkdf2_key_derive(
     let oid_tag = match hash_algorithm {
         HashAlgorithm::SHA256 => SECOidTag::SEC_OID_HMAC_SHA256 as u32,
         HashAlgorithm::SHA384 => SECOidTag::SEC_OID_HMAC_SHA384 as u32,
+        HashAlgorithm::SHA1 | HashAlgorithm::SHA512 => {
+            return Err(ErrorKind::InputError(format!(
+                "PBKDF2 is not supported with {:?}",
+                hash_algorithm
+            ))
+            .into())
+        }
     };
     let mut sec_salt = nss_sys::SECItem {
         len: u32::try_from(salt.len())?,
@@ -20,7 +20,7 @@ pub fn hkdf_expand(
     len: usize,
 ) -> Result<Vec<u8>> {
     ensure_nss_initialized();
-    let mech = digest_alg.as_hkdf_mechanism();
+    let mech = digest_alg.as_hkdf_mechanism()?;
     // Most of the following code is inspired by the Firefox WebCrypto implementation:
     // https://searchfox.org/mozilla-central/rev/ee3905439acbf81e9c829ece0b46d09d2fa26c5c/dom/crypto/WebCryptoTask.cpp#2530-2597
     // Except that we only do the expand part, which explains why we use null pointers below.
@@ -108,6 +108,18 @@ mod tests {
         assert!(verify_with_own_key(&key, MESSAGE, &expected_signature).is_ok());
     }
 
+    #[test]
+    fn hmac_sign_other_algorithms() {
+        for (alg, signature_hex) in [
+            (&digest::SHA1, "de7c9b85b8b78aa6bc8a7a36f70a90701c9db4d9"),
+            (&digest::SHA512, "b42af09057bac1e2d41708e48a902e09b5ff7f12ab428a4fe86653c73dd248fb82f948a549f7b791a5b41915ee4d1ec3935357e4e2317250d0372afa2ebeeb3a"),
+        ] {
+            let key = SigningKey::new(alg, KEY);
+            let signature = sign(&key, MESSAGE).unwrap();
+            assert_eq!(hex::encode(signature.as_ref()), signature_hex);
+        }
+    }
+
     #[test]
     fn hmac_sign_gives_different_signatures_for_different_keys() {
         let key = SigningKey::new(&digest::SHA256, b"another key");
@@ -19,8 +19,11 @@
 // OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR IN
 // CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
 
-use crate::Result;
-use nss::{ec::Curve, ec::PublicKey, pbkdf2::HashAlgorithm};
+use crate::error::*;
+use nss::{
+    ec::{Curve, EcKey, PrivateKey, PublicKey},
+    pbkdf2::HashAlgorithm,
+};
 
 /// A signature verification algorithm.
 pub struct VerificationAlgorithm {
@@ -66,10 +69,100 @@ impl<'a> UnparsedPublicKey<'a> {
     }
 }
 
+/// A signing algorithm.
+pub struct EcdsaSigningAlgorithm {
+    curve: Curve,
+    digest_alg: HashAlgorithm,
+}
+
+/// ECDSA signatures using the P-256 curve and SHA-256, in the fixed-length `r || s` format
+/// accepted by [`ECDSA_P256_SHA256`].
+pub static ECDSA_P256_SHA256_FIXED_SIGNING: EcdsaSigningAlgorithm = EcdsaSigningAlgorithm {
+    curve: Curve::P256,
+    digest_alg: HashAlgorithm::SHA256,
+};
+
+/// An ECDSA key pair, used for signing.
+pub struct EcdsaKeyPair {
+    alg: &'static EcdsaSigningAlgorithm,
+    private_key: PrivateKey,
+    public_key: Vec<u8>,
+}
+
+impl EcdsaKeyPair {
+    /// Generate a new random key pair.
+    pub fn generate(alg: &'static EcdsaSigningAlgorithm) -> Result<Self> {
+        let (private_key, public_key) = nss::ec::generate_keypair(alg.curve)?;
+        Ok(Self {
+            alg,
+            private_key,
+            public_key: public_key.to_bytes()?,
+        })
+    }
+
+    /// Import a key pair previously exported with [`EcdsaKeyPair::export`].
+    pub fn import(alg: &'static EcdsaSigningAlgorithm, ec_key: &EcKey) -> Result<Self> {
+        if ec_key.curve() != alg.curve {
+            return Err(ErrorKind::InternalError.into());
+        }
+        Ok(Self {
+            alg,
+            private_key: PrivateKey::import(ec_key)?,
+            public_key: ec_key.public_key().to_vec(),
+        })
+    }
+
+    pub fn export(&self) -> Result<EcKey> {
+        Ok(self.private_key.export()?)
+    }
+
+    pub fn algorithm(&self) -> &'static EcdsaSigningAlgorithm {
+        self.alg
+    }
+
+    /// The public key, as an uncompressed point.
+    pub fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+
+    pub fn sign(&self, message: &[u8]) -> Result<Vec<u8>> {
+        Ok(self.private_key.sign(message, self.alg.digest_alg)?)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_ecdsa_p256_sha256_sign() {
+        let key_pair = EcdsaKeyPair::generate(&ECDSA_P256_SHA256_FIXED_SIGNING).unwrap();
+        let message = b"message to sign";
+        let signature = key_pair.sign(message).unwrap();
+        assert_eq!(signature.len(), 64);
+        let public_key = UnparsedPublicKey::new(&ECDSA_P256_SHA256, key_pair.public_key());
+        assert!(public_key.verify(message, &signature).is_ok());
+        assert!(public_key.verify(b"another message", &signature).is_err());
+
+        // Signatures from an exported and re-imported key pair verify with the same public key.
+        let imported = EcdsaKeyPair::import(
+            &ECDSA_P256_SHA256_FIXED_SIGNING,
+            &key_pair.export().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(imported.public_key(), key_pair.public_key());
+        let signature = imported.sign(message).unwrap();
+        assert!(public_key.verify(message, &signature).is_ok());
+
+        // Keys for other curves are rejected.
+        let (p384_key, _) = nss::ec::generate_keypair(Curve::P384).unwrap();
+        assert!(EcdsaKeyPair::import(
+            &ECDSA_P256_SHA256_FIXED_SIGNING,
+            &p384_key.export().unwrap()
+        )
+        .is_err());
+    }
+
     #[test]
     fn test_ecdsa_p384_sha384_verify() {
         // Test generated with JS DOM's WebCrypto.
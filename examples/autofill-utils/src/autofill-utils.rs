@@ -192,7 +192,7 @@ fn run_add_address(store: &Store) -> Result<()> {
     };
 
     println!("Making `add_address` api call");
-    let address = Store::add_address(store, address_fields)?;
+    let address = Store::add_address(store, address_fields, false)?;
 
     println!("Created address: {:#?}", address);
     Ok(())
@@ -57,7 +57,11 @@ fn read_form_based_login() -> LoginEntry {
             http_realm: None,
             origin,
         },
-        sec_fields: SecureLoginFields { username, password },
+        sec_fields: SecureLoginFields {
+            username,
+            password,
+            otp_secret: None,
+        },
     }
 }
 
@@ -76,7 +80,11 @@ fn read_auth_based_login() -> LoginEntry {
             http_realm,
             origin,
         },
-        sec_fields: SecureLoginFields { username, password },
+        sec_fields: SecureLoginFields {
+            username,
+            password,
+            otp_secret: None,
+        },
     }
 }
 
@@ -65,6 +65,8 @@ impl TestDb {
             ConnectionType::ReadWrite,
             0,
             Arc::new(parking_lot::Mutex::new(())),
+            Default::default(),
+            Default::default(),
         )
         .unwrap();
         println!("Populating test database...");
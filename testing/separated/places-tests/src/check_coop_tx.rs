@@ -30,11 +30,27 @@ fn test_coop_tx() {
 
     let coop_tx_lock = Arc::new(parking_lot::Mutex::new(()));
 
-    let dbmain = PlacesDb::open(path, ConnectionType::ReadWrite, 0, coop_tx_lock.clone()).unwrap();
+    let dbmain = PlacesDb::open(
+        path,
+        ConnectionType::ReadWrite,
+        0,
+        coop_tx_lock.clone(),
+        Default::default(),
+        Default::default(),
+    )
+    .unwrap();
     let (tx, rx) = sync_channel(0);
 
     let child = thread::spawn(move || {
-        let db1 = PlacesDb::open(path, ConnectionType::Sync, 0, coop_tx_lock.clone()).unwrap();
+        let db1 = PlacesDb::open(
+            path,
+            ConnectionType::Sync,
+            0,
+            coop_tx_lock.clone(),
+            Default::default(),
+            Default::default(),
+        )
+        .unwrap();
         // assert_eq!(rx.recv().unwrap(), 0);
         let mut t = db1
             .begin_transaction()
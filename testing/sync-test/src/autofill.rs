@@ -21,7 +21,7 @@ pub fn sync_addresses(client: &mut TestClient) -> Result<()> {
 }
 
 pub fn add_address(s: &AutofillStore, a: UpdatableAddressFields) -> AutofillResult<Address> {
-    let id = s.add_address(a)?.guid;
+    let id = s.add_address(a, false)?.guid;
     Ok(s.get_address(id).expect("Address has been added"))
 }
 
@@ -0,0 +1,22 @@
+#!/bin/sh
+#
+# Replaces the copy of the Public Suffix List in components/support/psl with
+# the current one from publicsuffix.org. Run it from the root of the repository.
+
+set -eu
+
+URL=https://publicsuffix.org/list/public_suffix_list.dat
+LIST=components/support/psl/src/public_suffix_list.dat
+
+TMP=$(mktemp)
+trap 'rm -f "$TMP"' EXIT
+
+curl --fail --silent --show-error --location "$URL" --output "$TMP"
+
+# Make sure we got the whole list, including the private domains.
+grep -q "===END ICANN DOMAINS===" "$TMP"
+grep -q "===END PRIVATE DOMAINS===" "$TMP"
+
+mv "$TMP" "$LIST"
+echo "Updated $LIST:"
+grep -E "^// (VERSION|COMMIT):" "$LIST" || echo "The list doesn't say which version it is."